@@ -0,0 +1,101 @@
+//! `--append`: add a run's records to the end of existing outputs, for
+//! collecting per-chunk basecaller output into one growing file.
+//!
+//! An output left half-written by an interrupted run would swallow the first
+//! appended record, so the existing file is checked to end on a record
+//! boundary first. Gzip outputs are decompressed in full for the check (a
+//! truncated member has no trailer); the appended records go in a new gzip
+//! member, which every gzip reader concatenates.
+
+use crate::output::{self, RecordFormat};
+use anyhow::{bail, Context, Result};
+use flate2::read::MultiGzDecoder;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::Path;
+
+/// Bytes of plain output read to find its last records.
+const TAIL: u64 = 1 << 16;
+
+/// Fail unless `path` is missing, empty, or ends with a complete record.
+pub fn check_boundary(path: &Path, format: RecordFormat) -> Result<()> {
+    let len = match fs::metadata(path) {
+        Ok(m) if !m.is_file() => bail!("--append needs a regular file, not {:?}", path),
+        Ok(m) => m.len(),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e).with_context(|| format!("checking {:?}", path)),
+    };
+    if len == 0 {
+        return Ok(());
+    }
+    let mut file = File::open(path).with_context(|| format!("open {:?}", path))?;
+    let tail = if output::is_gzip_path(path) {
+        gzip_tail(file).with_context(|| {
+            format!(
+                "{:?} is not a complete gzip file; was the run writing it interrupted?",
+                path
+            )
+        })?
+    } else {
+        file.seek(SeekFrom::Start(len.saturating_sub(TAIL)))?;
+        let mut tail = Vec::new();
+        file.read_to_end(&mut tail)?;
+        tail
+    };
+    if let Some(problem) = unfinished(&tail, format) {
+        bail!("cannot append to {:?}: {}", path, problem);
+    }
+    Ok(())
+}
+
+/// The last decompressed bytes of a gzip file, failing on a truncated member.
+fn gzip_tail(file: File) -> Result<Vec<u8>> {
+    let mut decoder = MultiGzDecoder::new(io::BufReader::new(file));
+    let (mut tail, mut buf) = (Vec::new(), vec![0; TAIL as usize]);
+    loop {
+        let n = decoder.read(&mut buf)?;
+        if n == 0 {
+            return Ok(tail);
+        }
+        tail.extend_from_slice(&buf[..n]);
+        if tail.len() > 2 * TAIL as usize {
+            tail.drain(..tail.len() - TAIL as usize);
+        }
+    }
+}
+
+/// Why `tail` does not end on a record boundary, if it does not.
+fn unfinished(tail: &[u8], format: RecordFormat) -> Option<&'static str> {
+    if tail.is_empty() {
+        return None;
+    }
+    let Some(body) = tail.strip_suffix(b"\n") else {
+        return Some("it does not end with a newline");
+    };
+    let last_lines: Vec<&[u8]> = body.rsplit(|&b| b == b'\n').take(4).collect();
+    match format {
+        RecordFormat::Fasta if last_lines[0].starts_with(b">") => {
+            Some("its last FASTA record has no sequence")
+        }
+        RecordFormat::Fastq => match last_lines[..] {
+            [qual, plus, seq, header]
+                if header.starts_with(b"@")
+                    && plus.starts_with(b"+")
+                    && qual.len() == seq.len() =>
+            {
+                None
+            }
+            _ => Some("its last four lines are not a complete FASTQ record"),
+        },
+        _ => None,
+    }
+}
+
+/// Open `path` for appending, creating it if needed.
+pub fn open(path: &Path) -> Result<File> {
+    OpenOptions::new()
+        .append(true)
+        .create(true)
+        .open(path)
+        .with_context(|| format!("open {:?} for appending", path))
+}
@@ -0,0 +1,388 @@
+//! BAM input: each primary record is read back as the read it was made from,
+//! with SEQ and QUAL reverse-complemented again when FLAG 0x10 says the
+//! aligner stored them on the reverse strand (as `samtools fastq` does).
+//! Secondary and supplementary records repeat a primary read and are
+//! skipped. BAM is told from its magic once any compression is undone, so
+//! `samtools view -b ... | restrand-fasta -f - ...` works without an
+//! intermediate file.
+//!
+//! `--out-format bam` writes the input records back instead, SEQ and QUAL
+//! untouched, with only the orientation recorded: in an aux tag, or for
+//! unmapped records by toggling FLAG 0x10 on those that would be flipped.
+//! The [`Annotator`] shares the reader's current record, so it needs a
+//! pipeline that writes each read as soon as it is read. Secondary and
+//! supplementary records are passed through unchanged.
+//!
+//! `--bam-tag` makes an aux tag an evidence source, for spliced RNA
+//! alignments whose transcript strand is not the mapping strand: `ts:A` is
+//! relative to the read as sequenced, as minimap2 writes it, and `XS:A` to
+//! the reference, so it is flipped for records with FLAG 0x10. Needs the
+//! `bam` feature.
+
+use clap::ValueEnum;
+use std::io::{self, BufRead};
+
+/// True when the (decompressed) data in `reader` starts with the BAM magic.
+pub fn is_bam(reader: &mut dyn BufRead) -> io::Result<bool> {
+    Ok(reader.fill_buf()?.starts_with(b"BAM\x01"))
+}
+
+/// How `--out-format bam` records the orientation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Patch {
+    /// Set `--bam-patch-tag` to `A:+` or `A:-`, the orientation the read was found in
+    Tag,
+    /// Toggle FLAG 0x10 on unmapped records that would be flipped; mapped records are left as they are
+    Flag,
+}
+
+/// The aux tag `--bam-tag` orients reads by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum AuxTag {
+    /// Transcript strand relative to the read (minimap2)
+    #[value(name = "ts")]
+    Ts,
+    /// Transcript strand relative to the reference (HISAT2, STAR, TopHat)
+    #[value(name = "XS")]
+    Xs,
+}
+
+impl AuxTag {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            AuxTag::Ts => "ts",
+            AuxTag::Xs => "XS",
+        }
+    }
+}
+
+/// A two-character aux tag name, as SAM allows it.
+pub fn parse_tag(s: &str) -> Result<[u8; 2], String> {
+    match s.as_bytes() {
+        &[a, b] if a.is_ascii_alphabetic() && b.is_ascii_alphanumeric() => Ok([a, b]),
+        _ => Err(format!("'{}' is not a SAM tag ([A-Za-z][A-Za-z0-9])", s)),
+    }
+}
+
+#[cfg(feature = "bam")]
+mod reader {
+    use super::{AuxTag, Patch};
+    use crate::bgzf::BgzfWriter;
+    use anyhow::{Context, Result};
+    use bio::alphabets::dna;
+    use bio::io::{fasta, fastq};
+    use flate2::Compression;
+    use noodles_bam as bam;
+    use noodles_sam::alignment::io::Write as _;
+    use noodles_sam::alignment::record::data::field::{Tag, Value as FieldValue};
+    use noodles_sam::alignment::record::Flags;
+    use noodles_sam::alignment::record_buf::data::field::Value;
+    use noodles_sam::alignment::RecordBuf;
+    use noodles_sam::Header;
+    use std::cell::RefCell;
+    use std::io::{self, BufRead, Write};
+    use std::rc::Rc;
+
+    /// The record last read, shared with an [`Annotator`].
+    struct Current {
+        header: Header,
+        record: bam::Record,
+        /// Secondary and supplementary records read since, kept for an annotator
+        passed: Option<Vec<bam::Record>>,
+    }
+
+    /// Reads BAM records as FASTA or FASTQ records.
+    pub struct Reader {
+        inner: bam::io::Reader<Box<dyn BufRead>>,
+        current: Rc<RefCell<Current>>,
+        n: u64,
+    }
+
+    impl Reader {
+        pub fn new(inner: Box<dyn BufRead>, name: &str) -> Result<Self> {
+            let mut inner = bam::io::Reader::from(inner);
+            let header = inner
+                .read_header()
+                .with_context(|| format!("read BAM header of {}", name))?;
+            Ok(Reader {
+                inner,
+                current: Rc::new(RefCell::new(Current {
+                    header,
+                    record: bam::Record::default(),
+                    passed: None,
+                })),
+                n: 0,
+            })
+        }
+
+        /// A writer of the records this reader returns, patched by `patch`.
+        pub fn annotator(&self, patch: Patch, tag: [u8; 2]) -> Result<Annotator> {
+            let mut current = self.current.borrow_mut();
+            current.passed = Some(Vec::new());
+            let mut writer =
+                bam::io::Writer::from(BgzfWriter::new(Vec::new(), Compression::default()));
+            writer.write_header(&current.header)?;
+            Ok(Annotator {
+                current: Rc::clone(&self.current),
+                writer,
+                patch,
+                tag: Tag::new(tag[0], tag[1]),
+            })
+        }
+
+        /// The orientations `tag` gives the records this reader returns.
+        pub fn strands(&self, tag: AuxTag) -> Result<Strands> {
+            Ok(Strands {
+                current: Rc::clone(&self.current),
+                tag,
+            })
+        }
+
+        /// The next primary record, or None at the end of input.
+        fn next(&mut self) -> io::Result<Option<Read>> {
+            let mut current = self.current.borrow_mut();
+            let current = &mut *current;
+            loop {
+                if self.inner.read_record(&mut current.record)? == 0 {
+                    return Ok(None);
+                }
+                self.n += 1;
+                let flags = current.record.flags();
+                if flags.is_secondary() || flags.is_supplementary() {
+                    if let Some(passed) = current.passed.as_mut() {
+                        passed.push(current.record.clone());
+                    }
+                    continue;
+                }
+                let Some(name) = current.record.name() else {
+                    return Err(invalid(self.n, "no read name"));
+                };
+                let mut seq: Vec<u8> = current.record.sequence().iter().collect();
+                let mut qual: Vec<u8> = current
+                    .record
+                    .quality_scores()
+                    .iter()
+                    .map(|q| q.min(93) + 33)
+                    .collect();
+                if flags.is_reverse_complemented() {
+                    seq = dna::revcomp(&seq);
+                    qual.reverse();
+                }
+                return Ok(Some(Read {
+                    record: self.n,
+                    id: String::from_utf8_lossy(name).into_owned(),
+                    seq,
+                    qual,
+                }));
+            }
+        }
+    }
+
+    struct Read {
+        record: u64,
+        id: String,
+        seq: Vec<u8>,
+        qual: Vec<u8>,
+    }
+
+    fn invalid(record: u64, what: &str) -> io::Error {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("BAM record {}: {}", record, what),
+        )
+    }
+
+    /// QUAL is ignored in FASTA mode.
+    impl fasta::FastaRead for Reader {
+        fn read(&mut self, record: &mut fasta::Record) -> io::Result<()> {
+            *record = match self.next()? {
+                Some(r) => fasta::Record::with_attrs(&r.id, None, &r.seq),
+                None => fasta::Record::new(),
+            };
+            Ok(())
+        }
+    }
+
+    impl fastq::FastqRead for Reader {
+        fn read(&mut self, record: &mut fastq::Record) -> fastq::Result<()> {
+            *record = match self.next()? {
+                Some(r) => {
+                    if r.qual.is_empty() && !r.seq.is_empty() {
+                        return Err(invalid(r.record, "no QUAL for --fastq").into());
+                    }
+                    fastq::Record::with_attrs(&r.id, None, &r.seq, &r.qual)
+                }
+                None => fastq::Record::new(),
+            };
+            Ok(())
+        }
+    }
+
+    /// Reads the orientation of the reader's current record from an aux tag.
+    pub struct Strands {
+        current: Rc<RefCell<Current>>,
+        tag: AuxTag,
+    }
+
+    impl Strands {
+        /// The orientation of the record last read, if it has the tag as `A:+` or `A:-`.
+        pub fn call(&self) -> Option<u8> {
+            let current = self.current.borrow();
+            let name = match self.tag {
+                AuxTag::Ts => b"ts",
+                AuxTag::Xs => b"XS",
+            };
+            let Ok(FieldValue::Character(strand @ (b'+' | b'-'))) =
+                current.record.data().get(name)?
+            else {
+                return None;
+            };
+            let reverse = current.record.flags().is_reverse_complemented();
+            Some(match (self.tag, reverse, strand) {
+                (AuxTag::Xs, true, b'+') => b'-',
+                (AuxTag::Xs, true, _) => b'+',
+                _ => strand,
+            })
+        }
+    }
+
+    /// Writes the reader's current record as BAM, with the orientation patched in.
+    pub struct Annotator {
+        current: Rc<RefCell<Current>>,
+        writer: bam::io::Writer<BgzfWriter<Vec<u8>>>,
+        patch: Patch,
+        tag: Tag,
+    }
+
+    impl Annotator {
+        /// Write the record last read, `orientation` being the one it was
+        /// found in (None if unresolved) and `flipped` whether it would
+        /// have been reverse-complemented.
+        pub fn push(
+            &mut self,
+            orientation: Option<u8>,
+            flipped: bool,
+            out: &mut dyn Write,
+        ) -> Result<()> {
+            self.write_passed()?;
+            let current = self.current.borrow();
+            let unmapped = current.record.flags().is_unmapped();
+            match (self.patch, orientation) {
+                (Patch::Tag, Some(orientation)) => {
+                    let mut record =
+                        RecordBuf::try_from_alignment_record(&current.header, &current.record)?;
+                    record
+                        .data_mut()
+                        .insert(self.tag, Value::Character(orientation));
+                    self.writer
+                        .write_alignment_record(&current.header, &record)?;
+                }
+                (Patch::Flag, _) if flipped && unmapped => {
+                    let mut record =
+                        RecordBuf::try_from_alignment_record(&current.header, &current.record)?;
+                    record.flags_mut().toggle(Flags::REVERSE_COMPLEMENTED);
+                    self.writer
+                        .write_alignment_record(&current.header, &record)?;
+                }
+                _ => self.writer.write_record(&current.header, &current.record)?,
+            }
+            drop(current);
+            self.drain(out)
+        }
+
+        /// Write the records passed through since the last read and the
+        /// end of the BAM.
+        pub fn finish(&mut self, out: &mut dyn Write) -> Result<()> {
+            self.write_passed()?;
+            self.writer.get_mut().try_finish()?;
+            self.drain(out)
+        }
+
+        fn write_passed(&mut self) -> Result<()> {
+            let mut current = self.current.borrow_mut();
+            let current = &mut *current;
+            for record in current.passed.iter_mut().flat_map(|p| p.drain(..)) {
+                self.writer.write_record(&current.header, &record)?;
+            }
+            Ok(())
+        }
+
+        /// Hand the BGZF blocks completed so far to `out`.
+        fn drain(&mut self, out: &mut dyn Write) -> Result<()> {
+            let blocks = self.writer.get_mut().get_mut();
+            out.write_all(blocks)?;
+            blocks.clear();
+            Ok(())
+        }
+    }
+}
+
+/// Stand-in for builds without the `bam` feature, which fails to start.
+#[cfg(not(feature = "bam"))]
+mod disabled {
+    use super::{AuxTag, Patch};
+    use anyhow::{bail, Result};
+    use bio::io::{fasta, fastq};
+    use std::io::{self, BufRead, Write};
+
+    pub enum Reader {}
+
+    impl Reader {
+        pub fn new(_inner: Box<dyn BufRead>, name: &str) -> Result<Self> {
+            bail!(
+                "{} is BAM, which needs the `bam` feature; this build was compiled without it",
+                name
+            )
+        }
+
+        pub fn annotator(&self, _patch: Patch, _tag: [u8; 2]) -> Result<Annotator> {
+            match *self {}
+        }
+
+        pub fn strands(&self, _tag: AuxTag) -> Result<Strands> {
+            match *self {}
+        }
+    }
+
+    pub enum Strands {}
+
+    impl Strands {
+        pub fn call(&self) -> Option<u8> {
+            match *self {}
+        }
+    }
+
+    impl fasta::FastaRead for Reader {
+        fn read(&mut self, _record: &mut fasta::Record) -> io::Result<()> {
+            match *self {}
+        }
+    }
+
+    impl fastq::FastqRead for Reader {
+        fn read(&mut self, _record: &mut fastq::Record) -> fastq::Result<()> {
+            match *self {}
+        }
+    }
+
+    pub enum Annotator {}
+
+    impl Annotator {
+        pub fn push(
+            &mut self,
+            _orientation: Option<u8>,
+            _flipped: bool,
+            _out: &mut dyn Write,
+        ) -> Result<()> {
+            match *self {}
+        }
+
+        pub fn finish(&mut self, _out: &mut dyn Write) -> Result<()> {
+            match *self {}
+        }
+    }
+}
+
+#[cfg(not(feature = "bam"))]
+pub use disabled::{Annotator, Reader, Strands};
+#[cfg(feature = "bam")]
+pub use reader::{Annotator, Reader, Strands};
@@ -0,0 +1,317 @@
+//! Batch runs over a sample manifest (`--manifest`) or several inputs
+//! (`--out-dir`), optionally in parallel (`--jobs`), with one combined
+//! summary table.
+
+use crate::compress;
+use crate::{run, share_table, Cli, Outcome};
+use anyhow::{bail, Context, Result};
+use csv::ReaderBuilder;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+/// One manifest row.
+#[derive(Debug, Clone)]
+struct Sample {
+    name: String,
+    input: String,
+    table: Option<PathBuf>,
+    output: PathBuf,
+}
+
+/// Read `input`, `output` and optional `table`/`sample` columns from a TSV manifest.
+fn load_manifest(path: &Path) -> Result<Vec<Sample>> {
+    let mut reader = ReaderBuilder::new()
+        .delimiter(b'\t')
+        .has_headers(true)
+        .from_path(path)
+        .with_context(|| format!("open manifest {:?}", path))?;
+    let headers = reader
+        .headers()
+        .context("reading manifest headers")?
+        .clone();
+    let col = |name: &str| headers.iter().position(|h| h == name);
+    let (Some(input_idx), Some(output_idx)) = (col("input"), col("output")) else {
+        bail!("manifest {:?} needs 'input' and 'output' columns", path);
+    };
+    let (table_idx, sample_idx) = (col("table"), col("sample"));
+
+    let mut samples = Vec::new();
+    for rec in reader.records() {
+        let rec = rec.context("parsing manifest")?;
+        let field = |idx: Option<usize>| {
+            idx.and_then(|i| rec.get(i))
+                .map(str::trim)
+                .filter(|s| !s.is_empty())
+        };
+        let (Some(input), Some(output)) = (field(Some(input_idx)), field(Some(output_idx))) else {
+            bail!(
+                "manifest row {} is missing input or output",
+                samples.len() + 1
+            );
+        };
+        let name = field(sample_idx).map_or_else(
+            || {
+                Path::new(input)
+                    .file_name()
+                    .map_or(input.to_owned(), |n| n.to_string_lossy().into_owned())
+            },
+            str::to_owned,
+        );
+        samples.push(Sample {
+            name,
+            input: input.to_owned(),
+            table: field(table_idx).map(PathBuf::from),
+            output: PathBuf::from(output),
+        });
+    }
+    Ok(samples)
+}
+
+/// Extensions dropped from an input name, after any compression extension,
+/// to form `{stem}`.
+const SEQUENCE_EXTENSIONS: [&str; 7] = [".fasta", ".fastq", ".2bit", ".fna", ".fa", ".fq", ".fas"];
+
+/// Input file name without compression and sequence extensions.
+fn stem(name: &str) -> &str {
+    let name = compress::strip_extension(name);
+    SEQUENCE_EXTENSIONS
+        .iter()
+        .find_map(|ext| name.strip_suffix(ext))
+        .unwrap_or(name)
+}
+
+/// Whether `name` has a sequence extension, optionally compressed.
+fn is_sequence_file(name: &str) -> bool {
+    let name = compress::strip_extension(name);
+    SEQUENCE_EXTENSIONS.iter().any(|ext| name.ends_with(ext))
+}
+
+/// Deepest directory containing every input, so outputs keep the layout below it.
+fn common_base(inputs: &[&Path]) -> PathBuf {
+    let mut base: Vec<Component> = match inputs.first().and_then(|p| p.parent()) {
+        Some(parent) => parent.components().collect(),
+        None => return PathBuf::new(),
+    };
+    for input in &inputs[1..] {
+        let parent: Vec<Component> = input
+            .parent()
+            .map_or(Vec::new(), |p| p.components().collect());
+        let shared = base.iter().zip(&parent).take_while(|(a, b)| a == b).count();
+        base.truncate(shared);
+    }
+    base.iter().collect()
+}
+
+/// Output path for `input` under `out_dir`: its directory relative to
+/// `base`, then the expanded `template`.
+fn output_path(out_dir: &Path, base: &Path, input: &Path, template: &str) -> PathBuf {
+    let name = input
+        .file_name()
+        .map_or_else(String::new, |n| n.to_string_lossy().into_owned());
+    let file = template
+        .replace("{name}", &name)
+        .replace("{stem}", stem(&name));
+    let rel_dir = input
+        .parent()
+        .and_then(|p| p.strip_prefix(base).ok())
+        .unwrap_or(Path::new(""));
+    out_dir.join(rel_dir).join(file)
+}
+
+/// Restrand each input into its own file under `out_dir`.
+pub fn run_inputs(cli: &Cli, inputs: &[String], out_dir: &Path) -> Result<Outcome> {
+    if inputs.iter().any(|i| i == "-") {
+        bail!("stdin ('-') cannot be combined with --out-dir");
+    }
+    let paths: Vec<&Path> = inputs.iter().map(Path::new).collect();
+    run_paths(cli, inputs, &common_base(&paths), out_dir)
+}
+
+/// Restrand every matching file under `dir` (`--input-dir`), keeping the
+/// layout below `dir` in `out_dir`.
+pub fn run_input_dir(cli: &Cli, dir: &Path, out_dir: &Path) -> Result<Outcome> {
+    let mut inputs = Vec::new();
+    discover(dir, cli.recursive, cli.pattern.as_deref(), &mut inputs)?;
+    if inputs.is_empty() {
+        bail!("no matching input files in {:?}", dir);
+    }
+    inputs.sort();
+    let inputs: Vec<String> = inputs
+        .iter()
+        .map(|p| p.to_string_lossy().into_owned())
+        .collect();
+    run_paths(cli, &inputs, dir, out_dir)
+}
+
+/// Collect files in `dir` whose name matches `pattern`, or that look like
+/// sequence files when no pattern is given. Hidden entries are skipped.
+fn discover(
+    dir: &Path,
+    recursive: bool,
+    pattern: Option<&str>,
+    found: &mut Vec<PathBuf>,
+) -> Result<()> {
+    for entry in fs::read_dir(dir).with_context(|| format!("read directory {:?}", dir))? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if name.starts_with('.') {
+            continue;
+        }
+        let path = entry.path();
+        // Follow symlinks, as MinKNOW output is often linked into place.
+        let meta = fs::metadata(&path).with_context(|| format!("stat {:?}", path))?;
+        if meta.is_dir() {
+            if recursive {
+                discover(&path, recursive, pattern, found)?;
+            }
+        } else if match pattern {
+            Some(p) => glob_match(p.as_bytes(), name.as_bytes()),
+            None => is_sequence_file(&name),
+        } {
+            found.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Shell-style match of `name` against `pattern`, where `*` matches any run
+/// of characters and `?` any single character.
+fn glob_match(pattern: &[u8], name: &[u8]) -> bool {
+    let (mut p, mut n) = (0, 0);
+    let mut backtrack = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some(b'*') => {
+                backtrack = Some((p, n));
+                p += 1;
+            }
+            Some(&c) if c == b'?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match backtrack {
+                Some((bp, bn)) => {
+                    p = bp + 1;
+                    n = bn + 1;
+                    backtrack = Some((bp, bn + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == b'*')
+}
+
+fn run_paths(cli: &Cli, inputs: &[String], base: &Path, out_dir: &Path) -> Result<Outcome> {
+    let paths: Vec<&Path> = inputs.iter().map(Path::new).collect();
+    let mut samples: Vec<Sample> = Vec::with_capacity(inputs.len());
+    for (input, path) in inputs.iter().zip(&paths) {
+        let output = output_path(out_dir, base, path, &cli.out_template);
+        if let Some(other) = samples.iter().find(|s| s.output == output) {
+            bail!(
+                "inputs '{}' and '{}' both map to {:?}; add {{stem}} or {{name}} to --out-template",
+                other.input,
+                input,
+                output
+            );
+        }
+        if let Some(dir) = output.parent() {
+            fs::create_dir_all(dir).with_context(|| format!("create {:?}", dir))?;
+        }
+        let name = path.strip_prefix(base).unwrap_or(path);
+        samples.push(Sample {
+            name: name.to_string_lossy().into_owned(),
+            input: input.clone(),
+            table: None,
+            output,
+        });
+    }
+    run_samples(cli, samples)
+}
+
+/// Process every manifest row; failed samples are reported and the run as a
+/// whole fails after the rest have finished.
+pub fn run_manifest(cli: &Cli, manifest: &Path) -> Result<Outcome> {
+    run_samples(cli, load_manifest(manifest)?)
+}
+
+fn run_samples(cli: &Cli, samples: Vec<Sample>) -> Result<Outcome> {
+    let results: Vec<Mutex<Option<Result<Outcome>>>> =
+        samples.iter().map(|_| Mutex::new(None)).collect();
+    let next = AtomicUsize::new(0);
+    let jobs = (cli.jobs as usize).clamp(1, samples.len().max(1));
+
+    // `--table` is loaded once and looked up by every job; tables named by
+    // single samples are loaded by their job. `--max-memory` is split
+    // between the tables that can be loaded at the same time.
+    let own_table = |s: &Sample| s.table.is_some() && s.table != cli.table;
+    let shared = cli.table.is_some() && samples.iter().any(|s| !own_table(s));
+    let own = samples.iter().filter(|s| own_table(s)).count().min(jobs);
+    let tables = (own + usize::from(shared)).max(1) as u64;
+    let mut cli = cli.clone();
+    cli.max_memory = cli.max_memory.map(|m| m / tables);
+    if shared {
+        cli.shared_table = share_table(&cli)?;
+    }
+    let cli = &cli;
+    std::thread::scope(|scope| {
+        for _ in 0..jobs {
+            scope.spawn(|| loop {
+                let i = next.fetch_add(1, Ordering::Relaxed);
+                let Some(sample) = samples.get(i) else {
+                    break;
+                };
+                let table = sample.table.clone().or_else(|| cli.table.clone());
+                let result = run(&cli.for_input(&sample.input, table, sample.output.clone()))
+                    .with_context(|| format!("sample '{}'", sample.name));
+                if let Err(e) = &result {
+                    eprintln!("Error: {:?}", e);
+                }
+                *results[i].lock().unwrap() = Some(result);
+            });
+        }
+    });
+
+    let mut table =
+        String::from("sample\tinput\toutput\tstatus\tprocessed\tflipped\tmissing\twarnings\n");
+    let mut total = Outcome::default();
+    let mut failed = 0;
+    for (sample, result) in samples.iter().zip(results) {
+        let result = result.into_inner().unwrap().expect("every sample ran");
+        let (status, o) = match result {
+            Ok(o) => ("ok", o),
+            Err(_) => {
+                failed += 1;
+                ("error", Outcome::default())
+            }
+        };
+        writeln!(
+            table,
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+            sample.name,
+            sample.input,
+            sample.output.display(),
+            status,
+            o.processed,
+            o.flipped,
+            o.missing,
+            o.warnings
+        )?;
+        total.processed += o.processed;
+        total.flipped += o.flipped;
+        total.missing += o.missing;
+        total.warnings += o.warnings;
+    }
+    match &cli.summary_file {
+        Some(p) => fs::write(p, &table).with_context(|| format!("write summary {:?}", p))?,
+        None if !cli.quiet => eprint!("{}", table),
+        None => {}
+    }
+    if failed > 0 {
+        bail!("{} of {} sample(s) failed", failed, samples.len());
+    }
+    Ok(total)
+}
@@ -0,0 +1,133 @@
+//! BGZF, the blocked gzip of bgzip and htslib, for `.gz` outputs: each block
+//! of at most 0xff00 bytes is its own gzip member carrying its compressed
+//! size, so the file is still ordinary gzip but can be seeked into. The
+//! `--gzi-out` index lists where each block after the first starts, both in
+//! the file and in the uncompressed data.
+
+use anyhow::{Context, Result};
+use flate2::{Compress, Compression, Crc, FlushCompress, Status};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+/// Uncompressed bytes per block, as bgzip uses, leaving room for incompressible data.
+const BLOCK_DATA: usize = 0xff00;
+
+/// Largest whole block (header, deflate data and trailer).
+const MAX_BLOCK: usize = 1 << 16;
+
+const HEADER_LEN: usize = 18;
+const TRAILER_LEN: usize = 8;
+
+/// The empty block bgzip ends every file with.
+const EOF_BLOCK: [u8; 28] = [
+    0x1f, 0x8b, 0x08, 0x04, 0, 0, 0, 0, 0, 0xff, 0x06, 0, b'B', b'C', 0x02, 0, 0x1b, 0, 0x03, 0, 0,
+    0, 0, 0, 0, 0, 0, 0,
+];
+
+/// Compresses everything written to it into BGZF blocks on `inner`.
+pub struct BgzfWriter<W: Write> {
+    inner: W,
+    compress: Compress,
+    data: Vec<u8>,
+    block: Vec<u8>,
+    /// (compressed, uncompressed) offsets of each block start after the first
+    index: Vec<(u64, u64)>,
+    compressed: u64,
+    uncompressed: u64,
+}
+
+impl<W: Write> BgzfWriter<W> {
+    pub fn new(inner: W, level: Compression) -> Self {
+        Self {
+            inner,
+            compress: Compress::new(level, false),
+            data: Vec::with_capacity(BLOCK_DATA),
+            block: Vec::with_capacity(MAX_BLOCK),
+            index: Vec::new(),
+            compressed: 0,
+            uncompressed: 0,
+        }
+    }
+
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.inner
+    }
+
+    /// Block offsets for a `.gzi` index; complete once [`Self::try_finish`] returned.
+    pub fn index(&self) -> &[(u64, u64)] {
+        &self.index
+    }
+
+    /// Write the pending block and the end-of-file block.
+    pub fn try_finish(&mut self) -> io::Result<()> {
+        self.write_block()?;
+        self.inner.write_all(&EOF_BLOCK)?;
+        self.compressed += EOF_BLOCK.len() as u64;
+        Ok(())
+    }
+
+    fn write_block(&mut self) -> io::Result<()> {
+        if self.data.is_empty() {
+            return Ok(());
+        }
+        self.compress.reset();
+        self.block.clear();
+        self.block.resize(HEADER_LEN, 0);
+        let status = self
+            .compress
+            .compress_vec(&self.data, &mut self.block, FlushCompress::Finish)
+            .map_err(io::Error::other)?;
+        if status != Status::StreamEnd || self.block.len() + TRAILER_LEN > MAX_BLOCK {
+            return Err(io::Error::other("BGZF block does not fit in 64 KiB"));
+        }
+        let mut crc = Crc::new();
+        crc.update(&self.data);
+        self.block.extend_from_slice(&crc.sum().to_le_bytes());
+        self.block
+            .extend_from_slice(&(self.data.len() as u32).to_le_bytes());
+        let [lo, hi] = ((self.block.len() - 1) as u16).to_le_bytes();
+        self.block[..HEADER_LEN].copy_from_slice(&[
+            0x1f, 0x8b, 0x08, 0x04, 0, 0, 0, 0, 0, 0xff, 0x06, 0, b'B', b'C', 0x02, 0, lo, hi,
+        ]);
+        if self.compressed > 0 {
+            self.index.push((self.compressed, self.uncompressed));
+        }
+        self.inner.write_all(&self.block)?;
+        self.compressed += self.block.len() as u64;
+        self.uncompressed += self.data.len() as u64;
+        self.data.clear();
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for BgzfWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.data.len() == BLOCK_DATA {
+            self.write_block()?;
+        }
+        let n = buf.len().min(BLOCK_DATA - self.data.len());
+        self.data.extend_from_slice(&buf[..n]);
+        Ok(n)
+    }
+
+    /// Ends the current block early, so everything written so far can be read.
+    fn flush(&mut self) -> io::Result<()> {
+        self.write_block()?;
+        self.inner.flush()
+    }
+}
+
+/// Write `index` as a bgzip `.gzi` file: the entry count, then each
+/// compressed and uncompressed offset, all little-endian u64.
+pub fn write_gzi(path: &Path, index: &[(u64, u64)]) -> Result<()> {
+    let file = File::create(path).with_context(|| format!("create BGZF index {:?}", path))?;
+    let mut w = BufWriter::new(file);
+    w.write_all(&(index.len() as u64).to_le_bytes())?;
+    for (compressed, uncompressed) in index {
+        w.write_all(&compressed.to_le_bytes())?;
+        w.write_all(&uncompressed.to_le_bytes())?;
+    }
+    w.flush()
+        .with_context(|| format!("write BGZF index {:?}", path))
+}
@@ -0,0 +1,28 @@
+//! Illumina (CASAVA 1.8+) read comments: `@<id> <mate>:<filtered>:<control>:<index>`,
+//! e.g. `@M00123:1:000:1:1101:1:1 1:N:0:ACGT+TTGA`. The comment names the
+//! mate and sample index, so downstream tools parse it by position and
+//! nothing may be glued onto it.
+
+/// Whether `desc` (the header after the read ID) starts with a CASAVA comment.
+pub fn is_comment(desc: &str) -> bool {
+    mate(desc).is_some()
+}
+
+/// The mate number (1 or 2) of a CASAVA comment at the start of `desc`.
+pub fn mate(desc: &str) -> Option<u8> {
+    let field = desc.split_whitespace().next().unwrap_or("");
+    let mut parts = field.splitn(4, ':');
+    let (Some(mate), Some(filtered), Some(control), Some(index)) =
+        (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        return None;
+    };
+    let valid = matches!(mate, "1" | "2")
+        && matches!(filtered, "Y" | "N")
+        && !control.is_empty()
+        && control.bytes().all(|b| b.is_ascii_digit())
+        && index
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'+');
+    valid.then(|| mate.as_bytes()[0] - b'0')
+}
@@ -0,0 +1,108 @@
+//! Output digests computed while writing (`--checksum`), so outputs never need re-reading.
+
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use md5::Md5;
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+/// Digest algorithm for the checksum manifest.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ChecksumAlgo {
+    Md5,
+    Sha256,
+}
+
+impl ChecksumAlgo {
+    /// Conventional manifest extension (`.md5`, `.sha256`).
+    pub fn extension(self) -> &'static str {
+        match self {
+            ChecksumAlgo::Md5 => "md5",
+            ChecksumAlgo::Sha256 => "sha256",
+        }
+    }
+}
+
+enum Digester {
+    Md5(Md5),
+    Sha256(Sha256),
+}
+
+impl Digester {
+    fn new(algo: ChecksumAlgo) -> Self {
+        match algo {
+            ChecksumAlgo::Md5 => Digester::Md5(Md5::new()),
+            ChecksumAlgo::Sha256 => Digester::Sha256(Sha256::new()),
+        }
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        match self {
+            Digester::Md5(d) => d.update(data),
+            Digester::Sha256(d) => d.update(data),
+        }
+    }
+
+    fn hex(&self) -> String {
+        let bytes = match self {
+            Digester::Md5(d) => d.clone().finalize().to_vec(),
+            Digester::Sha256(d) => d.clone().finalize().to_vec(),
+        };
+        bytes.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+}
+
+/// Pass-through writer that feeds every byte actually written into a digest.
+pub struct HashingWriter<W> {
+    inner: W,
+    digest: Arc<Mutex<Digester>>,
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.digest.lock().unwrap().update(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Collects one digest per output and writes them in `md5sum`/`sha256sum -c` format.
+pub struct Manifest {
+    algo: ChecksumAlgo,
+    entries: Vec<(String, Arc<Mutex<Digester>>)>,
+}
+
+impl Manifest {
+    pub fn new(algo: ChecksumAlgo) -> Self {
+        Self {
+            algo,
+            entries: Vec::new(),
+        }
+    }
+
+    /// Wrap an output so its bytes are digested under `name` (`-` for stdout).
+    pub fn wrap<W: Write>(&mut self, name: String, inner: W) -> HashingWriter<W> {
+        let digest = Arc::new(Mutex::new(Digester::new(self.algo)));
+        self.entries.push((name, Arc::clone(&digest)));
+        HashingWriter { inner, digest }
+    }
+
+    /// Write the manifest; call only after every wrapped writer is flushed.
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let mut w = BufWriter::new(
+            File::create(path).with_context(|| format!("create checksum manifest {:?}", path))?,
+        );
+        for (name, digest) in &self.entries {
+            writeln!(w, "{}  {}", digest.lock().unwrap().hex(), name)?;
+        }
+        w.flush()?;
+        Ok(())
+    }
+}
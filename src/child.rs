@@ -0,0 +1,68 @@
+//! Streaming the output of a helper process (an object-store client, SRA
+//! tools) as a reader.
+
+use anyhow::{Context, Result};
+use std::io::{self, Read};
+use std::process::{Child, ChildStdout, Command};
+
+/// Spawn `cmd` for `what`, naming the program in the error if it cannot be run.
+pub fn spawn(mut cmd: Command, what: &str) -> Result<Child> {
+    let program = cmd.get_program().to_string_lossy().into_owned();
+    cmd.spawn().with_context(|| {
+        format!(
+            "starting '{}' for {} (is it installed and on PATH?)",
+            program, what
+        )
+    })
+}
+
+/// A process's stdout; reading past the end fails if the process did.
+pub struct ChildReader {
+    /// What the process does, for errors, e.g. "download"
+    action: &'static str,
+    what: String,
+    child: Child,
+    stdout: ChildStdout,
+    done: bool,
+}
+
+impl ChildReader {
+    /// Run `cmd`, whose stdout must be piped, to `action` `what`.
+    pub fn run(cmd: Command, what: &str, action: &'static str) -> Result<Self> {
+        let mut child = spawn(cmd, what)?;
+        let stdout = child.stdout.take().expect("stdout is piped");
+        Ok(Self {
+            action,
+            what: what.to_owned(),
+            child,
+            stdout,
+            done: false,
+        })
+    }
+}
+
+impl Read for ChildReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.stdout.read(buf)?;
+        if n == 0 && !buf.is_empty() && !self.done {
+            self.done = true;
+            let status = self.child.wait()?;
+            if !status.success() {
+                return Err(io::Error::other(format!(
+                    "{} of {} failed ({})",
+                    self.action, self.what, status
+                )));
+            }
+        }
+        Ok(n)
+    }
+}
+
+impl Drop for ChildReader {
+    fn drop(&mut self) {
+        if !self.done {
+            let _ = self.child.kill();
+            let _ = self.child.wait();
+        }
+    }
+}
@@ -0,0 +1,229 @@
+//! `--out-format arrow|parquet`: reads written as table rows (`read_id`,
+//! `sequence`, `quality`, `final_orientation`, `flipped`) for loading
+//! straight into Polars, DuckDB or pyarrow. `read_id` is the output ID
+//! (first word of the output header), `quality` is null for FASTA input and
+//! `final_orientation` is null for reads whose orientation was not resolved.
+//!
+//! Rows are collected into record batches, encoded in memory and then
+//! passed to the ordinary `--out` writer, so checksums, uploads and byte
+//! counts treat the file like any other output. Arrow output is the IPC
+//! file format (`.arrow`/`.feather`); Parquet output is Snappy-compressed.
+//! Both need the `arrow` feature.
+
+use crate::output::RecordFormat;
+
+/// Whether `format` is written by [`ColumnarWriter`] rather than as text.
+pub fn is_columnar(format: RecordFormat) -> bool {
+    matches!(format, RecordFormat::Arrow | RecordFormat::Parquet)
+}
+
+#[cfg(feature = "arrow")]
+mod writer {
+    use crate::output::RecordFormat;
+    use anyhow::Result;
+    use arrow_array::builder::{BooleanBuilder, StringBuilder};
+    use arrow_array::{ArrayRef, RecordBatch};
+    use arrow_ipc::writer::FileWriter;
+    use arrow_schema::{DataType, Field, Schema, SchemaRef};
+    use parquet::arrow::ArrowWriter;
+    use parquet::basic::Compression;
+    use parquet::file::properties::WriterProperties;
+    use std::io::{self, Write};
+    use std::sync::{Arc, Mutex};
+
+    /// Rows per record batch (and Parquet row group).
+    const BATCH_ROWS: usize = 8192;
+
+    /// Sequence and quality bytes after which a batch is written early, so
+    /// long reads do not pile up.
+    const BATCH_BYTES: usize = 64 << 20;
+
+    /// Encoded bytes waiting to be passed to the output.
+    #[derive(Clone, Default)]
+    struct Spool(Arc<Mutex<Vec<u8>>>);
+
+    impl Spool {
+        fn drain_into(&self, out: &mut dyn Write) -> io::Result<()> {
+            let mut buf = self.0.lock().unwrap();
+            out.write_all(&buf)?;
+            buf.clear();
+            Ok(())
+        }
+    }
+
+    impl Write for Spool {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    enum Encoder {
+        Arrow(FileWriter<Spool>),
+        Parquet(ArrowWriter<Spool>),
+    }
+
+    /// Collects reads into record batches for one Arrow or Parquet output.
+    pub struct ColumnarWriter {
+        schema: SchemaRef,
+        encoder: Option<Encoder>,
+        spool: Spool,
+        read_id: StringBuilder,
+        sequence: StringBuilder,
+        quality: StringBuilder,
+        final_orientation: StringBuilder,
+        flipped: BooleanBuilder,
+        rows: usize,
+        bytes: usize,
+    }
+
+    impl ColumnarWriter {
+        pub fn new(format: RecordFormat) -> Result<Self> {
+            let schema = Arc::new(Schema::new(vec![
+                Field::new("read_id", DataType::Utf8, false),
+                Field::new("sequence", DataType::Utf8, false),
+                Field::new("quality", DataType::Utf8, true),
+                Field::new("final_orientation", DataType::Utf8, true),
+                Field::new("flipped", DataType::Boolean, false),
+            ]));
+            let spool = Spool::default();
+            let encoder = match format {
+                RecordFormat::Arrow => Encoder::Arrow(FileWriter::try_new(spool.clone(), &schema)?),
+                RecordFormat::Parquet => {
+                    let props = WriterProperties::builder()
+                        .set_compression(Compression::SNAPPY)
+                        .set_max_row_group_size(BATCH_ROWS)
+                        .build();
+                    Encoder::Parquet(ArrowWriter::try_new(
+                        spool.clone(),
+                        Arc::clone(&schema),
+                        Some(props),
+                    )?)
+                }
+                _ => unreachable!("{:?} is not a columnar format", format),
+            };
+            Ok(Self {
+                schema,
+                encoder: Some(encoder),
+                spool,
+                read_id: StringBuilder::new(),
+                sequence: StringBuilder::new(),
+                quality: StringBuilder::new(),
+                final_orientation: StringBuilder::new(),
+                flipped: BooleanBuilder::new(),
+                rows: 0,
+                bytes: 0,
+            })
+        }
+
+        /// Add a read, passing any finished batch on to `out`.
+        pub fn push(
+            &mut self,
+            header: &str,
+            seq: &[u8],
+            qual: Option<&[u8]>,
+            orientation: Option<u8>,
+            flipped: bool,
+            out: &mut dyn Write,
+        ) -> Result<()> {
+            let id = header.split_ascii_whitespace().next().unwrap_or("");
+            self.read_id.append_value(id);
+            self.sequence.append_value(String::from_utf8_lossy(seq));
+            self.quality
+                .append_option(qual.map(|q| String::from_utf8_lossy(q)));
+            self.final_orientation
+                .append_option(orientation.map(|o| char::from(o).to_string()));
+            self.flipped.append_value(flipped);
+            self.rows += 1;
+            self.bytes += seq.len() + qual.map_or(0, <[u8]>::len);
+            if self.rows >= BATCH_ROWS || self.bytes >= BATCH_BYTES {
+                self.write_batch(out)?;
+            }
+            Ok(())
+        }
+
+        fn write_batch(&mut self, out: &mut dyn Write) -> Result<()> {
+            if self.rows == 0 {
+                return Ok(());
+            }
+            let columns: Vec<ArrayRef> = vec![
+                Arc::new(self.read_id.finish()),
+                Arc::new(self.sequence.finish()),
+                Arc::new(self.quality.finish()),
+                Arc::new(self.final_orientation.finish()),
+                Arc::new(self.flipped.finish()),
+            ];
+            let batch = RecordBatch::try_new(Arc::clone(&self.schema), columns)?;
+            match self.encoder.as_mut().expect("writer is not finished") {
+                Encoder::Arrow(w) => w.write(&batch)?,
+                Encoder::Parquet(w) => w.write(&batch)?,
+            }
+            (self.rows, self.bytes) = (0, 0);
+            self.spool.drain_into(out)?;
+            Ok(())
+        }
+
+        /// Write the last batch and the file footer to `out`.
+        pub fn finish(&mut self, out: &mut dyn Write) -> Result<()> {
+            self.write_batch(out)?;
+            match self.encoder.take() {
+                Some(Encoder::Arrow(mut w)) => w.finish()?,
+                Some(Encoder::Parquet(w)) => {
+                    w.close()?;
+                }
+                None => {}
+            }
+            self.spool.drain_into(out)?;
+            Ok(())
+        }
+    }
+}
+
+/// Stand-in for builds without the `arrow` feature, which fails to start.
+#[cfg(not(feature = "arrow"))]
+mod disabled {
+    use crate::output::RecordFormat;
+    use anyhow::{bail, Result};
+    use std::io::Write;
+
+    pub enum ColumnarWriter {}
+
+    impl ColumnarWriter {
+        pub fn new(format: RecordFormat) -> Result<Self> {
+            let name = if format == RecordFormat::Arrow {
+                "arrow"
+            } else {
+                "parquet"
+            };
+            bail!(
+                "--out-format {} needs the `arrow` feature, which this build was compiled without",
+                name
+            )
+        }
+
+        pub fn push(
+            &mut self,
+            _header: &str,
+            _seq: &[u8],
+            _qual: Option<&[u8]>,
+            _orientation: Option<u8>,
+            _flipped: bool,
+            _out: &mut dyn Write,
+        ) -> Result<()> {
+            match *self {}
+        }
+
+        pub fn finish(&mut self, _out: &mut dyn Write) -> Result<()> {
+            match *self {}
+        }
+    }
+}
+
+#[cfg(not(feature = "arrow"))]
+pub use disabled::ColumnarWriter;
+#[cfg(feature = "arrow")]
+pub use writer::ColumnarWriter;
@@ -0,0 +1,100 @@
+//! Compressed inputs (tables and reads), recognized by their magic bytes
+//! rather than the file name and decoded in-process: gzip (and BGZF) always,
+//! zstd, xz and bzip2 with the features of the same names.
+
+use anyhow::{Context, Result};
+use flate2::bufread::MultiGzDecoder;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Chain, Cursor, Read};
+use std::path::Path;
+/// Leading bytes needed to tell the formats apart.
+const MAGIC_LEN: u64 = 6;
+
+/// Compression formats told apart by their leading bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Codec {
+    Gzip,
+    Zstd,
+    Xz,
+    Bzip2,
+}
+
+impl Codec {
+    fn sniff(magic: &[u8]) -> Option<Codec> {
+        if magic.starts_with(&[0x1f, 0x8b]) {
+            Some(Codec::Gzip)
+        } else if magic.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Some(Codec::Zstd)
+        } else if magic.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0x00]) {
+            Some(Codec::Xz)
+        } else if magic.starts_with(b"BZh") {
+            Some(Codec::Bzip2)
+        } else {
+            None
+        }
+    }
+
+    /// The format's name, which is also the cargo feature that decodes it
+    /// (gzip needs none).
+    fn name(self) -> &'static str {
+        match self {
+            Codec::Gzip => "gzip",
+            Codec::Zstd => "zstd",
+            Codec::Xz => "xz",
+            Codec::Bzip2 => "bzip2",
+        }
+    }
+}
+
+/// `path` without a compression extension, for telling `.csv.zst` from `.tsv.zst`.
+pub fn strip_extension(path: &str) -> &str {
+    [".gz", ".zst", ".xz", ".bz2"]
+        .iter()
+        .find_map(|ext| path.strip_suffix(ext))
+        .unwrap_or(path)
+}
+
+/// A reader replaying the bytes [`sniff`] read before the rest.
+pub type Sniffed<R> = Chain<Cursor<Vec<u8>>, R>;
+
+/// `reader`'s compression format, and a reader that still returns the bytes
+/// read to tell it. Short reads (pipes, tiny buffers) do not hide the magic.
+pub fn sniff<R: Read>(mut reader: R) -> io::Result<(Option<Codec>, Sniffed<R>)> {
+    let mut magic = Vec::with_capacity(MAGIC_LEN as usize);
+    (&mut reader).take(MAGIC_LEN).read_to_end(&mut magic)?;
+    Ok((Codec::sniff(&magic), Cursor::new(magic).chain(reader)))
+}
+
+/// Open `path`, decompressing it when it starts with a known magic number.
+pub fn open(path: &Path) -> Result<Box<dyn Read>> {
+    let file = File::open(path).with_context(|| format!("open {:?}", path))?;
+    let (codec, file) = sniff(file).with_context(|| format!("read {:?}", path))?;
+    let file = BufReader::new(file);
+    match codec {
+        None => Ok(Box::new(file)),
+        Some(codec) => decode(file, codec, &format!("{:?}", path)),
+    }
+}
+
+/// `reader` decoded from `codec`; `name` is what errors call it.
+pub fn decode<R: BufRead + 'static>(reader: R, codec: Codec, name: &str) -> Result<Box<dyn Read>> {
+    Ok(match codec {
+        Codec::Gzip => Box::new(MultiGzDecoder::new(reader)),
+        #[cfg(feature = "zstd")]
+        Codec::Zstd => Box::new(
+            zstd::Decoder::with_buffer(reader)
+                .with_context(|| format!("starting zstd decoder for {}", name))?,
+        ),
+        #[cfg(feature = "xz")]
+        Codec::Xz => Box::new(xz2::bufread::XzDecoder::new_multi_decoder(reader)),
+        #[cfg(feature = "bzip2")]
+        Codec::Bzip2 => Box::new(bzip2::bufread::MultiBzDecoder::new(reader)),
+        #[allow(unreachable_patterns)]
+        codec => anyhow::bail!(
+            "{} is {}-compressed, but this build was compiled without the `{}` feature",
+            name,
+            codec.name(),
+            codec.name()
+        ),
+    })
+}
@@ -0,0 +1,220 @@
+//! Combining orientation evidence from several sources (`--evidence-priority`,
+//! `--on-conflict`) and logging each read's decision (`--decision-log`).
+
+use anyhow::{bail, Context, Result};
+use clap::ValueEnum;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+/// Where an orientation call came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Source {
+    /// Per-read orientation table (`--table`)
+    Table,
+    /// `orientation:` header tag
+    Tag,
+    /// Primer/preset detection at the read ends
+    Primers,
+    /// Minimizers shared with `--reference`
+    Reference,
+    /// Transcript strand from spliced alignments (`--paf`)
+    Splice,
+    /// Aux tag of BAM input (`--bam-tag`)
+    Bam,
+    /// Every read, taken to be on the opposite strand (`--all`)
+    All,
+}
+
+impl Source {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Source::Table => "table",
+            Source::Tag => "tag",
+            Source::Primers => "primers",
+            Source::Reference => "reference",
+            Source::Splice => "splice",
+            Source::Bam => "bam",
+            Source::All => "all",
+        }
+    }
+}
+
+/// One source's orientation call for a read, with a confidence in 0-1.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Evidence {
+    pub source: Source,
+    pub orientation: u8,
+    /// 1 for asserted calls (table, tag); inferred calls report their margin
+    pub confidence: f64,
+}
+
+impl Evidence {
+    /// An orientation stated outright by a table or header tag.
+    pub fn asserted(source: Source, orientation: u8) -> Self {
+        Self {
+            source,
+            orientation,
+            confidence: 1.0,
+        }
+    }
+}
+
+/// What to do when sources disagree on a read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OnConflict {
+    /// Abort the run, naming the read
+    Error,
+    /// Take the orientation most sources agree on; a tie leaves the read unresolved
+    Majority,
+    /// Take the highest-priority source
+    Priority,
+    /// Take the table's call, else the highest-priority source
+    PreferTable,
+    /// Take the header tag's call, else the highest-priority source
+    PreferTag,
+    /// Leave the read unresolved
+    Unknown,
+}
+
+/// Where reads with primer or tail hits for both orientations go.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OnAmbiguous {
+    /// Write them like any other read, oriented by the stronger signal
+    Keep,
+    /// Skip them
+    Drop,
+    /// Write them to --ambiguous-out instead
+    SeparateFile,
+}
+
+/// Resolves per-read evidence into one orientation and counts disagreements.
+pub struct Consensus {
+    priority: Vec<Source>,
+    on_conflict: OnConflict,
+    conflicts: u64,
+}
+
+impl Consensus {
+    /// Sources missing from `priority` rank after the listed ones.
+    pub fn new(priority: &[Source], on_conflict: OnConflict) -> Self {
+        let mut order = priority.to_vec();
+        for s in Source::value_variants() {
+            if !order.contains(s) {
+                order.push(*s);
+            }
+        }
+        Self {
+            priority: order,
+            on_conflict,
+            conflicts: 0,
+        }
+    }
+
+    /// Reads whose sources disagreed so far.
+    pub fn conflicts(&self) -> u64 {
+        self.conflicts
+    }
+
+    /// Pick the orientation for `read_id`, credited to the deciding source;
+    /// `None` when there is no evidence or a conflict leaves it unresolved.
+    /// After a conflict the confidence is scaled by the share of sources
+    /// that agree with the decision.
+    pub fn resolve(&mut self, read_id: &str, evidence: &[Evidence]) -> Result<Option<Evidence>> {
+        let mut ranked = evidence.to_vec();
+        ranked.sort_by_key(|e| self.priority.iter().position(|p| *p == e.source));
+        let Some(&top) = ranked.first() else {
+            return Ok(None);
+        };
+        if ranked.iter().all(|e| e.orientation == top.orientation) {
+            return Ok(Some(top));
+        }
+        self.conflicts += 1;
+        let preferred = |source| *ranked.iter().find(|e| e.source == source).unwrap_or(&top);
+        let chosen = match self.on_conflict {
+            OnConflict::Priority => top,
+            OnConflict::PreferTable => preferred(Source::Table),
+            OnConflict::PreferTag => preferred(Source::Tag),
+            OnConflict::Unknown => return Ok(None),
+            OnConflict::Majority => {
+                let plus = ranked.iter().filter(|e| e.orientation == b'+').count();
+                let minus = ranked.len() - plus;
+                if plus == minus {
+                    return Ok(None);
+                }
+                let winner = if plus > minus { b'+' } else { b'-' };
+                *ranked
+                    .iter()
+                    .find(|e| e.orientation == winner)
+                    .expect("majority orientation has evidence")
+            }
+            OnConflict::Error => {
+                let calls: Vec<String> = ranked
+                    .iter()
+                    .map(|e| format!("{}={}", e.source.as_str(), e.orientation as char))
+                    .collect();
+                bail!(
+                    "conflicting orientation evidence for read '{}': {} (--on-conflict error)",
+                    read_id,
+                    calls.join(" ")
+                );
+            }
+        };
+        let agreeing = ranked
+            .iter()
+            .filter(|e| e.orientation == chosen.orientation)
+            .count();
+        Ok(Some(Evidence {
+            confidence: chosen.confidence * agreeing as f64 / ranked.len() as f64,
+            ..chosen
+        }))
+    }
+}
+
+/// Per-read TSV of orientation decisions (`--decision-log`).
+pub struct DecisionLog {
+    w: BufWriter<File>,
+}
+
+impl DecisionLog {
+    pub fn create(path: &Path) -> Result<Self> {
+        let mut w = BufWriter::new(
+            File::create(path).with_context(|| format!("create decision log {:?}", path))?,
+        );
+        writeln!(
+            w,
+            "read_id\tsource\torientation\taction\tconfidence\tambiguous"
+        )?;
+        Ok(Self { w })
+    }
+
+    /// `action` is `flipped`, `kept` or `unknown`; unresolved reads log `none`/`.`.
+    /// `ambiguous` marks reads with hits for both orientations.
+    pub fn record(
+        &mut self,
+        read_id: &str,
+        decision: Option<&Evidence>,
+        action: &str,
+        ambiguous: bool,
+    ) -> Result<()> {
+        match decision {
+            Some(d) => writeln!(
+                self.w,
+                "{}\t{}\t{}\t{}\t{:.3}\t{}",
+                read_id,
+                d.source.as_str(),
+                d.orientation as char,
+                action,
+                d.confidence,
+                ambiguous
+            )?,
+            None => writeln!(self.w, "{}\tnone\t.\t{}\t.\t{}", read_id, action, ambiguous)?,
+        }
+        Ok(())
+    }
+
+    pub fn finish(&mut self) -> Result<()> {
+        self.w.flush()?;
+        Ok(())
+    }
+}
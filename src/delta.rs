@@ -0,0 +1,274 @@
+//! `--delta-out` and the `apply` subcommand: store a restranding as a patch
+//! against its input instead of a second full copy.
+//!
+//! Every change a run makes to a kept record is a reverse complement and a
+//! new header, so the patch holds no sequence. It is a TSV of the records
+//! that differ from the input, by 0-based record index. After a
+//! `#restrand-delta 1` line naming the format, each line is one of
+//! `flip index id header`, `rename index id header` (header changed only) or
+//! `drop first last`, tab-separated, and a closing `#records` line gives the
+//! input's record count.
+
+use crate::output::{self, Output};
+use crate::progress::ByteCounter;
+use crate::scramble::set_header;
+use crate::timing::Timings;
+use crate::{open_text, open_writer, revcomp_in_place, FASTA_WRAP_WIDTH};
+use anyhow::{bail, Context, Result};
+use bio::io::fasta::{self, FastaRead};
+use bio::io::fastq::{self, FastqRead};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+const MAGIC: &str = "#restrand-delta 1";
+
+/// Writes the patch while a run processes its input.
+pub struct DeltaWriter {
+    out: Output,
+    /// Index of the next record not yet accounted for
+    next: u64,
+}
+
+impl DeltaWriter {
+    /// Start a patch for a `format` ("fasta"/"fastq") run.
+    pub fn create(path: &Path, format: &str) -> Result<Self> {
+        let mut out = open_writer(&Some(path.to_owned()), None, &mut None)?;
+        writeln!(out, "{}\t{}", MAGIC, format)?;
+        Ok(DeltaWriter { out, next: 0 })
+    }
+
+    /// Note input record `index` (`id`, `desc`), written with `output_header`
+    /// and `flipped` or not. Records skipped since the last call are dropped.
+    pub fn record(
+        &mut self,
+        index: u64,
+        id: &str,
+        desc: Option<&str>,
+        flipped: bool,
+        output_header: &str,
+    ) -> Result<()> {
+        self.drop_until(index)?;
+        let same_header = match (
+            output_header.strip_prefix(id),
+            desc.filter(|d| !d.is_empty()),
+        ) {
+            (Some(rest), None) => rest.is_empty(),
+            (Some(rest), Some(desc)) => rest.strip_prefix(' ') == Some(desc),
+            (None, _) => false,
+        };
+        if flipped || !same_header {
+            let op = if flipped { "flip" } else { "rename" };
+            writeln!(self.out, "{}\t{}\t{}\t{}", op, index, id, output_header)?;
+        }
+        self.next = index + 1;
+        Ok(())
+    }
+
+    fn drop_until(&mut self, index: u64) -> Result<()> {
+        if index > self.next {
+            writeln!(self.out, "drop\t{}\t{}", self.next, index - 1)?;
+        }
+        Ok(())
+    }
+
+    /// Close the patch of an input of `records` records.
+    /// Bytes of patch written so far.
+    pub fn bytes_written(&self) -> u64 {
+        self.out.bytes_written()
+    }
+
+    pub fn finish(&mut self, records: u64) -> Result<()> {
+        self.drop_until(records)?;
+        writeln!(self.out, "#records\t{}", records)?;
+        self.out.finish()
+    }
+}
+
+enum Op {
+    Flip(String, String),
+    Rename(String, String),
+    Drop(u64),
+}
+
+/// A parsed `--delta-out` patch.
+struct Patch {
+    /// Patch lines by first input index
+    ops: Vec<(u64, Op)>,
+    fastq: bool,
+    /// Records in the input the patch was made from
+    records: u64,
+}
+
+fn read_delta(path: &str) -> Result<Patch> {
+    let buffer = output::buffer_size(None, path == "-");
+    let text = open_text(path, buffer, &ByteCounter::default(), &Timings::new(false))?;
+    let mut lines = BufReader::new(text).lines();
+    let first = lines.next().transpose()?.unwrap_or_default();
+    let fastq = match first.split_once('\t') {
+        Some((MAGIC, "fasta")) => false,
+        Some((MAGIC, "fastq")) => true,
+        _ => bail!(
+            "{} is not a restrand-fasta delta (no '{}' line)",
+            path,
+            MAGIC
+        ),
+    };
+    let (mut ops, mut records) = (Vec::new(), None);
+    for (i, line) in lines.enumerate() {
+        let line = line.with_context(|| format!("reading {}", path))?;
+        let n = i + 2;
+        let cols: Vec<&str> = line.splitn(4, '\t').collect();
+        let index = |s: &str| -> Result<u64> {
+            s.parse()
+                .with_context(|| format!("{} line {}: '{}' is not a record index", path, n, s))
+        };
+        let (at, op) = match cols[..] {
+            ["#records", count] => {
+                records = Some(index(count)?);
+                continue;
+            }
+            ["flip", at, id, header] => (index(at)?, Op::Flip(id.into(), header.into())),
+            ["rename", at, id, header] => (index(at)?, Op::Rename(id.into(), header.into())),
+            ["drop", first, last] => (index(first)?, Op::Drop(index(last)?)),
+            _ => bail!("{} line {}: unrecognized patch line", path, n),
+        };
+        if ops.last().is_some_and(|(prev, _)| *prev >= at) {
+            bail!("{} line {}: record indices out of order", path, n);
+        }
+        ops.push((at, op));
+    }
+    let Some(records) = records else {
+        bail!("{} is truncated (no '#records' line)", path);
+    };
+    Ok(Patch {
+        ops,
+        fastq,
+        records,
+    })
+}
+
+/// Settings from the `apply` subcommand.
+pub struct ApplyOptions<'a> {
+    pub original: &'a str,
+    pub delta: &'a str,
+    pub out: &'a Option<PathBuf>,
+}
+
+/// Applies patch lines to input records in order.
+struct Patcher<'a> {
+    opts: &'a ApplyOptions<'a>,
+    ops: std::iter::Peekable<std::vec::IntoIter<(u64, Op)>>,
+    fastq: bool,
+    out: Output,
+    out_buf: Vec<u8>,
+    dropping_until: Option<u64>,
+    n_read: u64,
+    n_flipped: u64,
+}
+
+impl Patcher<'_> {
+    fn record(&mut self, id: &str, header: &str, seq: &mut [u8], qual: &mut [u8]) -> Result<()> {
+        let index = self.n_read;
+        self.n_read += 1;
+        if self.dropping_until.is_some_and(|last| index <= last) {
+            return Ok(());
+        }
+        let mut header = header;
+        let op = self.ops.next_if(|(at, _)| *at == index).map(|(_, op)| op);
+        match &op {
+            None => {}
+            Some(Op::Drop(last)) => {
+                self.dropping_until = Some(*last);
+                return Ok(());
+            }
+            Some(Op::Flip(want, _) | Op::Rename(want, _)) if want != id => bail!(
+                "record {} of {} is {}, but the patch expects {}; is this the file the patch was made from?",
+                index,
+                self.opts.original,
+                id,
+                want
+            ),
+            Some(Op::Flip(_, new)) => {
+                revcomp_in_place(seq);
+                qual.reverse();
+                self.n_flipped += 1;
+                header = new;
+            }
+            Some(Op::Rename(_, new)) => header = new,
+        }
+        self.out_buf.clear();
+        if self.fastq {
+            output::push_fastq(&mut self.out_buf, header, seq, qual);
+        } else {
+            output::push_fasta(&mut self.out_buf, header, seq, FASTA_WRAP_WIDTH);
+        }
+        self.out.write_all(&self.out_buf)?;
+        Ok(())
+    }
+}
+
+/// Rebuild a run's output from its input and `--delta-out` patch.
+pub fn apply(opts: &ApplyOptions) -> Result<()> {
+    let Patch {
+        ops,
+        fastq,
+        records,
+    } = read_delta(opts.delta)?;
+    let buffer = output::buffer_size(None, opts.original == "-");
+    let timings = Timings::new(false);
+    let handle = open_text(opts.original, buffer, &ByteCounter::default(), &timings)?;
+    let mut p = Patcher {
+        opts,
+        ops: ops.into_iter().peekable(),
+        fastq,
+        out: open_writer(opts.out, None, &mut None)?,
+        out_buf: Vec::new(),
+        dropping_until: None,
+        n_read: 0,
+        n_flipped: 0,
+    };
+    let (mut header, mut seq, mut qual) = (String::new(), Vec::new(), Vec::new());
+    if fastq {
+        let mut reader = fastq::Reader::with_capacity(buffer, handle);
+        let mut record = fastq::Record::new();
+        loop {
+            reader.read(&mut record).context("parsing FASTQ record")?;
+            if record.is_empty() {
+                break;
+            }
+            set_header(&mut header, record.id(), record.desc());
+            seq.clear();
+            seq.extend_from_slice(record.seq());
+            qual.clear();
+            qual.extend_from_slice(record.qual());
+            p.record(record.id(), &header, &mut seq, &mut qual)?;
+        }
+    } else {
+        let mut reader = fasta::Reader::with_capacity(buffer, handle);
+        let mut record = fasta::Record::new();
+        loop {
+            reader.read(&mut record).context("parsing FASTA record")?;
+            if record.is_empty() {
+                break;
+            }
+            set_header(&mut header, record.id(), record.desc());
+            seq.clear();
+            seq.extend_from_slice(record.seq());
+            p.record(record.id(), &header, &mut seq, &mut [])?;
+        }
+    }
+    if p.n_read != records {
+        bail!(
+            "{} has {} records, but the patch was made from {}",
+            opts.original,
+            p.n_read,
+            records
+        );
+    }
+    p.out.finish()?;
+    eprintln!(
+        "applied {}: {} of {} records flipped",
+        opts.delta, p.n_flipped, p.n_read
+    );
+    Ok(())
+}
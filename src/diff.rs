@@ -0,0 +1,145 @@
+//! `diff` subcommand: recover the orientation table of a dataset restranded
+//! by some earlier tool, by comparing it read by read with the original.
+//!
+//! Reads identical in both files are `+`, reads that are each other's reverse
+//! complement are `-`, so running the original with the table reproduces the
+//! restranded file. Both files must list the same reads in the same order,
+//! which any streaming restrander preserves.
+
+use crate::output;
+use crate::progress::ByteCounter;
+use crate::timing::Timings;
+use crate::{open_text, open_writer, revcomp_in_place};
+use anyhow::{bail, Context, Result};
+use bio::io::fasta::{self, FastaRead};
+use bio::io::fastq::{self, FastqRead};
+use std::io::{Read, Write};
+use std::path::PathBuf;
+
+/// Settings from the `diff` subcommand.
+pub struct DiffOptions<'a> {
+    pub original: &'a str,
+    pub restranded: &'a str,
+    pub fastq: bool,
+    pub out: &'a Option<PathBuf>,
+    pub flipped_suffix: &'a str,
+}
+
+/// One file's records as (ID, sequence), read one at a time.
+enum Records {
+    Fasta(
+        fasta::Reader<std::io::BufReader<Box<dyn Read>>>,
+        fasta::Record,
+    ),
+    Fastq(
+        fastq::Reader<std::io::BufReader<Box<dyn Read>>>,
+        fastq::Record,
+    ),
+}
+
+impl Records {
+    fn open(path: &str, fastq: bool, timings: &Timings) -> Result<Self> {
+        let buffer = output::buffer_size(None, path == "-");
+        let handle = open_text(path, buffer, &ByteCounter::default(), timings)?;
+        Ok(if fastq {
+            Records::Fastq(
+                fastq::Reader::with_capacity(buffer, handle),
+                fastq::Record::new(),
+            )
+        } else {
+            Records::Fasta(
+                fasta::Reader::with_capacity(buffer, handle),
+                fasta::Record::new(),
+            )
+        })
+    }
+
+    fn next(&mut self, path: &str) -> Result<Option<(&str, &[u8])>> {
+        match self {
+            Records::Fasta(reader, record) => {
+                reader
+                    .read(record)
+                    .with_context(|| format!("parsing FASTA record of {}", path))?;
+                Ok((!record.is_empty()).then(|| (record.id(), record.seq())))
+            }
+            Records::Fastq(reader, record) => {
+                reader
+                    .read(record)
+                    .with_context(|| format!("parsing FASTQ record of {}", path))?;
+                Ok((!record.is_empty()).then(|| (record.id(), record.seq())))
+            }
+        }
+    }
+}
+
+pub fn diff(opts: &DiffOptions) -> Result<()> {
+    if opts.original == "-" && opts.restranded == "-" {
+        bail!("only one of --original and --restranded can be read from stdin");
+    }
+    let timings = Timings::new(false);
+    let mut original = Records::open(opts.original, opts.fastq, &timings)?;
+    let mut restranded = Records::open(opts.restranded, opts.fastq, &timings)?;
+    let mut out = open_writer(opts.out, None, &mut None)?;
+    writeln!(out, "ReadName\torientation")?;
+
+    let (mut n_total, mut n_flipped) = (0u64, 0u64);
+    let mut flipped = Vec::new();
+    loop {
+        let a = original.next(opts.original)?;
+        let b = restranded.next(opts.restranded)?;
+        let ((id, seq), (new_id, new_seq)) = match (a, b) {
+            (Some(a), Some(b)) => (a, b),
+            (None, None) => break,
+            (Some((id, _)), None) => bail!(
+                "{} ends before read {} (record {} of {})",
+                opts.restranded,
+                id,
+                n_total + 1,
+                opts.original
+            ),
+            (None, Some((id, _))) => bail!(
+                "{} has more reads than {}, starting with {}",
+                opts.restranded,
+                opts.original,
+                id
+            ),
+        };
+        n_total += 1;
+        let base_id = new_id
+            .strip_suffix(opts.flipped_suffix)
+            .filter(|_| !opts.flipped_suffix.is_empty())
+            .unwrap_or(new_id);
+        if base_id != id {
+            bail!(
+                "record {}: {} has {} where {} has {}; both files must list the same reads in the same order",
+                n_total,
+                opts.original,
+                id,
+                opts.restranded,
+                new_id
+            );
+        }
+        let ori = if seq == new_seq {
+            '+'
+        } else {
+            flipped.clear();
+            flipped.extend_from_slice(seq);
+            revcomp_in_place(&mut flipped);
+            if flipped != new_seq {
+                bail!(
+                    "read {}: the restranded sequence is neither the original nor its reverse complement",
+                    id
+                );
+            }
+            n_flipped += 1;
+            '-'
+        };
+        writeln!(out, "{}\t{}", id, ori)?;
+    }
+    out.finish()?;
+    eprintln!(
+        "{} of {} reads differ by reverse complement",
+        n_flipped, n_total
+    );
+    Ok(())
+}
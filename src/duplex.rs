@@ -0,0 +1,49 @@
+//! ONT duplex reads (`--duplex`): basecallers name them `template;complement`
+//! after the two simplex reads they were called from. The duplex sequence
+//! follows the template strand, so the complement read's orientation is
+//! inverted when it is the one found in the table.
+
+use crate::opposite;
+use crate::table::{MatchOn, OrientationTable};
+use anyhow::Result;
+use clap::ValueEnum;
+
+/// What to do with duplex reads.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Duplex {
+    /// Treat them like any other read, looked up by their full ID
+    Keep,
+    /// Skip them
+    Drop,
+    /// Look them up by their full ID, else the template's, else the complement's (inverted)
+    MatchEither,
+}
+
+/// The template and complement IDs of a duplex read ID.
+pub fn split(id: &str) -> Option<(&str, &str)> {
+    id.split_once(';')
+        .filter(|(t, c)| !t.is_empty() && !c.is_empty() && !c.contains(';'))
+}
+
+/// Look a record up as `match_on` says, falling back to its simplex reads'
+/// IDs under `match-either`.
+pub fn lookup(
+    table: &mut OrientationTable,
+    match_on: MatchOn,
+    policy: Duplex,
+    id: &str,
+    header: &str,
+    seq: &[u8],
+) -> Result<Option<u8>> {
+    let found = table.lookup(match_on, id, header, seq)?;
+    if found.is_some() || policy != Duplex::MatchEither {
+        return Ok(found);
+    }
+    let Some((template, complement)) = split(id) else {
+        return Ok(None);
+    };
+    if let Some(ori) = table.get(template)? {
+        return Ok(Some(ori));
+    }
+    Ok(table.get(complement)?.map(opposite))
+}
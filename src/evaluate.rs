@@ -0,0 +1,116 @@
+//! `evaluate` subcommand: run the pipeline with any orientation settings and
+//! score its calls against a truth table of known orientations.
+//!
+//! The calls are read back from a `--decision-log`, so every inference method
+//! (table, tags, primers, presets) is scored the same way. The score is the
+//! read's original orientation as called, compared with the truth.
+
+use crate::table::{self, Encoding};
+use crate::{run, Cleanup, Cli};
+use anyhow::{Context, Result};
+use clap::Parser;
+use csv::ReaderBuilder;
+use std::io::Write;
+use std::path::Path;
+
+/// Settings from the `evaluate` subcommand.
+pub struct EvaluateOptions<'a> {
+    pub truth: &'a Path,
+    pub id_col: &'a str,
+    pub orientation_col: &'a str,
+    /// Restranding options choosing the input and inference method
+    pub args: &'a [String],
+}
+
+/// Calls tallied against the truth: rows are truth `+`/`-`, columns call `+`/`-`/none.
+#[derive(Debug, Default)]
+struct Confusion {
+    counts: [[u64; 3]; 2],
+    not_in_truth: u64,
+}
+
+impl Confusion {
+    fn add(&mut self, truth: u8, call: Option<u8>) {
+        let row = usize::from(truth != b'+');
+        let col = match call {
+            Some(b'+') => 0,
+            Some(_) => 1,
+            None => 2,
+        };
+        self.counts[row][col] += 1;
+    }
+
+    fn write(&self, w: &mut impl Write) -> Result<()> {
+        let c = &self.counts;
+        let evaluated: u64 = c.iter().flatten().sum();
+        let called = evaluated - c[0][2] - c[1][2];
+        let correct = c[0][0] + c[1][1];
+        let ratio = |n: u64, d: u64| {
+            if d == 0 {
+                "NA".to_owned()
+            } else {
+                format!("{:.4}", n as f64 / d as f64)
+            }
+        };
+        writeln!(w, "metric\tvalue")?;
+        writeln!(w, "evaluated\t{}", evaluated)?;
+        writeln!(w, "not_in_truth\t{}", self.not_in_truth)?;
+        writeln!(w, "called\t{}", called)?;
+        writeln!(w, "call_rate\t{}", ratio(called, evaluated))?;
+        writeln!(w, "accuracy\t{}", ratio(correct, called))?;
+        for (i, strand) in ["plus", "minus"].into_iter().enumerate() {
+            let called_as = c[0][i] + c[1][i];
+            let truly = c[i].iter().sum();
+            writeln!(w, "precision_{}\t{}", strand, ratio(c[i][i], called_as))?;
+            writeln!(w, "recall_{}\t{}", strand, ratio(c[i][i], truly))?;
+        }
+        writeln!(w)?;
+        writeln!(w, "truth\\call\t+\t-\tnone")?;
+        for (strand, row) in ["+", "-"].into_iter().zip(c) {
+            writeln!(w, "{}\t{}\t{}\t{}", strand, row[0], row[1], row[2])?;
+        }
+        Ok(())
+    }
+}
+
+pub fn evaluate(opts: &EvaluateOptions) -> Result<()> {
+    let argv = std::iter::once("restrand-fasta").chain(opts.args.iter().map(String::as_str));
+    let mut cli = Cli::try_parse_from(argv).map_err(|e| anyhow::anyhow!("{}", e))?;
+    let budget = cli.budget();
+    let tmp = |tag: &str| {
+        budget
+            .tmpdir
+            .join(format!("restrand-evaluate-{}.{}", std::process::id(), tag))
+    };
+    let (log, out) = (tmp("decisions.tsv"), tmp("out"));
+    let _cleanup = Cleanup(vec![log.clone(), out.clone()]);
+    cli.decision_log = Some(log.clone());
+    cli.out = Some(out);
+    cli.quiet = true;
+    run(&cli).context("running the pipeline")?;
+
+    let (mut truth, _) = table::load(
+        opts.truth,
+        opts.id_col,
+        opts.orientation_col,
+        Encoding::Auto,
+        &budget,
+    )
+    .context("loading truth table")?;
+    let mut confusion = Confusion::default();
+    let mut reader = ReaderBuilder::new()
+        .delimiter(b'\t')
+        .from_path(&log)
+        .context("reading decision log")?;
+    for row in reader.records() {
+        let row = row?;
+        let (Some(id), Some(call)) = (row.get(0), row.get(2)) else {
+            continue;
+        };
+        match truth.get(id)? {
+            Some(t) => confusion.add(t, call.bytes().next().filter(|&b| b != b'.')),
+            None => confusion.not_in_truth += 1,
+        }
+    }
+    confusion.write(&mut std::io::stdout().lock())
+}
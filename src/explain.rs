@@ -0,0 +1,241 @@
+//! `--explain N`: instead of restranding, show for the first N reads (with
+//! `--include-ids`, the listed ones) what each orientation source said, how
+//! the calls were resolved, and what a run would do with the read and why.
+//! Nothing is written but the explanations, on stdout.
+
+use crate::consensus::{Evidence, OnAmbiguous, Source};
+use crate::primers::PrimerCall;
+use crate::table::MatchOn;
+use crate::Cli;
+use anyhow::Result;
+use clap::ValueEnum;
+use std::fmt::Write as _;
+use std::io::{self, Write};
+
+/// Prints explanations until `limit` reads are covered.
+pub struct Explainer {
+    limit: u64,
+    explained: u64,
+    /// Whether header tags are consulted in this mode
+    tags: bool,
+}
+
+impl Explainer {
+    /// `tags` says whether the run reads `orientation:` header tags.
+    pub fn new(limit: u64, tags: bool) -> Self {
+        Explainer {
+            limit,
+            explained: 0,
+            tags,
+        }
+    }
+
+    /// Whether `limit` reads have been explained.
+    pub fn done(&self) -> bool {
+        self.explained >= self.limit
+    }
+
+    fn emit(&mut self, text: &str) -> Result<()> {
+        self.explained += 1;
+        let mut stdout = io::stdout().lock();
+        stdout.write_all(text.as_bytes())?;
+        stdout.flush()?;
+        Ok(())
+    }
+
+    /// Explain a read dropped before any source was consulted.
+    pub fn skipped(&mut self, id: &str, reason: &str) -> Result<()> {
+        self.emit(&format!("{}\n  action     drop: {}\n", id, reason))
+    }
+
+    /// Explain a read from the `evidence` of each source, the primer `call`
+    /// behind any primer evidence, and the consensus `decision`.
+    pub fn read(
+        &mut self,
+        cli: &Cli,
+        id: &str,
+        evidence: &[Evidence],
+        call: Option<&PrimerCall>,
+        decision: Option<&Evidence>,
+        target: u8,
+    ) -> Result<()> {
+        let mut text = format!("{}\n", id);
+        let said = |source| evidence.iter().find(|e| e.source == source);
+        let mut line = |source: Source, detail: String| {
+            let _ = writeln!(text, "  {:<10} {}", source.as_str(), detail);
+        };
+        if cli.table.is_some() {
+            let key = match cli.match_on {
+                MatchOn::Id => String::new(),
+                m => format!(" (--match-on {})", value_name(m)),
+            };
+            line(
+                Source::Table,
+                match said(Source::Table) {
+                    Some(e) => format!("'{}'{}", e.orientation as char, key),
+                    None => format!("no row for this read{}", key),
+                },
+            );
+        }
+        if self.tags {
+            line(
+                Source::Tag,
+                match said(Source::Tag) {
+                    Some(e) => format!("'{}' from the orientation tag", e.orientation as char),
+                    None => "no orientation tag in the header".to_owned(),
+                },
+            );
+        }
+        if let Some(c) = call {
+            let hits: Vec<String> = c
+                .alignments
+                .iter()
+                .filter(|h| h.passed)
+                .map(|h| {
+                    format!(
+                        "{} at {} {}-{} (score {}, identity {:.2})",
+                        h.primer,
+                        h.end.as_str(),
+                        h.start,
+                        h.stop,
+                        h.score,
+                        h.identity
+                    )
+                })
+                .collect();
+            let hits = if hits.is_empty() {
+                "no passing hits".to_owned()
+            } else {
+                hits.join("; ")
+            };
+            line(
+                Source::Primers,
+                match said(Source::Primers) {
+                    Some(e) => format!(
+                        "'{}' confidence {:.3}: {}",
+                        e.orientation as char, e.confidence, hits
+                    ),
+                    None if c.is_ambiguous() => format!("tie between orientations: {}", hits),
+                    None => hits,
+                },
+            );
+        }
+        if cli.reference.is_some() {
+            line(
+                Source::Reference,
+                match said(Source::Reference) {
+                    Some(e) => {
+                        format!("'{}' confidence {:.3}", e.orientation as char, e.confidence)
+                    }
+                    None => "no call from shared minimizers".to_owned(),
+                },
+            );
+        }
+        if cli.paf.is_some() {
+            line(
+                Source::Splice,
+                match said(Source::Splice) {
+                    Some(e) => {
+                        format!("'{}' confidence {:.3}", e.orientation as char, e.confidence)
+                    }
+                    None => "no spliced primary alignment with a strand".to_owned(),
+                },
+            );
+        }
+        if let Some(tag) = cli.bam_tag {
+            line(
+                Source::Bam,
+                match said(Source::Bam) {
+                    Some(e) => format!(
+                        "'{}' from the {}:A tag",
+                        e.orientation as char,
+                        tag.as_str()
+                    ),
+                    None => format!("no {}:A:+ or {}:A:- tag", tag.as_str(), tag.as_str()),
+                },
+            );
+        }
+        if let Some(e) = said(Source::All) {
+            line(
+                Source::All,
+                format!("'{}' for every read", e.orientation as char),
+            );
+        }
+
+        let disagree = evidence
+            .iter()
+            .any(|e| e.orientation != evidence[0].orientation);
+        let on_conflict = value_name(cli.on_conflict);
+        let _ = match (decision, disagree) {
+            (Some(d), false) => writeln!(
+                text,
+                "  decision   '{}' from {}",
+                d.orientation as char,
+                d.source.as_str()
+            ),
+            (Some(d), true) => writeln!(
+                text,
+                "  decision   '{}' from {}: sources disagree, settled by --on-conflict {}",
+                d.orientation as char,
+                d.source.as_str(),
+                on_conflict
+            ),
+            (None, false) => writeln!(text, "  decision   none: no source oriented the read"),
+            (None, true) => writeln!(
+                text,
+                "  decision   none: sources disagree, and --on-conflict {} leaves it unresolved",
+                on_conflict
+            ),
+        };
+        let ambiguous = call.is_some_and(PrimerCall::is_ambiguous);
+        let _ = writeln!(
+            text,
+            "  action     {}",
+            action(cli, decision, ambiguous, target)
+        );
+        self.emit(&text)
+    }
+}
+
+/// The command-line spelling of an option value.
+fn value_name(v: impl ValueEnum) -> String {
+    v.to_possible_value()
+        .map_or_else(String::new, |v| v.get_name().to_owned())
+}
+
+/// What a run does with a read, and why.
+fn action(cli: &Cli, decision: Option<&Evidence>, ambiguous: bool, target: u8) -> String {
+    let target = target as char;
+    if ambiguous {
+        match cli.on_ambiguous {
+            OnAmbiguous::Drop => {
+                return "drop: primer hits for both orientations (--on-ambiguous drop)".to_owned()
+            }
+            OnAmbiguous::SeparateFile => {
+                return "write to --ambiguous-out: primer hits for both orientations".to_owned()
+            }
+            OnAmbiguous::Keep => {}
+        }
+    }
+    let Some(d) = decision else {
+        return if cli.drop_missing {
+            "drop: unresolved (--drop-missing)".to_owned()
+        } else {
+            "keep as is: unresolved reads pass through unchanged".to_owned()
+        };
+    };
+    let called = d.orientation as char;
+    if called != target {
+        format!(
+            "flip: called '{}', the target orientation is '{}'",
+            called, target
+        )
+    } else if cli.only_flipped {
+        format!(
+            "drop: already in the target orientation '{}' (--only-flipped)",
+            target
+        )
+    } else {
+        format!("keep: already in the target orientation '{}'", target)
+    }
+}
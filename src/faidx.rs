@@ -0,0 +1,54 @@
+//! samtools-compatible `.fai` index of the FASTA output (`--faidx-out`).
+
+use anyhow::{Context, Result};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+/// Tracks byte offsets of the records written to one FASTA stream.
+pub struct FaiIndex {
+    w: BufWriter<File>,
+    offset: u64,
+    line_width: usize,
+}
+
+impl FaiIndex {
+    pub fn create(path: &Path, line_width: usize) -> Result<Self> {
+        let w = BufWriter::new(
+            File::create(path).with_context(|| format!("create FASTA index {:?}", path))?,
+        );
+        Ok(Self {
+            w,
+            offset: 0,
+            line_width,
+        })
+    }
+
+    /// Account for non-record bytes (e.g. a leading comment line).
+    pub fn skip(&mut self, bytes: usize) {
+        self.offset += bytes as u64;
+    }
+
+    /// Account for `>header\n` followed by `seq_len` bases wrapped at the
+    /// line width, and emit its `NAME LENGTH OFFSET LINEBASES LINEWIDTH` row.
+    pub fn add(&mut self, header: &str, seq_len: usize) -> Result<()> {
+        let name = header.split_ascii_whitespace().next().unwrap_or("");
+        self.offset += header.len() as u64 + 2;
+        writeln!(
+            self.w,
+            "{}\t{}\t{}\t{}\t{}",
+            name,
+            seq_len,
+            self.offset,
+            self.line_width,
+            self.line_width + 1
+        )?;
+        self.offset += (seq_len + seq_len.div_ceil(self.line_width)) as u64;
+        Ok(())
+    }
+
+    pub fn finish(&mut self) -> Result<()> {
+        self.w.flush()?;
+        Ok(())
+    }
+}
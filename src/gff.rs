@@ -0,0 +1,78 @@
+//! `--remap-gff`: carry GFF3 annotation over to the restranded sequences.
+//! Features on flipped sequences get mirrored coordinates and the opposite
+//! strand; every feature moves to its sequence's output ID. CDS phase counts
+//! from the feature's own 5' end, so it is unchanged.
+
+use crate::liftover::Liftover;
+use crate::output::Output;
+use anyhow::{bail, Context, Result};
+use std::io::{BufRead, Write};
+
+/// Rewrite the GFF3 read from `input` into `out`.
+pub fn remap(input: impl BufRead, out: &mut Output, liftover: &Liftover) -> Result<()> {
+    let placements = liftover.by_source();
+    let mut lines = input.lines();
+    let mut n = 0;
+    while let Some(line) = lines.next() {
+        let line = line.context("reading GFF")?;
+        n += 1;
+        if line.starts_with("##FASTA") {
+            // Embedded sequences are passed through as they are
+            writeln!(out, "{}", line)?;
+            for line in lines.by_ref() {
+                writeln!(out, "{}", line.context("reading GFF")?)?;
+            }
+            break;
+        }
+        if let Some(region) = line.strip_prefix("##sequence-region") {
+            let fields: Vec<&str> = region.split_whitespace().collect();
+            if let [seqid, start, end] = fields[..] {
+                if let Some(p) = placements.get(seqid) {
+                    let (start, end) = p.interval(parse(start, n)?, parse(end, n)?);
+                    writeln!(out, "##sequence-region {} {} {}", p.target, start, end)?;
+                    continue;
+                }
+            }
+        }
+        if line.is_empty() || line.starts_with('#') {
+            writeln!(out, "{}", line)?;
+            continue;
+        }
+        let mut cols: Vec<&str> = line.split('\t').collect();
+        if cols.len() != 9 {
+            bail!(
+                "GFF line {}: expected 9 tab-separated columns, found {}",
+                n,
+                cols.len()
+            );
+        }
+        let Some(p) = placements.get(cols[0]) else {
+            writeln!(out, "{}", line)?;
+            continue;
+        };
+        let (start, end) = (parse(cols[3], n)?, parse(cols[4], n)?);
+        if start < 1 || end < start || end > p.len {
+            bail!(
+                "GFF line {}: feature {}-{} does not fit on {} ({} bp)",
+                n,
+                start,
+                end,
+                p.source,
+                p.len
+            );
+        }
+        let (start, end) = p.interval(start, end);
+        let (start, end) = (start.to_string(), end.to_string());
+        cols[0] = &p.target;
+        cols[3] = &start;
+        cols[4] = &end;
+        cols[6] = p.strand(cols[6]);
+        writeln!(out, "{}", cols.join("\t"))?;
+    }
+    Ok(())
+}
+
+fn parse(s: &str, line: usize) -> Result<u64> {
+    s.parse()
+        .with_context(|| format!("GFF line {}: '{}' is not a coordinate", line, s))
+}
@@ -0,0 +1,46 @@
+//! Grouping key for the per-group strand balance in `--report`
+//! (`--group-col` from the table, `--group-tag` from the header).
+
+use crate::{table, tags};
+use anyhow::{bail, Result};
+use std::collections::HashMap;
+use std::path::Path;
+
+/// Group of reads with no group value.
+pub const UNASSIGNED: &str = "unassigned";
+
+pub enum Grouping {
+    /// Read ID to table column value
+    Column(HashMap<String, String>),
+    /// Header field key, e.g. `CB` for `CB:Z:ACGT`
+    Tag(String),
+}
+
+impl Grouping {
+    pub fn from_options(
+        table: Option<&Path>,
+        id_col: &str,
+        group_col: Option<&str>,
+        group_tag: Option<&str>,
+    ) -> Result<Option<Self>> {
+        match (group_col, group_tag) {
+            (Some(col), _) => match table {
+                Some(path) => Ok(Some(Grouping::Column(table::load_groups(
+                    path, id_col, col,
+                )?))),
+                None => bail!("--group-col needs --table"),
+            },
+            (None, Some(key)) => Ok(Some(Grouping::Tag(key.to_owned()))),
+            (None, None) => Ok(None),
+        }
+    }
+
+    /// The read's group, or [`UNASSIGNED`].
+    pub fn group<'a>(&'a self, id: &str, header: &'a str) -> &'a str {
+        let group = match self {
+            Grouping::Column(map) => map.get(id).map(String::as_str),
+            Grouping::Tag(key) => tags::value(header, key),
+        };
+        group.unwrap_or(UNASSIGNED)
+    }
+}
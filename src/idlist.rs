@@ -0,0 +1,21 @@
+//! Read ID lists (`--include-ids`, `--exclude-ids`): one ID per line, plain or gzipped. Only
+//! the first word of a line counts, and a leading `>`/`@` is dropped, so
+//! headers grepped out of a FASTA/FASTQ work as they are.
+
+use anyhow::{Context, Result};
+use std::collections::HashSet;
+use std::io::BufRead;
+
+pub fn read(input: impl BufRead) -> Result<HashSet<String>> {
+    let mut ids = HashSet::new();
+    for line in input.lines() {
+        let line = line.context("reading ID list")?;
+        if let Some(id) = line.split_ascii_whitespace().next() {
+            let id = id.strip_prefix(['>', '@']).unwrap_or(id);
+            if !id.is_empty() {
+                ids.insert(id.to_owned());
+            }
+        }
+    }
+    Ok(ids)
+}
@@ -0,0 +1,118 @@
+//! Coordinate liftover for restranded contigs: which input sequences were
+//! written under which name, and whether they were flipped, as an AGP
+//! (`--agp-out`) or UCSC chain (`--chain-out`) file, and used to remap
+//! annotation (`--remap-gff`).
+
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+/// One written sequence.
+#[derive(Debug)]
+pub struct Placement {
+    /// ID in the input
+    pub source: String,
+    /// ID in the output (differs with `--flipped-suffix` or a header template)
+    pub target: String,
+    pub len: u64,
+    pub flipped: bool,
+}
+
+impl Placement {
+    /// The 1-based inclusive interval `start..=end` on the output sequence.
+    pub fn interval(&self, start: u64, end: u64) -> (u64, u64) {
+        if self.flipped {
+            (self.len + 1 - end, self.len + 1 - start)
+        } else {
+            (start, end)
+        }
+    }
+
+    /// A GFF/BED-style strand on the output sequence.
+    pub fn strand<'a>(&self, strand: &'a str) -> &'a str {
+        match (self.flipped, strand) {
+            (true, "+") => "-",
+            (true, "-") => "+",
+            _ => strand,
+        }
+    }
+}
+
+/// Every sequence written by a FASTA run, in output order.
+#[derive(Debug, Default)]
+pub struct Liftover {
+    placements: Vec<Placement>,
+}
+
+impl Liftover {
+    /// Record a sequence written as `header` (its ID is the first word).
+    pub fn add(&mut self, source: &str, header: &str, len: usize, flipped: bool) {
+        let target = header.split_ascii_whitespace().next().unwrap_or(header);
+        self.placements.push(Placement {
+            source: source.to_owned(),
+            target: target.to_owned(),
+            len: len as u64,
+            flipped,
+        });
+    }
+
+    /// Placements by input ID; a repeated ID keeps its last placement.
+    pub fn by_source(&self) -> HashMap<&str, &Placement> {
+        self.placements
+            .iter()
+            .map(|p| (p.source.as_str(), p))
+            .collect()
+    }
+
+    /// AGP 2.1: each output sequence is one component, the input sequence,
+    /// in `-` orientation when flipped.
+    pub fn write_agp(&self, path: &Path) -> Result<()> {
+        let mut w = create(path, "AGP")?;
+        writeln!(w, "##agp-version\t2.1")?;
+        writeln!(
+            w,
+            "# restrand-fasta {}: output sequences as components of the input",
+            env!("CARGO_PKG_VERSION")
+        )?;
+        for p in self.placements.iter().filter(|p| p.len > 0) {
+            writeln!(
+                w,
+                "{}\t1\t{}\t1\tW\t{}\t1\t{}\t{}",
+                p.target,
+                p.len,
+                p.source,
+                p.len,
+                if p.flipped { '-' } else { '+' }
+            )?;
+        }
+        w.flush()?;
+        Ok(())
+    }
+
+    /// UCSC chain from input (reference side) to output (query side); a
+    /// flipped sequence aligns whole to the query's `-` strand.
+    pub fn write_chain(&self, path: &Path) -> Result<()> {
+        let mut w = create(path, "chain")?;
+        for (id, p) in self.placements.iter().filter(|p| p.len > 0).enumerate() {
+            writeln!(
+                w,
+                "chain {len} {source} {len} + 0 {len} {target} {len} {strand} 0 {len} {id}\n{len}\n",
+                len = p.len,
+                source = p.source,
+                target = p.target,
+                strand = if p.flipped { '-' } else { '+' },
+                id = id + 1
+            )?;
+        }
+        w.flush()?;
+        Ok(())
+    }
+}
+
+fn create(path: &Path, what: &str) -> Result<BufWriter<File>> {
+    Ok(BufWriter::new(File::create(path).with_context(|| {
+        format!("create {} file {:?}", what, path)
+    })?))
+}
@@ -0,0 +1,434 @@
+//! `lint` subcommand: check a table and sequence file before a long run.
+//!
+//! The table is read in full: column names, orientation values, duplicate IDs
+//! and empty or padded IDs. The first `--sample` reads are then looked up in
+//! it, and when few match, other `--match-on` keys are tried so the report
+//! can say which option would fix it. Each finding is one line, so problems
+//! are all listed at once instead of one failed run at a time.
+
+use crate::output;
+use crate::progress::ByteCounter;
+use crate::table::{self, Encoding, TableFormat};
+use crate::timing::Timings;
+use crate::{compress, open_text};
+use anyhow::{anyhow, Context, Result};
+use bio::io::fasta::{self, FastaRead};
+use bio::io::fastq::{self, FastqRead};
+use clap::ValueEnum;
+use std::collections::{HashMap, HashSet};
+use std::io::{BufRead, BufReader, Read};
+use std::path::Path;
+
+/// Bytes at the start of a file checked for line endings.
+const HEAD: usize = 1 << 20;
+
+/// Examples given per finding.
+const EXAMPLES: usize = 3;
+
+/// Settings from the `lint` subcommand.
+pub struct LintOptions<'a> {
+    pub table: Option<&'a Path>,
+    pub reads: Option<&'a str>,
+    pub fastq: bool,
+    pub id_col: &'a str,
+    pub orientation_col: &'a str,
+    pub encoding: Encoding,
+    pub table_format: TableFormat,
+    pub sample: u64,
+}
+
+/// Counts findings as they are printed.
+#[derive(Default)]
+struct Findings {
+    errors: u64,
+    warnings: u64,
+}
+
+impl Findings {
+    fn ok(&mut self, msg: impl AsRef<str>) {
+        println!("  ok       {}", msg.as_ref());
+    }
+
+    fn note(&mut self, msg: impl AsRef<str>) {
+        println!("  note     {}", msg.as_ref());
+    }
+
+    fn warn(&mut self, msg: impl AsRef<str>) {
+        self.warnings += 1;
+        println!("  warning  {}", msg.as_ref());
+    }
+
+    fn error(&mut self, msg: impl AsRef<str>) {
+        self.errors += 1;
+        println!("  error    {}", msg.as_ref());
+    }
+}
+
+/// Up to [`EXAMPLES`] items, quoted and comma-separated.
+fn examples<'a>(items: impl IntoIterator<Item = &'a String>) -> String {
+    let quoted: Vec<String> = items
+        .into_iter()
+        .take(EXAMPLES)
+        .map(|s| format!("'{}'", s))
+        .collect();
+    quoted.join(", ")
+}
+
+/// Report line-ending problems in `head`, the start of a text file.
+fn line_endings(f: &mut Findings, head: &[u8]) {
+    let crlf = head.windows(2).filter(|w| w == b"\r\n").count();
+    let cr = head.iter().filter(|&&b| b == b'\r').count();
+    let lf = head.iter().filter(|&&b| b == b'\n').count();
+    if cr > crlf {
+        f.warn("bare carriage returns (old Mac line endings), which are not line breaks here; convert them with tr '\\r' '\\n'");
+    } else if crlf > 0 && crlf < lf {
+        f.warn("mixed CRLF and LF line endings; normalize them with dos2unix");
+    } else if crlf > 0 {
+        f.note("CRLF (Windows) line endings; they are read fine, but outputs use LF");
+    }
+}
+
+/// What the table check leaves for the reads check.
+struct Table {
+    ids: HashMap<String, u8>,
+}
+
+fn lint_table(f: &mut Findings, opts: &LintOptions, path: &Path) -> Result<Option<Table>> {
+    let mut head = Vec::new();
+    compress::open(path)?
+        .take(HEAD as u64)
+        .read_to_end(&mut head)
+        .with_context(|| format!("reading {:?}", path))?;
+    line_endings(f, &head);
+
+    let (id_col, orientation_col, encoding) =
+        opts.table_format
+            .columns()
+            .unwrap_or((opts.id_col, opts.orientation_col, opts.encoding));
+    let (reader, headers) = table::open_tsv(path)?;
+    let names: Vec<&str> = headers.iter().collect();
+    let mut missing = false;
+    for (col, flag) in [(id_col, "--id-col"), (orientation_col, "--orientation-col")] {
+        if names.contains(&col) {
+            continue;
+        }
+        missing = true;
+        let close = names
+            .iter()
+            .find(|n| n.trim().eq_ignore_ascii_case(col) || n.trim_start_matches('#') == col);
+        match close {
+            Some(n) => f.error(format!(
+                "no column '{}', but there is '{}'; rename it or pass {} '{}'",
+                col, n, flag, n
+            )),
+            None => f.error(format!(
+                "no column '{}' (columns: {}); pass {} with the right name",
+                col,
+                names.join(", "),
+                flag
+            )),
+        }
+    }
+    if missing {
+        if let [only] = names[..] {
+            if only.contains(',') || only.contains(' ') {
+                f.note("the header is read as one column; tables are tab-separated, or comma-separated with a .csv name");
+            }
+        }
+        return Ok(None);
+    }
+    f.ok(format!(
+        "columns '{}' and '{}' found",
+        id_col, orientation_col
+    ));
+
+    let id_idx = names.iter().position(|n| *n == id_col).unwrap_or(0);
+    let ori_idx = names
+        .iter()
+        .position(|n| *n == orientation_col)
+        .unwrap_or(0);
+    // Buffer the first rows for `auto`, as loading the table does
+    let mut records = reader.into_records();
+    let head: Vec<_> = records.by_ref().take(table::DETECT_ROWS).collect();
+    let encoding = encoding.detect(
+        head.iter()
+            .flatten()
+            .map(|r| r.get(ori_idx).unwrap_or("").trim()),
+    );
+
+    let mut ids = HashMap::new();
+    let (mut n_rows, mut n_duplicates) = (0u64, 0u64);
+    let (mut malformed, mut n_malformed) = (Vec::new(), 0u64);
+    let (mut conflicting, mut n_conflicting) = (Vec::new(), 0u64);
+    let (mut padded, mut n_padded, mut n_empty) = (Vec::new(), 0u64, 0u64);
+    let mut bad_row = None;
+    for rec in head.into_iter().chain(records) {
+        let rec = match rec {
+            Ok(rec) => rec,
+            Err(e) => {
+                bad_row = Some(e);
+                break;
+            }
+        };
+        n_rows += 1;
+        // One row per line after the header (csv's own count drifts on CRLF)
+        let line = n_rows + 1;
+        let id = rec.get(id_idx).unwrap_or("");
+        if id.is_empty() {
+            n_empty += 1;
+            continue;
+        }
+        if id.trim() != id {
+            n_padded += 1;
+            if padded.len() < EXAMPLES {
+                padded.push(id.to_owned());
+            }
+        }
+        let value = rec.get(ori_idx).unwrap_or("").trim();
+        let ori = match table::parse_orientation(value.as_bytes(), id, encoding) {
+            Ok(ori) => ori,
+            Err(_) => {
+                n_malformed += 1;
+                if malformed.len() < EXAMPLES {
+                    malformed.push(format!("line {}: '{}'", line, value));
+                }
+                continue;
+            }
+        };
+        if let Some(prev) = ids.insert(id.to_owned(), ori) {
+            n_duplicates += 1;
+            if prev != ori {
+                n_conflicting += 1;
+                if conflicting.len() < EXAMPLES {
+                    conflicting.push(id.to_owned());
+                }
+            }
+        }
+    }
+
+    if let Some(e) = bad_row {
+        f.error(format!(
+            "{}; every row needs as many columns as the header",
+            e
+        ));
+    }
+    let encoding_name = encoding
+        .to_possible_value()
+        .map_or_else(String::new, |v| v.get_name().to_owned());
+    f.ok(format!(
+        "{} row(s), orientations read as {}",
+        n_rows, encoding_name
+    ));
+    if n_malformed > 0 {
+        let hint = if encoding == Encoding::Strand && malformed.iter().any(|m| m.contains("p--")) {
+            "; lima reports need --table-format lima"
+        } else {
+            ""
+        };
+        f.error(format!(
+            "{} orientation value(s) not recognized as {}, e.g. {}{}",
+            n_malformed,
+            encoding_name,
+            malformed.join(", "),
+            hint
+        ));
+    }
+    if n_duplicates > 0 {
+        let conflicts = match n_conflicting {
+            0 => String::new(),
+            n => format!(
+                ", {} with conflicting orientations (e.g. {})",
+                n,
+                examples(&conflicting)
+            ),
+        };
+        f.warn(format!(
+            "{} duplicate ID(s){}; the last row wins, and --fail-on-warnings makes them fatal",
+            n_duplicates, conflicts
+        ));
+    }
+    if n_empty > 0 {
+        f.warn(format!(
+            "{} row(s) with an empty ID, which never match a read",
+            n_empty
+        ));
+    }
+    if n_padded > 0 {
+        f.warn(format!(
+            "{} ID(s) with leading or trailing spaces, which never match a read, e.g. {}",
+            n_padded,
+            examples(&padded)
+        ));
+    }
+    Ok(Some(Table { ids }))
+}
+
+/// Sampled reads found under each way of matching them to the table.
+#[derive(Default)]
+struct Overlap {
+    id: u64,
+    full_header: u64,
+    pacbio: u64,
+    /// Table IDs kept with their `>`/`@`
+    prefixed: u64,
+}
+
+/// What the reads check has seen so far.
+#[derive(Default)]
+struct Sample {
+    n: u64,
+    seen: HashSet<String>,
+    duplicates: Vec<String>,
+    overlap: Overlap,
+    unmatched: Vec<String>,
+}
+
+impl Sample {
+    fn add(&mut self, table: Option<&Table>, id: &str, desc: Option<&str>) {
+        self.n += 1;
+        if !self.seen.insert(id.to_owned()) && self.duplicates.len() < EXAMPLES {
+            self.duplicates.push(id.to_owned());
+        }
+        let Some(t) = table else { return };
+        if t.ids.contains_key(id) {
+            self.overlap.id += 1;
+            return;
+        }
+        if self.unmatched.len() < EXAMPLES && !self.unmatched.iter().any(|u| u == id) {
+            self.unmatched.push(id.to_owned());
+        }
+        let header = match desc.filter(|d| !d.is_empty()) {
+            Some(d) => format!("{} {}", id, d),
+            None => id.to_owned(),
+        };
+        if t.ids.contains_key(&header) {
+            self.overlap.full_header += 1;
+        }
+        if table::pacbio_keys(id).iter().any(|k| t.ids.contains_key(k)) {
+            self.overlap.pacbio += 1;
+        }
+        if [">", "@"]
+            .iter()
+            .any(|p| t.ids.contains_key(&format!("{}{}", p, id)))
+        {
+            self.overlap.prefixed += 1;
+        }
+    }
+}
+
+fn lint_reads(
+    f: &mut Findings,
+    opts: &LintOptions,
+    path: &str,
+    table: Option<&Table>,
+) -> Result<()> {
+    let buffer = output::buffer_size(None, path == "-").max(HEAD);
+    let text = open_text(path, buffer, &ByteCounter::default(), &Timings::new(false))?;
+    let mut text = BufReader::with_capacity(buffer, text);
+    line_endings(f, text.fill_buf()?);
+
+    let mut sample = Sample::default();
+    if opts.fastq {
+        let mut reader = fastq::Reader::from_bufread(text);
+        let mut record = fastq::Record::new();
+        while sample.n < opts.sample {
+            reader.read(&mut record).context("parsing FASTQ record")?;
+            if record.is_empty() {
+                break;
+            }
+            record
+                .check()
+                .map_err(|e| anyhow!("read '{}': {}", record.id(), e))?;
+            sample.add(table, record.id(), record.desc());
+        }
+    } else {
+        let mut reader = fasta::Reader::from_bufread(text);
+        let mut record = fasta::Record::new();
+        while sample.n < opts.sample {
+            reader.read(&mut record).context("parsing FASTA record")?;
+            if record.is_empty() {
+                break;
+            }
+            sample.add(table, record.id(), record.desc());
+        }
+    }
+    let Sample {
+        n: n_sampled,
+        duplicates,
+        overlap,
+        unmatched,
+        ..
+    } = sample;
+
+    if n_sampled == 0 {
+        f.error("no records; is this the right file, and FASTQ only with --fastq?");
+        return Ok(());
+    }
+    f.ok(format!("{} record(s) parsed", n_sampled));
+    if !duplicates.is_empty() {
+        f.warn(format!(
+            "repeated read IDs, e.g. {}; every copy gets the same table orientation",
+            examples(&duplicates)
+        ));
+    }
+    if table.is_none() {
+        return Ok(());
+    }
+    let pct = 100.0 * overlap.id as f64 / n_sampled as f64;
+    let summary = format!(
+        "{} of {} sampled read(s) ({:.1}%) are in the table",
+        overlap.id, n_sampled, pct
+    );
+    if overlap.id == n_sampled {
+        f.ok(summary);
+        return Ok(());
+    }
+    let missing = format!("{}; not found: {}", summary, examples(&unmatched));
+    if overlap.id == 0 {
+        f.error(missing);
+    } else {
+        f.warn(missing);
+    }
+    let hints = [
+        (
+            overlap.full_header,
+            "--match-on full-header (the table lists whole header lines)",
+        ),
+        (
+            overlap.pacbio,
+            "--match-on pacbio (the table lists PacBio movie/ZMW names)",
+        ),
+        (
+            overlap.prefixed,
+            "stripping the '>'/'@' the table IDs start with",
+        ),
+    ];
+    if let Some((n, hint)) = hints.iter().filter(|(n, _)| *n > 0).max_by_key(|(n, _)| *n) {
+        f.note(format!("{} more would match with {}", n, hint));
+    }
+    Ok(())
+}
+
+/// Print the checks of `opts`; true when no errors were found.
+pub fn lint(opts: &LintOptions) -> Result<bool> {
+    let mut f = Findings::default();
+    let mut table = None;
+    if let Some(path) = opts.table {
+        println!("table {}", path.display());
+        match lint_table(&mut f, opts, path) {
+            Ok(t) => table = t,
+            Err(e) => f.error(format!("{:#}", e)),
+        }
+    }
+    if let Some(path) = opts.reads {
+        println!("reads {}", path);
+        if opts.table.is_some() && table.is_none() {
+            f.note("ID overlap not checked: the table could not be read");
+        }
+        if let Err(e) = lint_reads(&mut f, opts, path, table.as_ref()) {
+            f.error(format!("{:#}", e));
+        }
+    }
+    println!("lint: {} error(s), {} warning(s)", f.errors, f.warnings);
+    Ok(f.errors == 0)
+}
@@ -1,30 +1,342 @@
 use anyhow::{bail, Context, Result};
 use bio::alphabets::dna;
-use bio::io::{fasta, fastq};
-use clap::{ArgAction, Parser};
-use csv::ReaderBuilder;
-use flate2::read::MultiGzDecoder;
-use std::collections::HashMap;
-use std::fs::File;
-use std::io::{self, BufWriter, Read, Write};
-use std::path::PathBuf;
+use bio::io::fasta::{self, FastaRead};
+use bio::io::fastq::{self, FastqRead};
+use clap::{ArgAction, CommandFactory, Parser, Subcommand, ValueEnum};
+use clap_complete::Shell;
+use std::collections::HashSet;
+use std::fmt::Write as _;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::time::Instant;
+
+mod append;
+mod bam;
+mod batch;
+mod bgzf;
+mod casava;
+mod checksum;
+#[cfg(feature = "remote")]
+mod child;
+mod columnar;
+mod compress;
+mod consensus;
+mod delta;
+mod diff;
+mod duplex;
+mod evaluate;
+mod explain;
+mod faidx;
+mod gff;
+mod groups;
+mod idlist;
+mod liftover;
+mod lint;
+mod metrics;
+mod output;
+mod pairing;
+mod presets;
+mod primers;
+mod progress;
+mod provenance;
+mod quality;
+mod remote;
+mod report;
+mod resources;
+mod scramble;
+mod selftest;
+#[cfg(feature = "server")]
+mod serve;
+mod simulate;
+mod sketch;
+mod splice;
+mod split;
+mod summary;
+mod table;
+mod table_cache;
+mod tabular;
+mod tags;
+mod template;
+mod timing;
+mod trace;
+mod twobit;
+mod vcf;
+mod verify;
+mod watch;
+
+use checksum::{ChecksumAlgo, Manifest};
+use columnar::ColumnarWriter;
+use consensus::{Consensus, DecisionLog, Evidence, OnAmbiguous, OnConflict, Source};
+use delta::DeltaWriter;
+use duplex::Duplex;
+use explain::Explainer;
+use faidx::FaiIndex;
+use groups::Grouping;
+use liftover::Liftover;
+use output::{Output, RecordFormat};
+use pairing::{MergeCounter, Merger, Pairer};
+use presets::{BarcodeLayout, Preset};
+use primers::{PrimerCall, PrimerDetector, PrimerMask, PrimerScoring, PrimerSet};
+use progress::{ByteCounter, CountingReader, Heartbeat};
+use provenance::Provenance;
+use report::Report;
+use resources::Resources;
+use sketch::{Sketch, SketchParams};
+use splice::SpliceStrands;
+use split::{SplitCounter, Splitter};
+use summary::{Summary, SummaryFormat};
+use table::{Encoding, MatchOn, OrientationTable, SharedTable, TableFormat};
+use tags::StrandTag;
+use template::{HeaderFields, HeaderTemplate};
+use timing::{Stage, TimedReader, Timings};
+use trace::Trace;
+use verify::IdSet;
+
+/// Records longer than this (FASTA mode) are written straight from the
+/// parsed record instead of through a reoriented copy.
+const STREAM_RECORD_LEN: usize = 8 << 20;
 
 /// Conventional FASTA wrap width.
 const FASTA_WRAP_WIDTH: usize = 60;
 
+/// Bases searched at each read end for `--primers` when no preset sets a window.
+const DEFAULT_PRIMER_WINDOW: usize = 200;
+
+/// Exit status when the run completed but reads were missing from the table (`--fail-on-missing`).
+const EXIT_MISSING: u8 = 3;
+
+/// Exit status when the run completed with warnings (`--fail-on-warnings`).
+const EXIT_WARNINGS: u8 = 4;
+
+/// What a completed run observed that may warrant a non-zero exit status.
+#[derive(Debug, Default)]
+struct Outcome {
+    /// Records read
+    processed: u64,
+    /// Records reverse-complemented
+    flipped: u64,
+    /// Reads absent from the orientation table
+    missing: u64,
+    /// Recoverable problems: unresolved reads, duplicate table IDs, conflicting evidence
+    warnings: u64,
+}
+
+/// Counters kept by both record loops, summarized, reported and turned into
+/// an [`Outcome`] the same way in FASTA and FASTQ mode.
+#[derive(Default)]
+struct Tally {
+    processed: u64,
+    flipped: u64,
+    excluded: u64,
+    masked: u64,
+    missing: u64,
+    duplex: u64,
+    ambiguous: u64,
+    no_orientation: u64,
+    primers: u64,
+    /// Reads no source gave an orientation
+    unresolved: u64,
+    duplicates: u64,
+    conflicts: u64,
+    orphans: u64,
+}
+
+impl Tally {
+    /// The summary fields common to both modes; `tags` says whether header
+    /// orientation tags were read and `primers` whether primers were searched.
+    fn summary(
+        &self,
+        mode: &'static str,
+        cli: &Cli,
+        tags: bool,
+        primers: bool,
+        splits: &SplitCounter,
+    ) -> Summary {
+        let mut summary = Summary::new(mode);
+        summary
+            .field("processed", self.processed)
+            .field("flipped", self.flipped);
+        if cli.table.is_some() {
+            summary.field("missing_in_table", self.missing);
+        }
+        if tags {
+            summary.field("no_orientation_tag", self.no_orientation);
+        }
+        if primers {
+            summary
+                .field("oriented_by_primers", self.primers)
+                .field("ambiguous", self.ambiguous);
+        }
+        if Self::sources(cli, tags, primers) > 1 {
+            summary.field("conflicts", self.conflicts);
+        }
+        if cli.duplex != Duplex::Keep {
+            summary.field("duplex", self.duplex);
+        }
+        if cli.exclude_ids.is_some() {
+            summary.field("excluded", self.excluded);
+        }
+        if cli.mask_primers.is_some() {
+            summary.field("masked_bases", self.masked);
+        }
+        if cli.split_on_internal_adapter {
+            summary
+                .field("split_reads", splits.reads())
+                .field("fragments", splits.fragments());
+        }
+        summary
+    }
+
+    /// The `--report` counts common to both modes.
+    fn report(&self, r: &mut Report, cli: &Cli, tags: bool, primers: bool) {
+        r.set_count("processed", self.processed);
+        if cli.exclude_ids.is_some() {
+            r.set_count("excluded", self.excluded);
+        }
+        r.set_count("flipped", self.flipped);
+        if cli.table.is_some() {
+            r.set_count("missing_in_table", self.missing);
+            r.set_count("duplicate_ids_in_table", self.duplicates);
+        }
+        if tags {
+            r.set_count("no_orientation_tag", self.no_orientation);
+        }
+        if primers {
+            r.set_count("oriented_by_primers", self.primers);
+        }
+        if Self::sources(cli, tags, primers) > 1 {
+            r.set_count("conflicts", self.conflicts);
+        }
+    }
+
+    /// Evidence sources consulted; conflicts need at least two.
+    fn sources(cli: &Cli, tags: bool, primers: bool) -> usize {
+        [
+            cli.table.is_some(),
+            tags,
+            primers,
+            cli.reference.is_some(),
+            cli.paf.is_some(),
+            cli.bam_tag.is_some(),
+        ]
+        .into_iter()
+        .filter(|&s| s)
+        .count()
+    }
+
+    /// Unresolved reads pass through, but are worth a warning.
+    fn outcome(&self) -> Outcome {
+        Outcome {
+            processed: self.processed,
+            flipped: self.flipped,
+            missing: self.missing,
+            warnings: self.unresolved + self.duplicates + self.conflicts + self.orphans,
+        }
+    }
+}
+
+/// Letter case of written sequences (`--output-case`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum OutputCase {
+    /// Uppercase every base
+    Upper,
+    /// Lowercase every base
+    Lower,
+    /// Keep the input's case (e.g. soft-masking)
+    Preserve,
+}
+
+impl OutputCase {
+    fn apply(self, seq: &mut [u8]) {
+        match self {
+            OutputCase::Upper => seq.make_ascii_uppercase(),
+            OutputCase::Lower => seq.make_ascii_lowercase(),
+            OutputCase::Preserve => {}
+        }
+    }
+}
+
 /// Re-orient FASTA/FASTQ reads to a constant direction using a TSV with per-read orientation or embedded orientation tags.
-#[derive(Parser, Debug)]
-#[command(author, version, about)]
+#[derive(Parser, Debug, Clone)]
+#[command(
+    author,
+    version,
+    about,
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
 struct Cli {
-    /// Input FASTA/FASTQ (can be .fa/.fasta/.fq/.fastq(.gz)); use '-' for stdin (plain text, not gz)
-    #[arg(short = 'f', long)]
-    fasta: String,
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Input FASTA/FASTQ (can be .fa/.fasta/.fq/.fastq(.gz), or .2bit in FASTA mode); use '-' for stdin (plain or gzip), or an s3:// or gs:// URI. Several inputs need --out-dir
+    #[arg(short = 'f', long, num_args = 1.., required_unless_present_any = ["list_presets", "manifest", "watch", "input_dir", "sra"])]
+    fasta: Vec<String>,
+
+    /// Stream this SRA run (e.g. SRR1234567) through sra-tools' fastq-dump instead of reading -f
+    #[arg(long, value_name = "ACCESSION", value_parser = remote::parse_accession, requires = "fastq", conflicts_with_all = ["fasta", "input_dir", "manifest", "watch"])]
+    sra: Option<String>,
+
+    /// Process every sequence file in this directory (see --recursive, --pattern); needs --out-dir
+    #[arg(long, value_name = "DIR", requires = "out_dir", conflicts_with_all = ["fasta", "manifest", "watch"])]
+    input_dir: Option<PathBuf>,
 
-    /// Tab-delimited table with headers (can be .tsv/.txt(.gz)); not required for --fastq mode
+    /// Descend into subdirectories of --input-dir (e.g. MinKNOW's fastq_pass/barcodeXX/)
+    #[arg(long, requires = "input_dir")]
+    recursive: bool,
+
+    /// Only take --input-dir files whose name matches this glob ('*' and '?'); default: .fa/.fasta/.fq/.fastq(.gz) and similar
+    #[arg(long, value_name = "GLOB", requires = "input_dir")]
+    pattern: Option<String>,
+
+    /// Write one output per input into this directory, keeping the inputs' relative directory layout
+    #[arg(long, value_name = "DIR", conflicts_with_all = ["out", "unchanged_out", "ambiguous_out", "report", "decision_log", "provenance_out", "faidx_out", "agp_out", "chain_out", "remap_gff_out", "remap_vcf_out", "primer_debug", "primer_hits_out", "checksum_manifest", "trace_output", "delta_out"])]
+    out_dir: Option<PathBuf>,
+
+    /// Output file name under --out-dir: {name} is the input file name, {stem} the name without sequence/compression extensions
+    #[arg(
+        long,
+        value_name = "TEMPLATE",
+        default_value = "{name}",
+        requires = "out_dir"
+    )]
+    out_template: String,
+
+    /// TSV of samples to process in one run: 'input' and 'output' columns, optional 'table' (defaults to --table) and 'sample'
+    #[arg(long, conflicts_with_all = ["fasta", "out", "unchanged_out", "ambiguous_out", "report", "decision_log", "provenance_out", "faidx_out", "agp_out", "chain_out", "remap_gff_out", "remap_vcf_out", "primer_debug", "primer_hits_out", "checksum_manifest", "trace_output", "delta_out"])]
+    manifest: Option<PathBuf>,
+
+    /// Watch this directory for new FASTA/FASTQ chunks and restrand each as it is completed
+    #[arg(long, value_name = "DIR", requires = "watch_out_dir", conflicts_with_all = ["fasta", "manifest", "out", "unchanged_out", "ambiguous_out", "report", "decision_log", "provenance_out", "faidx_out", "agp_out", "chain_out", "remap_gff_out", "remap_vcf_out", "primer_debug", "primer_hits_out", "checksum_manifest", "trace_output", "delta_out"])]
+    watch: Option<PathBuf>,
+
+    /// Directory receiving one output per watched chunk (same name, without .gz)
+    #[arg(long, value_name = "DIR", requires = "watch")]
+    watch_out_dir: Option<PathBuf>,
+
+    /// Seconds between directory scans in --watch mode
+    #[arg(long, value_name = "SECS", default_value_t = 5.0)]
+    watch_interval: f64,
+
+    /// Stop watching after this many seconds without a new chunk (default: run until killed)
+    #[arg(long, value_name = "SECS", requires = "watch")]
+    watch_idle_exit: Option<f64>,
+
+    /// Serve Prometheus metrics at /metrics on this address while watching
+    #[arg(long, value_name = "ADDR", requires = "watch")]
+    metrics_listen: Option<String>,
+
+    /// Samples processed in parallel with --manifest or --out-dir
+    #[arg(long, env = "RESTRAND_JOBS", default_value_t = 1, value_parser = clap::value_parser!(u64).range(1..))]
+    jobs: u64,
+
+    /// Tab-delimited table with headers (can be .tsv/.txt(.gz)); optional in --fastq mode, where it joins the header tags
     #[arg(short = 't', long)]
     table: Option<PathBuf>,
 
-    /// Output path (default: stdout)
+    /// Output path (default: stdout); '-' and '/dev/stdout' also mean stdout, other special files (/dev/fd/N, FIFOs) are never truncated, and s3:// or gs:// URIs are streamed to the aws or gcloud CLI
     #[arg(short = 'o', long)]
     out: Option<PathBuf>,
 
@@ -32,264 +344,2201 @@ struct Cli {
     #[arg(long, action = ArgAction::SetTrue)]
     fastq: bool,
 
-    /// Name of the read ID column in the table (FASTA mode only)
-    #[arg(long, default_value = "ReadName")]
+    /// Name of the read ID column in the table
+    #[arg(long, env = "RESTRAND_ID_COL", default_value = "ReadName")]
     id_col: String,
 
-    /// Name of the orientation column in the table ('+' for cDNA, '-' for rc(cDNA)) (FASTA mode only)
-    #[arg(long, default_value = "orientation")]
+    /// How the table writes orientations: '+'/'-' style, 1/-1, or SAM flags (16 = reverse); auto detects from the first rows
+    #[arg(long, value_enum, default_value_t = Encoding::Auto)]
+    orientation_encoding: Encoding,
+
+    /// Read the table as a vendor report with fixed columns instead of --id-col/--orientation-col/--orientation-encoding
+    #[arg(long, value_enum, default_value_t = TableFormat::Tsv, requires = "table")]
+    table_format: TableFormat,
+
+    /// Match table IDs against the read ID, the full header line (for tables keyed by whole headers), the sequence's MD5 (for reads renamed upstream) or PacBio movie/ZMW names
+    #[arg(long, value_enum, default_value_t = MatchOn::Id)]
+    match_on: MatchOn,
+
+    /// Name of the orientation column in the table ('+' for cDNA, '-' for rc(cDNA))
+    #[arg(long, env = "RESTRAND_ORIENTATION_COL", default_value = "orientation")]
     orientation_col: String,
 
     /// Target orientation to keep as-is; reads not matching are reverse-complemented. Allowed: '+' or '-'
-    #[arg(long, default_value = "+")]
+    #[arg(long, env = "RESTRAND_TARGET_ORIENTATION", default_value = "+")]
     target_orientation: String,
 
-    /// If true, drop reads missing in the table (instead of passing through unchanged) (FASTA mode only)
+    /// Drop reads no source could orient (missing from the table, untagged, no primer call) instead of passing them through unchanged
     #[arg(long, action = ArgAction::SetTrue)]
     drop_missing: bool,
 
-    /// Append a suffix to headers of flipped reads (e.g., '/rc'); empty = no suffix (FASTA mode only)
+    /// Emit only reads listed in the table, whatever their orientation; other reads are skipped without any tag or primer evaluation
+    #[arg(long, requires = "table")]
+    only_listed: bool,
+
+    /// Process only reads whose IDs are listed in this file (one per line, optionally gzipped); other reads are skipped and not counted
+    #[arg(long, value_name = "FILE")]
+    include_ids: Option<String>,
+
+    /// Instead of restranding, check on the first records that flipping twice and header rewrites round-trip exactly; nothing is written
+    #[arg(long)]
+    self_test: bool,
+
+    /// Records --self-test checks
+    #[arg(long, value_name = "N", default_value_t = 1000, requires = "self_test")]
+    self_test_records: u64,
+
+    /// Drop reads whose IDs are listed in this file (one per line, optionally gzipped), e.g. contaminants; counted as excluded in the summary
+    #[arg(long, value_name = "FILE")]
+    exclude_ids: Option<String>,
+
+    /// ONT duplex reads ('template;complement' IDs): keep them as they are, drop them, or also match the table by either simplex ID
+    #[arg(long, value_enum, default_value_t = Duplex::Keep)]
+    duplex: Duplex,
+
+    /// Append a suffix to headers of flipped reads (e.g., '/rc'); empty = no suffix. In FASTQ mode it is appended to the read ID, not the comment
     #[arg(long, default_value = "")]
     flipped_suffix: String,
+
+    /// Append 'restrand=<flipped|kept> source=<table|tag|primers|none>' key=value fields to each description
+    #[arg(long, action = ArgAction::SetTrue)]
+    annotate: bool,
+
+    /// Compose output headers from a template instead: {id}, {desc}, {ori} (as written), {input_ori}, {action}, {source}, {len}; '.' marks an unknown orientation
+    #[arg(long, value_name = "TEMPLATE", value_parser = HeaderTemplate::parse, conflicts_with_all = ["flipped_suffix", "annotate"])]
+    header_template: Option<HeaderTemplate>,
+
+    /// Append the read's mean quality as 'mq=Q' (error-probability mean, Phred scale) to each header (FASTQ mode only)
+    #[arg(long, action = ArgAction::SetTrue, requires = "fastq")]
+    mean_quality_tag: bool,
+
+    /// Trim bases below this Phred quality from both ends of each read, in output orientation (FASTQ mode only)
+    #[arg(
+        long,
+        value_name = "Q",
+        requires = "fastq",
+        conflicts_with = "barcode_window"
+    )]
+    trim_qual: Option<u8>,
+
+    /// Read orientation tags (e.g. 'orientation:-') from FASTA headers as evidence; FASTQ mode always reads them
+    #[arg(long, action = ArgAction::SetTrue, conflicts_with = "fastq")]
+    use_orientation_tag: bool,
+
+    /// Reverse-complement every record (reversing FASTQ qualities), without a table, tags or primers
+    #[arg(
+        long,
+        conflicts_with_all = ["table", "use_orientation_tag", "primers", "preset", "reference", "paf", "bam_tag"]
+    )]
+    all: bool,
+
+    /// Remove the orientation tag from output headers instead of rewriting it (FASTQ mode, or --use-orientation-tag)
+    #[arg(long, action = ArgAction::SetTrue, conflicts_with = "rewrite_orientation_tag")]
+    strip_orientation_tag: bool,
+
+    /// Rewrite the orientation tag in output headers as 'KEY:<final orientation>' (FASTQ mode, or --use-orientation-tag)
+    #[arg(long, value_name = "KEY")]
+    rewrite_orientation_tag: Option<String>,
+
+    /// Replace any orientation tag with the final orientation in this layout, appended to every resolved header
+    #[arg(long, value_enum, value_name = "FORMAT", conflicts_with_all = ["strip_orientation_tag", "rewrite_orientation_tag"])]
+    emit_strand_tag: Option<StrandTag>,
+
+    /// Letter case of output sequences
+    #[arg(long, value_enum, default_value_t = OutputCase::Preserve)]
+    output_case: OutputCase,
+
+    /// Output record format (default: that of the input); 'tab' writes 'header<TAB>sequence[<TAB>quality]' lines
+    #[arg(long, value_enum)]
+    out_format: Option<RecordFormat>,
+
+    /// How --out-format bam records the orientation
+    #[arg(long, value_enum, default_value_t = bam::Patch::Tag, requires = "out_format")]
+    bam_patch: bam::Patch,
+
+    /// Aux tag --bam-patch tag sets
+    #[arg(long, value_name = "TAG", default_value = "ts", value_parser = bam::parse_tag, requires = "out_format")]
+    bam_patch_tag: [u8; 2],
+
+    /// Read 'header<TAB>sequence[<TAB>quality]' lines instead of FASTA/FASTQ (qualities required with --fastq)
+    #[arg(long, action = ArgAction::SetTrue)]
+    tab_input: bool,
+
+    /// Write only reads that were reverse-complemented; kept reads are discarded
+    #[arg(long, action = ArgAction::SetTrue)]
+    only_flipped: bool,
+
+    /// Memory budget for the orientation table (e.g. 512M, 4G); larger tables are sorted to a temporary file and searched on disk
+    #[arg(long, value_name = "SIZE", value_parser = table::parse_size)]
+    max_memory: Option<u64>,
+
+    /// `--table`, already loaded once for every input of a batch
+    #[arg(skip)]
+    shared_table: Option<SharedTable>,
+
+    /// Input read buffer (e.g. 256K, 4M; default 1M for regular files, 64K for stdin and pipes)
+    #[arg(long, value_name = "SIZE", value_parser = table::parse_size)]
+    read_buffer_size: Option<u64>,
+
+    /// Output write buffer (default 1M for regular files, 64K for stdout, pipes and uploads)
+    #[arg(long, value_name = "SIZE", value_parser = table::parse_size)]
+    write_buffer_size: Option<u64>,
+
+    /// gzip level for .gz read outputs, from 0 (fastest; stored) to 9 (smallest); default 6
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(0..=9))]
+    compress_level: Option<u32>,
+
+    /// Append to existing read outputs (after checking they end on a complete record) instead of replacing them
+    #[arg(long, action = ArgAction::SetTrue, requires = "out", conflicts_with_all = ["checksum", "faidx_out", "delta_out", "verify_ids", "provenance_comment"])]
+    append: bool,
+
+    /// Directory for temporary files such as the --max-memory spill (default: TMPDIR, else the system temp directory)
+    #[arg(long, value_name = "DIR", env = "RESTRAND_TMPDIR")]
+    tmpdir: Option<PathBuf>,
+
+    /// Binary cache of the parsed --table: built on first use, memory-mapped on later runs (rebuilt when the table changes)
+    #[arg(long, value_name = "PATH", requires = "table")]
+    table_cache: Option<PathBuf>,
+
+    /// Write reads that were not flipped (including pass-through reads missing from the table) to this file instead of --out
+    #[arg(long, conflicts_with = "only_flipped")]
+    unchanged_out: Option<PathBuf>,
+
+    /// Write mate 1 of each pair to this file and mate 2 to --out-r2 (FASTQ mode; input interleaved, or R1 and R2 given to -f)
+    #[arg(long, value_name = "FILE", requires_all = ["fastq", "out_r2"], conflicts_with_all = ["out", "out_interleaved", "unchanged_out", "ambiguous_out", "delta_out", "verify_ids", "out_dir", "input_dir", "manifest", "watch", "explain"])]
+    out_r1: Option<PathBuf>,
+
+    /// Mate 2 output of --out-r1
+    #[arg(long, value_name = "FILE", requires = "out_r1")]
+    out_r2: Option<PathBuf>,
+
+    /// Write each pair as two consecutive records, mate 1 first (FASTQ mode; input interleaved, or R1 and R2 given to -f)
+    #[arg(long, value_name = "FILE", requires = "fastq", conflicts_with_all = ["out", "unchanged_out", "ambiguous_out", "delta_out", "verify_ids", "out_dir", "input_dir", "manifest", "watch", "explain"])]
+    out_interleaved: Option<PathBuf>,
+
+    /// Write reads whose mate is missing here instead of leaving them out of --out-r1/--out-r2/--out-interleaved
+    #[arg(long, value_name = "FILE", requires = "fastq")]
+    orphans_out: Option<PathBuf>,
+
+    /// Reads a read may wait for its mate in --out-r1/--out-interleaved before it is orphaned, bounding the reads held in memory
+    #[arg(long, value_name = "N", default_value_t = 1_000_000, value_parser = clap::value_parser!(u64).range(1..))]
+    pair_window: u64,
+
+    /// Merge mates that overlap by at least 20 bases into one read on mate 1's strand before orienting (FASTQ mode; input interleaved, or R1 and R2 given to -f)
+    #[arg(long, requires = "fastq", conflicts_with_all = ["out_r1", "out_interleaved", "delta_out"])]
+    merge_pairs: bool,
+
+    /// Also write a patch that rebuilds --out from the input with 'apply', for archiving a small delta instead of a second copy
+    #[arg(long, value_name = "PATH", conflicts_with_all = ["unchanged_out", "ambiguous_out", "out_format", "tab_input", "trim_qual", "provenance_comment"])]
+    delta_out: Option<PathBuf>,
+
+    /// After writing, read the outputs back and fail unless they hold exactly the reads written
+    #[arg(long, action = ArgAction::SetTrue)]
+    verify_ids: bool,
+
+    /// Start FASTA outputs with a '; restrand-fasta VERSION: COMMAND' comment line
+    #[arg(long, action = ArgAction::SetTrue, conflicts_with = "fastq")]
+    provenance_comment: bool,
+
+    /// Write tool version, command line and inputs as JSON to this path
+    #[arg(long)]
+    provenance_out: Option<PathBuf>,
+
+    /// Write an AGP file placing each output sequence on its input sequence, '-' for flipped ones (FASTA mode only)
+    #[arg(long, value_name = "FILE", conflicts_with = "fastq")]
+    agp_out: Option<PathBuf>,
+
+    /// Write a UCSC chain file lifting input coordinates onto the restranded output (FASTA mode only)
+    #[arg(long, value_name = "FILE", conflicts_with = "fastq")]
+    chain_out: Option<PathBuf>,
+
+    /// GFF3 annotation of the input sequences to carry over to the restranded output (FASTA mode only)
+    #[arg(
+        long,
+        value_name = "GFF3",
+        requires = "remap_gff_out",
+        conflicts_with = "fastq"
+    )]
+    remap_gff: Option<String>,
+
+    /// Where to write the --remap-gff annotation with flipped sequences' coordinates and strands rewritten
+    #[arg(long, value_name = "GFF3", requires = "remap_gff")]
+    remap_gff_out: Option<PathBuf>,
+
+    /// VCF of variants on the input sequences to carry over to the restranded output (FASTA mode only)
+    #[arg(
+        long,
+        value_name = "VCF",
+        requires = "remap_vcf_out",
+        conflicts_with = "fastq"
+    )]
+    remap_vcf: Option<String>,
+
+    /// Where to write the --remap-vcf variants with flipped sequences' positions and alleles rewritten
+    #[arg(long, value_name = "VCF", requires = "remap_vcf")]
+    remap_vcf_out: Option<PathBuf>,
+
+    /// Write a samtools-compatible .fai index of the --out FASTA to this path
+    #[arg(long, conflicts_with = "fastq")]
+    faidx_out: Option<PathBuf>,
+
+    /// Write a bgzip-compatible .gzi index of the .gz --out to this path, so tools can seek into it (e.g. samtools faidx with --faidx-out)
+    #[arg(long, value_name = "PATH", requires = "out", conflicts_with = "append")]
+    gzi_out: Option<PathBuf>,
+
+    /// Write a JSON run report (counts and read-length histograms) to this path
+    #[arg(long)]
+    report: Option<PathBuf>,
+
+    /// Write Chrome trace-event JSON with spans for the major run phases to this path (view in chrome://tracing or Perfetto)
+    #[arg(long, value_name = "PATH")]
+    trace_output: Option<PathBuf>,
+
+    /// Time each pipeline stage for the --report `timings` object and the --trace-output `records` span; reads the clocks several times per record
+    #[arg(long)]
+    stage_timings: bool,
+
+    /// Table column whose values group reads for per-group flip rates and strand balance in --report
+    #[arg(
+        long,
+        value_name = "COL",
+        requires = "report",
+        conflicts_with = "group_tag"
+    )]
+    group_col: Option<String>,
+
+    /// Header field key (e.g. 'CB' for 'CB:Z:ACGT' or 'CB=ACGT') grouping reads in --report
+    #[arg(long, value_name = "KEY", requires = "report")]
+    group_tag: Option<String>,
+
+    /// Bin width (bp) of the read-length histograms in --report
+    #[arg(long, default_value_t = 100, value_parser = clap::value_parser!(u64).range(1..))]
+    length_bin_width: u64,
+
+    /// Digest every output file while writing and emit a checksum manifest
+    #[arg(long, value_enum)]
+    checksum: Option<ChecksumAlgo>,
+
+    /// Checksum manifest path (default: '<out>.md5' or '<out>.sha256', after --out-r1 or --out-interleaved for paired output; required when writing to stdout)
+    #[arg(long, requires = "checksum")]
+    checksum_manifest: Option<PathBuf>,
+
+    /// Exit with status 3 if any read was missing from the table (output is still written)
+    #[arg(long, action = ArgAction::SetTrue)]
+    fail_on_missing: bool,
+
+    /// Fail the run if fewer than this fraction of reads are found in the table (catches a table meant for another sample)
+    #[arg(long, value_name = "FRACTION", requires = "table", value_parser = scramble::parse_fraction)]
+    min_found_fraction: Option<f64>,
+
+    /// Warn (counted for --fail-on-warnings) when more than this fraction of reads is flipped
+    #[arg(long, value_name = "FRACTION", value_parser = scramble::parse_fraction)]
+    warn_flip_fraction: Option<f64>,
+
+    /// Fail the run when more than this fraction of reads is flipped (usually a backwards --target-orientation)
+    #[arg(long, value_name = "FRACTION", value_parser = scramble::parse_fraction)]
+    max_flip_fraction: Option<f64>,
+
+    /// Exit with status 4 if the run produced warnings, e.g. untagged FASTQ reads or duplicate table IDs
+    #[arg(long, action = ArgAction::SetTrue)]
+    fail_on_warnings: bool,
+
+    /// Suppress the stderr summary and warnings (errors are still reported)
+    #[arg(short = 'q', long, action = ArgAction::SetTrue)]
+    quiet: bool,
+
+    /// Write the run summary to this file instead of stderr
+    #[arg(long)]
+    summary_file: Option<PathBuf>,
+
+    /// Format of the run summary
+    #[arg(long, value_enum, default_value_t = SummaryFormat::Human)]
+    summary_format: SummaryFormat,
+
+    /// Print a machine-readable progress line to stderr every N records (even with --quiet)
+    #[arg(long, value_name = "N")]
+    report_every: Option<u64>,
+
+    /// Primer FASTA (first record forward/5', second reverse/3') used to orient reads the table or header tag leave unresolved
+    #[arg(long, conflicts_with = "preset")]
+    primers: Option<PathBuf>,
+
+    /// Built-in library-prep preset supplying primers, search window, thresholds (and polyA/barcode geometry) instead of --primers
+    #[arg(long, value_enum)]
+    preset: Option<Preset>,
+
+    /// Print the built-in presets with their primers and settings, then exit
+    #[arg(long, action = ArgAction::SetTrue)]
+    list_presets: bool,
+
+    /// Append 'cb_window=S-E umi_window=S-E' (0-based, half-open, in output orientation) to headers (barcoded presets only)
+    #[arg(long, action = ArgAction::SetTrue, requires = "preset")]
+    barcode_window: bool,
+
+    /// Number of bases at each read end searched for primers [default: 200, or the preset's]
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    primer_window: Option<u64>,
+
+    /// Primer alignment match score
+    #[arg(long, default_value_t = PrimerScoring::default().match_score, value_parser = clap::value_parser!(i32).range(1..))]
+    primer_match: i32,
+
+    /// Primer alignment mismatch penalty (positive magnitude)
+    #[arg(long, default_value_t = PrimerScoring::default().mismatch, value_parser = clap::value_parser!(i32).range(0..))]
+    primer_mismatch: i32,
+
+    /// Primer alignment gap-open penalty (positive magnitude)
+    #[arg(long, default_value_t = PrimerScoring::default().gap_open, value_parser = clap::value_parser!(i32).range(0..))]
+    primer_gap_open: i32,
+
+    /// Primer alignment gap-extend penalty (positive magnitude)
+    #[arg(long, default_value_t = PrimerScoring::default().gap_extend, value_parser = clap::value_parser!(i32).range(0..))]
+    primer_gap_extend: i32,
+
+    /// Minimum identity (matches / aligned columns, 0-1) for a primer hit to count [default: 0.75, or the preset's]
+    #[arg(long)]
+    primer_min_identity: Option<f64>,
+
+    /// Minimum alignment score for a primer hit to count
+    #[arg(long, default_value_t = PrimerScoring::default().min_score)]
+    primer_min_score: i32,
+
+    /// Dump every per-read primer alignment (passing or not) as TSV to this path
+    #[arg(long)]
+    primer_debug: Option<PathBuf>,
+
+    /// Write each passing primer/polyA hit with its read subsequence, position and score (FASTA for .fa/.fasta, else TSV)
+    #[arg(long, value_name = "FILE")]
+    primer_hits_out: Option<PathBuf>,
+
+    /// Add per-position adapter content and adapter start positions from the primer search to the --report
+    #[arg(long, requires = "report")]
+    adapter_report: bool,
+
+    /// Mask passing primer/adapter hits in the output instead of leaving them, keeping read length and coordinates
+    #[arg(long, value_enum, value_name = "HOW", conflicts_with = "delta_out")]
+    mask_primers: Option<PrimerMask>,
+
+    /// Cut reads where --primers/--preset primers occur away from the read ends (ligation chimeras) and orient the fragments, named ID:START-END, on their own
+    #[arg(long, conflicts_with_all = ["delta_out", "out_r1", "out_interleaved"])]
+    split_on_internal_adapter: bool,
+
+    /// Orient reads by forward-strand minimizers shared with this FASTA (transcripts or other sequences already in '+' orientation)
+    #[arg(long, value_name = "FASTA")]
+    reference: Option<PathBuf>,
+
+    /// k-mer length of the --reference minimizers
+    #[arg(long, default_value_t = 15, requires = "reference")]
+    minimizer_k: usize,
+
+    /// Window of consecutive k-mers each --reference minimizer is picked from
+    #[arg(long, default_value_t = 10, requires = "reference")]
+    minimizer_w: usize,
+
+    /// Minimizers a read must share with --reference on its winning strand
+    #[arg(long, default_value_t = 3, requires = "reference")]
+    min_shared_minimizers: usize,
+
+    /// Orient reads by the transcript strand of their primary minimap2 -x splice alignment in this PAF (the ts:A tag, else the cs tag's splice motifs)
+    #[arg(long, value_name = "PAF")]
+    paf: Option<PathBuf>,
+
+    /// Orient BAM input by this aux tag (ts:A relative to the read, XS:A relative to the reference) instead of leaving strand to other sources
+    #[arg(long, value_enum, value_name = "TAG")]
+    bam_tag: Option<bam::AuxTag>,
+
+    /// Orientation sources from most to least trusted (comma-separated); unlisted sources rank last
+    #[arg(long, value_enum, value_delimiter = ',', default_values_t = [Source::Table, Source::Tag, Source::Splice, Source::Bam, Source::Primers, Source::Reference])]
+    evidence_priority: Vec<Source>,
+
+    /// How to resolve reads whose orientation sources disagree
+    #[arg(long, value_enum, default_value_t = OnConflict::Priority)]
+    on_conflict: OnConflict,
+
+    /// What to do with reads whose primer or tail hits point both ways (e.g. a 5' polyT and a 3' polyA)
+    #[arg(long, value_enum, default_value_t = OnAmbiguous::Keep)]
+    on_ambiguous: OnAmbiguous,
+
+    /// Where --on-ambiguous separate-file writes ambiguous reads
+    #[arg(long, value_name = "FILE")]
+    ambiguous_out: Option<PathBuf>,
+
+    /// Write each read's orientation decision (source, call, action, confidence) as TSV to this path
+    #[arg(long)]
+    decision_log: Option<PathBuf>,
+
+    /// Instead of writing reads, print for the first N reads (or the --include-ids reads) what each source said, the call and the action a run would take
+    #[arg(long, value_name = "N", conflicts_with_all = ["out", "out_dir", "input_dir", "manifest", "watch", "unchanged_out", "ambiguous_out", "delta_out", "report", "decision_log", "provenance_out", "faidx_out", "agp_out", "chain_out", "remap_gff_out", "remap_vcf_out", "checksum", "verify_ids", "summary_file"])]
+    explain: Option<u64>,
+
+    /// Append 'restrand_confidence=X' (0-1) to the header of every resolved read
+    #[arg(long, action = ArgAction::SetTrue)]
+    confidence_in_header: bool,
 }
 
-fn open_writer(path: &Option<PathBuf>) -> Result<Box<dyn Write>> {
-    Ok(match path {
-        Some(p) => Box::new(BufWriter::new(
-            File::create(p).with_context(|| format!("create {:?}", p))?,
-        )),
-        None => Box::new(BufWriter::new(io::stdout())),
-    })
+/// True for output paths that name our own stdout.
+fn is_stdout_path(path: &Path) -> bool {
+    path == Path::new("-") || path == Path::new("/dev/stdout")
 }
 
-fn open_text(path: &str) -> Result<Box<dyn Read>> {
-    if path == "-" {
-        // stdin (expect plain text; if gz, pipe through zcat/gunzip externally)
-        return Ok(Box::new(io::stdin()));
-    }
-    let fh = File::open(path).with_context(|| format!("open '{}'", path))?;
-    if path.ends_with(".gz") {
-        Ok(Box::new(MultiGzDecoder::new(fh)))
+/// Open an output file. Devices, FIFOs and process-substitution paths
+/// (`/dev/fd/N`) are opened for writing without create/truncate semantics.
+fn create_output(path: &Path) -> Result<File> {
+    let special =
+        path.starts_with("/dev/") || fs::metadata(path).is_ok_and(|m| !m.file_type().is_file());
+    if special {
+        OpenOptions::new()
+            .write(true)
+            .open(path)
+            .with_context(|| format!("open {:?} for writing", path))
     } else {
-        Ok(Box::new(fh))
+        File::create(path).with_context(|| format!("create {:?}", path))
     }
 }
 
-fn load_orientation_map(
-    table_path: &PathBuf,
-    id_col: &str,
-    orientation_col: &str,
-) -> Result<HashMap<String, u8>> {
-    // Support gz TSV by looking at extension.
-    let rdr: Box<dyn Read> = if table_path.to_string_lossy().ends_with(".gz") {
-        Box::new(MultiGzDecoder::new(
-            File::open(table_path).with_context(|| format!("open {:?}", table_path))?,
+/// Auxiliary subcommands; restranding itself runs without one.
+#[derive(Subcommand, Debug, Clone)]
+enum Command {
+    /// Print a shell completion script to stdout
+    #[command(hide = true)]
+    Completions {
+        /// Shell to generate completions for
+        #[arg(value_enum)]
+        shell: Shell,
+    },
+    /// Serve restranding over HTTP: POST records to /restrand?format=fasta|fastq&target=+|-
+    #[cfg(feature = "server")]
+    Serve {
+        /// Address to listen on (port 0 picks a free port, printed on stderr)
+        #[arg(long, default_value = "127.0.0.1:8080")]
+        listen: String,
+        /// Orientation table used for FASTA requests
+        #[arg(short = 't', long)]
+        table: Option<PathBuf>,
+        /// Exit after this many requests (default: serve until killed)
+        #[arg(long)]
+        max_requests: Option<u64>,
+        /// Directory for request bodies and results while they are processed
+        #[arg(long, value_name = "DIR", env = "RESTRAND_TMPDIR")]
+        tmpdir: Option<PathBuf>,
+        /// Also serve Prometheus metrics at /metrics on this address
+        #[arg(long, value_name = "ADDR")]
+        metrics_listen: Option<String>,
+    },
+    /// Score orientation calls against known orientations: evaluate --truth truth.tsv -- -f reads.fq --fastq --preset pcs111
+    Evaluate {
+        /// TSV of true orientations per read
+        #[arg(long)]
+        truth: PathBuf,
+        /// Read ID column of the truth table
+        #[arg(long, default_value = "ReadName")]
+        truth_id_col: String,
+        /// Orientation column of the truth table
+        #[arg(long, default_value = "orientation")]
+        truth_orientation_col: String,
+        /// Restranding options after '--' choosing the input and inference method
+        #[arg(last = true, required = true)]
+        args: Vec<String>,
+    },
+    /// Recover the orientation table of an already restranded file: diff --original in.fa --restranded out.fa
+    Diff {
+        /// The reads as they were before restranding (optionally .gz)
+        #[arg(long)]
+        original: String,
+        /// The same reads after restranding, in the same order
+        #[arg(long)]
+        restranded: String,
+        /// Inputs are FASTQ
+        #[arg(long)]
+        fastq: bool,
+        /// Where to write the ReadName/orientation table (default: stdout; .gz compresses)
+        #[arg(short = 'o', long)]
+        out: Option<PathBuf>,
+        /// Suffix the restranding appended to flipped read IDs, ignored when matching IDs
+        #[arg(long, default_value = "")]
+        flipped_suffix: String,
+    },
+    /// Check a table and sequence file before a run: lint -t map.tsv -f reads.fa
+    Lint {
+        /// Orientation table to check (optionally compressed)
+        #[arg(short = 't', long, required_unless_present = "fasta")]
+        table: Option<PathBuf>,
+        /// Reads (optionally .gz) to parse and look up in the table
+        #[arg(short = 'f', long)]
+        fasta: Option<String>,
+        /// Reads are FASTQ
+        #[arg(long)]
+        fastq: bool,
+        /// Name of the read ID column in the table
+        #[arg(long, env = "RESTRAND_ID_COL", default_value = "ReadName")]
+        id_col: String,
+        /// Name of the orientation column in the table
+        #[arg(long, env = "RESTRAND_ORIENTATION_COL", default_value = "orientation")]
+        orientation_col: String,
+        /// How the table writes orientations
+        #[arg(long, value_enum, default_value_t = Encoding::Auto)]
+        orientation_encoding: Encoding,
+        /// Read the table as a vendor report with fixed columns
+        #[arg(long, value_enum, default_value_t = TableFormat::Tsv)]
+        table_format: TableFormat,
+        /// Reads parsed and looked up in the table
+        #[arg(long, default_value_t = 10000)]
+        sample: u64,
+    },
+    /// Rebuild a restranded file from its input and a --delta-out patch: apply --original in.fq --delta run.delta
+    Apply {
+        /// The input the patch was made from (optionally .gz)
+        #[arg(long)]
+        original: String,
+        /// Patch written by --delta-out (optionally .gz)
+        #[arg(long)]
+        delta: String,
+        /// Output file (default: stdout; .gz compresses)
+        #[arg(short = 'o', long)]
+        out: Option<PathBuf>,
+    },
+    /// Reverse-complement a random fraction of reads and write the truth table, for benchmarking
+    Scramble {
+        /// Input FASTA/FASTQ (optionally .gz), or '-' for stdin
+        #[arg(short = 'f', long)]
+        fasta: String,
+        /// Input is FASTQ
+        #[arg(long)]
+        fastq: bool,
+        /// Output file (default: stdout; .gz compresses)
+        #[arg(short = 'o', long)]
+        out: Option<PathBuf>,
+        /// Where to write the true orientation of each output read
+        #[arg(long, value_name = "TSV")]
+        truth_out: PathBuf,
+        /// Fraction of reads to reverse-complement
+        #[arg(long, default_value_t = 0.5, value_parser = scramble::parse_fraction)]
+        fraction: f64,
+        /// Random seed; the same seed scrambles the same reads
+        #[arg(long, default_value_t = 1)]
+        seed: u64,
+    },
+    /// Generate synthetic reads with embedded primers/polyA and known orientations
+    Simulate {
+        /// Number of reads
+        #[arg(short = 'n', long, default_value_t = 1000)]
+        reads: u64,
+        /// Shortest insert between the adapters (uniformly distributed up to --max-length)
+        #[arg(long, default_value_t = 300)]
+        min_length: usize,
+        /// Longest insert between the adapters
+        #[arg(long, default_value_t = 3000)]
+        max_length: usize,
+        /// Primer FASTA (forward, then reverse) to embed at the read ends
+        #[arg(long, conflicts_with = "preset")]
+        primers: Option<PathBuf>,
+        /// Embed the primers of a library-prep kit preset
+        #[arg(long, value_enum)]
+        preset: Option<Preset>,
+        /// PolyA tail length after the insert (0 for none)
+        #[arg(long, default_value_t = 20)]
+        poly_a_length: usize,
+        /// Per-base error rate, split evenly between substitutions, insertions and deletions
+        #[arg(long, default_value_t = 0.0, value_parser = scramble::parse_fraction)]
+        error_rate: f64,
+        /// Fraction of reads emitted as the minus strand
+        #[arg(long, default_value_t = 0.5, value_parser = scramble::parse_fraction)]
+        minus_fraction: f64,
+        /// Write FASTQ (constant Q30 qualities) instead of FASTA
+        #[arg(long)]
+        fastq: bool,
+        /// Output file (default: stdout; .gz compresses)
+        #[arg(short = 'o', long)]
+        out: Option<PathBuf>,
+        /// Where to write the true orientation of each read
+        #[arg(long, value_name = "TSV")]
+        truth_out: PathBuf,
+        /// Random seed; the same seed produces the same reads
+        #[arg(long, default_value_t = 1)]
+        seed: u64,
+    },
+}
+
+impl Cli {
+    /// The sequence input; guaranteed by clap whenever no subcommand is given.
+    fn input(&self) -> &str {
+        self.fasta.first().expect("--fasta is required")
+    }
+
+    /// Whether reads are handled as pairs (`--out-r1`/`--out-interleaved`/`--merge-pairs`).
+    fn paired(&self) -> bool {
+        self.out_r1.is_some() || self.out_interleaved.is_some() || self.merge_pairs
+    }
+
+    /// ID column, orientation column and encoding of `--table`.
+    fn table_columns(&self) -> (&str, &str, Encoding) {
+        self.table_format.columns().unwrap_or((
+            &self.id_col,
+            &self.orientation_col,
+            self.orientation_encoding,
         ))
-    } else {
-        Box::new(File::open(table_path).with_context(|| format!("open {:?}", table_path))?)
+    }
+
+    /// Layout of the read outputs.
+    fn record_format(&self) -> RecordFormat {
+        match (self.out_format, self.fastq) {
+            (Some(format), _) => format,
+            (None, true) => RecordFormat::Fastq,
+            (None, false) => RecordFormat::Fasta,
+        }
+    }
+
+    /// `--max-memory`, spilling to `--tmpdir`/`RESTRAND_TMPDIR`/`TMPDIR` (else the system default).
+    fn budget(&self) -> table::Budget {
+        table::Budget {
+            max_memory: self.max_memory,
+            tmpdir: self.tmpdir.clone().unwrap_or_else(std::env::temp_dir),
+        }
+    }
+
+    /// Options for one input of a multi-input run (`--manifest`, `--watch`):
+    /// its own paths, quiet, with the summary left to the caller.
+    fn for_input(&self, input: &str, table: Option<PathBuf>, out: PathBuf) -> Cli {
+        let mut c = self.clone();
+        c.manifest = None;
+        c.watch = None;
+        c.out_dir = None;
+        c.input_dir = None;
+        c.fasta = vec![input.to_owned()];
+        if table != self.table {
+            c.shared_table = None;
+        }
+        c.table = table;
+        c.out = Some(out);
+        c.quiet = true;
+        c.summary_file = None;
+        c
+    }
+}
+
+/// Whether `path` is (or resolves to) a regular file rather than a pipe,
+/// terminal or other device.
+fn is_regular_file(path: &Path) -> bool {
+    fs::metadata(path).is_ok_and(|m| m.is_file())
+}
+
+fn open_writer(
+    path: &Option<PathBuf>,
+    buffer: Option<u64>,
+    manifest: &mut Option<Manifest>,
+) -> Result<Output> {
+    open_writer_with(path, buffer, None, false, manifest, &Timings::default())
+}
+
+/// Open one of the read outputs (`--out`, `--unchanged-out`,
+/// `--ambiguous-out`) as `--compress-level` and `--append` say.
+fn open_read_writer(
+    cli: &Cli,
+    path: &Option<PathBuf>,
+    manifest: &mut Option<Manifest>,
+    timings: &Timings,
+) -> Result<Output> {
+    if let (true, Some(p)) = (cli.append, path) {
+        if is_stdout_path(p) || remote::is_remote(p) {
+            bail!("--append needs a local output file, not {}", p.display());
+        }
+        append::check_boundary(p, cli.record_format())?;
+    }
+    open_writer_with(
+        path,
+        cli.write_buffer_size,
+        cli.compress_level,
+        cli.append,
+        manifest,
+        timings,
+    )
+}
+
+/// [`open_writer`] compressing `.gz` output at `level` and appending to an
+/// existing file when `append`.
+fn open_writer_with(
+    path: &Option<PathBuf>,
+    buffer: Option<u64>,
+    level: Option<u32>,
+    append: bool,
+    manifest: &mut Option<Manifest>,
+    timings: &Timings,
+) -> Result<Output> {
+    let path = path.as_deref().filter(|p| !is_stdout_path(p));
+    let (inner, upload, pipe): (Box<dyn Write>, _, _) = match path {
+        Some(p) if remote::is_remote(p) => {
+            let (upload, stdin) = remote::Upload::start(p)?;
+            (Box::new(stdin), Some(upload), true)
+        }
+        Some(p) if append => (Box::new(append::open(p)?), None, !is_regular_file(p)),
+        Some(p) => (Box::new(create_output(p)?), None, !is_regular_file(p)),
+        None => (
+            Box::new(io::stdout()),
+            None,
+            !is_regular_file(Path::new("/dev/stdout")),
+        ),
+    };
+    let capacity = output::buffer_size(buffer, pipe);
+    let gzip = path.is_some_and(output::is_gzip_path);
+    // Digest beneath the buffer and compressor so the manifest covers exactly the bytes on disk.
+    let inner: Box<dyn Write> = match manifest {
+        Some(m) => {
+            let name = path.map_or_else(|| "-".to_owned(), |p| p.display().to_string());
+            Box::new(m.wrap(name, inner))
+        }
+        None => inner,
     };
+    Ok(match upload {
+        Some(upload) => Output::uploading(inner, gzip, level, capacity, timings, upload),
+        None => Output::with_level(inner, gzip, level, capacity, timings),
+    })
+}
 
-    let mut reader = ReaderBuilder::new()
-        .delimiter(b'\t')
-        .has_headers(true)
-        .from_reader(rdr);
-
-    let headers = reader.headers().context("reading TSV headers")?.clone();
-
-    let id_idx = headers
-        .iter()
-        .position(|h| h == id_col)
-        .with_context(|| format!("column '{}' not found", id_col))?;
-    let ori_idx = headers
-        .iter()
-        .position(|h| h == orientation_col)
-        .with_context(|| format!("column '{}' not found", orientation_col))?;
-
-    let mut map = HashMap::with_capacity(1 << 16);
-    for rec in reader.records() {
-        let rec = rec?;
-        let id = rec.get(id_idx).unwrap().to_owned();
-        let ori_field = rec.get(ori_idx).unwrap().trim().as_bytes();
-        // Accept '+', '-', or words starting with those.
-        let ori = if !ori_field.is_empty() {
-            match ori_field[0] as char {
-                '+' => b'+',
-                '-' => b'-',
-                _ => {
-                    let s = String::from_utf8_lossy(ori_field).to_ascii_lowercase();
-                    if s.starts_with("plus") || s.starts_with("fwd") || s == "1" {
-                        b'+'
-                    } else if s.starts_with("minus")
-                        || s.starts_with("rev")
-                        || s == "0"
-                        || s == "rc"
-                    {
-                        b'-'
-                    } else {
-                        bail!("Unrecognized orientation value '{}' for read '{}'", s, id);
-                    }
-                }
+/// Resolve where the `--checksum` manifest goes, failing early if there is nowhere sensible.
+fn checksum_manifest_path(cli: &Cli) -> Result<Option<PathBuf>> {
+    let Some(algo) = cli.checksum else {
+        return Ok(None);
+    };
+    if let Some(p) = &cli.checksum_manifest {
+        return Ok(Some(p.clone()));
+    }
+    let out = (cli.out_r1.as_ref())
+        .or(cli.out_interleaved.as_ref())
+        .or(cli.out.as_ref());
+    match out.filter(|p| !is_stdout_path(p)) {
+        Some(out) if remote::is_remote(out) => {
+            bail!("--checksum with an s3:// or gs:// output requires --checksum-manifest")
+        }
+        Some(out) => {
+            let mut name = out.clone().into_os_string();
+            name.push(".");
+            name.push(algo.extension());
+            Ok(Some(PathBuf::from(name)))
+        }
+        None => bail!("--checksum with stdout output requires --checksum-manifest"),
+    }
+}
+
+/// Start a `--report` accumulator if one was requested.
+fn new_report(cli: &Cli, mode: &'static str, target: u8) -> Option<Report> {
+    cli.report.as_ref().map(|_| {
+        let mut report = Report::new(mode, target, cli.length_bin_width);
+        if cli.adapter_report {
+            report.enable_adapters();
+        }
+        report
+    })
+}
+
+/// Load `--table` (through `--table-cache` if given) as the `table_load`
+/// trace span; empty without a table.
+fn load_table(cli: &Cli, timings: &Timings, trace: &mut Trace) -> Result<(OrientationTable, u64)> {
+    let phase = Instant::now();
+    let (table, n_duplicates) = match (&cli.shared_table, &cli.table) {
+        (Some(shared), _) => shared.handle()?,
+        (None, Some(table)) => read_table(cli, table, timings)?,
+        (None, None) => (OrientationTable::empty(), 0),
+    };
+    trace.span(
+        "table_load",
+        phase,
+        serde_json::json!({ "on_disk": table.is_on_disk(), "duplicate_ids": n_duplicates }),
+    );
+    Ok((table, n_duplicates))
+}
+
+/// Load `--table` once for a batch whose inputs all look reads up in it.
+fn share_table(cli: &Cli) -> Result<Option<SharedTable>> {
+    let Some(table) = &cli.table else {
+        return Ok(None);
+    };
+    let (table, n_duplicates) = read_table(cli, table, &Timings::default())?;
+    Ok(Some(SharedTable::new(table, n_duplicates)))
+}
+
+/// Parse or map the table at `table`, noting on stderr when it went to disk
+/// or had duplicate IDs.
+fn read_table(cli: &Cli, table: &Path, timings: &Timings) -> Result<(OrientationTable, u64)> {
+    let (id_col, orientation_col, encoding) = cli.table_columns();
+    let (table, n_duplicates) = timings
+        .time(Stage::TableLoad, || match &cli.table_cache {
+            Some(cache) => table_cache::load(
+                table,
+                id_col,
+                orientation_col,
+                encoding,
+                &cli.budget(),
+                cache,
+                cli.quiet,
+            ),
+            None => table::load(table, id_col, orientation_col, encoding, &cli.budget()),
+        })
+        .context("loading orientation table")?;
+    if table.is_on_disk() && !cli.quiet {
+        eprintln!("note: orientation table exceeds --max-memory; looking reads up in a sorted on-disk copy");
+    }
+    if n_duplicates > 0 && !cli.quiet {
+        eprintln!(
+            "warning: {} duplicate read ID(s) in table; the last occurrence wins",
+            n_duplicates
+        );
+    }
+    Ok((table, n_duplicates))
+}
+
+/// Load an `--include-ids`/`--exclude-ids` list.
+fn load_ids(path: &str) -> Result<HashSet<String>> {
+    let buffer = output::buffer_size(None, false);
+    let text = open_text(path, buffer, &ByteCounter::default(), &Timings::new(false))?;
+    idlist::read(BufReader::new(text)).with_context(|| format!("loading ID list {}", path))
+}
+
+/// Per-group report statistics, when a grouping key was given.
+fn new_grouping(cli: &Cli) -> Result<Option<Grouping>> {
+    Grouping::from_options(
+        cli.table.as_deref(),
+        cli.table_columns().0,
+        cli.group_col.as_deref(),
+        cli.group_tag.as_deref(),
+    )
+}
+
+/// Label of a read's outcome in the report.
+fn action_label(ori: Option<u8>, target: u8) -> &'static str {
+    match ori {
+        Some(o) if o != target => "flipped",
+        Some(_) => "kept",
+        None => "unknown",
+    }
+}
+
+/// Arguments of the `records` trace span: counts and per-stage times so far.
+fn records_args(processed: u64, flipped: u64, timings: &Timings) -> serde_json::Value {
+    serde_json::json!({
+        "processed": processed,
+        "flipped": flipped,
+        "stages": timings.snapshot(),
+    })
+}
+
+/// Primers, polyA minimum, window and scoring from `--primers` or `--preset`.
+type PrimerSettings = (PrimerSet, Option<usize>, usize, PrimerScoring);
+
+/// The primer search settings, if primers were given.
+fn primer_settings(cli: &Cli) -> Result<Option<PrimerSettings>> {
+    let (primers, poly_a_min, window, min_identity) = match (&cli.primers, cli.preset) {
+        (Some(path), _) => (
+            PrimerSet::from_fasta(path)?,
+            None,
+            DEFAULT_PRIMER_WINDOW,
+            PrimerScoring::default().min_identity,
+        ),
+        (None, Some(preset)) => {
+            let spec = preset.spec();
+            (
+                spec.primers,
+                spec.poly_a_min,
+                spec.window,
+                spec.min_identity,
+            )
+        }
+        (None, None) => {
+            if cli.primer_debug.is_some() {
+                bail!("--primer-debug requires --primers or --preset");
             }
-        } else {
-            bail!("Empty orientation for read '{}'", id);
-        };
-        map.insert(id, ori);
+            if cli.primer_hits_out.is_some() {
+                bail!("--primer-hits-out requires --primers or --preset");
+            }
+            if cli.adapter_report {
+                bail!("--adapter-report requires --primers or --preset");
+            }
+            if cli.mask_primers.is_some() {
+                bail!("--mask-primers requires --primers or --preset");
+            }
+            if cli.split_on_internal_adapter {
+                bail!("--split-on-internal-adapter requires --primers or --preset");
+            }
+            return Ok(None);
+        }
+    };
+    let min_identity = cli.primer_min_identity.unwrap_or(min_identity);
+    if !(0.0..=1.0).contains(&min_identity) {
+        bail!(
+            "--primer-min-identity must be between 0 and 1, got {}",
+            min_identity
+        );
     }
-    Ok(map)
+    let window = cli.primer_window.map_or(window, |w| w as usize);
+    let scoring = PrimerScoring {
+        match_score: cli.primer_match,
+        mismatch: cli.primer_mismatch,
+        gap_open: cli.primer_gap_open,
+        gap_extend: cli.primer_gap_extend,
+        min_identity,
+        min_score: cli.primer_min_score,
+    };
+    Ok(Some((primers, poly_a_min, window, scoring)))
 }
 
-/// Extract orientation from FASTQ header (looks for "orientation:+" or "orientation:-")
-fn extract_orientation_from_header(header: &str) -> Option<u8> {
-    if let Some(start) = header.find("orientation:") {
-        let rest = &header[start + "orientation:".len()..];
-        if let Some(first_char) = rest.chars().next() {
-            return match first_char {
-                '+' => Some(b'+'),
-                '-' => Some(b'-'),
-                _ => None,
-            };
+/// Build the `--primers` detector if requested.
+fn new_primer_detector(cli: &Cli) -> Result<Option<PrimerDetector>> {
+    if cli.mask_primers == Some(PrimerMask::Lowercase) && cli.output_case != OutputCase::Preserve {
+        bail!("--mask-primers lowercase marks primers by letter case, so it cannot be combined with --output-case");
+    }
+    let Some((primers, poly_a_min, window, scoring)) = primer_settings(cli)? else {
+        return Ok(None);
+    };
+    let mut detector = PrimerDetector::new(primers, window, scoring);
+    if let Some(min_len) = poly_a_min {
+        detector = detector.with_poly_a(min_len);
+    }
+    if let Some(debug) = &cli.primer_debug {
+        detector = detector.with_debug(debug)?;
+    }
+    if let Some(hits) = &cli.primer_hits_out {
+        detector = detector.with_hits_out(hits)?;
+    }
+    Ok(Some(detector))
+}
+
+/// The primer search for `--split-on-internal-adapter`, if requested.
+fn internal_adapter_detector(cli: &Cli) -> Result<Option<PrimerDetector>> {
+    if !cli.split_on_internal_adapter {
+        return Ok(None);
+    }
+    let (primers, _, window, scoring) = primer_settings(cli)?.expect("primers were checked");
+    Ok(Some(PrimerDetector::new(primers, window, scoring)))
+}
+
+/// Barcode geometry for `--barcode-window`, if requested.
+fn barcode_layout(cli: &Cli) -> Result<Option<BarcodeLayout>> {
+    if !cli.barcode_window {
+        return Ok(None);
+    }
+    match cli.preset.and_then(|p| p.spec().barcode) {
+        Some(layout) => Ok(Some(layout)),
+        None => bail!("--barcode-window needs a preset with a cell-barcode layout (e.g. 10x-3p)"),
+    }
+}
+
+/// `cb_window=S-E umi_window=S-E` header field, empty when the adapter was not found.
+fn barcode_field(
+    layout: Option<&BarcodeLayout>,
+    call: Option<&PrimerCall>,
+    read_len: usize,
+    flipped: bool,
+    final_ori: Option<u8>,
+) -> String {
+    let (Some(layout), Some(call), Some(ori)) = (layout, call, final_ori) else {
+        return String::new();
+    };
+    match layout.locate(call, read_len, flipped, ori) {
+        Some(w) => format!(
+            " cb_window={}-{} umi_window={}-{}",
+            w.cb.0, w.cb.1, w.umi.0, w.umi.1
+        ),
+        None => String::new(),
+    }
+}
+
+/// Build the `--reference` minimizer sketch if requested.
+fn new_sketch(cli: &Cli) -> Result<Option<Sketch>> {
+    let Some(path) = &cli.reference else {
+        return Ok(None);
+    };
+    let params = SketchParams {
+        k: cli.minimizer_k,
+        w: cli.minimizer_w,
+        min_shared: cli.min_shared_minimizers,
+    };
+    Sketch::build(path, params).map(Some)
+}
+
+/// Primer detection as consensus evidence, when it made a call.
+fn primer_evidence(call: &PrimerCall) -> Option<Evidence> {
+    call.orientation.map(|orientation| Evidence {
+        source: Source::Primers,
+        orientation,
+        confidence: call.confidence(),
+    })
+}
+
+/// ` restrand_confidence=X` header field for `--confidence-in-header`.
+fn confidence_field(cli: &Cli, decision: Option<&Evidence>) -> String {
+    match decision {
+        Some(d) if cli.confidence_in_header => format!(" restrand_confidence={:.3}", d.confidence),
+        _ => String::new(),
+    }
+}
+
+/// `--emit-strand-tag` field giving the orientation a read is written in:
+/// the target when resolved, else what its own tag said.
+fn strand_tag_field(cli: &Cli, ori: Option<u8>, tag: Option<u8>, target: u8) -> String {
+    match (cli.emit_strand_tag, ori.map(|_| target).or(tag)) {
+        (Some(format), Some(ori)) => format.field(ori),
+        _ => String::new(),
+    }
+}
+
+/// Strip or update a header's orientation tag for output. Resolved reads
+/// leave in the target orientation; a read left unresolved by a conflict
+/// keeps its original tag value.
+fn rewrite_tag(cli: &Cli, header: &str, tag: u8, ori: Option<u8>, target: u8) -> String {
+    if cli.strip_orientation_tag || cli.emit_strand_tag.is_some() {
+        tags::strip(header)
+    } else {
+        tags::update(
+            header,
+            ori.map_or(tag, |_| target),
+            cli.rewrite_orientation_tag.as_deref(),
+        )
+    }
+}
+
+/// `--out`, `--unchanged-out` and `--ambiguous-out`, in that order.
+fn output_paths(cli: &Cli) -> [&Option<PathBuf>; 3] {
+    [&cli.out, &cli.unchanged_out, &cli.ambiguous_out]
+}
+
+/// Open the optional `--delta-out` writer for a `format` run.
+fn open_delta_writer(cli: &Cli, format: &str) -> Result<Option<DeltaWriter>> {
+    let Some(path) = &cli.delta_out else {
+        return Ok(None);
+    };
+    if cli.output_case != OutputCase::Preserve {
+        bail!("--delta-out records flips and headers only, so it cannot be combined with --output-case");
+    }
+    Ok(Some(DeltaWriter::create(path, format)?))
+}
+
+/// The first option given that only works with text output.
+fn text_only_option(cli: &Cli) -> Option<&'static str> {
+    let text_only = [
+        ("--unchanged-out", cli.unchanged_out.is_some()),
+        ("--ambiguous-out", cli.ambiguous_out.is_some()),
+        ("--out-r1", cli.out_r1.is_some()),
+        ("--out-interleaved", cli.out_interleaved.is_some()),
+        ("--append", cli.append),
+        ("--delta-out", cli.delta_out.is_some()),
+        ("--verify-ids", cli.verify_ids),
+        ("--faidx-out", cli.faidx_out.is_some()),
+        ("--provenance-comment", cli.provenance_comment),
+    ];
+    text_only
+        .into_iter()
+        .find_map(|(flag, set)| set.then_some(flag))
+}
+
+/// The `--out-format arrow|parquet` writer, if any, after refusing the
+/// options that need text output.
+fn open_columnar(cli: &Cli) -> Result<Option<ColumnarWriter>> {
+    let Some(format) = cli.out_format.filter(|f| columnar::is_columnar(*f)) else {
+        return Ok(None);
+    };
+    if let Some(flag) = text_only_option(cli) {
+        bail!(
+            "--out-format arrow/parquet writes one table to --out, so it cannot be combined with {}",
+            flag
+        );
+    }
+    Ok(Some(ColumnarWriter::new(format)?))
+}
+
+/// The first option given that regroups or splits reads, so that records
+/// are no longer processed one at a time as they are read.
+fn regrouping_option(cli: &Cli) -> Option<&'static str> {
+    let regrouping = [
+        ("a second input", cli.fasta.len() > 1),
+        ("--merge-pairs", cli.merge_pairs),
+        ("--split-on-internal-adapter", cli.split_on_internal_adapter),
+    ];
+    regrouping
+        .into_iter()
+        .find_map(|(flag, set)| set.then_some(flag))
+}
+
+/// The `--out-format bam` writer, if any. It writes each read's BAM record
+/// as the read is processed, so nothing may regroup or split reads.
+fn open_annotator(cli: &Cli, reads: &Reads) -> Result<Option<bam::Annotator>> {
+    if cli.out_format != Some(RecordFormat::Bam) {
+        return Ok(None);
+    }
+    let Reads::Bam(reader) = reads else {
+        bail!(
+            "--out-format bam patches the records of BAM input, and {} is not BAM",
+            cli.input()
+        );
+    };
+    if let Some(flag) = text_only_option(cli).or_else(|| regrouping_option(cli)) {
+        bail!(
+            "--out-format bam writes the input records back to --out, so it cannot be combined with {}",
+            flag
+        );
+    }
+    Ok(Some(reader.annotator(cli.bam_patch, cli.bam_patch_tag)?))
+}
+
+/// The `--bam-tag` evidence, if any, read from each BAM record as the read
+/// is processed.
+fn open_strands(cli: &Cli, reads: &Reads) -> Result<Option<bam::Strands>> {
+    let Some(tag) = cli.bam_tag else {
+        return Ok(None);
+    };
+    let Reads::Bam(reader) = reads else {
+        bail!(
+            "--bam-tag reads the aux tags of BAM input, and {} is not BAM",
+            cli.input()
+        );
+    };
+    if let Some(flag) = regrouping_option(cli) {
+        bail!(
+            "--bam-tag reads each BAM record's tag as the read is processed, so it cannot be combined with {}",
+            flag
+        );
+    }
+    Ok(Some(reader.strands(tag)?))
+}
+
+/// Open the `--out-r1`/`--out-r2` or `--out-interleaved` writers, if any.
+fn open_pairer(
+    cli: &Cli,
+    manifest: &mut Option<Manifest>,
+    timings: &Timings,
+) -> Result<Option<Pairer>> {
+    let (r1, r2) = match (&cli.out_r1, &cli.out_interleaved) {
+        (Some(_), _) => (
+            open_read_writer(cli, &cli.out_r1, manifest, timings)?,
+            Some(open_read_writer(cli, &cli.out_r2, manifest, timings)?),
+        ),
+        (None, Some(_)) => (
+            open_read_writer(cli, &cli.out_interleaved, manifest, timings)?,
+            None,
+        ),
+        (None, None) if cli.orphans_out.is_some() => {
+            bail!("--orphans-out requires --out-r1/--out-r2 or --out-interleaved")
+        }
+        (None, None) => return Ok(None),
+    };
+    let orphans_out = match cli.orphans_out {
+        Some(_) => Some(open_read_writer(cli, &cli.orphans_out, manifest, timings)?),
+        None => None,
+    };
+    Ok(Some(Pairer::new(r1, r2, orphans_out, cli.pair_window)))
+}
+
+/// Open the optional `--unchanged-out` writer.
+fn open_unchanged_writer(
+    cli: &Cli,
+    manifest: &mut Option<Manifest>,
+    timings: &Timings,
+) -> Result<Option<Output>> {
+    match cli.unchanged_out {
+        Some(_) => Ok(Some(open_read_writer(
+            cli,
+            &cli.unchanged_out,
+            manifest,
+            timings,
+        )?)),
+        None => Ok(None),
+    }
+}
+
+/// Open the `--ambiguous-out` writer that `--on-ambiguous separate-file` needs.
+fn open_ambiguous_writer(
+    cli: &Cli,
+    manifest: &mut Option<Manifest>,
+    timings: &Timings,
+) -> Result<Option<Output>> {
+    match (cli.on_ambiguous, &cli.ambiguous_out) {
+        (OnAmbiguous::SeparateFile, Some(_)) => Ok(Some(open_read_writer(
+            cli,
+            &cli.ambiguous_out,
+            manifest,
+            timings,
+        )?)),
+        (OnAmbiguous::SeparateFile, None) => {
+            bail!("--on-ambiguous separate-file requires --ambiguous-out")
         }
+        (_, Some(_)) => bail!("--ambiguous-out requires --on-ambiguous separate-file"),
+        (_, None) => Ok(None),
     }
-    None
 }
 
-/// Update header to change orientation:- to orientation:+
-fn update_orientation_in_header(header: &str) -> String {
-    header.replace("orientation:-", "orientation:+")
+/// Total bytes written to the `outputs` that were opened.
+fn bytes_written(outputs: &[Option<&Output>]) -> u64 {
+    outputs.iter().flatten().map(|o| o.bytes_written()).sum()
+}
+
+/// Reverse-complement `seq` in place (IUPAC codes, case kept), sparing the
+/// allocation `dna::revcomp` makes.
+fn revcomp_in_place(seq: &mut [u8]) {
+    seq.reverse();
+    for b in seq.iter_mut() {
+        *b = dna::complement(*b);
+    }
+}
+
+/// Removes temporary files when dropped.
+struct Cleanup(Vec<PathBuf>);
+
+impl Drop for Cleanup {
+    fn drop(&mut self) {
+        for p in &self.0 {
+            let _ = fs::remove_file(p);
+        }
+    }
+}
+
+/// Buffer size for reading the input: `--read-buffer-size`, else a default
+/// depending on whether it is a regular file.
+fn read_buffer(cli: &Cli) -> usize {
+    let input = cli.input();
+    let pipe = match input {
+        "-" => !is_regular_file(Path::new("/dev/stdin")),
+        _ => {
+            remote::sra_accession(input).is_some()
+                || remote::is_remote(Path::new(input))
+                || !is_regular_file(Path::new(input))
+        }
+    };
+    output::buffer_size(cli.read_buffer_size, pipe)
+}
+
+/// Open a text input for reading, decompressing `.gz`; compressed input is
+/// read in `buffer`-sized blocks. BAM is refused: only the reads are read
+/// from BAM, through [`open_reads`].
+fn open_text(
+    path: &str,
+    buffer: usize,
+    counter: &ByteCounter,
+    timings: &Timings,
+) -> Result<Box<dyn Read>> {
+    let (mut text, name) = open_input(path, buffer, counter, timings)?;
+    if bam::is_bam(&mut text)? {
+        bail!(
+            "{} is BAM, which is only read as the reads input (-f)",
+            name
+        );
+    }
+    Ok(Box::new(text))
+}
+
+/// Reads input, either text (FASTA, FASTQ or tabular) or BAM.
+enum Reads {
+    Text(Box<dyn BufRead>),
+    Bam(bam::Reader),
+}
+
+/// Open the reads input like [`open_text`], telling BAM apart by its magic.
+fn open_reads(
+    path: &str,
+    buffer: usize,
+    counter: &ByteCounter,
+    timings: &Timings,
+) -> Result<Reads> {
+    let (mut text, name) = open_input(path, buffer, counter, timings)?;
+    Ok(if bam::is_bam(&mut text)? {
+        Reads::Bam(bam::Reader::new(text, &name)?)
+    } else {
+        Reads::Text(text)
+    })
+}
+
+/// The decompressed input and its name for messages.
+fn open_input(
+    path: &str,
+    buffer: usize,
+    counter: &ByteCounter,
+    timings: &Timings,
+) -> Result<(Box<dyn BufRead>, String)> {
+    let (fh, name): (Box<dyn Read>, _) = if path == "-" {
+        (Box::new(io::stdin()), "stdin".to_owned())
+    } else if let Some(accession) = remote::sra_accession(path) {
+        (
+            Box::new(remote::Download::sra(accession)?),
+            format!("'{}'", path),
+        )
+    } else if remote::is_remote(Path::new(path)) {
+        (
+            Box::new(remote::Download::start(path)?),
+            format!("'{}'", path),
+        )
+    } else {
+        let file = File::open(path).with_context(|| format!("open '{}'", path))?;
+        (Box::new(file), format!("'{}'", path))
+    };
+    // Compression is told from the data rather than the name, as for tables
+    let fh = CountingReader::new(TimedReader::new(fh, timings, Stage::Read), counter);
+    let (codec, fh) = compress::sniff(fh).with_context(|| format!("read {}", name))?;
+    let fh = BufReader::with_capacity(buffer, fh);
+    let Some(codec) = codec else {
+        return Ok((Box::new(fh), name));
+    };
+    let fh = compress::decode(fh, codec, &name)?;
+    let text = BufReader::with_capacity(buffer, TimedReader::new(fh, timings, Stage::Decompress));
+    Ok((Box::new(text), name))
 }
 
-fn wrap_and_write<W: Write>(w: &mut W, seq: &[u8]) -> Result<()> {
-    for chunk in seq.chunks(FASTA_WRAP_WIDTH) {
-        w.write_all(chunk)?;
-        w.write_all(b"\n")?;
+/// The strand `--all` treats every read as being on.
+fn opposite(orientation: u8) -> u8 {
+    if orientation == b'+' {
+        b'-'
+    } else {
+        b'+'
     }
-    Ok(())
 }
 
-fn process_fastq(cli: &Cli, target: u8) -> Result<()> {
-    let handle = open_text(&cli.fasta)?;
-    let reader = fastq::Reader::new(handle);
-    let mut out = open_writer(&cli.out)?;
+fn process_fastq(cli: &Cli, target: u8) -> Result<Outcome> {
+    let started = Instant::now();
+    if twobit::is_twobit(cli.input()) {
+        bail!("2bit input holds no qualities; drop --fastq to read it in FASTA mode");
+    }
+    let mut trace = Trace::new(cli.trace_output.as_deref());
+    let timings = Timings::new(cli.stage_timings);
+    // An optional table adds evidence alongside the header tags
+    let (mut ori_map, n_duplicates) = load_table(cli, &timings, &mut trace)?;
+    let phase = Instant::now();
+    let bytes_read = ByteCounter::default();
+    let heartbeat = Heartbeat::new(cli.report_every, &bytes_read);
+    let buffer = read_buffer(cli);
+    let fastq_reader = |reads: Reads| -> Box<dyn FastqRead> {
+        match reads {
+            Reads::Bam(reader) => Box::new(reader),
+            Reads::Text(handle) if cli.tab_input => Box::new(tabular::Reader::new(handle)),
+            Reads::Text(handle) => Box::new(fastq::Reader::from_bufread(handle)),
+        }
+    };
+    let reads = open_reads(cli.input(), buffer, &bytes_read, &timings)?;
+    let mut annotator = open_annotator(cli, &reads)?;
+    let strands = open_strands(cli, &reads)?;
+    let mut reader = fastq_reader(reads);
+    if let [_, r2] = &cli.fasta[..] {
+        let r2 = open_reads(r2, buffer, &bytes_read, &timings)?;
+        reader = Box::new(pairing::Interleave::new(reader, fastq_reader(r2)));
+    }
+    let merges = MergeCounter::default();
+    if cli.merge_pairs {
+        reader = Box::new(Merger::new(reader, &merges));
+    }
+    let splits = SplitCounter::default();
+    if let Some(d) = internal_adapter_detector(cli)? {
+        reader = Box::new(Splitter::new(reader, d, &splits));
+    }
+    if cli.verify_ids {
+        verify::check_paths(&output_paths(cli))?;
+    }
+    let manifest_path = checksum_manifest_path(cli)?;
+    let mut manifest = cli.checksum.map(Manifest::new);
+    let mut pairs = open_pairer(cli, &mut manifest, &timings)?;
+    // Paired output leaves stdout unused, so it stays out of the manifest
+    let digest = if pairs.is_some() {
+        &mut None
+    } else {
+        &mut manifest
+    };
+    let mut out = open_read_writer(cli, &cli.out, digest, &timings)?;
+    let mut unchanged_out = open_unchanged_writer(cli, &mut manifest, &timings)?;
+    let mut ambiguous_out = open_ambiguous_writer(cli, &mut manifest, &timings)?;
+    let mut columns = open_columnar(cli)?;
+    let mut orphans_warned = false;
+    let mut report = new_report(cli, "fastq", target);
+    let grouping = new_grouping(cli)?;
+    if let Some(path) = &cli.provenance_out {
+        Provenance::new("fastq", cli.input(), cli.table.as_deref(), target).write(path)?;
+    }
+    let mut detector = new_primer_detector(cli)?;
+    let barcodes = barcode_layout(cli)?;
 
+    let include_ids = cli.include_ids.as_deref().map(load_ids).transpose()?;
+    let exclude_ids = cli.exclude_ids.as_deref().map(load_ids).transpose()?;
+    let mut delta = open_delta_writer(cli, "fastq")?;
+    let mut written = IdSet::default();
+    let mut n_read: u64 = 0;
     let mut n_total: u64 = 0;
     let mut n_flipped: u64 = 0;
+    let mut n_excluded: u64 = 0;
+    let mut n_masked: u64 = 0;
     let mut n_no_orientation: u64 = 0;
+    let mut n_missing: u64 = 0;
+    let mut n_duplex: u64 = 0;
+    let mut n_ambiguous: u64 = 0;
+    let mut n_primers: u64 = 0;
+    let mut n_unresolved: u64 = 0;
+    let (mut n_trimmed_reads, mut n_trimmed_leading, mut n_trimmed_trailing) = (0u64, 0u64, 0u64);
+    let mut sketch = new_sketch(cli)?;
+    let splice = cli
+        .paf
+        .as_deref()
+        .map(SpliceStrands::from_paf)
+        .transpose()?;
+    let mut consensus = Consensus::new(&cli.evidence_priority, cli.on_conflict);
+    let mut decision_log = cli
+        .decision_log
+        .as_deref()
+        .map(DecisionLog::create)
+        .transpose()?;
+    let mut explain = cli.explain.map(|n| Explainer::new(n, !cli.all));
+    trace.span("setup", phase, serde_json::json!({}));
 
-    for result in reader.records() {
-        let record = result.context("parsing FASTQ record")?;
+    let phase = Instant::now();
+    // Buffers reused across records so the loop does not allocate per read
+    let mut record = fastq::Record::new();
+    let (mut header, mut output_header, mut templated) =
+        (String::new(), String::new(), String::new());
+    let (mut seq, mut qual, mut out_buf) = (Vec::new(), Vec::new(), Vec::new());
+    loop {
+        timings
+            .time(Stage::Parse, || reader.read(&mut record))
+            .context("parsing FASTQ record")?;
+        if record.is_empty() {
+            break;
+        }
+        let index = n_read;
+        n_read += 1;
+        if include_ids
+            .as_ref()
+            .is_some_and(|ids| !ids.contains(record.id()))
+        {
+            continue;
+        }
+        if exclude_ids
+            .as_ref()
+            .is_some_and(|ids| ids.contains(record.id()))
+        {
+            n_excluded += 1;
+            continue;
+        }
         n_total += 1;
+        if let Some(hb) = &heartbeat {
+            hb.tick(n_total, n_flipped, pairs.as_ref().map(|p| p.orphans));
+        }
 
-        let id = record.id().to_string();
+        let id = record.id();
         let desc = record.desc().unwrap_or("");
-        let mut header = id.clone();
+        header.clear();
+        header.push_str(id);
         if !desc.is_empty() {
             header.push(' ');
             header.push_str(desc);
         }
+        let full_header = header.as_str();
 
-        // Extract orientation from header
-        let full_header = if desc.is_empty() {
-            id.as_str()
-        } else {
-            header.as_str()
-        };
+        if duplex::split(id).is_some() {
+            n_duplex += 1;
+            if cli.duplex == Duplex::Drop {
+                if let Some(e) = explain.as_mut() {
+                    e.skipped(id, "duplex read (--duplex drop)")?;
+                    if e.done() {
+                        break;
+                    }
+                }
+                continue;
+            }
+        }
+
+        let lookup = timings.start();
+        let table_ori = duplex::lookup(
+            &mut ori_map,
+            cli.match_on,
+            cli.duplex,
+            id,
+            full_header,
+            record.seq(),
+        )?;
+        if table_ori.is_none() && cli.table.is_some() {
+            n_missing += 1;
+            if cli.only_listed {
+                timings.stop(Stage::Lookup, lookup);
+                if let Some(e) = explain.as_mut() {
+                    e.skipped(id, "no row in the table (--only-listed)")?;
+                    if e.done() {
+                        break;
+                    }
+                }
+                continue;
+            }
+        }
+        let tag_ori = (!cli.all).then(|| tags::orientation(full_header)).flatten();
+        if tag_ori.is_none() && !cli.all {
+            n_no_orientation += 1;
+        }
+        let call = detector
+            .as_mut()
+            .map(|d| d.detect(record.id(), record.seq()))
+            .transpose()?;
+        let ambiguous = call.as_ref().is_some_and(PrimerCall::is_ambiguous);
+        let evidence: Vec<Evidence> = [
+            table_ori.map(|o| Evidence::asserted(Source::Table, o)),
+            tag_ori.map(|o| Evidence::asserted(Source::Tag, o)),
+            call.as_ref().and_then(primer_evidence),
+            sketch
+                .as_mut()
+                .and_then(|s| s.call(record.seq()))
+                .map(|c| Evidence {
+                    source: Source::Reference,
+                    orientation: c.orientation,
+                    confidence: c.confidence,
+                }),
+            splice
+                .as_ref()
+                .and_then(|s| s.call(record.id()))
+                .map(|c| Evidence {
+                    source: Source::Splice,
+                    orientation: c.orientation,
+                    confidence: c.confidence,
+                }),
+            strands
+                .as_ref()
+                .and_then(bam::Strands::call)
+                .map(|o| Evidence::asserted(Source::Bam, o)),
+            cli.all
+                .then(|| Evidence::asserted(Source::All, opposite(target))),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+        let decision = consensus.resolve(record.id(), &evidence)?;
+        let ori = decision.map(|d| d.orientation);
+        timings.stop(Stage::Lookup, lookup);
+        if let Some(log) = decision_log.as_mut() {
+            log.record(
+                record.id(),
+                decision.as_ref(),
+                action_label(ori, target),
+                ambiguous,
+            )?;
+        }
+        if let Some(e) = explain.as_mut() {
+            e.read(
+                cli,
+                record.id(),
+                &evidence,
+                call.as_ref(),
+                decision.as_ref(),
+                target,
+            )?;
+            if e.done() {
+                break;
+            }
+            continue;
+        }
+        if ambiguous {
+            n_ambiguous += 1;
+            if cli.on_ambiguous == OnAmbiguous::Drop {
+                continue;
+            }
+        }
+        if decision.is_some_and(|d| d.source == Source::Primers) {
+            n_primers += 1;
+        }
 
-        let ori = extract_orientation_from_header(full_header);
+        let flip = match ori {
+            Some(o) => o != target,
+            None => {
+                // No orientation found, keep as-is
+                n_unresolved += 1;
+                false
+            }
+        };
+        if let Some(r) = report.as_mut() {
+            r.observe(
+                record.seq().len(),
+                action_label(ori, target),
+                ori.map(|_| target),
+            );
+            if let Some(c) = &call {
+                r.observe_adapters(c, record.seq().len());
+            }
+            if let Some(g) = &grouping {
+                r.observe_group(g.group(id, full_header), action_label(ori, target), ori);
+            }
+        }
+        if (ori.is_none() && cli.drop_missing) || (cli.only_flipped && !flip) {
+            continue;
+        }
 
-        let mut seq = record.seq().to_vec();
-        let mut qual = record.qual().to_vec();
-        let mut output_header = header.clone();
+        seq.clear();
+        seq.extend_from_slice(record.seq());
+        qual.clear();
+        qual.extend_from_slice(record.qual());
+        output_header.clear();
+        output_header.push_str(&header);
+        if let (Some(how), Some(c)) = (cli.mask_primers, &call) {
+            n_masked += c.mask(&mut seq, how) as u64;
+        }
 
-        match ori {
-            Some(o) if o != target => {
-                // Need to flip
-                n_flipped += 1;
-                seq = dna::revcomp(&seq);
-                qual.reverse(); // Reverse quality scores to match reversed sequence
-                output_header = update_orientation_in_header(&output_header);
+        if flip {
+            n_flipped += 1;
+            timings.time(Stage::Revcomp, || revcomp_in_place(&mut seq));
+            qual.reverse(); // Reverse quality scores to match reversed sequence
+            if !cli.flipped_suffix.is_empty() {
+                output_header.insert_str(id.len(), &cli.flipped_suffix);
             }
-            Some(_) => {
-                // Already at target orientation, keep as-is
+        }
+        cli.output_case.apply(&mut seq);
+        if let Some(min_q) = cli.trim_qual {
+            let keep = quality::trim_ends(&qual, min_q);
+            let (leading, trailing) = (keep.start, qual.len() - keep.end);
+            if leading + trailing > 0 {
+                n_trimmed_reads += 1;
+                n_trimmed_leading += leading as u64;
+                n_trimmed_trailing += trailing as u64;
+                for buf in [&mut seq, &mut qual] {
+                    buf.truncate(keep.end);
+                    buf.drain(..keep.start);
+                }
             }
-            None => {
-                // No orientation tag found, keep as-is
-                n_no_orientation += 1;
+        }
+
+        if let Some(tag) = tag_ori {
+            output_header = rewrite_tag(cli, &output_header, tag, ori, target);
+        }
+        if cli.annotate {
+            let state = if flip { "flipped" } else { "kept" };
+            let source = decision.map_or("none", |d| d.source.as_str());
+            let _ = write!(output_header, " restrand={} source={}", state, source);
+        }
+        if let Some(t) = &cli.header_template {
+            let fields = HeaderFields {
+                id,
+                desc: output_header[id.len()..].trim_start(),
+                ori: ori.map(|_| target),
+                input_ori: ori,
+                flipped: flip,
+                source: decision.map_or("none", |d| d.source.as_str()),
+                len: seq.len(),
+            };
+            t.render(&fields, &mut templated);
+            std::mem::swap(&mut output_header, &mut templated);
+        }
+
+        output_header.push_str(&strand_tag_field(cli, ori, tag_ori, target));
+        output_header.push_str(&barcode_field(
+            barcodes.as_ref(),
+            call.as_ref(),
+            seq.len(),
+            flip,
+            ori.map(|_| target),
+        ));
+        output_header.push_str(&confidence_field(cli, decision.as_ref()));
+        if cli.mean_quality_tag {
+            if let Some(mq) = quality::mean_phred(&qual) {
+                let _ = write!(output_header, " mq={:.1}", mq);
             }
         }
 
+        if let Some(d) = delta.as_mut() {
+            d.record(index, id, record.desc(), flip, &output_header)?;
+        }
+        if cli.verify_ids {
+            written.add(&output_header);
+        }
+
         // Write FASTQ record
-        writeln!(out, "@{}", output_header)?;
-        out.write_all(&seq)?;
-        out.write_all(b"\n")?;
-        writeln!(out, "+")?;
-        out.write_all(&qual)?;
-        out.write_all(b"\n")?;
+        let w = match (ambiguous_out.as_mut(), unchanged_out.as_mut()) {
+            (Some(a), _) if ambiguous => a,
+            (_, Some(u)) if !flip => u,
+            _ => &mut out,
+        };
+        timings.time(Stage::Write, || -> Result<()> {
+            if let Some(c) = columns.as_mut() {
+                return c.push(
+                    &output_header,
+                    &seq,
+                    Some(&qual),
+                    ori.map(|_| target),
+                    flip,
+                    w,
+                );
+            }
+            if let Some(a) = annotator.as_mut() {
+                return a.push(ori, flip, w);
+            }
+            out_buf.clear();
+            match cli.out_format.unwrap_or(RecordFormat::Fastq) {
+                RecordFormat::Fastq => {
+                    output::push_fastq(&mut out_buf, &output_header, &seq, &qual)
+                }
+                RecordFormat::Fasta => {
+                    output::push_fasta(&mut out_buf, &output_header, &seq, FASTA_WRAP_WIDTH)
+                }
+                RecordFormat::Tab => {
+                    output::push_tab(&mut out_buf, &output_header, &seq, Some(&qual))
+                }
+                RecordFormat::Arrow | RecordFormat::Parquet | RecordFormat::Bam => {
+                    unreachable!("columnar and BAM output go through their own writers")
+                }
+            }
+            match pairs.as_mut() {
+                Some(p) => p.push(id, desc, &out_buf)?,
+                None => w.write_all(&out_buf)?,
+            }
+            Ok(())
+        })?;
+        // Say so as soon as pairing breaks down, not only in the closing summary
+        if let Some(p) = pairs.as_ref().filter(|p| p.orphans > 0 && !orphans_warned) {
+            orphans_warned = true;
+            if !cli.quiet {
+                eprintln!(
+                    "warning: read {} has lost its mate (none within --pair-window {} reads, or a second copy of the same mate); counting orphans",
+                    p.examples[0], cli.pair_window
+                );
+            }
+        }
     }
+    trace.span("records", phase, records_args(n_total, n_flipped, &timings));
 
-    eprintln!(
-        "FASTQ mode: processed={} flipped={} no_orientation_tag={}",
-        n_total, n_flipped, n_no_orientation
-    );
-
-    Ok(())
-}
-
-fn main() -> Result<()> {
-    let cli = Cli::parse();
+    let phase = Instant::now();
+    timings.time(Stage::Write, || -> Result<()> {
+        if let Some(c) = columns.as_mut() {
+            c.finish(&mut out)?;
+        }
+        if let Some(a) = annotator.as_mut() {
+            a.finish(&mut out)?;
+        }
+        out.finish()?;
+        if let Some(path) = &cli.gzi_out {
+            out.write_gzi(path)?;
+        }
+        if let Some(d) = delta.as_mut() {
+            d.finish(n_read)?;
+        }
+        if let Some(u) = unchanged_out.as_mut() {
+            u.finish()?;
+        }
+        if let Some(a) = ambiguous_out.as_mut() {
+            a.finish()?;
+        }
+        if let Some(p) = pairs.as_mut() {
+            p.finish()?;
+        }
+        Ok(())
+    })?;
+    let n_orphans = pairs.as_ref().map_or(0, |p| p.orphans);
+    if let Some(p) = pairs.as_ref().filter(|p| p.orphans > 0 && !cli.quiet) {
+        let fate = match &cli.orphans_out {
+            Some(path) => format!("written to {}", path.display()),
+            None => "left out of the paired output (--orphans-out keeps them)".to_owned(),
+        };
+        eprintln!(
+            "warning: {} read(s) without their mate, e.g. {}; {}",
+            p.orphans,
+            p.examples.join(", "),
+            fate
+        );
+    }
+    if let Some(d) = detector.as_mut() {
+        d.finish()?;
+    }
+    if let Some(log) = decision_log.as_mut() {
+        log.finish()?;
+    }
+    if let (Some(m), Some(path)) = (&manifest, &manifest_path) {
+        m.write(path)?;
+    }
+    if cli.verify_ids {
+        let format = cli.out_format.unwrap_or(RecordFormat::Fastq);
+        verify::verify(written, &output_paths(cli), format)?;
+    }
 
-    let target = match cli.target_orientation.as_str() {
-        "+" => b'+',
-        "-" => b'-',
-        other => bail!("--target-orientation must be '+' or '-', got '{}'", other),
+    let tally = Tally {
+        processed: n_total,
+        flipped: n_flipped,
+        excluded: n_excluded,
+        masked: n_masked,
+        missing: n_missing,
+        duplex: n_duplex,
+        ambiguous: n_ambiguous,
+        no_orientation: n_no_orientation,
+        primers: n_primers,
+        unresolved: n_unresolved,
+        duplicates: n_duplicates,
+        conflicts: consensus.conflicts(),
+        orphans: n_orphans,
     };
+    let tags = !cli.all;
+    let mut summary = tally.summary("fastq", cli, tags, detector.is_some(), &splits);
+    if cli.trim_qual.is_some() {
+        summary.field("trimmed_bases", n_trimmed_leading + n_trimmed_trailing);
+    }
+    if let Some(p) = &pairs {
+        summary.field("pairs", p.pairs).field("orphans", p.orphans);
+    }
+    if cli.merge_pairs {
+        summary
+            .field("merged_pairs", merges.merged())
+            .field("unmerged_pairs", merges.unmerged());
+    }
+    summary.emit(
+        cli.summary_file.as_deref(),
+        cli.summary_format,
+        cli.quiet || cli.explain.is_some(),
+    )?;
 
-    // If FASTQ mode, process as FASTQ
-    if cli.fastq {
-        return process_fastq(&cli, target);
+    if let (Some(mut r), Some(path)) = (report, cli.report.as_ref()) {
+        tally.report(&mut r, cli, tags, detector.is_some());
+        if cli.trim_qual.is_some() {
+            r.set_count("reads_trimmed", n_trimmed_reads);
+            r.set_count("trimmed_leading_bases", n_trimmed_leading);
+            r.set_count("trimmed_trailing_bases", n_trimmed_trailing);
+        }
+        let written = bytes_written(&[Some(&out), unchanged_out.as_ref(), ambiguous_out.as_ref()])
+            + delta.as_ref().map_or(0, DeltaWriter::bytes_written)
+            + pairs.as_ref().map_or(0, Pairer::bytes_written);
+        r.set_resources(Resources::measure(started, bytes_read.get(), written));
+        r.set_timings(timings.snapshot());
+        r.write(path)?;
     }
+    trace.span("finish", phase, serde_json::json!({}));
+    trace.write()?;
 
-    // FASTA mode requires a table
-    let table = cli
-        .table
-        .as_ref()
-        .context("--table is required for FASTA mode (or use --fastq for FASTQ mode)")?;
+    Ok(tally.outcome())
+}
 
-    let ori_map = load_orientation_map(table, &cli.id_col, &cli.orientation_col)
-        .context("loading orientation table")?;
-    let mut out = open_writer(&cli.out)?;
+fn process_fasta(cli: &Cli, target: u8) -> Result<Outcome> {
+    let started = Instant::now();
+    let mut trace = Trace::new(cli.trace_output.as_deref());
+    let timings = Timings::new(cli.stage_timings);
+    // FASTA mode requires a table unless primers or header tags can orient the reads
+    if cli.table.is_none()
+        && cli.primers.is_none()
+        && cli.preset.is_none()
+        && cli.reference.is_none()
+        && cli.paf.is_none()
+        && cli.bam_tag.is_none()
+        && !cli.use_orientation_tag
+        && !cli.all
+    {
+        bail!(
+            "--table (or --primers/--preset/--reference/--paf/--bam-tag/--use-orientation-tag) is required for FASTA mode (or use --fastq for FASTQ mode)"
+        );
+    }
+    let (mut ori_map, n_duplicates) = load_table(cli, &timings, &mut trace)?;
+    let phase = Instant::now();
+    if cli.verify_ids {
+        verify::check_paths(&output_paths(cli))?;
+    }
+    let manifest_path = checksum_manifest_path(cli)?;
+    let mut manifest = cli.checksum.map(Manifest::new);
+    let mut out = open_read_writer(cli, &cli.out, &mut manifest, &timings)?;
+    let mut unchanged_out = open_unchanged_writer(cli, &mut manifest, &timings)?;
+    let mut ambiguous_out = open_ambiguous_writer(cli, &mut manifest, &timings)?;
+    let mut report = new_report(cli, "fasta", target);
+    let grouping = new_grouping(cli)?;
+    let tab_out = match cli.out_format {
+        Some(RecordFormat::Fastq) => bail!("--out-format fastq needs FASTQ input (--fastq)"),
+        Some(RecordFormat::Tab) => true,
+        Some(
+            RecordFormat::Fasta | RecordFormat::Arrow | RecordFormat::Parquet | RecordFormat::Bam,
+        )
+        | None => false,
+    };
+    let mut columns = open_columnar(cli)?;
+    if tab_out && (cli.faidx_out.is_some() || cli.provenance_comment) {
+        bail!("--faidx-out and --provenance-comment need FASTA output, not --out-format tab");
+    }
+    if cli.faidx_out.is_some()
+        && cli.out.as_deref().is_some_and(output::is_gzip_path)
+        && cli.gzi_out.is_none()
+    {
+        bail!("--faidx-out with a .gz --out also needs --gzi-out, which samtools reads alongside the .fai");
+    }
+    let mut fai = cli
+        .faidx_out
+        .as_deref()
+        .map(|p| FaiIndex::create(p, FASTA_WRAP_WIDTH))
+        .transpose()?;
+    let mut liftover = (cli.agp_out.is_some()
+        || cli.chain_out.is_some()
+        || cli.remap_gff.is_some()
+        || cli.remap_vcf.is_some())
+    .then(Liftover::default);
+    // The VCF is read twice, so it cannot come from stdin
+    let mut anchors = match cli.remap_vcf.as_deref() {
+        Some("-") => bail!("--remap-vcf cannot read from stdin"),
+        Some(path) => {
+            let buffer = output::buffer_size(None, false);
+            let text = open_text(path, buffer, &ByteCounter::default(), &timings)?;
+            Some(
+                vcf::AnchorBases::scan(BufReader::new(text))
+                    .with_context(|| format!("scanning {}", path))?,
+            )
+        }
+        None => None,
+    };
+    let provenance = Provenance::new("fasta", cli.input(), cli.table.as_deref(), target);
+    if cli.provenance_comment {
+        let comment = provenance.comment();
+        out.write_all(comment.as_bytes())?;
+        if let Some(u) = unchanged_out.as_mut() {
+            u.write_all(comment.as_bytes())?;
+        }
+        if let Some(fai) = fai.as_mut() {
+            fai.skip(comment.len());
+        }
+    }
+    if let Some(path) = &cli.provenance_out {
+        provenance.write(path)?;
+    }
+    let mut detector = new_primer_detector(cli)?;
+    let barcodes = barcode_layout(cli)?;
 
-    // Open FASTA (plain or gz). Use '-' to read from stdin (plain).
-    let handle = open_text(&cli.fasta)?;
-    let reader = fasta::Reader::new(handle);
+    // Open FASTA (plain or gz), tabular, BAM or 2bit. Use '-' to read from stdin.
+    let bytes_read = ByteCounter::default();
+    let heartbeat = Heartbeat::new(cli.report_every, &bytes_read);
+    let (mut annotator, mut strands) = (None, None);
+    let mut reader: Box<dyn FastaRead> = if !cli.tab_input && twobit::is_twobit(cli.input()) {
+        if cli.out_format == Some(RecordFormat::Bam) {
+            bail!(
+                "--out-format bam patches the records of BAM input, and {} is 2bit",
+                cli.input()
+            );
+        }
+        if cli.bam_tag.is_some() {
+            bail!(
+                "--bam-tag reads the aux tags of BAM input, and {} is 2bit",
+                cli.input()
+            );
+        }
+        if remote::is_remote(Path::new(cli.input())) {
+            bail!(
+                "2bit input needs random access; copy {} locally first",
+                cli.input()
+            );
+        }
+        Box::new(twobit::Reader::from_path(
+            Path::new(cli.input()),
+            &bytes_read,
+        )?)
+    } else {
+        let buffer = read_buffer(cli);
+        let reads = open_reads(cli.input(), buffer, &bytes_read, &timings)?;
+        annotator = open_annotator(cli, &reads)?;
+        strands = open_strands(cli, &reads)?;
+        match reads {
+            Reads::Bam(reader) => Box::new(reader),
+            Reads::Text(handle) if cli.tab_input => Box::new(tabular::Reader::new(handle)),
+            Reads::Text(handle) => Box::new(fasta::Reader::from_bufread(handle)),
+        }
+    };
+    let splits = SplitCounter::default();
+    if let Some(d) = internal_adapter_detector(cli)? {
+        reader = Box::new(Splitter::new(reader, d, &splits));
+    }
 
+    let include_ids = cli.include_ids.as_deref().map(load_ids).transpose()?;
+    let exclude_ids = cli.exclude_ids.as_deref().map(load_ids).transpose()?;
+    let mut delta = open_delta_writer(cli, "fasta")?;
+    // Bytes of --remap-gff-out/--remap-vcf-out, written at the end
+    let mut n_remapped: u64 = 0;
+    let mut written = IdSet::default();
+    let mut n_read: u64 = 0;
     let mut n_total: u64 = 0;
     let mut n_flipped: u64 = 0;
+    let mut n_excluded: u64 = 0;
+    let mut n_masked: u64 = 0;
     let mut n_missing: u64 = 0;
+    let mut n_duplex: u64 = 0;
+    let mut n_ambiguous: u64 = 0;
+    let mut n_no_orientation: u64 = 0;
+    let mut n_primers: u64 = 0;
+    let mut n_unresolved: u64 = 0;
+    let mut sketch = new_sketch(cli)?;
+    let splice = cli
+        .paf
+        .as_deref()
+        .map(SpliceStrands::from_paf)
+        .transpose()?;
+    let mut consensus = Consensus::new(&cli.evidence_priority, cli.on_conflict);
+    let mut decision_log = cli
+        .decision_log
+        .as_deref()
+        .map(DecisionLog::create)
+        .transpose()?;
+    let mut explain = cli
+        .explain
+        .map(|n| Explainer::new(n, cli.use_orientation_tag));
+    trace.span("setup", phase, serde_json::json!({}));
 
-    for result in reader.records() {
-        let record = result.context("parsing FASTA record")?;
+    let phase = Instant::now();
+    // Buffers reused across records so the loop does not allocate per read
+    let mut record = fasta::Record::new();
+    let (mut header, mut seq, mut out_buf) = (String::new(), Vec::new(), Vec::new());
+    let mut templated = String::new();
+    loop {
+        timings
+            .time(Stage::Parse, || reader.read(&mut record))
+            .context("parsing FASTA record")?;
+        if record.is_empty() {
+            break;
+        }
+        let index = n_read;
+        n_read += 1;
+        if include_ids
+            .as_ref()
+            .is_some_and(|ids| !ids.contains(record.id()))
+        {
+            continue;
+        }
+        if exclude_ids
+            .as_ref()
+            .is_some_and(|ids| ids.contains(record.id()))
+        {
+            n_excluded += 1;
+            continue;
+        }
         n_total += 1;
+        if let Some(hb) = &heartbeat {
+            hb.tick(n_total, n_flipped, None);
+        }
 
-        let id = record.id().to_string();
+        let id = record.id();
         let desc = record.desc().unwrap_or("");
-        let mut header = id.clone();
+        header.clear();
+        header.push_str(id);
         if !desc.is_empty() {
             header.push(' ');
             header.push_str(desc);
         }
 
-        // Decide action
-        let action = match ori_map.get(&id) {
-            Some(&ori) => {
-                if ori == target {
+        // Decide action from the table, header tag and primer evidence
+        if duplex::split(id).is_some() {
+            n_duplex += 1;
+            if cli.duplex == Duplex::Drop {
+                if let Some(e) = explain.as_mut() {
+                    e.skipped(id, "duplex read (--duplex drop)")?;
+                    if e.done() {
+                        break;
+                    }
+                }
+                continue;
+            }
+        }
+
+        let lookup = timings.start();
+        let table_ori = duplex::lookup(
+            &mut ori_map,
+            cli.match_on,
+            cli.duplex,
+            id,
+            &header,
+            record.seq(),
+        )?;
+        if table_ori.is_none() && cli.table.is_some() {
+            n_missing += 1;
+            if cli.only_listed {
+                timings.stop(Stage::Lookup, lookup);
+                if let Some(e) = explain.as_mut() {
+                    e.skipped(id, "no row in the table (--only-listed)")?;
+                    if e.done() {
+                        break;
+                    }
+                }
+                continue;
+            }
+        }
+        let tag_ori = cli
+            .use_orientation_tag
+            .then(|| tags::orientation(&header))
+            .flatten();
+        if cli.use_orientation_tag && tag_ori.is_none() {
+            n_no_orientation += 1;
+        }
+        let call = detector
+            .as_mut()
+            .map(|d| d.detect(record.id(), record.seq()))
+            .transpose()?;
+        let ambiguous = call.as_ref().is_some_and(PrimerCall::is_ambiguous);
+        let evidence: Vec<Evidence> = [
+            table_ori.map(|o| Evidence::asserted(Source::Table, o)),
+            tag_ori.map(|o| Evidence::asserted(Source::Tag, o)),
+            call.as_ref().and_then(primer_evidence),
+            sketch
+                .as_mut()
+                .and_then(|s| s.call(record.seq()))
+                .map(|c| Evidence {
+                    source: Source::Reference,
+                    orientation: c.orientation,
+                    confidence: c.confidence,
+                }),
+            splice
+                .as_ref()
+                .and_then(|s| s.call(record.id()))
+                .map(|c| Evidence {
+                    source: Source::Splice,
+                    orientation: c.orientation,
+                    confidence: c.confidence,
+                }),
+            strands
+                .as_ref()
+                .and_then(bam::Strands::call)
+                .map(|o| Evidence::asserted(Source::Bam, o)),
+            cli.all
+                .then(|| Evidence::asserted(Source::All, opposite(target))),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+        let decision = consensus.resolve(record.id(), &evidence)?;
+        let ori = decision.map(|d| d.orientation);
+        timings.stop(Stage::Lookup, lookup);
+        if let Some(log) = decision_log.as_mut() {
+            log.record(
+                record.id(),
+                decision.as_ref(),
+                action_label(ori, target),
+                ambiguous,
+            )?;
+        }
+        if let Some(e) = explain.as_mut() {
+            e.read(
+                cli,
+                record.id(),
+                &evidence,
+                call.as_ref(),
+                decision.as_ref(),
+                target,
+            )?;
+            if e.done() {
+                break;
+            }
+            continue;
+        }
+        if ambiguous {
+            n_ambiguous += 1;
+            if cli.on_ambiguous == OnAmbiguous::Drop {
+                continue;
+            }
+        }
+        let source = decision.map_or("none", |d| d.source.as_str());
+        if decision.is_some_and(|d| d.source == Source::Primers) {
+            n_primers += 1;
+        }
+        if let Some(r) = report.as_mut() {
+            r.observe(
+                record.seq().len(),
+                action_label(ori, target),
+                ori.map(|_| target),
+            );
+            if let Some(c) = &call {
+                r.observe_adapters(c, record.seq().len());
+            }
+            if let Some(g) = &grouping {
+                let key = cli.match_on.key(id, &header);
+                r.observe_group(g.group(key, &header), action_label(ori, target), ori);
+            }
+        }
+        let action = match ori {
+            Some(o) => {
+                if o == target {
                     "keep"
                 } else {
                     "flip"
                 }
             }
             None => {
+                n_unresolved += 1;
                 if cli.drop_missing {
-                    n_missing += 1;
                     continue; // skip this record
                 } else {
                     "keep"
@@ -297,30 +2546,561 @@ fn main() -> Result<()> {
             }
         };
 
-        // Sequence handling
-        let mut seq = record.seq().to_vec();
+        if cli.only_flipped && action != "flip" {
+            continue;
+        }
+
+        if let Some(tag) = tag_ori {
+            header = rewrite_tag(cli, &header, tag, ori, target);
+        } else if cli.emit_strand_tag.is_some() {
+            header = tags::strip(&header);
+        }
+
+        // Sequence handling; huge records are streamed out of the parsed
+        // record rather than copied
+        let seq_len = record.seq().len();
+        let streamed = seq_len > STREAM_RECORD_LEN
+            && !tab_out
+            && columns.is_none()
+            && annotator.is_none()
+            && cli.mask_primers.is_none();
+        if !streamed {
+            seq.clear();
+            seq.extend_from_slice(record.seq());
+        }
+        if let (Some(how), Some(c)) = (cli.mask_primers, &call) {
+            n_masked += c.mask(&mut seq, how) as u64;
+        }
         if action == "flip" {
             n_flipped += 1;
-            seq = dna::revcomp(&seq);
+            if !streamed {
+                timings.time(Stage::Revcomp, || revcomp_in_place(&mut seq));
+            }
             if !cli.flipped_suffix.is_empty() {
-                header.push_str(&cli.flipped_suffix);
+                if casava::is_comment(desc) && header.starts_with(record.id()) {
+                    // On the ID, so the comment's mate and index stay intact
+                    header.insert_str(record.id().len(), &cli.flipped_suffix);
+                } else {
+                    header.push_str(&cli.flipped_suffix);
+                }
             }
         }
+        if !streamed {
+            cli.output_case.apply(&mut seq);
+        }
+        if cli.annotate {
+            let state = if action == "flip" { "flipped" } else { "kept" };
+            let _ = write!(header, " restrand={} source={}", state, source);
+        }
+        if let Some(t) = &cli.header_template {
+            let fields = HeaderFields {
+                id: record.id(),
+                desc: header[record.id().len()..].trim_start(),
+                ori: ori.map(|_| target),
+                input_ori: ori,
+                flipped: action == "flip",
+                source,
+                len: seq_len,
+            };
+            t.render(&fields, &mut templated);
+            std::mem::swap(&mut header, &mut templated);
+        }
+        header.push_str(&strand_tag_field(cli, ori, tag_ori, target));
+        header.push_str(&barcode_field(
+            barcodes.as_ref(),
+            call.as_ref(),
+            seq_len,
+            action == "flip",
+            ori.map(|_| target),
+        ));
+        header.push_str(&confidence_field(cli, decision.as_ref()));
+
+        if let Some(l) = liftover.as_mut() {
+            l.add(record.id(), &header, seq_len, action == "flip");
+        }
+        if let Some(a) = anchors.as_mut().filter(|_| action == "flip") {
+            a.collect(record.id(), record.seq());
+        }
+
+        if let Some(d) = delta.as_mut() {
+            d.record(index, record.id(), record.desc(), action == "flip", &header)?;
+        }
+        if cli.verify_ids {
+            written.add(&header);
+        }
 
         // Emit FASTA with wrapping
-        writeln!(out, ">{}", header)?;
-        wrap_and_write(&mut out, &seq)?;
+        timings.time(Stage::Write, || -> Result<()> {
+            let w = match (ambiguous_out.as_mut(), unchanged_out.as_mut()) {
+                (Some(a), _) if ambiguous => a,
+                (_, Some(u)) if action != "flip" => u,
+                _ => {
+                    if let Some(fai) = fai.as_mut() {
+                        fai.add(&header, seq_len)?;
+                    }
+                    &mut out
+                }
+            };
+            if streamed {
+                let case = |line: &mut [u8]| cli.output_case.apply(line);
+                output::write_fasta_streamed(
+                    w,
+                    &header,
+                    record.seq(),
+                    FASTA_WRAP_WIDTH,
+                    action == "flip",
+                    case,
+                )?;
+                return Ok(());
+            }
+            if let Some(c) = columns.as_mut() {
+                return c.push(
+                    &header,
+                    &seq,
+                    None,
+                    ori.map(|_| target),
+                    action == "flip",
+                    w,
+                );
+            }
+            if let Some(a) = annotator.as_mut() {
+                return a.push(ori, action == "flip", w);
+            }
+            out_buf.clear();
+            if tab_out {
+                output::push_tab(&mut out_buf, &header, &seq, None);
+            } else {
+                output::push_fasta(&mut out_buf, &header, &seq, FASTA_WRAP_WIDTH);
+            }
+            w.write_all(&out_buf)?;
+            Ok(())
+        })?;
+    }
+    trace.span("records", phase, records_args(n_total, n_flipped, &timings));
+
+    let phase = Instant::now();
+    timings.time(Stage::Write, || -> Result<()> {
+        if let Some(fai) = fai.as_mut() {
+            fai.finish()?;
+        }
+        if let Some(l) = &liftover {
+            if let Some(path) = &cli.agp_out {
+                l.write_agp(path)?;
+            }
+            if let Some(path) = &cli.chain_out {
+                l.write_chain(path)?;
+            }
+            if let (Some(input), Some(output)) = (&cli.remap_gff, &cli.remap_gff_out) {
+                let buffer = output::buffer_size(None, false);
+                let text = open_text(input, buffer, &ByteCounter::default(), &timings)?;
+                let mut gff_out = open_writer(&Some(output.clone()), None, &mut None)?;
+                gff::remap(BufReader::new(text), &mut gff_out, l)
+                    .with_context(|| format!("remapping {}", input))?;
+                gff_out.finish()?;
+                n_remapped += gff_out.bytes_written();
+            }
+            if let (Some(input), Some(output), Some(anchors)) =
+                (&cli.remap_vcf, &cli.remap_vcf_out, &anchors)
+            {
+                let buffer = output::buffer_size(None, false);
+                let text = open_text(input, buffer, &ByteCounter::default(), &timings)?;
+                let mut vcf_out = open_writer(&Some(output.clone()), None, &mut None)?;
+                vcf::remap(BufReader::new(text), &mut vcf_out, l, anchors)
+                    .with_context(|| format!("remapping {}", input))?;
+                vcf_out.finish()?;
+                n_remapped += vcf_out.bytes_written();
+            }
+        }
+        if let Some(c) = columns.as_mut() {
+            c.finish(&mut out)?;
+        }
+        if let Some(a) = annotator.as_mut() {
+            a.finish(&mut out)?;
+        }
+        out.finish()?;
+        if let Some(path) = &cli.gzi_out {
+            out.write_gzi(path)?;
+        }
+        if let Some(d) = delta.as_mut() {
+            d.finish(n_read)?;
+        }
+        if let Some(u) = unchanged_out.as_mut() {
+            u.finish()?;
+        }
+        if let Some(a) = ambiguous_out.as_mut() {
+            a.finish()?;
+        }
+        Ok(())
+    })?;
+    if let Some(d) = detector.as_mut() {
+        d.finish()?;
+    }
+    if let Some(log) = decision_log.as_mut() {
+        log.finish()?;
+    }
+    if let (Some(m), Some(path)) = (&manifest, &manifest_path) {
+        m.write(path)?;
+    }
+    if cli.verify_ids {
+        let format = if tab_out {
+            RecordFormat::Tab
+        } else {
+            RecordFormat::Fasta
+        };
+        verify::verify(written, &output_paths(cli), format)?;
     }
 
     // Progress to stderr
-    eprintln!(
-        "FASTA mode: processed={} flipped={} missing_in_table={} ({} mode) | wrap={} cols",
-        n_total,
-        n_flipped,
-        n_missing,
-        if cli.drop_missing { "dropped" } else { "kept" },
-        FASTA_WRAP_WIDTH
-    );
+    let tally = Tally {
+        processed: n_total,
+        flipped: n_flipped,
+        excluded: n_excluded,
+        masked: n_masked,
+        missing: n_missing,
+        duplex: n_duplex,
+        ambiguous: n_ambiguous,
+        no_orientation: n_no_orientation,
+        primers: n_primers,
+        unresolved: n_unresolved,
+        duplicates: n_duplicates,
+        conflicts: consensus.conflicts(),
+        orphans: 0,
+    };
+    let tags = cli.use_orientation_tag;
+    tally
+        .summary("fasta", cli, tags, detector.is_some(), &splits)
+        .human_suffix(format!(
+            " ({} mode) | wrap={} cols",
+            if cli.drop_missing { "dropped" } else { "kept" },
+            FASTA_WRAP_WIDTH
+        ))
+        .emit(
+            cli.summary_file.as_deref(),
+            cli.summary_format,
+            cli.quiet || cli.explain.is_some(),
+        )?;
+
+    if let (Some(mut r), Some(path)) = (report, cli.report.as_ref()) {
+        tally.report(&mut r, cli, tags, detector.is_some());
+        let written = bytes_written(&[Some(&out), unchanged_out.as_ref(), ambiguous_out.as_ref()])
+            + delta.as_ref().map_or(0, DeltaWriter::bytes_written)
+            + n_remapped;
+        r.set_resources(Resources::measure(started, bytes_read.get(), written));
+        r.set_timings(timings.snapshot());
+        r.write(path)?;
+    }
+    trace.span("finish", phase, serde_json::json!({}));
+    trace.write()?;
+
+    Ok(tally.outcome())
+}
+
+fn run(cli: &Cli) -> Result<Outcome> {
+    let target = match cli.target_orientation.as_str() {
+        "+" => b'+',
+        "-" => b'-',
+        other => bail!("--target-orientation must be '+' or '-', got '{}'", other),
+    };
+    if cli.gzi_out.is_some() && !cli.out.as_deref().is_some_and(output::is_gzip_path) {
+        bail!("--gzi-out needs a .gz --out");
+    }
+    if cli.preset.is_some_and(Preset::is_pacbio) && cli.flipped_suffix.contains('/') && !cli.quiet {
+        eprintln!(
+            "warning: --flipped-suffix '{}' adds a '/' field to PacBio movie/zmw/... read names; consider e.g. '_rc'",
+            cli.flipped_suffix
+        );
+    }
+
+    if cli.self_test {
+        if twobit::is_twobit(cli.input()) || cli.tab_input {
+            bail!("--self-test reads FASTA or FASTQ text, not 2bit or --tab-input");
+        }
+        return selftest::self_test(cli);
+    }
+
+    let mut outcome = if cli.fastq {
+        process_fastq(cli, target)?
+    } else {
+        process_fasta(cli, target)?
+    };
+    if let Some(min) = cli.min_found_fraction {
+        let found = outcome.processed - outcome.missing;
+        if outcome.processed > 0 && (found as f64) < min * outcome.processed as f64 {
+            bail!(
+                "only {} of {} reads ({:.1}%) were found in the table, below --min-found-fraction {}; is this the right table for {}?",
+                found,
+                outcome.processed,
+                100.0 * found as f64 / outcome.processed as f64,
+                min,
+                cli.input()
+            );
+        }
+    }
+    if outcome.processed > 0 {
+        let share = outcome.flipped as f64 / outcome.processed as f64;
+        let message = |limit: f64, option: &str| {
+            format!(
+                "{} of {} reads ({:.1}%) were flipped, above {} {}; is --target-orientation {} right for {}?",
+                outcome.flipped,
+                outcome.processed,
+                100.0 * share,
+                option,
+                limit,
+                cli.target_orientation,
+                cli.input()
+            )
+        };
+        if let Some(max) = cli.max_flip_fraction.filter(|&max| share > max) {
+            bail!("{}", message(max, "--max-flip-fraction"));
+        }
+        if let Some(warn) = cli.warn_flip_fraction.filter(|&warn| share > warn) {
+            if !cli.quiet {
+                eprintln!("warning: {}", message(warn, "--warn-flip-fraction"));
+            }
+            outcome.warnings += 1;
+        }
+    }
+    Ok(outcome)
+}
 
-    Ok(())
+fn main() -> ExitCode {
+    let mut cli = Cli::parse();
+    if let Some(accession) = &cli.sra {
+        cli.fasta = vec![remote::sra_input(accession)];
+    }
+    if let Some(Command::Completions { shell }) = &cli.command {
+        let mut cmd = Cli::command();
+        let name = cmd.get_name().to_owned();
+        clap_complete::generate(*shell, &mut cmd, name, &mut io::stdout());
+        return ExitCode::SUCCESS;
+    }
+    if let Some(Command::Evaluate {
+        truth,
+        truth_id_col,
+        truth_orientation_col,
+        args,
+    }) = &cli.command
+    {
+        let opts = evaluate::EvaluateOptions {
+            truth,
+            id_col: truth_id_col,
+            orientation_col: truth_orientation_col,
+            args,
+        };
+        return match evaluate::evaluate(&opts) {
+            Ok(()) => ExitCode::SUCCESS,
+            Err(e) => {
+                eprintln!("Error: {:?}", e);
+                ExitCode::FAILURE
+            }
+        };
+    }
+    if let Some(Command::Diff {
+        original,
+        restranded,
+        fastq,
+        out,
+        flipped_suffix,
+    }) = &cli.command
+    {
+        let opts = diff::DiffOptions {
+            original,
+            restranded,
+            fastq: *fastq,
+            out,
+            flipped_suffix,
+        };
+        return match diff::diff(&opts) {
+            Ok(()) => ExitCode::SUCCESS,
+            Err(e) => {
+                eprintln!("Error: {:?}", e);
+                ExitCode::FAILURE
+            }
+        };
+    }
+    if let Some(Command::Lint {
+        table,
+        fasta,
+        fastq,
+        id_col,
+        orientation_col,
+        orientation_encoding,
+        table_format,
+        sample,
+    }) = &cli.command
+    {
+        let opts = lint::LintOptions {
+            table: table.as_deref(),
+            reads: fasta.as_deref(),
+            fastq: *fastq,
+            id_col,
+            orientation_col,
+            encoding: *orientation_encoding,
+            table_format: *table_format,
+            sample: *sample,
+        };
+        return match lint::lint(&opts) {
+            Ok(true) => ExitCode::SUCCESS,
+            Ok(false) => ExitCode::FAILURE,
+            Err(e) => {
+                eprintln!("Error: {:?}", e);
+                ExitCode::FAILURE
+            }
+        };
+    }
+    if let Some(Command::Apply {
+        original,
+        delta,
+        out,
+    }) = &cli.command
+    {
+        let opts = delta::ApplyOptions {
+            original,
+            delta,
+            out,
+        };
+        return match delta::apply(&opts) {
+            Ok(()) => ExitCode::SUCCESS,
+            Err(e) => {
+                eprintln!("Error: {:?}", e);
+                ExitCode::FAILURE
+            }
+        };
+    }
+    if let Some(Command::Scramble {
+        fasta,
+        fastq,
+        out,
+        truth_out,
+        fraction,
+        seed,
+    }) = &cli.command
+    {
+        let opts = scramble::ScrambleOptions {
+            input: fasta,
+            fastq: *fastq,
+            out,
+            truth_out,
+            fraction: *fraction,
+            seed: *seed,
+        };
+        return match scramble::scramble(&opts) {
+            Ok(()) => ExitCode::SUCCESS,
+            Err(e) => {
+                eprintln!("Error: {:?}", e);
+                ExitCode::FAILURE
+            }
+        };
+    }
+    if let Some(Command::Simulate {
+        reads,
+        min_length,
+        max_length,
+        primers,
+        preset,
+        poly_a_length,
+        error_rate,
+        minus_fraction,
+        fastq,
+        out,
+        truth_out,
+        seed,
+    }) = &cli.command
+    {
+        let result = match (primers, preset) {
+            (Some(path), _) => PrimerSet::from_fasta(path).map(Some),
+            (None, Some(preset)) => Ok(Some(preset.spec().primers)),
+            (None, None) => Ok(None),
+        }
+        .and_then(|primers| {
+            simulate::simulate(&simulate::SimulateOptions {
+                reads: *reads,
+                min_length: *min_length,
+                max_length: *max_length,
+                primers,
+                poly_a_length: *poly_a_length,
+                error_rate: *error_rate,
+                minus_fraction: *minus_fraction,
+                fastq: *fastq,
+                out,
+                truth_out,
+                seed: *seed,
+            })
+        });
+        return match result {
+            Ok(()) => ExitCode::SUCCESS,
+            Err(e) => {
+                eprintln!("Error: {:?}", e);
+                ExitCode::FAILURE
+            }
+        };
+    }
+    #[cfg(feature = "server")]
+    if let Some(Command::Serve {
+        listen,
+        table,
+        max_requests,
+        tmpdir,
+        metrics_listen,
+    }) = &cli.command
+    {
+        let opts = serve::ServeOptions {
+            listen,
+            table: table.as_deref(),
+            max_requests: *max_requests,
+            tmpdir: tmpdir.clone().unwrap_or_else(std::env::temp_dir),
+            metrics_listen: metrics_listen.as_deref(),
+        };
+        return match serve::serve(&opts) {
+            Ok(()) => ExitCode::SUCCESS,
+            Err(e) => {
+                eprintln!("Error: {:?}", e);
+                ExitCode::FAILURE
+            }
+        };
+    }
+    if cli.list_presets {
+        return match Preset::write_list(&mut io::stdout().lock()) {
+            Ok(()) => ExitCode::SUCCESS,
+            Err(e) => {
+                eprintln!("Error: {:?}", e);
+                ExitCode::FAILURE
+            }
+        };
+    }
+    let result = match (&cli.manifest, &cli.watch, &cli.out_dir) {
+        (Some(manifest), _, _) => batch::run_manifest(&cli, manifest),
+        (None, Some(dir), _) => watch::watch(&cli, dir),
+        (None, None, Some(out_dir)) => match &cli.input_dir {
+            Some(dir) => batch::run_input_dir(&cli, dir, out_dir),
+            None => batch::run_inputs(&cli, &cli.fasta, out_dir),
+        },
+        (None, None, None) if cli.fasta.len() == 2 && cli.paired() => run(&cli),
+        (None, None, None) if cli.fasta.len() > 1 => Err(anyhow::anyhow!(
+            "{} inputs given; use --out-dir to write one output per input",
+            cli.fasta.len()
+        )),
+        (None, None, None) => run(&cli),
+    };
+    match result {
+        Ok(outcome) if cli.fail_on_missing && outcome.missing > 0 => {
+            eprintln!(
+                "Error: {} read(s) missing from the table (--fail-on-missing)",
+                outcome.missing
+            );
+            ExitCode::from(EXIT_MISSING)
+        }
+        Ok(outcome) if cli.fail_on_warnings && outcome.warnings > 0 => {
+            eprintln!(
+                "Error: completed with {} warning(s) (--fail-on-warnings)",
+                outcome.warnings
+            );
+            ExitCode::from(EXIT_WARNINGS)
+        }
+        Ok(_) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {:?}", e);
+            ExitCode::FAILURE
+        }
+    }
 }
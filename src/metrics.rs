@@ -0,0 +1,161 @@
+//! Prometheus metrics for long-running modes (`serve`, `--watch`), scraped
+//! from `GET /metrics` on `--metrics-listen`. The exporter runs on its own
+//! thread so a scrape is answered while a batch is still being processed.
+//! Without the `server` feature the totals are kept but never exported.
+#![cfg_attr(not(feature = "server"), allow(dead_code))]
+
+#[cfg(feature = "server")]
+use crate::serve::{accept, read_request, write_response, Response};
+use crate::Outcome;
+use anyhow::Result;
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Running totals over all requests or chunks.
+#[derive(Debug, Default, Clone, Copy)]
+struct Totals {
+    batches: u64,
+    processed: u64,
+    flipped: u64,
+    missing: u64,
+    warnings: u64,
+    errors: u64,
+    seconds: f64,
+    /// Records per second in the last batch
+    throughput: f64,
+    /// Unix time the last batch finished
+    last_batch: f64,
+}
+
+/// Counters shared between the processing loop and the exporter.
+#[derive(Debug, Default)]
+pub struct Metrics(Mutex<Totals>);
+
+impl Metrics {
+    /// Count a finished request or chunk that took `elapsed`.
+    pub fn record(&self, o: &Outcome, elapsed: Duration) {
+        let mut t = self.0.lock().expect("metrics lock");
+        t.batches += 1;
+        t.processed += o.processed;
+        t.flipped += o.flipped;
+        t.missing += o.missing;
+        t.warnings += o.warnings;
+        t.seconds += elapsed.as_secs_f64();
+        t.throughput = o.processed as f64 / elapsed.as_secs_f64().max(1e-9);
+        t.last_batch = now();
+    }
+
+    /// Count a failed request or chunk.
+    pub fn error(&self) {
+        self.0.lock().expect("metrics lock").errors += 1;
+    }
+
+    /// Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let t = *self.0.lock().expect("metrics lock");
+        let metrics: [(&str, &str, &str, f64); 9] = [
+            (
+                "batches_total",
+                "counter",
+                "Requests or chunks processed",
+                t.batches as f64,
+            ),
+            (
+                "records_processed_total",
+                "counter",
+                "Records read",
+                t.processed as f64,
+            ),
+            (
+                "records_flipped_total",
+                "counter",
+                "Records reverse-complemented",
+                t.flipped as f64,
+            ),
+            (
+                "records_missing_total",
+                "counter",
+                "Records not found in the table",
+                t.missing as f64,
+            ),
+            (
+                "warnings_total",
+                "counter",
+                "Warnings raised",
+                t.warnings as f64,
+            ),
+            (
+                "errors_total",
+                "counter",
+                "Requests or chunks that failed",
+                t.errors as f64,
+            ),
+            (
+                "processing_seconds_total",
+                "counter",
+                "Time spent processing",
+                t.seconds,
+            ),
+            (
+                "throughput_records_per_second",
+                "gauge",
+                "Records per second in the last request or chunk",
+                t.throughput,
+            ),
+            (
+                "last_batch_timestamp_seconds",
+                "gauge",
+                "Unix time the last request or chunk finished",
+                t.last_batch,
+            ),
+        ];
+        let mut out = String::new();
+        for (name, kind, help, value) in metrics {
+            let _ = writeln!(out, "# HELP restrand_{} {}.", name, help);
+            let _ = writeln!(out, "# TYPE restrand_{} {}", name, kind);
+            let _ = writeln!(out, "restrand_{} {}", name, value);
+        }
+        out
+    }
+}
+
+fn now() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0.0, |d| d.as_secs_f64())
+}
+
+/// Serve `metrics` on `addr` from a background thread.
+#[cfg(feature = "server")]
+pub fn spawn_exporter(addr: &str, metrics: Arc<Metrics>) -> Result<()> {
+    use anyhow::Context;
+    use std::io::BufReader;
+    use std::net::TcpListener;
+    use std::thread;
+
+    let listener =
+        TcpListener::bind(addr).with_context(|| format!("bind --metrics-listen {}", addr))?;
+    eprintln!("metrics on {}", listener.local_addr()?);
+    thread::spawn(move || {
+        accept(&listener, None, |stream, _| {
+            let mut reader = BufReader::new(stream);
+            let response = match read_request(&mut reader) {
+                Ok(req) if req.path == "/metrics" => Response::text("200 OK", metrics.render()),
+                Ok(_) => Response::text("404 Not Found", ""),
+                Err(e) => Response::text("400 Bad Request", format!("{:#}\n", e)),
+            };
+            if let Err(e) = write_response(reader.get_mut(), response) {
+                eprintln!("warning: writing metrics: {:#}", e);
+            }
+        })
+    });
+    Ok(())
+}
+
+#[cfg(not(feature = "server"))]
+pub fn spawn_exporter(_addr: &str, _metrics: Arc<Metrics>) -> Result<()> {
+    anyhow::bail!(
+        "--metrics-listen needs the `server` feature, which this build was compiled without"
+    )
+}
@@ -0,0 +1,266 @@
+//! Buffered record output, BGZF-compressed when the path ends in `.gz`, and
+//! the I/O buffer sizes shared with input.
+
+use crate::bgzf::{self, BgzfWriter};
+use crate::progress::{ByteCounter, CountingWriter};
+use crate::remote::Upload;
+use crate::timing::{Stage, TimedWriter, Timings};
+use anyhow::{bail, Result};
+use bio::alphabets::dna;
+use clap::ValueEnum;
+use flate2::Compression;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+/// Default buffer for regular files: large blocks keep parallel filesystems
+/// (Lustre, GPFS) from being dominated by small reads and writes.
+pub const FILE_BUFFER: usize = 1 << 20;
+/// Default buffer for pipes and terminals, matching the Linux pipe capacity
+/// so the other end sees records promptly.
+pub const PIPE_BUFFER: usize = 64 << 10;
+
+/// The `--read-buffer-size`/`--write-buffer-size` value, else the default
+/// for a pipe or a regular file.
+pub fn buffer_size(explicit: Option<u64>, pipe: bool) -> usize {
+    match explicit {
+        Some(n) => n.clamp(1, usize::MAX as u64) as usize,
+        None if pipe => PIPE_BUFFER,
+        None => FILE_BUFFER,
+    }
+}
+
+/// Append a FASTA record to `buf`, the sequence wrapped at `width` columns.
+/// Records are assembled whole so each costs one write into the buffer.
+pub fn push_fasta(buf: &mut Vec<u8>, header: &str, seq: &[u8], width: usize) {
+    buf.reserve(header.len() + seq.len() + seq.len() / width + 3);
+    buf.push(b'>');
+    buf.extend_from_slice(header.as_bytes());
+    buf.push(b'\n');
+    for line in seq.chunks(width) {
+        buf.extend_from_slice(line);
+        buf.push(b'\n');
+    }
+}
+
+/// Write a FASTA record straight from `seq`, reverse-complemented when
+/// `revcomp`, with `case` applied to each line. Lines pass through a small
+/// buffer, so chromosome-scale records are never copied whole.
+pub fn write_fasta_streamed(
+    w: &mut impl Write,
+    header: &str,
+    seq: &[u8],
+    width: usize,
+    revcomp: bool,
+    case: impl Fn(&mut [u8]),
+) -> io::Result<()> {
+    // Lines gathered per write
+    const LINES: usize = 1024;
+    let mut buf = Vec::with_capacity(LINES * (width + 1));
+    buf.push(b'>');
+    buf.extend_from_slice(header.as_bytes());
+    buf.push(b'\n');
+    let mut push_line = |buf: &mut Vec<u8>, line: &[u8]| -> io::Result<()> {
+        let start = buf.len();
+        if revcomp {
+            buf.extend(line.iter().rev().map(|&b| dna::complement(b)));
+        } else {
+            buf.extend_from_slice(line);
+        }
+        case(&mut buf[start..]);
+        buf.push(b'\n');
+        if buf.len() >= LINES * (width + 1) {
+            w.write_all(buf)?;
+            buf.clear();
+        }
+        Ok(())
+    };
+    if revcomp {
+        // The last `width` bases, reversed, make the first output line
+        for line in seq.rchunks(width) {
+            push_line(&mut buf, line)?;
+        }
+    } else {
+        for line in seq.chunks(width) {
+            push_line(&mut buf, line)?;
+        }
+    }
+    w.write_all(&buf)
+}
+
+/// Append a FASTQ record to `buf`.
+pub fn push_fastq(buf: &mut Vec<u8>, header: &str, seq: &[u8], qual: &[u8]) {
+    buf.reserve(header.len() + seq.len() + qual.len() + 6);
+    buf.push(b'@');
+    buf.extend_from_slice(header.as_bytes());
+    buf.push(b'\n');
+    buf.extend_from_slice(seq);
+    buf.extend_from_slice(b"\n+\n");
+    buf.extend_from_slice(qual);
+    buf.push(b'\n');
+}
+
+/// Append a `header<TAB>sequence[<TAB>quality]` line to `buf`.
+pub fn push_tab(buf: &mut Vec<u8>, header: &str, seq: &[u8], qual: Option<&[u8]>) {
+    buf.reserve(header.len() + seq.len() + qual.map_or(0, |q| q.len() + 1) + 2);
+    buf.extend_from_slice(header.as_bytes());
+    buf.push(b'\t');
+    buf.extend_from_slice(seq);
+    if let Some(qual) = qual {
+        buf.push(b'\t');
+        buf.extend_from_slice(qual);
+    }
+    buf.push(b'\n');
+}
+
+/// Layout of written records (`--out-format`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum RecordFormat {
+    /// Wrapped FASTA
+    Fasta,
+    /// FASTQ (FASTQ input only)
+    Fastq,
+    /// One `header<TAB>sequence[<TAB>quality]` line per record
+    Tab,
+    /// Arrow IPC file of read_id, sequence, quality, final_orientation and flipped columns
+    Arrow,
+    /// Parquet file with the columns of `arrow`
+    Parquet,
+    /// The BAM input's records with SEQ/QUAL untouched and the orientation patched in (see --bam-patch)
+    Bam,
+}
+
+/// True when output to `path` should be gzip-compressed.
+pub fn is_gzip_path(path: &Path) -> bool {
+    path.extension().is_some_and(|e| e == "gz")
+}
+
+/// Where buffered bytes go; gzip time is charged to [`Stage::Compress`] and
+/// the compressed writes beneath it to [`Stage::Write`].
+enum Sink {
+    Plain(Box<dyn Write>),
+    Gzip(BgzfWriter<Box<dyn Write>>, Timings),
+}
+
+impl Write for Sink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Sink::Plain(w) => w.write(buf),
+            Sink::Gzip(w, timings) => timings.time(Stage::Compress, || w.write(buf)),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Sink::Plain(w) => w.flush(),
+            Sink::Gzip(w, timings) => timings.time(Stage::Compress, || w.flush()),
+        }
+    }
+}
+
+/// One output stream; call [`Output::finish`] once all records are written.
+pub struct Output {
+    w: BufWriter<Sink>,
+    /// Set when `inner` feeds an object-store upload
+    upload: Option<Upload>,
+    /// Bytes handed to `inner`, i.e. after compression
+    written: ByteCounter,
+}
+
+impl Output {
+    pub fn new(inner: Box<dyn Write>, gzip: bool, capacity: usize) -> Self {
+        Self::with_level(inner, gzip, None, capacity, &Timings::default())
+    }
+
+    /// Output compressed at `level` (0-9, default 6) when `gzip`, charging
+    /// compression to `timings`.
+    pub fn with_level(
+        inner: Box<dyn Write>,
+        gzip: bool,
+        level: Option<u32>,
+        capacity: usize,
+        timings: &Timings,
+    ) -> Self {
+        let written = ByteCounter::default();
+        let inner: Box<dyn Write> = Box::new(CountingWriter::new(inner, &written));
+        let sink = if gzip {
+            let level = level.map_or_else(Compression::default, Compression::new);
+            let inner: Box<dyn Write> = Box::new(TimedWriter::new(inner, timings, Stage::Write));
+            Sink::Gzip(BgzfWriter::new(inner, level), timings.clone())
+        } else {
+            Sink::Plain(inner)
+        };
+        Self {
+            w: BufWriter::with_capacity(capacity, sink),
+            upload: None,
+            written,
+        }
+    }
+
+    /// Bytes written out so far (compressed size for gzip output).
+    pub fn bytes_written(&self) -> u64 {
+        self.written.get()
+    }
+
+    /// Output whose bytes go to `upload`; `inner` is (or wraps) its stdin.
+    pub fn uploading(
+        inner: Box<dyn Write>,
+        gzip: bool,
+        level: Option<u32>,
+        capacity: usize,
+        timings: &Timings,
+        upload: Upload,
+    ) -> Self {
+        let mut out = Self::with_level(inner, gzip, level, capacity, timings);
+        out.upload = Some(upload);
+        out
+    }
+
+    /// Flush everything, writing the last BGZF block, so the bytes on disk (and
+    /// any checksum beneath) are complete. An upload is closed and waited for.
+    pub fn finish(&mut self) -> Result<()> {
+        self.w.flush()?;
+        match self.w.get_mut() {
+            Sink::Plain(w) => w.flush()?,
+            Sink::Gzip(w, timings) => {
+                timings.time(Stage::Compress, || w.try_finish())?;
+                w.get_mut().flush()?;
+            }
+        }
+        if let Some(upload) = self.upload.take() {
+            // Dropping the writer closes the client's stdin, ending the object.
+            self.w = BufWriter::new(Sink::Plain(Box::new(io::sink())));
+            upload.finish()?;
+        }
+        Ok(())
+    }
+
+    /// Write the `.gzi` index of a finished `.gz` output to `path`.
+    pub fn write_gzi(&mut self, path: &Path) -> Result<()> {
+        match self.w.get_ref() {
+            Sink::Gzip(w, _) => bgzf::write_gzi(path, w.index()),
+            Sink::Plain(_) => bail!("--gzi-out needs a .gz --out"),
+        }
+    }
+}
+
+impl Drop for Output {
+    fn drop(&mut self) {
+        if let Some(upload) = self.upload.take() {
+            upload.abort();
+        }
+    }
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.w.write(buf)
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        self.w.write_all(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.w.flush()
+    }
+}
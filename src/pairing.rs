@@ -0,0 +1,338 @@
+//! Paired-end layouts for FASTQ output: `--out-r1`/`--out-r2` split the mates
+//! into two files, `--out-interleaved` writes each pair as two consecutive
+//! records, mate 1 first. The input is one file with both mates, or two
+//! files (R1 then R2) read in lockstep.
+//!
+//! Mates are matched by name, so pairs broken upstream or by dropped reads
+//! are caught: a read whose mate never arrives is an orphan and is kept out
+//! of the paired outputs, which stay in step for aligners. Reads wait in
+//! memory until their mate turns up, which for interleaved or lockstep input
+//! is the next record; one still waiting after `--pair-window` more reads is
+//! orphaned then, so memory stays bounded whatever the input.
+//!
+//! `--merge-pairs` instead joins mates whose ends overlap (amplicons shorter
+//! than twice the read length) into one read on mate 1's strand, which is
+//! then oriented like any other read.
+
+use crate::casava;
+use crate::output::Output;
+use anyhow::Result;
+use bio::alphabets::dna;
+use bio::io::fastq::{self, FastqRead};
+use std::cell::Cell;
+use std::collections::{HashMap, VecDeque};
+use std::io::Write;
+use std::rc::Rc;
+
+/// Orphan IDs named in the warning.
+const EXAMPLES: usize = 3;
+
+/// The pair name of a read, and its mate number when the `/1`/`/2` ID suffix
+/// or a CASAVA comment gives it.
+pub fn mate<'a>(id: &'a str, desc: &str) -> (&'a str, Option<u8>) {
+    if let Some(name) = id.strip_suffix("/1") {
+        return (name, Some(1));
+    }
+    if let Some(name) = id.strip_suffix("/2") {
+        return (name, Some(2));
+    }
+    (id, casava::mate(desc))
+}
+
+/// Records of two readers in turn, R1 then R2; once one ends, the rest of
+/// the other follows (and ends up orphaned).
+pub struct Interleave {
+    readers: [Box<dyn FastqRead>; 2],
+    next: usize,
+    done: [bool; 2],
+}
+
+impl Interleave {
+    pub fn new(r1: Box<dyn FastqRead>, r2: Box<dyn FastqRead>) -> Self {
+        Interleave {
+            readers: [r1, r2],
+            next: 0,
+            done: [false; 2],
+        }
+    }
+}
+
+impl FastqRead for Interleave {
+    fn read(&mut self, record: &mut fastq::Record) -> fastq::Result<()> {
+        for _ in 0..2 {
+            let i = self.next;
+            self.next = 1 - i;
+            if self.done[i] {
+                continue;
+            }
+            self.readers[i].read(record)?;
+            if !record.is_empty() {
+                return Ok(());
+            }
+            self.done[i] = true;
+        }
+        Ok(())
+    }
+}
+
+/// Shortest mate overlap `--merge-pairs` accepts.
+const MIN_OVERLAP: usize = 20;
+
+/// Most mismatches per overlapping base `--merge-pairs` accepts.
+const MAX_MISMATCH_RATE: f64 = 0.1;
+
+/// Counts of merged pairs and of pairs left as two reads.
+#[derive(Debug, Clone, Default)]
+pub struct MergeCounter {
+    merged: Rc<Cell<u64>>,
+    unmerged: Rc<Cell<u64>>,
+}
+
+impl MergeCounter {
+    pub fn merged(&self) -> u64 {
+        self.merged.get()
+    }
+
+    pub fn unmerged(&self) -> u64 {
+        self.unmerged.get()
+    }
+}
+
+/// Reader adapter that returns each pair of consecutive mates as one merged
+/// read, named after the pair, when their ends overlap. Other pairs and
+/// unpaired reads pass through as they are.
+pub struct Merger {
+    inner: Box<dyn FastqRead>,
+    /// A read taken while looking for a mate that turned out not to be one
+    next: Option<fastq::Record>,
+    /// Mate 2 of a pair that did not merge, returned after mate 1
+    pending: Option<fastq::Record>,
+    counter: MergeCounter,
+}
+
+impl Merger {
+    pub fn new(inner: Box<dyn FastqRead>, counter: &MergeCounter) -> Self {
+        Merger {
+            inner,
+            next: None,
+            pending: None,
+            counter: counter.clone(),
+        }
+    }
+}
+
+impl FastqRead for Merger {
+    fn read(&mut self, record: &mut fastq::Record) -> fastq::Result<()> {
+        if let Some(r) = self.pending.take() {
+            *record = r;
+            return Ok(());
+        }
+        match self.next.take() {
+            Some(r) => *record = r,
+            None => self.inner.read(record)?,
+        }
+        if record.is_empty() {
+            return Ok(());
+        }
+        let mut second = fastq::Record::new();
+        self.inner.read(&mut second)?;
+        if second.is_empty() {
+            return Ok(());
+        }
+        let (name1, mate1) = mate(record.id(), record.desc().unwrap_or(""));
+        let (name2, mate2) = mate(second.id(), second.desc().unwrap_or(""));
+        if name1 != name2 || mate1.is_some_and(|m| Some(m) == mate2) {
+            self.next = Some(second);
+            return Ok(());
+        }
+        let name = name1.to_owned();
+        if mate1 == Some(2) {
+            std::mem::swap(record, &mut second);
+        }
+        match merge(record, &second) {
+            Some((seq, qual)) => {
+                *record = fastq::Record::with_attrs(&name, record.desc(), &seq, &qual);
+                self.counter.merged.set(self.counter.merged.get() + 1);
+            }
+            None => {
+                self.counter.unmerged.set(self.counter.unmerged.get() + 1);
+                self.pending = Some(second);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Mate 1 extended by the reverse complement of mate 2 where they overlap
+/// by at least [`MIN_OVERLAP`] bases, taking the better-quality base where
+/// they disagree; the overlap with the most agreeing bases wins.
+fn merge(r1: &fastq::Record, r2: &fastq::Record) -> Option<(Vec<u8>, Vec<u8>)> {
+    let (s1, q1) = (r1.seq(), r1.qual());
+    let s2 = dna::revcomp(r2.seq());
+    let q2: Vec<u8> = r2.qual().iter().rev().copied().collect();
+    let mut best: Option<(usize, usize)> = None;
+    for offset in 0..s1.len() {
+        let overlap = (s1.len() - offset).min(s2.len());
+        if overlap < MIN_OVERLAP {
+            break;
+        }
+        let mismatches = s1[offset..offset + overlap]
+            .iter()
+            .zip(&s2[..overlap])
+            .filter(|(a, b)| !a.eq_ignore_ascii_case(b))
+            .count();
+        let agreeing = overlap - mismatches;
+        if mismatches as f64 <= overlap as f64 * MAX_MISMATCH_RATE
+            && best.is_none_or(|(_, a)| agreeing > a)
+        {
+            best = Some((offset, agreeing));
+        }
+    }
+    let (offset, _) = best?;
+    let len = s1.len().max(offset + s2.len());
+    let (mut seq, mut qual) = (Vec::with_capacity(len), Vec::with_capacity(len));
+    for i in 0..len {
+        let a = (i < s1.len()).then(|| (s1[i], q1[i]));
+        let b = i
+            .checked_sub(offset)
+            .filter(|&j| j < s2.len())
+            .map(|j| (s2[j], q2[j]));
+        let (base, q) = match (a, b) {
+            (Some((x, qx)), Some((y, qy))) if x.eq_ignore_ascii_case(&y) => (x, qx.max(qy)),
+            // Disagreement: keep the likelier base, at the quality margin
+            (Some((x, qx)), Some((y, qy))) => {
+                let (base, margin) = if qx >= qy { (x, qx - qy) } else { (y, qy - qx) };
+                (base, b'!' + margin.max(2))
+            }
+            (Some(only), None) | (None, Some(only)) => only,
+            (None, None) => unreachable!("mate 2 starts within mate 1"),
+        };
+        seq.push(base);
+        qual.push(q);
+    }
+    Some((seq, qual))
+}
+
+/// Matches written records into pairs and writes mate 1 to `r1` and mate 2
+/// to `r2`, or both to `r1` when interleaving.
+pub struct Pairer {
+    r1: Output,
+    r2: Option<Output>,
+    orphans_out: Option<Output>,
+    /// Reads waiting for their mate, by pair name: arrival, mate, record
+    pending: HashMap<String, (u64, u8, Vec<u8>)>,
+    /// Arrival and pair name of waiting reads, oldest first; entries whose
+    /// read has since been paired are skipped when they come up
+    waiting: VecDeque<(u64, String)>,
+    /// Arrivals a read may wait for its mate before it is orphaned
+    window: u64,
+    arrivals: u64,
+    pub pairs: u64,
+    pub orphans: u64,
+    /// The first orphans' pair names
+    pub examples: Vec<String>,
+}
+
+impl Pairer {
+    pub fn new(r1: Output, r2: Option<Output>, orphans_out: Option<Output>, window: u64) -> Self {
+        Pairer {
+            r1,
+            r2,
+            orphans_out,
+            pending: HashMap::new(),
+            waiting: VecDeque::new(),
+            window,
+            arrivals: 0,
+            pairs: 0,
+            orphans: 0,
+            examples: Vec::new(),
+        }
+    }
+
+    /// Take `record`, the formatted output of read `id` (`desc` its comment).
+    pub fn push(&mut self, id: &str, desc: &str, record: &[u8]) -> Result<()> {
+        let (name, mate) = mate(id, desc);
+        self.arrivals += 1;
+        if let Some(through) = self.arrivals.checked_sub(self.window + 1) {
+            self.expire(through)?;
+        }
+        let Some((_, first_mate, first)) = self.pending.remove(name) else {
+            self.wait(name, mate.unwrap_or(1), record);
+            return Ok(());
+        };
+        let mate = mate.unwrap_or(3 - first_mate);
+        if mate == first_mate {
+            // Two copies of one mate: the earlier has lost its partner
+            self.orphan(name, &first)?;
+            self.wait(name, mate, record);
+            return Ok(());
+        }
+        let (r1, r2) = if first_mate == 1 {
+            (&first[..], record)
+        } else {
+            (record, &first[..])
+        };
+        self.r1.write_all(r1)?;
+        self.r2.as_mut().unwrap_or(&mut self.r1).write_all(r2)?;
+        self.pairs += 1;
+        Ok(())
+    }
+
+    fn wait(&mut self, name: &str, mate: u8, record: &[u8]) {
+        self.pending
+            .insert(name.to_owned(), (self.arrivals, mate, record.to_vec()));
+        self.waiting.push_back((self.arrivals, name.to_owned()));
+    }
+
+    /// Orphan the reads still waiting that arrived at or before `through`.
+    fn expire(&mut self, through: u64) -> Result<()> {
+        while let Some((arrival, _)) = self.waiting.front() {
+            if *arrival > through {
+                break;
+            }
+            let (arrival, name) = self.waiting.pop_front().unwrap();
+            if self
+                .pending
+                .get(&name)
+                .is_some_and(|(a, _, _)| *a == arrival)
+            {
+                let (_, _, record) = self.pending.remove(&name).unwrap();
+                self.orphan(&name, &record)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn orphan(&mut self, name: &str, record: &[u8]) -> Result<()> {
+        self.orphans += 1;
+        if self.examples.len() < EXAMPLES {
+            self.examples.push(name.to_owned());
+        }
+        if let Some(out) = self.orphans_out.as_mut() {
+            out.write_all(record)?;
+        }
+        Ok(())
+    }
+
+    /// Bytes written to the pair and orphan outputs so far.
+    pub fn bytes_written(&self) -> u64 {
+        [Some(&self.r1), self.r2.as_ref(), self.orphans_out.as_ref()]
+            .into_iter()
+            .flatten()
+            .map(Output::bytes_written)
+            .sum()
+    }
+
+    /// Orphan the reads still waiting, in input order, and close the outputs.
+    pub fn finish(&mut self) -> Result<()> {
+        self.expire(u64::MAX)?;
+        self.r1.finish()?;
+        if let Some(out) = self.r2.as_mut() {
+            out.finish()?;
+        }
+        if let Some(out) = self.orphans_out.as_mut() {
+            out.finish()?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,216 @@
+//! Built-in library-prep presets (`--preset`): primer pairs and read geometry
+//! for de novo orientation without a user-supplied primer FASTA.
+
+use crate::primers::{PrimerCall, PrimerSet};
+use bio::alphabets::dna;
+use clap::ValueEnum;
+use std::io::{self, Write};
+
+/// ONT strand-switching primer (cDNA-PCR and direct cDNA kits up to PCS110).
+const ONT_SSP_V1: &[u8] = b"TTTCTGTTGGTGCTGATATTGCTGGG";
+/// ONT VN primer (cDNA-PCR and direct cDNA kits up to PCS110).
+const ONT_VNP_V1: &[u8] = b"ACTTGCCTGTCGCTCTATCTTC";
+/// ONT strand-switching primer (PCS111 and later).
+const ONT_SSP_V2: &[u8] = b"TTTCTGTTGGTGCTGATATTGCTTT";
+/// ONT VN primer, including the anchored oligo-dT start (PCS111 and later).
+const ONT_VNP_V2: &[u8] = b"ACTTGCCTGTCGCTCTATCTTCAGAGGAGAGTCCGCCGCCCGCAAGTTTT";
+/// 10x Genomics template-switch oligo.
+const TENX_TSO: &[u8] = b"AAGCAGTGGTATCAACGCAGAGTACATGGG";
+/// 10x partial Read 1 adapter, 5' of the cell barcode on the gel-bead oligo.
+const TENX_READ1: &[u8] = b"CTACACGACGCTCTTCCGATCT";
+/// PacBio Iso-Seq Clontech SMARTer 5' primer.
+const ISOSEQ_CLONTECH_5P: &[u8] = b"AAGCAGTGGTATCAACGCAGAGTACATGGG";
+/// PacBio Iso-Seq NEBNext 5' primer.
+const ISOSEQ_NEB_5P: &[u8] = b"GCAATGAAGTCGCAGGGTTGGG";
+/// Iso-Seq 3' primer (shared by both kits), as it reads at the 3' end of a
+/// sense read; the preset stores its reverse complement as the reverse primer.
+const ISOSEQ_3P: &[u8] = b"GTACTCTGCGTTGATACCACTGCTT";
+
+/// Library-prep kit preset.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Preset {
+    /// ONT cDNA-PCR Sequencing Kit (SQK-PCS111): SSP ... polyA-rc(VNP)
+    #[value(name = "pcs111")]
+    Pcs111,
+    /// ONT Direct cDNA Sequencing Kit (SQK-DCS109): SSP ... polyA-rc(VNP)
+    #[value(name = "dcs109")]
+    Dcs109,
+    /// ONT PCR-cDNA Barcoding Kit V14 (SQK-PCB114.24): SSP ... polyA-rc(VNP)
+    #[value(name = "pcb114")]
+    Pcb114,
+    /// 10x Genomics 3' single-cell: TSO ... polyA-UMI-CB-Read1
+    #[value(name = "10x-3p")]
+    TenX3p,
+    /// PacBio Iso-Seq, Clontech SMARTer primers: 5p ... polyA-3p
+    #[value(name = "isoseq")]
+    IsoSeq,
+    /// PacBio Iso-Seq Express, NEBNext primers: 5p ... polyA-3p
+    #[value(name = "isoseq-neb")]
+    IsoSeqNeb,
+}
+
+/// Cell barcode/UMI geometry next to the barcode-side adapter.
+#[derive(Debug, Clone, Copy)]
+pub struct BarcodeLayout {
+    pub cb_len: usize,
+    pub umi_len: usize,
+}
+
+/// Everything a preset configures.
+pub struct PresetSpec {
+    /// Forward primer marks the 5' end of a `+` (sense) read, reverse the 3' end
+    pub primers: PrimerSet,
+    /// Minimum polyA/polyT run counted as evidence, if the prep has one
+    pub poly_a_min: Option<usize>,
+    /// Barcode geometry adjacent to the reverse primer, if any
+    pub barcode: Option<BarcodeLayout>,
+    /// Bases searched at each read end (overridden by `--primer-window`)
+    pub window: usize,
+    /// Minimum primer hit identity (overridden by `--primer-min-identity`)
+    pub min_identity: f64,
+}
+
+impl Preset {
+    pub fn spec(self) -> PresetSpec {
+        match self {
+            Preset::Pcs111 | Preset::Pcb114 => PresetSpec {
+                primers: PrimerSet {
+                    forward: ONT_SSP_V2.to_vec(),
+                    reverse: ONT_VNP_V2.to_vec(),
+                },
+                poly_a_min: Some(15),
+                barcode: None,
+                window: 200,
+                min_identity: 0.7,
+            },
+            Preset::Dcs109 => PresetSpec {
+                primers: PrimerSet {
+                    forward: ONT_SSP_V1.to_vec(),
+                    reverse: ONT_VNP_V1.to_vec(),
+                },
+                poly_a_min: Some(15),
+                barcode: None,
+                window: 200,
+                min_identity: 0.7,
+            },
+            // A sense read is TSO-cDNA-polyA-rc(UMI)-rc(CB)-rc(Read1), so
+            // the TSO plays the forward primer and Read1 the reverse.
+            Preset::TenX3p => PresetSpec {
+                primers: PrimerSet {
+                    forward: TENX_TSO.to_vec(),
+                    reverse: TENX_READ1.to_vec(),
+                },
+                poly_a_min: Some(15),
+                barcode: Some(BarcodeLayout {
+                    cb_len: 16,
+                    umi_len: 12,
+                }),
+                window: 200,
+                min_identity: 0.75,
+            },
+            // The Clontech 3' primer is nearly a prefix of its 5' primer, so
+            // the polyA tail carries much of the orientation signal.
+            Preset::IsoSeq | Preset::IsoSeqNeb => PresetSpec {
+                primers: PrimerSet {
+                    forward: match self {
+                        Preset::IsoSeqNeb => ISOSEQ_NEB_5P.to_vec(),
+                        _ => ISOSEQ_CLONTECH_5P.to_vec(),
+                    },
+                    reverse: dna::revcomp(ISOSEQ_3P),
+                },
+                poly_a_min: Some(15),
+                barcode: None,
+                // HiFi reads: primers sit right at the ends, nearly error-free
+                window: 100,
+                min_identity: 0.9,
+            },
+        }
+    }
+
+    /// Write the `--list-presets` table: name, search settings, primers and description.
+    pub fn write_list(out: &mut impl Write) -> io::Result<()> {
+        writeln!(
+            out,
+            "preset\twindow\tmin_identity\tpoly_a_min\tbarcode\tforward\treverse\tdescription"
+        )?;
+        for preset in Preset::value_variants() {
+            let spec = preset.spec();
+            let value = preset.to_possible_value().expect("presets are not hidden");
+            writeln!(
+                out,
+                "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+                value.get_name(),
+                spec.window,
+                spec.min_identity,
+                spec.poly_a_min.map_or("-".to_owned(), |n| n.to_string()),
+                spec.barcode.map_or("-".to_owned(), |b| format!(
+                    "cb{}+umi{}",
+                    b.cb_len, b.umi_len
+                )),
+                String::from_utf8_lossy(&spec.primers.forward),
+                String::from_utf8_lossy(&spec.primers.reverse),
+                value.get_help().map(|h| h.to_string()).unwrap_or_default()
+            )?;
+        }
+        Ok(())
+    }
+
+    /// PacBio presets, whose `movie/zmw/...` read names must stay parseable.
+    pub fn is_pacbio(self) -> bool {
+        matches!(self, Preset::IsoSeq | Preset::IsoSeqNeb)
+    }
+}
+
+/// Half-open cell-barcode and UMI spans on an output read.
+#[derive(Debug, Clone, Copy)]
+pub struct BarcodeWindows {
+    pub cb: (usize, usize),
+    pub umi: (usize, usize),
+}
+
+impl BarcodeLayout {
+    /// Cell-barcode and UMI spans in the read's final orientation, located
+    /// from the reverse-primer (barcode-side adapter) hit. `flipped` says
+    /// whether the read was reverse-complemented on output.
+    pub fn locate(
+        &self,
+        call: &PrimerCall,
+        read_len: usize,
+        flipped: bool,
+        final_ori: u8,
+    ) -> Option<BarcodeWindows> {
+        // In final '+' the adapter is reverse-complemented at the 3' end; in
+        // final '-' it reads forward at the 5' start.
+        let wanted = match (final_ori, flipped) {
+            (b'+', false) | (b'-', true) => "rev_rc",
+            _ => "rev",
+        };
+        let hit = call
+            .alignments
+            .iter()
+            .filter(|h| h.passed && h.primer == wanted)
+            .max_by_key(|h| h.score)?;
+        let (start, stop) = if flipped {
+            (read_len - hit.stop, read_len - hit.start)
+        } else {
+            (hit.start, hit.stop)
+        };
+        if final_ori == b'+' {
+            // ...polyA-rc(UMI)-rc(CB)-rc(adapter)
+            let cb_start = start.checked_sub(self.cb_len)?;
+            let umi_start = cb_start.checked_sub(self.umi_len)?;
+            Some(BarcodeWindows {
+                cb: (cb_start, start),
+                umi: (umi_start, cb_start),
+            })
+        } else {
+            // adapter-CB-UMI-polyT...
+            let cb_stop = stop + self.cb_len;
+            let umi_stop = cb_stop + self.umi_len;
+            (umi_stop <= read_len).then_some(BarcodeWindows {
+                cb: (stop, cb_stop),
+                umi: (cb_stop, umi_stop),
+            })
+        }
+    }
+}
@@ -0,0 +1,536 @@
+//! De novo orientation from primer sequences found at the read ends (`--primers`).
+//!
+//! A cDNA molecule reads `FWD ... rc(REV)`; its reverse complement reads
+//! `REV ... rc(FWD)`. Each primer is aligned semi-globally (whole primer, any
+//! window position) against the first and last `window` bases of the read, and
+//! the orientation with the larger summed score of passing hits wins.
+
+use anyhow::{bail, Context, Result};
+use bio::alignment::pairwise::{Aligner, MatchParams};
+use bio::alignment::AlignmentOperation;
+use bio::alphabets::dna;
+use bio::io::fasta;
+use clap::ValueEnum;
+use std::cmp::Ordering;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+/// Forward (5') and reverse (3') primer pair.
+#[derive(Debug, Clone)]
+pub struct PrimerSet {
+    pub forward: Vec<u8>,
+    pub reverse: Vec<u8>,
+}
+
+impl PrimerSet {
+    /// Load a primer FASTA: the first record is the forward primer, the second the reverse.
+    pub fn from_fasta(path: &Path) -> Result<Self> {
+        let reader = fasta::Reader::from_file(path)
+            .with_context(|| format!("open primer FASTA {:?}", path))?;
+        let mut seqs = Vec::new();
+        for rec in reader.records() {
+            let rec = rec.context("parsing primer FASTA")?;
+            seqs.push(rec.seq().to_ascii_uppercase());
+        }
+        if seqs.len() != 2 || seqs.iter().any(|s| s.is_empty()) {
+            bail!(
+                "primer FASTA {:?} must contain exactly two non-empty records (forward, then reverse); found {}",
+                path,
+                seqs.len()
+            );
+        }
+        let reverse = seqs.pop().unwrap();
+        let forward = seqs.pop().unwrap();
+        Ok(Self { forward, reverse })
+    }
+}
+
+/// Alignment scores (penalties as positive magnitudes) and hit thresholds.
+#[derive(Debug, Clone, Copy)]
+pub struct PrimerScoring {
+    pub match_score: i32,
+    pub mismatch: i32,
+    pub gap_open: i32,
+    pub gap_extend: i32,
+    pub min_identity: f64,
+    pub min_score: i32,
+}
+
+impl Default for PrimerScoring {
+    fn default() -> Self {
+        Self {
+            match_score: 2,
+            mismatch: 4,
+            gap_open: 4,
+            gap_extend: 2,
+            min_identity: 0.75,
+            min_score: 1,
+        }
+    }
+}
+
+/// Which read end a primer is searched at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReadEnd {
+    Start,
+    End,
+}
+
+impl ReadEnd {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ReadEnd::Start => "start",
+            ReadEnd::End => "end",
+        }
+    }
+}
+
+/// Best alignment of one primer pattern at one read end.
+#[derive(Debug, Clone)]
+pub struct PrimerHit {
+    /// `fwd`, `rev`, `fwd_rc` or `rev_rc`
+    pub primer: &'static str,
+    pub end: ReadEnd,
+    /// Half-open span on the read
+    pub start: usize,
+    pub stop: usize,
+    pub score: i32,
+    pub identity: f64,
+    /// Whether the hit met the score and identity thresholds
+    pub passed: bool,
+}
+
+impl PrimerHit {
+    /// Whether this is a polyA/polyT tail rather than a primer or adapter.
+    pub fn is_homopolymer(&self) -> bool {
+        matches!(self.primer, "polyA" | "polyT")
+    }
+}
+
+/// How `--mask-primers` hides primer and adapter bases.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum PrimerMask {
+    /// Replace them with N
+    N,
+    /// Lowercase them
+    Lowercase,
+}
+
+/// Outcome of primer search on one read.
+#[derive(Debug, Clone, Default)]
+pub struct PrimerCall {
+    pub orientation: Option<u8>,
+    pub plus_score: i32,
+    pub minus_score: i32,
+    /// Every alignment attempted, passing or not
+    pub alignments: Vec<PrimerHit>,
+}
+
+impl PrimerCall {
+    /// Share of the total evidence score behind the called orientation: 0.5
+    /// for a near-tie, 1 when only one orientation had passing hits.
+    pub fn confidence(&self) -> f64 {
+        let total = self.plus_score + self.minus_score;
+        if total <= 0 {
+            return 0.0;
+        }
+        self.plus_score.max(self.minus_score) as f64 / total as f64
+    }
+
+    /// Whether passing hits point both ways, e.g. a 5' polyT and a 3' polyA
+    /// or primers in both orientations; usually a chimera or other artifact.
+    pub fn is_ambiguous(&self) -> bool {
+        self.plus_score > 0 && self.minus_score > 0
+    }
+
+    /// Mask the passing primer and adapter hits (not polyA/polyT tails) in
+    /// `seq`, the read as searched; returns the number of bases covered.
+    pub fn mask(&self, seq: &mut [u8], how: PrimerMask) -> usize {
+        let mut spans: Vec<(usize, usize)> = self
+            .alignments
+            .iter()
+            .filter(|h| h.passed && !h.is_homopolymer())
+            .map(|h| (h.start, h.stop))
+            .collect();
+        spans.sort_unstable();
+        // Overlapping hits count each base once
+        let (mut masked, mut done) = (0, 0);
+        for (start, stop) in spans {
+            let start = start.max(done);
+            if stop <= start {
+                continue;
+            }
+            for b in &mut seq[start..stop] {
+                *b = match how {
+                    PrimerMask::N => b'N',
+                    PrimerMask::Lowercase => b.to_ascii_lowercase(),
+                };
+            }
+            masked += stop - start;
+            done = stop;
+        }
+        masked
+    }
+}
+
+/// One primer pattern searched for at one read end, voting for `orientation`.
+struct Pattern {
+    name: &'static str,
+    seq: Vec<u8>,
+    end: ReadEnd,
+    orientation: u8,
+}
+
+/// Scans read ends for the primer pair.
+pub struct PrimerDetector {
+    patterns: Vec<Pattern>,
+    window: usize,
+    scoring: PrimerScoring,
+    aligner: Aligner<MatchParams>,
+    buf: Vec<u8>,
+    poly_a_min: Option<usize>,
+    debug: Option<BufWriter<File>>,
+    hits: Option<(BufWriter<File>, HitsFormat)>,
+}
+
+/// Layout of the `--primer-hits-out` file, chosen by its extension.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum HitsFormat {
+    Tsv,
+    Fasta,
+}
+
+impl PrimerDetector {
+    pub fn new(primers: PrimerSet, window: usize, scoring: PrimerScoring) -> Self {
+        let longest = primers.forward.len().max(primers.reverse.len());
+        let forward_rc = dna::revcomp(&primers.forward);
+        let reverse_rc = dna::revcomp(&primers.reverse);
+        let patterns = vec![
+            Pattern {
+                name: "fwd",
+                seq: primers.forward,
+                end: ReadEnd::Start,
+                orientation: b'+',
+            },
+            Pattern {
+                name: "rev_rc",
+                seq: reverse_rc,
+                end: ReadEnd::End,
+                orientation: b'+',
+            },
+            Pattern {
+                name: "rev",
+                seq: primers.reverse,
+                end: ReadEnd::Start,
+                orientation: b'-',
+            },
+            Pattern {
+                name: "fwd_rc",
+                seq: forward_rc,
+                end: ReadEnd::End,
+                orientation: b'-',
+            },
+        ];
+        Self {
+            patterns,
+            window,
+            scoring,
+            aligner: Aligner::with_capacity(
+                longest,
+                window,
+                -scoring.gap_open,
+                -scoring.gap_extend,
+                MatchParams::new(scoring.match_score, -scoring.mismatch),
+            ),
+            buf: Vec::with_capacity(window),
+            poly_a_min: None,
+            debug: None,
+            hits: None,
+        }
+    }
+
+    /// Also count a polyA tail near the 3' end (`+`) or a polyT near the 5'
+    /// start (`-`) as evidence, when the run scores at least `min_len`.
+    pub fn with_poly_a(mut self, min_len: usize) -> Self {
+        self.poly_a_min = Some(min_len);
+        self
+    }
+
+    /// Dump every per-read primer alignment as TSV to `path`.
+    pub fn with_debug(mut self, path: &Path) -> Result<Self> {
+        let mut w = BufWriter::new(
+            File::create(path).with_context(|| format!("create primer debug {:?}", path))?,
+        );
+        writeln!(
+            w,
+            "read_id\tprimer\tread_end\tstart\tend\tscore\tidentity\tpassed\tcall"
+        )?;
+        self.debug = Some(w);
+        Ok(self)
+    }
+
+    /// Write every passing hit with its subsequence to `path`: FASTA when it
+    /// ends in `.fa`/`.fasta`, TSV otherwise.
+    pub fn with_hits_out(mut self, path: &Path) -> Result<Self> {
+        let format = match path.extension().and_then(|e| e.to_str()) {
+            Some("fa" | "fasta") => HitsFormat::Fasta,
+            _ => HitsFormat::Tsv,
+        };
+        let mut w = BufWriter::new(
+            File::create(path).with_context(|| format!("create primer hits {:?}", path))?,
+        );
+        if format == HitsFormat::Tsv {
+            writeln!(
+                w,
+                "read_id\tprimer\tread_end\tstart\tend\tscore\tidentity\tsequence"
+            )?;
+        }
+        self.hits = Some((w, format));
+        Ok(self)
+    }
+
+    /// Flush the debug dump and hits file, if any.
+    pub fn finish(&mut self) -> Result<()> {
+        if let Some(w) = self.debug.as_mut() {
+            w.flush()?;
+        }
+        if let Some((w, _)) = self.hits.as_mut() {
+            w.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Search both read ends and call the orientation; ties and no hits give `None`.
+    pub fn detect(&mut self, read_id: &str, seq: &[u8]) -> Result<PrimerCall> {
+        let call = self.search(seq);
+        if let Some(w) = self.debug.as_mut() {
+            let label = call
+                .orientation
+                .map_or("none".to_owned(), |o| (o as char).to_string());
+            for hit in &call.alignments {
+                writeln!(
+                    w,
+                    "{}\t{}\t{}\t{}\t{}\t{}\t{:.3}\t{}\t{}",
+                    read_id,
+                    hit.primer,
+                    hit.end.as_str(),
+                    hit.start,
+                    hit.stop,
+                    hit.score,
+                    hit.identity,
+                    hit.passed,
+                    label
+                )?;
+            }
+        }
+        if let Some((w, format)) = self.hits.as_mut() {
+            for hit in call.alignments.iter().filter(|h| h.passed) {
+                let sub = String::from_utf8_lossy(&seq[hit.start..hit.stop]);
+                match format {
+                    HitsFormat::Tsv => writeln!(
+                        w,
+                        "{}\t{}\t{}\t{}\t{}\t{}\t{:.3}\t{}",
+                        read_id,
+                        hit.primer,
+                        hit.end.as_str(),
+                        hit.start,
+                        hit.stop,
+                        hit.score,
+                        hit.identity,
+                        sub
+                    )?,
+                    HitsFormat::Fasta => writeln!(
+                        w,
+                        ">{}:{}-{} primer={} read_end={} score={} identity={:.3}\n{}",
+                        read_id,
+                        hit.start,
+                        hit.stop,
+                        hit.primer,
+                        hit.end.as_str(),
+                        hit.score,
+                        hit.identity,
+                        sub
+                    )?,
+                }
+            }
+        }
+        Ok(call)
+    }
+
+    /// Positions at which to cut `seq` where primers occur away from its
+    /// ends (further in than the search window), as in ligation chimeras.
+    /// A 5' primer starts the next piece and a 3' one ends the previous, so
+    /// each piece keeps its own primers.
+    pub fn internal_cuts(&mut self, seq: &[u8]) -> Vec<usize> {
+        let mut hits = Vec::new();
+        if seq.len() > 2 * self.window {
+            self.buf.clear();
+            self.buf.extend(seq.iter().map(u8::to_ascii_uppercase));
+            self.find_internal(self.window, seq.len() - self.window, &mut hits);
+        }
+        // The two halves of a junction (`rc(REV)` then `FWD`) cut at about
+        // the same place; keep one cut per primer length
+        let shortest = self.patterns.iter().map(|p| p.seq.len()).min().unwrap_or(0);
+        let mut cuts: Vec<usize> = Vec::new();
+        hits.sort_unstable();
+        for cut in hits {
+            if cuts.last().is_none_or(|&last| cut >= last + shortest) {
+                cuts.push(cut);
+            }
+        }
+        cuts
+    }
+
+    /// Add the cut of the best passing hit in `buf[lo..hi]`, then search
+    /// either side of it.
+    fn find_internal(&mut self, lo: usize, hi: usize, cuts: &mut Vec<usize>) {
+        let mut best: Option<(i32, usize, usize, ReadEnd)> = None;
+        for pattern in &self.patterns {
+            if hi - lo < pattern.seq.len() {
+                continue;
+            }
+            let aln = self.aligner.semiglobal(&pattern.seq, &self.buf[lo..hi]);
+            if aln.score < self.scoring.min_score
+                || identity(&aln.operations) < self.scoring.min_identity
+                || best.is_some_and(|(score, ..)| score >= aln.score)
+            {
+                continue;
+            }
+            best = Some((aln.score, lo + aln.ystart, lo + aln.yend, pattern.end));
+        }
+        let Some((_, start, stop, end)) = best else {
+            return;
+        };
+        cuts.push(match end {
+            ReadEnd::Start => start,
+            ReadEnd::End => stop,
+        });
+        self.find_internal(lo, start, cuts);
+        self.find_internal(stop, hi, cuts);
+    }
+
+    fn search(&mut self, seq: &[u8]) -> PrimerCall {
+        let mut call = PrimerCall::default();
+        if seq.is_empty() {
+            return call;
+        }
+        let Self {
+            patterns,
+            window,
+            scoring,
+            aligner,
+            buf,
+            ..
+        } = self;
+        let len = (*window).min(seq.len());
+        for pattern in patterns.iter() {
+            let offset = match pattern.end {
+                ReadEnd::Start => 0,
+                ReadEnd::End => seq.len() - len,
+            };
+            buf.clear();
+            buf.extend(seq[offset..offset + len].iter().map(u8::to_ascii_uppercase));
+
+            let aln = aligner.semiglobal(&pattern.seq, buf);
+            let identity = identity(&aln.operations);
+            let passed = aln.score >= scoring.min_score && identity >= scoring.min_identity;
+            call.alignments.push(PrimerHit {
+                primer: pattern.name,
+                end: pattern.end,
+                start: offset + aln.ystart,
+                stop: offset + aln.yend,
+                score: aln.score,
+                identity,
+                passed,
+            });
+            if !passed {
+                continue;
+            }
+            if pattern.orientation == b'+' {
+                call.plus_score += aln.score;
+            } else {
+                call.minus_score += aln.score;
+            }
+        }
+        if let Some(min_len) = self.poly_a_min {
+            let len = self.window.min(seq.len());
+            let tails = [
+                ("polyA", b'A', ReadEnd::End, seq.len() - len, b'+'),
+                ("polyT", b'T', ReadEnd::Start, 0, b'-'),
+            ];
+            for (name, base, end, offset, ori) in tails {
+                let (start, stop, run) = best_homopolymer(&seq[offset..offset + len], base);
+                let score = run as i32 * self.scoring.match_score;
+                let passed = run >= min_len;
+                call.alignments.push(PrimerHit {
+                    primer: name,
+                    end,
+                    start: offset + start,
+                    stop: offset + stop,
+                    score,
+                    identity: 1.0,
+                    passed,
+                });
+                if passed {
+                    if ori == b'+' {
+                        call.plus_score += score;
+                    } else {
+                        call.minus_score += score;
+                    }
+                }
+            }
+        }
+        call.orientation = match call.plus_score.cmp(&call.minus_score) {
+            Ordering::Greater => Some(b'+'),
+            Ordering::Less => Some(b'-'),
+            Ordering::Equal => None,
+        };
+        call
+    }
+}
+
+/// Share of alignment columns that are matches.
+fn identity(operations: &[AlignmentOperation]) -> f64 {
+    let (mut matches, mut columns) = (0usize, 0usize);
+    for op in operations {
+        match op {
+            AlignmentOperation::Match => {
+                matches += 1;
+                columns += 1;
+            }
+            AlignmentOperation::Subst | AlignmentOperation::Del | AlignmentOperation::Ins => {
+                columns += 1
+            }
+            _ => {}
+        }
+    }
+    if columns == 0 {
+        0.0
+    } else {
+        matches as f64 / columns as f64
+    }
+}
+
+/// Highest-scoring stretch rich in `base` (+1 per `base`, -2 otherwise), so a
+/// tail with the odd sequencing error still scores as one run. Returns the
+/// half-open span and its score.
+fn best_homopolymer(seq: &[u8], base: u8) -> (usize, usize, usize) {
+    let (mut best, mut best_span) = (0i64, (0, 0));
+    let (mut run, mut run_start) = (0i64, 0);
+    for (i, b) in seq.iter().enumerate() {
+        run += if b.to_ascii_uppercase() == base {
+            1
+        } else {
+            -2
+        };
+        if run <= 0 {
+            run = 0;
+            run_start = i + 1;
+        } else if run > best {
+            best = run;
+            best_span = (run_start, i + 1);
+        }
+    }
+    (best_span.0, best_span.1, best as usize)
+}
@@ -0,0 +1,105 @@
+//! Input/output byte accounting and the `--report-every` heartbeat line.
+
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+
+/// Shared count of raw (still compressed) bytes pulled from an input.
+#[derive(Debug, Clone, Default)]
+pub struct ByteCounter(Arc<AtomicU64>);
+
+impl ByteCounter {
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Reader adapter that adds every byte read to a [`ByteCounter`].
+pub struct CountingReader<R> {
+    inner: R,
+    counter: ByteCounter,
+}
+
+impl<R> CountingReader<R> {
+    pub fn new(inner: R, counter: &ByteCounter) -> Self {
+        Self {
+            inner,
+            counter: counter.clone(),
+        }
+    }
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.counter.0.fetch_add(n as u64, Ordering::Relaxed);
+        Ok(n)
+    }
+}
+
+impl<R: Seek> Seek for CountingReader<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.inner.seek(pos)
+    }
+}
+
+/// Writer adapter that adds every byte written to a [`ByteCounter`].
+pub struct CountingWriter<W> {
+    inner: W,
+    counter: ByteCounter,
+}
+
+impl<W> CountingWriter<W> {
+    pub fn new(inner: W, counter: &ByteCounter) -> Self {
+        Self {
+            inner,
+            counter: counter.clone(),
+        }
+    }
+}
+
+impl<W: Write> Write for CountingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.counter.0.fetch_add(n as u64, Ordering::Relaxed);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Prints one parseable progress line to stderr every `every` records.
+pub struct Heartbeat {
+    every: u64,
+    bytes: ByteCounter,
+    start: Instant,
+}
+
+impl Heartbeat {
+    /// `None` when `every` is unset or zero.
+    pub fn new(every: Option<u64>, bytes: &ByteCounter) -> Option<Self> {
+        every.filter(|&n| n > 0).map(|every| Self {
+            every,
+            bytes: bytes.clone(),
+            start: Instant::now(),
+        })
+    }
+
+    /// `orphans` is the count of reads without their mate, for paired output.
+    pub fn tick(&self, records: u64, flipped: u64, orphans: Option<u64>) {
+        if records % self.every == 0 {
+            let orphans = orphans.map_or(String::new(), |n| format!(" orphans={}", n));
+            eprintln!(
+                "progress records={} flipped={}{} bytes_read={} elapsed_secs={:.1}",
+                records,
+                flipped,
+                orphans,
+                self.bytes.get(),
+                self.start.elapsed().as_secs_f64()
+            );
+        }
+    }
+}
@@ -0,0 +1,105 @@
+//! Provenance for restranded outputs: a FASTA `;` comment line
+//! (`--provenance-comment`) and a JSON sidecar (`--provenance-out`).
+
+use anyhow::{Context, Result};
+use clap::parser::ValueSource;
+use clap::CommandFactory;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+/// Who produced an output and from what.
+#[derive(Debug, Serialize)]
+pub struct Provenance {
+    tool: &'static str,
+    version: &'static str,
+    command_line: String,
+    /// Options set on the command line or through environment variables
+    parameters: BTreeMap<String, Value>,
+    mode: &'static str,
+    input: String,
+    table: Option<String>,
+    target_orientation: String,
+}
+
+impl Provenance {
+    pub fn new(mode: &'static str, input: &str, table: Option<&Path>, target: u8) -> Self {
+        Self {
+            tool: env!("CARGO_PKG_NAME"),
+            version: env!("CARGO_PKG_VERSION"),
+            command_line: command_line(),
+            parameters: parameters(),
+            mode,
+            input: input.to_owned(),
+            table: table.map(|t| t.display().to_string()),
+            target_orientation: (target as char).to_string(),
+        }
+    }
+
+    /// `;`-prefixed FASTA comment line, newline included.
+    pub fn comment(&self) -> String {
+        format!("; {} {}: {}\n", self.tool, self.version, self.command_line)
+    }
+
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let mut w = BufWriter::new(
+            File::create(path).with_context(|| format!("create provenance {:?}", path))?,
+        );
+        serde_json::to_writer_pretty(&mut w, self).context("writing provenance")?;
+        w.write_all(b"\n")?;
+        w.flush()?;
+        Ok(())
+    }
+}
+
+/// The invocation as a shell-pasteable string; arguments with spaces or
+/// shell metacharacters are single-quoted.
+fn command_line() -> String {
+    std::env::args()
+        .map(|arg| {
+            let plain = !arg.is_empty()
+                && arg
+                    .bytes()
+                    .all(|b| b.is_ascii_alphanumeric() || b"-_./:=+,@%".contains(&b));
+            if plain {
+                arg
+            } else {
+                format!("'{}'", arg.replace('\'', r"'\''"))
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Every option given on the command line or taken from the environment,
+/// keyed by long name; repeated options become arrays.
+fn parameters() -> BTreeMap<String, Value> {
+    let mut params = BTreeMap::new();
+    let Ok(matches) = crate::Cli::command().try_get_matches_from(std::env::args_os()) else {
+        return params;
+    };
+    for id in matches.ids() {
+        let id = id.as_str();
+        if !matches!(
+            matches.value_source(id),
+            Some(ValueSource::CommandLine | ValueSource::EnvVariable)
+        ) {
+            continue;
+        }
+        let Ok(Some(raw)) = matches.try_get_raw(id) else {
+            continue;
+        };
+        let mut values: Vec<Value> = raw
+            .map(|v| Value::String(v.to_string_lossy().into_owned()))
+            .collect();
+        let value = match values.len() {
+            1 => values.pop().unwrap(),
+            _ => Value::Array(values),
+        };
+        params.insert(id.replace('_', "-"), value);
+    }
+    params
+}
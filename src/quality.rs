@@ -0,0 +1,26 @@
+//! Phred+33 quality helpers.
+
+use std::ops::Range;
+
+/// Mean quality of a read, averaged as error probabilities and converted
+/// back to a Phred score (as basecallers report it); `None` for empty reads.
+pub fn mean_phred(qual: &[u8]) -> Option<f64> {
+    if qual.is_empty() {
+        return None;
+    }
+    let sum: f64 = qual
+        .iter()
+        .map(|&q| 10f64.powf(-f64::from(q.saturating_sub(33)) / 10.0))
+        .sum();
+    Some(-10.0 * (sum / qual.len() as f64).log10())
+}
+
+/// Part of a read left after trimming bases below Phred `min_q` from both
+/// ends; empty when no base reaches it.
+pub fn trim_ends(qual: &[u8], min_q: u8) -> Range<usize> {
+    let good = |q: &u8| q.saturating_sub(33) >= min_q;
+    match (qual.iter().position(good), qual.iter().rposition(good)) {
+        (Some(start), Some(last)) => start..last + 1,
+        _ => 0..0,
+    }
+}
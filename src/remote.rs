@@ -0,0 +1,215 @@
+//! Object-store inputs and outputs (`s3://`, `gs://`), streamed through the
+//! provider's command-line client (`aws s3 cp`, `gcloud storage cat`/`cp`),
+//! and SRA runs (`--sra`) streamed by sra-tools' `fastq-dump --stdout`.
+//! Uploads go in parts as data arrives, so no local copy is needed, and the
+//! clients use whatever credentials they are configured with (for GCS, the
+//! active gcloud account). gcloud does not read `GOOGLE_APPLICATION_CREDENTIALS`
+//! itself, so a key file named there is handed to it as its credential file
+//! override. Builds without the `remote` feature leave the
+//! clients out: they still recognize these inputs and outputs, but refuse
+//! them.
+
+use std::path::Path;
+
+#[cfg(feature = "remote")]
+pub use client::{Download, Upload};
+#[cfg(not(feature = "remote"))]
+pub use disabled::{Download, Upload};
+
+/// True for `s3://` and `gs://` URIs.
+pub fn is_remote(path: &Path) -> bool {
+    path.to_str()
+        .is_some_and(|p| p.starts_with("s3://") || p.starts_with("gs://"))
+}
+
+/// Validate an SRA run accession (`--sra`).
+pub fn parse_accession(s: &str) -> Result<String, String> {
+    let (prefix, digits) = s.split_at(s.len().min(3));
+    if !matches!(prefix, "SRR" | "ERR" | "DRR")
+        || digits.is_empty()
+        || !digits.bytes().all(|b| b.is_ascii_digit())
+    {
+        return Err(format!(
+            "'{}' is not an SRA run accession (SRR/ERR/DRR followed by digits)",
+            s
+        ));
+    }
+    Ok(s.to_owned())
+}
+
+/// Input name standing for the SRA run `accession`.
+pub fn sra_input(accession: &str) -> String {
+    format!("sra:{}", accession)
+}
+
+/// The accession when `input` came from [`sra_input`].
+pub fn sra_accession(input: &str) -> Option<&str> {
+    input.strip_prefix("sra:")
+}
+
+#[cfg(feature = "remote")]
+mod client {
+    use crate::child::{self, ChildReader};
+    use anyhow::{bail, Context, Result};
+    use std::env;
+    use std::io::{self, Read};
+    use std::path::Path;
+    use std::process::{Child, ChildStdin, Command, Stdio};
+
+    /// Streams every read of an SRA run as FASTQ, mates as separate records,
+    /// without a prefetch step.
+    fn sra_command(accession: &str) -> Command {
+        let mut cmd = Command::new("fastq-dump");
+        cmd.args(["--stdout", "--skip-technical", "--split-spot", accession]);
+        cmd.stdin(Stdio::null()).stdout(Stdio::piped());
+        cmd
+    }
+
+    /// gcloud's environment form of `auth/credential_file_override`.
+    const CREDENTIAL_OVERRIDE: &str = "CLOUDSDK_AUTH_CREDENTIAL_FILE_OVERRIDE";
+
+    /// A `gcloud storage` invocation, authenticated with the key file in
+    /// `GOOGLE_APPLICATION_CREDENTIALS` when that is set and gcloud has no
+    /// credential file override of its own.
+    fn gcloud_storage(args: &[&str]) -> Command {
+        let mut cmd = Command::new("gcloud");
+        cmd.arg("storage").args(args);
+        if env::var_os(CREDENTIAL_OVERRIDE).is_none() {
+            if let Some(key) =
+                env::var_os("GOOGLE_APPLICATION_CREDENTIALS").filter(|k| !k.is_empty())
+            {
+                cmd.env(CREDENTIAL_OVERRIDE, key);
+            }
+        }
+        cmd
+    }
+
+    /// The client invocation that writes the object at `uri` to stdout.
+    fn download_command(uri: &str) -> Command {
+        let mut cmd = if uri.starts_with("s3://") {
+            let mut c = Command::new("aws");
+            c.args(["s3", "cp", uri, "-"]);
+            c
+        } else {
+            gcloud_storage(&["cat", uri])
+        };
+        cmd.stdin(Stdio::null()).stdout(Stdio::piped());
+        cmd
+    }
+
+    /// The client invocation that uploads stdin to `uri`.
+    fn upload_command(uri: &str) -> Command {
+        let mut cmd = if uri.starts_with("s3://") {
+            let mut c = Command::new("aws");
+            c.args(["s3", "cp", "-", uri]);
+            c
+        } else {
+            gcloud_storage(&["cp", "-", uri])
+        };
+        cmd.stdin(Stdio::piped()).stdout(Stdio::null());
+        cmd
+    }
+
+    /// A running upload: write the object to `stdin`, then [`Upload::finish`].
+    pub struct Upload {
+        uri: String,
+        child: Child,
+    }
+
+    impl Upload {
+        pub fn start(path: &Path) -> Result<(Self, ChildStdin)> {
+            let uri = path.to_string_lossy().into_owned();
+            let mut child = child::spawn(upload_command(&uri), &uri)?;
+            let stdin = child.stdin.take().expect("stdin is piped");
+            Ok((Self { uri, child }, stdin))
+        }
+
+        /// Wait for the client once its stdin has been closed.
+        pub fn finish(mut self) -> Result<()> {
+            let status = self
+                .child
+                .wait()
+                .with_context(|| format!("uploading {}", self.uri))?;
+            if !status.success() {
+                bail!("upload of {} failed ({})", self.uri, status);
+            }
+            Ok(())
+        }
+
+        /// Stop an unfinished upload so no partial object is committed.
+        pub fn abort(mut self) {
+            let _ = self.child.kill();
+            let _ = self.child.wait();
+        }
+    }
+
+    /// A streamed object; reading past the end fails if the client did.
+    pub struct Download(ChildReader);
+
+    impl Download {
+        pub fn start(uri: &str) -> Result<Self> {
+            ChildReader::run(download_command(uri), uri, "download").map(Self)
+        }
+
+        pub fn sra(accession: &str) -> Result<Self> {
+            ChildReader::run(sra_command(accession), accession, "download").map(Self)
+        }
+    }
+
+    impl Read for Download {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.0.read(buf)
+        }
+    }
+}
+
+/// Stand-ins for builds without the `remote` feature, which fail to start.
+#[cfg(not(feature = "remote"))]
+mod disabled {
+    use super::sra_input;
+    use anyhow::{bail, Result};
+    use std::io::{self, Read};
+    use std::path::Path;
+    use std::process::ChildStdin;
+
+    fn disabled<T>(what: &str) -> Result<T> {
+        bail!(
+            "{} needs the `remote` feature, which this build was compiled without",
+            what
+        )
+    }
+
+    pub enum Upload {}
+
+    impl Upload {
+        pub fn start(path: &Path) -> Result<(Self, ChildStdin)> {
+            disabled(&path.to_string_lossy())
+        }
+
+        pub fn finish(self) -> Result<()> {
+            match self {}
+        }
+
+        pub fn abort(self) {
+            match self {}
+        }
+    }
+
+    pub enum Download {}
+
+    impl Download {
+        pub fn start(uri: &str) -> Result<Self> {
+            disabled(uri)
+        }
+
+        pub fn sra(accession: &str) -> Result<Self> {
+            disabled(&sra_input(accession))
+        }
+    }
+
+    impl Read for Download {
+        fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+            match *self {}
+        }
+    }
+}
@@ -0,0 +1,293 @@
+//! Machine-readable JSON run report (`--report`).
+
+use crate::primers::{PrimerCall, ReadEnd};
+use crate::resources::Resources;
+use crate::timing::StageTime;
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+/// Sparse fixed-width read-length histogram.
+#[derive(Debug, Clone, Serialize)]
+pub struct LengthHistogram {
+    reads: u64,
+    bases: u64,
+    min: Option<u64>,
+    max: Option<u64>,
+    mean: Option<f64>,
+    bin_width: u64,
+    bins: Vec<Bin>,
+    #[serde(skip)]
+    counts: BTreeMap<u64, u64>,
+}
+
+/// One histogram bin covering lengths `[start, end)`.
+#[derive(Debug, Clone, Serialize)]
+struct Bin {
+    start: u64,
+    end: u64,
+    count: u64,
+}
+
+impl LengthHistogram {
+    pub fn new(bin_width: u64) -> Self {
+        Self {
+            reads: 0,
+            bases: 0,
+            min: None,
+            max: None,
+            mean: None,
+            bin_width: bin_width.max(1),
+            bins: Vec::new(),
+            counts: BTreeMap::new(),
+        }
+    }
+
+    pub fn observe(&mut self, len: u64) {
+        self.reads += 1;
+        self.bases += len;
+        self.min = Some(self.min.map_or(len, |m| m.min(len)));
+        self.max = Some(self.max.map_or(len, |m| m.max(len)));
+        *self.counts.entry(len / self.bin_width).or_insert(0) += 1;
+    }
+
+    /// Materialize the serialized fields from the running counts.
+    fn finish(&mut self) {
+        self.mean = (self.reads > 0).then(|| self.bases as f64 / self.reads as f64);
+        self.bins = self
+            .counts
+            .iter()
+            .map(|(&b, &count)| Bin {
+                start: b * self.bin_width,
+                end: (b + 1) * self.bin_width,
+                count,
+            })
+            .collect();
+    }
+}
+
+/// Read-length distributions, overall and split by outcome.
+#[derive(Debug, Serialize)]
+pub struct Lengths {
+    all: LengthHistogram,
+    by_final_orientation: BTreeMap<String, LengthHistogram>,
+    by_action: BTreeMap<String, LengthHistogram>,
+}
+
+/// Flip rate and strand balance of one `--group-col`/`--group-tag` group.
+#[derive(Debug, Default, Serialize)]
+pub struct GroupStats {
+    reads: u64,
+    flipped: u64,
+    kept: u64,
+    unknown: u64,
+    /// Reads that arrived in '+' / '-' orientation
+    plus: u64,
+    minus: u64,
+    flip_rate: Option<f64>,
+    /// plus / (plus + minus)
+    plus_fraction: Option<f64>,
+}
+
+impl GroupStats {
+    fn finish(&mut self) {
+        self.flip_rate = (self.reads > 0).then(|| self.flipped as f64 / self.reads as f64);
+        let called = self.plus + self.minus;
+        self.plus_fraction = (called > 0).then(|| self.plus as f64 / called as f64);
+    }
+}
+
+/// Where primer/adapter hits sit on the reads (`--adapter-report`).
+#[derive(Debug, Default, Serialize)]
+pub struct AdapterContent {
+    /// Reads searched
+    reads: u64,
+    /// Share of reads with an adapter covering each position, indexed from
+    /// the read start and from the read end
+    content: BTreeMap<&'static str, Vec<f64>>,
+    /// Passing hits per adapter by start position, counted from the read end it was found at
+    start_positions: BTreeMap<&'static str, BTreeMap<usize, u64>>,
+    #[serde(skip)]
+    covered: [Vec<u64>; 2],
+    #[serde(skip)]
+    scratch: Vec<bool>,
+}
+
+impl AdapterContent {
+    fn observe(&mut self, call: &PrimerCall, read_len: usize) {
+        self.reads += 1;
+        for (end, covered) in [ReadEnd::Start, ReadEnd::End]
+            .into_iter()
+            .zip(&mut self.covered)
+        {
+            self.scratch.clear();
+            for hit in call.alignments.iter().filter(|h| h.passed && h.end == end) {
+                if hit.is_homopolymer() {
+                    continue;
+                }
+                // Span measured inward from the end the hit was searched at
+                let (from, to) = match end {
+                    ReadEnd::Start => (hit.start, hit.stop),
+                    ReadEnd::End => (read_len - hit.stop, read_len - hit.start),
+                };
+                *self
+                    .start_positions
+                    .entry(hit.primer)
+                    .or_default()
+                    .entry(from)
+                    .or_default() += 1;
+                if self.scratch.len() < to {
+                    self.scratch.resize(to, false);
+                }
+                self.scratch[from..to].fill(true);
+            }
+            if covered.len() < self.scratch.len() {
+                covered.resize(self.scratch.len(), 0);
+            }
+            for (count, &hit) in covered.iter_mut().zip(&self.scratch) {
+                *count += u64::from(hit);
+            }
+        }
+    }
+
+    fn finish(&mut self) {
+        let reads = self.reads.max(1) as f64;
+        for (end, covered) in [ReadEnd::Start, ReadEnd::End].iter().zip(&self.covered) {
+            self.content.insert(
+                end.as_str(),
+                covered.iter().map(|&c| c as f64 / reads).collect(),
+            );
+        }
+    }
+}
+
+/// Run report accumulated while processing and written once at the end.
+#[derive(Debug, Serialize)]
+pub struct Report {
+    tool: &'static str,
+    version: &'static str,
+    mode: &'static str,
+    target_orientation: String,
+    counts: BTreeMap<&'static str, u64>,
+    read_lengths: Lengths,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    groups: BTreeMap<String, GroupStats>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    adapters: Option<AdapterContent>,
+    /// Wall/CPU seconds per pipeline stage, each excluding the stages nested in it
+    timings: BTreeMap<&'static str, StageTime>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    resources: Option<Resources>,
+    #[serde(skip)]
+    bin_width: u64,
+}
+
+impl Report {
+    pub fn new(mode: &'static str, target: u8, bin_width: u64) -> Self {
+        Self {
+            tool: env!("CARGO_PKG_NAME"),
+            version: env!("CARGO_PKG_VERSION"),
+            mode,
+            target_orientation: (target as char).to_string(),
+            counts: BTreeMap::new(),
+            read_lengths: Lengths {
+                all: LengthHistogram::new(bin_width),
+                by_final_orientation: BTreeMap::new(),
+                by_action: BTreeMap::new(),
+            },
+            groups: BTreeMap::new(),
+            adapters: None,
+            timings: BTreeMap::new(),
+            resources: None,
+            bin_width,
+        }
+    }
+
+    /// Record one input read: its length, the action taken (`flipped`, `kept`
+    /// or `unknown`) and its final orientation (`None` when unknown).
+    pub fn observe(&mut self, len: usize, action: &str, final_ori: Option<u8>) {
+        let len = len as u64;
+        let bin_width = self.bin_width;
+        let lengths = &mut self.read_lengths;
+        lengths.all.observe(len);
+        let ori_key = final_ori.map_or_else(|| "unknown".to_owned(), |o| (o as char).to_string());
+        lengths
+            .by_final_orientation
+            .entry(ori_key)
+            .or_insert_with(|| LengthHistogram::new(bin_width))
+            .observe(len);
+        lengths
+            .by_action
+            .entry(action.to_owned())
+            .or_insert_with(|| LengthHistogram::new(bin_width))
+            .observe(len);
+    }
+
+    /// Record one read of `group`: the action taken and the orientation it arrived in.
+    pub fn observe_group(&mut self, group: &str, action: &str, original: Option<u8>) {
+        if !self.groups.contains_key(group) {
+            self.groups.insert(group.to_owned(), GroupStats::default());
+        }
+        let g = self.groups.get_mut(group).expect("group just inserted");
+        g.reads += 1;
+        match action {
+            "flipped" => g.flipped += 1,
+            "kept" => g.kept += 1,
+            _ => g.unknown += 1,
+        }
+        match original {
+            Some(b'+') => g.plus += 1,
+            Some(_) => g.minus += 1,
+            None => {}
+        }
+    }
+
+    /// Start collecting adapter positions from primer calls.
+    pub fn enable_adapters(&mut self) {
+        self.adapters = Some(AdapterContent::default());
+    }
+
+    /// Record where the primer search placed adapters on a read of `read_len` bases.
+    pub fn observe_adapters(&mut self, call: &PrimerCall, read_len: usize) {
+        if let Some(a) = self.adapters.as_mut() {
+            a.observe(call, read_len);
+        }
+    }
+
+    pub fn set_count(&mut self, key: &'static str, value: u64) {
+        self.counts.insert(key, value);
+    }
+
+    pub fn set_timings(&mut self, timings: BTreeMap<&'static str, StageTime>) {
+        self.timings = timings;
+    }
+
+    pub fn set_resources(&mut self, resources: Resources) {
+        self.resources = Some(resources);
+    }
+
+    pub fn write(mut self, path: &Path) -> Result<()> {
+        let lengths = &mut self.read_lengths;
+        lengths.all.finish();
+        lengths
+            .by_final_orientation
+            .values_mut()
+            .chain(lengths.by_action.values_mut())
+            .for_each(LengthHistogram::finish);
+        self.groups.values_mut().for_each(GroupStats::finish);
+        if let Some(a) = self.adapters.as_mut() {
+            a.finish();
+        }
+
+        let mut w = BufWriter::new(
+            File::create(path).with_context(|| format!("create report {:?}", path))?,
+        );
+        serde_json::to_writer_pretty(&mut w, &self).context("writing JSON report")?;
+        w.write_all(b"\n")?;
+        w.flush()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,58 @@
+//! Whole-run resource usage for `--report`: wall and CPU time, peak RSS and
+//! bytes moved, for sizing pipeline resource requests from real runs.
+
+use serde::Serialize;
+use std::time::Instant;
+
+/// Resources one run used, measured at its end.
+#[derive(Debug, Serialize)]
+pub struct Resources {
+    wall_secs: f64,
+    /// `None` where `getrusage` is unavailable
+    user_cpu_secs: Option<f64>,
+    system_cpu_secs: Option<f64>,
+    /// High-water resident set size of the whole process
+    peak_rss_bytes: Option<u64>,
+    /// Raw (still compressed) input bytes
+    bytes_read: u64,
+    /// Bytes written to all read, side and patch outputs (compressed size for `.gz`)
+    bytes_written: u64,
+}
+
+impl Resources {
+    pub fn measure(started: Instant, bytes_read: u64, bytes_written: u64) -> Self {
+        let usage = rusage();
+        Self {
+            wall_secs: started.elapsed().as_secs_f64(),
+            user_cpu_secs: usage.map(|u| u.0),
+            system_cpu_secs: usage.map(|u| u.1),
+            peak_rss_bytes: usage.map(|u| u.2),
+            bytes_read,
+            bytes_written,
+        }
+    }
+}
+
+/// User CPU seconds, system CPU seconds and peak RSS bytes of this process.
+#[cfg(unix)]
+fn rusage() -> Option<(f64, f64, u64)> {
+    // SAFETY: `getrusage` fills the zeroed struct it is given.
+    let mut ru: libc::rusage = unsafe { std::mem::zeroed() };
+    let rc = unsafe { libc::getrusage(libc::RUSAGE_SELF, &mut ru) };
+    if rc != 0 {
+        return None;
+    }
+    let secs = |t: libc::timeval| t.tv_sec as f64 + t.tv_usec as f64 / 1e6;
+    // ru_maxrss is in bytes on macOS and KiB elsewhere
+    let scale = if cfg!(target_os = "macos") { 1 } else { 1024 };
+    Some((
+        secs(ru.ru_utime),
+        secs(ru.ru_stime),
+        ru.ru_maxrss as u64 * scale,
+    ))
+}
+
+#[cfg(not(unix))]
+fn rusage() -> Option<(f64, f64, u64)> {
+    None
+}
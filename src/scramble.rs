@@ -0,0 +1,147 @@
+//! `scramble` subcommand: reverse-complement a random fraction of reads and
+//! record which, producing a benchmark dataset with known orientations.
+//!
+//! Input reads are taken to be `+`; the truth table gives each output read's
+//! orientation (`-` for scrambled ones) in the `ReadName`/`orientation`
+//! layout that `--table` and `evaluate --truth` read by default.
+
+use crate::output::{self, Output};
+use crate::progress::ByteCounter;
+use crate::timing::Timings;
+use crate::{open_text, open_writer, revcomp_in_place, FASTA_WRAP_WIDTH};
+use anyhow::{Context, Result};
+use bio::io::fasta::{self, FastaRead};
+use bio::io::fastq::{self, FastqRead};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// Settings from the `scramble` subcommand.
+pub struct ScrambleOptions<'a> {
+    pub input: &'a str,
+    pub fastq: bool,
+    pub out: &'a Option<PathBuf>,
+    pub truth_out: &'a Path,
+    pub fraction: f64,
+    pub seed: u64,
+}
+
+/// Validate a fraction in `[0, 1]` (`--fraction` and other fraction options).
+pub fn parse_fraction(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(f) if (0.0..=1.0).contains(&f) => Ok(f),
+        _ => Err(format!("'{}' is not a fraction between 0 and 1", s)),
+    }
+}
+
+/// SplitMix64: small and seedable, so a given `--seed` always scrambles the
+/// same reads (and `simulate` the same dataset).
+pub struct SplitMix64(pub u64);
+
+impl SplitMix64 {
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform in `[0, 1)`.
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Uniform in `lo..=hi`.
+    pub fn range(&mut self, lo: usize, hi: usize) -> usize {
+        lo + (self.next_u64() % (hi - lo + 1) as u64) as usize
+    }
+}
+
+/// `id` and `desc` joined as a record's header line.
+pub fn set_header(header: &mut String, id: &str, desc: Option<&str>) {
+    header.clear();
+    header.push_str(id);
+    if let Some(desc) = desc.filter(|d| !d.is_empty()) {
+        header.push(' ');
+        header.push_str(desc);
+    }
+}
+
+pub fn scramble(opts: &ScrambleOptions) -> Result<()> {
+    let bytes_read = ByteCounter::default();
+    let timings = Timings::new(false);
+    let buffer = output::buffer_size(None, opts.input == "-");
+    let handle = open_text(opts.input, buffer, &bytes_read, &timings)?;
+    let mut out = open_writer(opts.out, None, &mut None)?;
+    let mut truth = Output::new(
+        Box::new(
+            std::fs::File::create(opts.truth_out)
+                .with_context(|| format!("creating {}", opts.truth_out.display()))?,
+        ),
+        output::is_gzip_path(opts.truth_out),
+        output::buffer_size(None, false),
+    );
+    writeln!(truth, "ReadName\torientation")?;
+
+    let mut rng = SplitMix64(opts.seed);
+    let (mut n_total, mut n_flipped) = (0u64, 0u64);
+    let (mut header, mut seq, mut out_buf) = (String::new(), Vec::new(), Vec::new());
+    let mut decide = |id: &str| -> Result<bool> {
+        let flip = rng.next_f64() < opts.fraction;
+        writeln!(truth, "{}\t{}", id, if flip { '-' } else { '+' })?;
+        Ok(flip)
+    };
+
+    if opts.fastq {
+        let mut reader = fastq::Reader::with_capacity(buffer, handle);
+        let mut record = fastq::Record::new();
+        let mut qual = Vec::new();
+        loop {
+            reader.read(&mut record).context("parsing FASTQ record")?;
+            if record.is_empty() {
+                break;
+            }
+            seq.clear();
+            seq.extend_from_slice(record.seq());
+            qual.clear();
+            qual.extend_from_slice(record.qual());
+            set_header(&mut header, record.id(), record.desc());
+            if decide(record.id())? {
+                revcomp_in_place(&mut seq);
+                qual.reverse();
+                n_flipped += 1;
+            }
+            n_total += 1;
+            out_buf.clear();
+            output::push_fastq(&mut out_buf, &header, &seq, &qual);
+            out.write_all(&out_buf)?;
+        }
+    } else {
+        let mut reader = fasta::Reader::with_capacity(buffer, handle);
+        let mut record = fasta::Record::new();
+        loop {
+            reader.read(&mut record).context("parsing FASTA record")?;
+            if record.is_empty() {
+                break;
+            }
+            seq.clear();
+            seq.extend_from_slice(record.seq());
+            set_header(&mut header, record.id(), record.desc());
+            if decide(record.id())? {
+                revcomp_in_place(&mut seq);
+                n_flipped += 1;
+            }
+            n_total += 1;
+            out_buf.clear();
+            output::push_fasta(&mut out_buf, &header, &seq, FASTA_WRAP_WIDTH);
+            out.write_all(&out_buf)?;
+        }
+    }
+    out.finish()?;
+    truth.finish()?;
+    eprintln!(
+        "scrambled {} of {} reads (seed {})",
+        n_flipped, n_total, opts.seed
+    );
+    Ok(())
+}
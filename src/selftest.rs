@@ -0,0 +1,177 @@
+//! `--self-test`: check on the first records of the input that everything a
+//! run does to a record can be undone. Flipping twice must give back the
+//! sequence and qualities byte for byte, the streamed writer must agree with
+//! the in-memory flip, every base must have a complement, and suffix and tag
+//! rewrites must be reversible. Nothing is written.
+
+use crate::output;
+use crate::progress::ByteCounter;
+use crate::scramble::set_header;
+use crate::timing::Timings;
+use crate::{open_text, read_buffer, revcomp_in_place, tags, Cli, Outcome, FASTA_WRAP_WIDTH};
+use anyhow::{bail, Context, Result};
+use bio::alphabets::dna;
+use bio::io::fasta::{self, FastaRead};
+use bio::io::fastq::{self, FastqRead};
+
+/// Failures printed before the rest are only counted.
+const MAX_SHOWN: usize = 10;
+
+/// Bases that are their own complement.
+const SELF_COMPLEMENTARY: &[u8] = b"NnSsWw-.*";
+
+struct Checker<'a> {
+    cli: &'a Cli,
+    checked: u64,
+    failures: Vec<String>,
+    flipped: Vec<u8>,
+    scratch: Vec<u8>,
+}
+
+impl Checker<'_> {
+    fn fail(&mut self, id: &str, what: String) {
+        self.failures.push(format!("{}: {}", id, what));
+    }
+
+    fn check(&mut self, id: &str, header: &str, seq: &[u8], qual: Option<&[u8]>) -> Result<()> {
+        self.checked += 1;
+        if let Some(&b) = seq
+            .iter()
+            .find(|&&b| dna::complement(b) == b && !SELF_COMPLEMENTARY.contains(&b))
+        {
+            self.fail(id, format!("base {:?} has no complement", b as char));
+        }
+
+        self.flipped.clear();
+        self.flipped.extend_from_slice(seq);
+        revcomp_in_place(&mut self.flipped);
+        let once = self.flipped.clone();
+        revcomp_in_place(&mut self.flipped);
+        if self.flipped != seq {
+            self.fail(id, "sequence differs after flipping twice".into());
+        }
+
+        if let Some(qual) = qual {
+            if qual.len() != seq.len() {
+                self.fail(
+                    id,
+                    format!("{} qualities for {} bases", qual.len(), seq.len()),
+                );
+            }
+            let mut twice = qual.to_vec();
+            twice.reverse();
+            twice.reverse();
+            if twice != qual {
+                self.fail(id, "qualities differ after flipping twice".into());
+            }
+        } else {
+            // Records over the streaming threshold take a separate code path
+            self.scratch.clear();
+            output::write_fasta_streamed(
+                &mut self.scratch,
+                header,
+                seq,
+                FASTA_WRAP_WIDTH,
+                true,
+                |_| {},
+            )?;
+            let streamed: Vec<u8> = self
+                .scratch
+                .split(|&b| b == b'\n')
+                .skip(1)
+                .flatten()
+                .copied()
+                .collect();
+            if streamed != once {
+                self.fail(id, "streamed and in-memory flips differ".into());
+            }
+        }
+
+        let suffix = &self.cli.flipped_suffix;
+        if !suffix.is_empty() && id.ends_with(suffix.as_str()) {
+            self.fail(
+                id,
+                format!(
+                    "ID already ends in --flipped-suffix '{}', so flipped and unflipped reads cannot be told apart",
+                    suffix
+                ),
+            );
+        }
+        if let Some(ori) = tags::orientation(header) {
+            let other = if ori == b'+' { b'-' } else { b'+' };
+            let back = tags::update(&tags::update(header, other, None), ori, None);
+            if back != header {
+                self.fail(
+                    id,
+                    format!("orientation tag rewrite is not reversible ({:?})", back),
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Check up to `--self-test-records` records and fail if any check does.
+pub fn self_test(cli: &Cli) -> Result<Outcome> {
+    let timings = Timings::new(false);
+    let buffer = read_buffer(cli);
+    let handle = open_text(cli.input(), buffer, &ByteCounter::default(), &timings)?;
+    let mut checker = Checker {
+        cli,
+        checked: 0,
+        failures: Vec::new(),
+        flipped: Vec::new(),
+        scratch: Vec::new(),
+    };
+    let mut header = String::new();
+    if cli.fastq {
+        let mut reader = fastq::Reader::with_capacity(buffer, handle);
+        let mut record = fastq::Record::new();
+        while checker.checked < cli.self_test_records {
+            reader.read(&mut record).context("parsing FASTQ record")?;
+            if record.is_empty() {
+                break;
+            }
+            set_header(&mut header, record.id(), record.desc());
+            checker.check(record.id(), &header, record.seq(), Some(record.qual()))?;
+        }
+    } else {
+        let mut reader = fasta::Reader::with_capacity(buffer, handle);
+        let mut record = fasta::Record::new();
+        while checker.checked < cli.self_test_records {
+            reader.read(&mut record).context("parsing FASTA record")?;
+            if record.is_empty() {
+                break;
+            }
+            set_header(&mut header, record.id(), record.desc());
+            checker.check(record.id(), &header, record.seq(), None)?;
+        }
+    }
+
+    let failures = &checker.failures;
+    for f in failures.iter().take(MAX_SHOWN) {
+        eprintln!("self-test: {}", f);
+    }
+    if failures.len() > MAX_SHOWN {
+        eprintln!("self-test: ... and {} more", failures.len() - MAX_SHOWN);
+    }
+    if !failures.is_empty() {
+        bail!(
+            "self-test failed: {} problem(s) in {} record(s) of {}",
+            failures.len(),
+            checker.checked,
+            cli.input()
+        );
+    }
+    if !cli.quiet {
+        eprintln!(
+            "self-test: {} record(s) of {} round-trip exactly",
+            checker.checked,
+            cli.input()
+        );
+    }
+    Ok(Outcome {
+        processed: checker.checked,
+        ..Outcome::default()
+    })
+}
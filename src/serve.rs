@@ -0,0 +1,341 @@
+//! `serve` subcommand: a minimal HTTP/1.1 endpoint for restranding request bodies.
+//!
+//! `POST /restrand?format=fasta|fastq&target=+|-` with the records as the body
+//! returns the restranded records; FASTA requests use the table given at
+//! startup, FASTQ requests the inline header tags. `GET /health` answers `ok`.
+//! Requests need a `Content-Length`; bodies are copied to a temporary file
+//! as they arrive and the output is streamed back from one. Each connection
+//! gets its own thread, up to [`MAX_CONNECTIONS`] at once. With
+//! `--metrics-listen`, Prometheus metrics are served on a second address.
+
+use crate::metrics::{self, Metrics};
+use crate::{run, Cleanup, Cli};
+use anyhow::{bail, Context, Result};
+use clap::Parser;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Largest request body accepted.
+const MAX_BODY: u64 = 1 << 30;
+
+/// Connections handled at once; further ones are answered `503` and closed.
+pub const MAX_CONNECTIONS: usize = 64;
+
+/// How long a read or write on a connection may stall before it is dropped.
+const IO_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Server settings from the `serve` subcommand.
+pub struct ServeOptions<'a> {
+    pub listen: &'a str,
+    pub table: Option<&'a Path>,
+    pub max_requests: Option<u64>,
+    pub tmpdir: PathBuf,
+    pub metrics_listen: Option<&'a str>,
+}
+
+/// A request line and headers; the body is left in the stream.
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    pub query: Vec<(String, String)>,
+    pub content_length: u64,
+}
+
+pub struct Response {
+    status: &'static str,
+    headers: Vec<(&'static str, String)>,
+    body: Body,
+}
+
+enum Body {
+    Bytes(Vec<u8>),
+    /// `len` bytes read from `file` as they are sent; the temporary files
+    /// go once the response is dropped
+    File {
+        file: File,
+        len: u64,
+        _cleanup: Cleanup,
+    },
+}
+
+impl Response {
+    pub fn text(status: &'static str, body: impl Into<String>) -> Self {
+        Self {
+            status,
+            headers: Vec::new(),
+            body: Body::Bytes(body.into().into_bytes()),
+        }
+    }
+}
+
+pub fn serve(opts: &ServeOptions) -> Result<()> {
+    let listener =
+        TcpListener::bind(opts.listen).with_context(|| format!("bind {}", opts.listen))?;
+    eprintln!("listening on {}", listener.local_addr()?);
+    let metrics = Arc::new(Metrics::default());
+    if let Some(addr) = opts.metrics_listen {
+        metrics::spawn_exporter(addr, metrics.clone())?;
+    }
+    accept(&listener, opts.max_requests, |stream, n| {
+        let mut reader = BufReader::new(stream);
+        let response = match read_request(&mut reader) {
+            Ok(req) => handle(opts, &req, &mut reader, n, &metrics),
+            Err(e) => Response::text("400 Bad Request", format!("{:#}\n", e)),
+        };
+        if let Err(e) = write_response(reader.get_mut(), response) {
+            eprintln!("warning: writing response: {:#}", e);
+        }
+    });
+    Ok(())
+}
+
+/// Hand each connection on `listener` to `handler` on its own thread, with
+/// read and write timeouts, until `limit` connections have been taken (or
+/// forever). Past [`MAX_CONNECTIONS`] open connections, new ones are turned
+/// away with `503`. Failed accepts are logged and skipped. Returns once every
+/// handler has finished.
+pub fn accept(listener: &TcpListener, limit: Option<u64>, handler: impl Fn(TcpStream, u64) + Sync) {
+    let open = AtomicUsize::new(0);
+    thread::scope(|scope| {
+        let mut n = 0u64;
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    eprintln!("warning: accepting connection: {}", e);
+                    continue;
+                }
+            };
+            let timeouts = stream
+                .set_read_timeout(Some(IO_TIMEOUT))
+                .and_then(|()| stream.set_write_timeout(Some(IO_TIMEOUT)));
+            if let Err(e) = timeouts {
+                eprintln!("warning: setting connection timeouts: {}", e);
+                continue;
+            }
+            if open.fetch_add(1, Ordering::SeqCst) >= MAX_CONNECTIONS {
+                open.fetch_sub(1, Ordering::SeqCst);
+                let busy = Response::text("503 Service Unavailable", "too many connections\n");
+                let _ = write_response(&mut &stream, busy);
+                continue;
+            }
+            let (open, handler) = (&open, &handler);
+            scope.spawn(move || {
+                handler(stream, n);
+                open.fetch_sub(1, Ordering::SeqCst);
+            });
+            n += 1;
+            if limit.is_some_and(|max| n >= max) {
+                break;
+            }
+        }
+    });
+}
+
+/// Read the request line and headers, leaving the body in `reader`.
+pub fn read_request(reader: &mut impl BufRead) -> Result<Request> {
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let mut parts = line.split_whitespace();
+    let (Some(method), Some(target)) = (parts.next(), parts.next()) else {
+        bail!("malformed request line");
+    };
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let query = query
+        .split('&')
+        .filter(|kv| !kv.is_empty())
+        .map(|kv| {
+            let (k, v) = kv.split_once('=').unwrap_or((kv, ""));
+            (k.to_owned(), percent_decode(v))
+        })
+        .collect();
+
+    let mut content_length = 0;
+    let mut header = String::new();
+    loop {
+        header.clear();
+        if reader.read_line(&mut header)? == 0 || header.trim_end().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("content-length") {
+                content_length = value.trim().parse::<u64>().context("bad Content-Length")?;
+            }
+        }
+    }
+    if content_length > MAX_BODY {
+        bail!("request body over {} bytes", MAX_BODY);
+    }
+    Ok(Request {
+        method: method.to_owned(),
+        path: path.to_owned(),
+        query,
+        content_length,
+    })
+}
+
+/// Copy the body of `req` from `reader` to `to` in buffer-sized pieces.
+fn copy_body(req: &Request, reader: &mut impl Read, to: &mut impl Write) -> Result<()> {
+    let copied = io::copy(&mut reader.take(req.content_length), to)?;
+    if copied < req.content_length {
+        bail!(
+            "request body ended after {} of {} bytes",
+            copied,
+            req.content_length
+        );
+    }
+    Ok(())
+}
+
+/// Decode `%XX` escapes. `+` is kept literally so `target=+` works.
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = (bytes[i] == b'%')
+            .then(|| bytes.get(i + 1..i + 3))
+            .flatten()
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(b) => {
+                out.push(b);
+                i += 3;
+            }
+            None => {
+                out.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+fn handle(
+    opts: &ServeOptions,
+    req: &Request,
+    body: &mut impl Read,
+    n: u64,
+    metrics: &Metrics,
+) -> Response {
+    match (req.method.as_str(), req.path.as_str()) {
+        ("GET", "/health") => Response::text("200 OK", "ok\n"),
+        ("POST", "/restrand") => match restrand(opts, req, body, n, metrics) {
+            Ok(resp) => resp,
+            Err(e) => {
+                metrics.error();
+                Response::text("422 Unprocessable Entity", format!("{:#}\n", e))
+            }
+        },
+        (_, "/restrand") | (_, "/health") => Response::text("405 Method Not Allowed", ""),
+        _ => Response::text("404 Not Found", ""),
+    }
+}
+
+/// Run the body through the normal pipeline via temporary files.
+fn restrand(
+    opts: &ServeOptions,
+    req: &Request,
+    body: &mut impl Read,
+    n: u64,
+    metrics: &Metrics,
+) -> Result<Response> {
+    let param = |key: &str| {
+        req.query
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    };
+    let fastq = match param("format").unwrap_or("fasta") {
+        "fasta" => false,
+        "fastq" => true,
+        other => bail!("format must be 'fasta' or 'fastq', got '{}'", other),
+    };
+    let tmp = |tag: &str| -> PathBuf {
+        opts.tmpdir.join(format!(
+            "restrand-serve-{}-{}.{}",
+            std::process::id(),
+            n,
+            tag
+        ))
+    };
+    let (input, output) = (tmp("in"), tmp("out"));
+    let cleanup = Cleanup(vec![input.clone(), output.clone()]);
+    let mut file = File::create(&input)?;
+    if let Err(e) = copy_body(req, body, &mut file).and_then(|()| Ok(file.flush()?)) {
+        metrics.error();
+        return Ok(Response::text("400 Bad Request", format!("{:#}\n", e)));
+    }
+    drop(file);
+
+    let mut args: Vec<String> = vec![
+        "restrand-fasta".into(),
+        "-q".into(),
+        "-f".into(),
+        input.to_string_lossy().into_owned(),
+        "-o".into(),
+        output.to_string_lossy().into_owned(),
+        "--target-orientation".into(),
+        param("target").unwrap_or("+").to_owned(),
+        "--tmpdir".into(),
+        opts.tmpdir.to_string_lossy().into_owned(),
+    ];
+    if fastq {
+        args.push("--fastq".into());
+    } else if let Some(table) = opts.table {
+        args.push("-t".into());
+        args.push(table.to_string_lossy().into_owned());
+    }
+    let cli = Cli::try_parse_from(&args).map_err(|e| anyhow::anyhow!("{}", e))?;
+    let started = Instant::now();
+    let outcome = run(&cli)?;
+    metrics.record(&outcome, started.elapsed());
+    let file = File::open(&output)?;
+    let len = file.metadata()?.len();
+    Ok(Response {
+        status: "200 OK",
+        headers: vec![(
+            "X-Restrand-Summary",
+            format!(
+                "processed={} flipped={} missing={} warnings={}",
+                outcome.processed, outcome.flipped, outcome.missing, outcome.warnings
+            ),
+        )],
+        body: Body::File {
+            file,
+            len,
+            _cleanup: cleanup,
+        },
+    })
+}
+
+pub fn write_response(stream: &mut impl Write, resp: Response) -> Result<()> {
+    let len = match &resp.body {
+        Body::Bytes(bytes) => bytes.len() as u64,
+        Body::File { len, .. } => *len,
+    };
+    let mut head = format!(
+        "HTTP/1.1 {}\r\nContent-Type: text/plain\r\nContent-Length: {}\r\nConnection: close\r\n",
+        resp.status, len
+    );
+    for (name, value) in &resp.headers {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    head.push_str("\r\n");
+    stream.write_all(head.as_bytes())?;
+    match resp.body {
+        Body::Bytes(bytes) => stream.write_all(&bytes)?,
+        Body::File { file, len, .. } => {
+            io::copy(&mut file.take(len), stream)?;
+        }
+    }
+    stream.flush()?;
+    Ok(())
+}
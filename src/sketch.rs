@@ -0,0 +1,152 @@
+//! Alignment-free orientation against a reference (`--reference`): the
+//! reference's forward-strand minimizers are collected once, and each read is
+//! called `+` or `-` by whether the read or its reverse complement shares more
+//! of them.
+//!
+//! Minimizers are not canonical, so the reference must be in the orientation
+//! reads should end up in (e.g. transcript sequences, not a genome with genes
+//! on both strands).
+
+use anyhow::{bail, Context, Result};
+use bio::alphabets::dna;
+use bio::io::fasta;
+use std::collections::HashSet;
+use std::path::Path;
+
+/// Settings for [`Sketch::build`].
+#[derive(Debug, Clone, Copy)]
+pub struct SketchParams {
+    /// k-mer length (1..=31)
+    pub k: usize,
+    /// Window of consecutive k-mers each minimizer is picked from
+    pub w: usize,
+    /// Shared minimizers the winning strand needs
+    pub min_shared: usize,
+}
+
+/// Strand call for one read.
+#[derive(Debug, Clone, Copy)]
+pub struct SketchCall {
+    pub orientation: u8,
+    /// Share of all matching minimizers on the winning strand
+    pub confidence: f64,
+}
+
+/// Forward-strand minimizers of the reference.
+pub struct Sketch {
+    params: SketchParams,
+    minimizers: HashSet<u64>,
+    hashes: Vec<Option<u64>>,
+    found: Vec<u64>,
+}
+
+impl Sketch {
+    pub fn build(path: &Path, params: SketchParams) -> Result<Self> {
+        if !(1..=31).contains(&params.k) || params.w == 0 {
+            bail!(
+                "--minimizer-k must be 1-31 and --minimizer-w at least 1 (got k={}, w={})",
+                params.k,
+                params.w
+            );
+        }
+        let reader = fasta::Reader::from_file(path)
+            .with_context(|| format!("open reference FASTA {:?}", path))?;
+        let mut sketch = Self {
+            params,
+            minimizers: HashSet::new(),
+            hashes: Vec::new(),
+            found: Vec::new(),
+        };
+        for rec in reader.records() {
+            let rec = rec.context("parsing reference FASTA")?;
+            sketch.collect(rec.seq());
+            let Self {
+                minimizers, found, ..
+            } = &mut sketch;
+            minimizers.extend(found.drain(..));
+        }
+        if sketch.minimizers.is_empty() {
+            bail!(
+                "reference {:?} has no sequence of at least {} bases",
+                path,
+                params.k
+            );
+        }
+        Ok(sketch)
+    }
+
+    /// Call the strand of `seq`; `None` when neither strand reaches
+    /// `min_shared` or both share the same number.
+    pub fn call(&mut self, seq: &[u8]) -> Option<SketchCall> {
+        let plus = self.shared(seq);
+        let minus = self.shared(&dna::revcomp(seq));
+        let best = plus.max(minus);
+        if best < self.params.min_shared || plus == minus {
+            return None;
+        }
+        Some(SketchCall {
+            orientation: if plus > minus { b'+' } else { b'-' },
+            confidence: best as f64 / (plus + minus) as f64,
+        })
+    }
+
+    /// Distinct minimizers of `seq` present in the reference.
+    fn shared(&mut self, seq: &[u8]) -> usize {
+        self.collect(seq);
+        self.found.sort_unstable();
+        self.found.dedup();
+        let n = self
+            .found
+            .iter()
+            .filter(|h| self.minimizers.contains(h))
+            .count();
+        self.found.clear();
+        n
+    }
+
+    /// Push the (w, k)-minimizers of `seq` onto `found`. Windows containing
+    /// a non-ACGT base contribute nothing.
+    fn collect(&mut self, seq: &[u8]) {
+        let SketchParams { k, w, .. } = self.params;
+        let mask = (1u64 << (2 * k)) - 1;
+        self.hashes.clear();
+        let (mut kmer, mut valid) = (0u64, 0usize);
+        for (i, &b) in seq.iter().enumerate() {
+            let code = match b.to_ascii_uppercase() {
+                b'A' => Some(0),
+                b'C' => Some(1),
+                b'G' => Some(2),
+                b'T' => Some(3),
+                _ => None,
+            };
+            match code {
+                Some(c) => {
+                    kmer = ((kmer << 2) | c) & mask;
+                    valid += 1;
+                }
+                None => valid = 0,
+            }
+            if i + 1 >= k {
+                self.hashes.push((valid >= k).then(|| mix(kmer)));
+            }
+        }
+        let mut last = None;
+        for window in self.hashes.windows(w.min(self.hashes.len()).max(1)) {
+            if !window.iter().all(Option::is_some) {
+                continue;
+            }
+            let min = window.iter().flatten().min().copied();
+            if min != last {
+                self.found.extend(min);
+                last = min;
+            }
+        }
+    }
+}
+
+/// Invertible 64-bit mix, so minimizers are not biased towards poly-A k-mers.
+fn mix(mut x: u64) -> u64 {
+    x = (x ^ (x >> 33)).wrapping_mul(0xff51_afd7_ed55_8ccd);
+    x = (x ^ (x >> 33)).wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+    x ^ (x >> 33)
+}
@@ -0,0 +1,122 @@
+//! Transcript strand from spliced alignments (`--paf`): minimap2 `-x splice`
+//! output already says which strand of the transcript each read came from,
+//! which for unstranded cDNA is what orientation should follow rather than
+//! the mapping strand.
+//!
+//! A read's `ts:A` tag is taken as is; it is relative to the read, so `+`
+//! means the read is in transcript orientation. Without it, the introns in
+//! the `cs` tag are read for their splice motifs. `cs` spells introns on the
+//! reference forward strand (`~gt123ag`), so GT-AG and GC-AG introns put the
+//! transcript on the reference `+` strand, CT-AC and CT-GC on `-`, and the
+//! read's orientation is its mapping strand, flipped for a `-` transcript.
+//! Only primary alignments (`tp:A:P`, or no `tp` tag) are used, and the
+//! first line with a call decides each read.
+
+use crate::compress;
+use anyhow::{bail, Context, Result};
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+
+/// Strand call for one read.
+#[derive(Debug, Clone, Copy)]
+pub struct SpliceCall {
+    pub orientation: u8,
+    /// 1 for `ts:A`; otherwise the share of canonical introns that agree
+    pub confidence: f64,
+}
+
+/// Per-read transcript strands from one alignment file.
+pub struct SpliceStrands {
+    calls: HashMap<String, SpliceCall>,
+}
+
+impl SpliceStrands {
+    pub fn from_paf(path: &Path) -> Result<Self> {
+        let reader = BufReader::new(compress::open(path)?);
+        let mut calls = HashMap::new();
+        let mut tagged = false;
+        for (n, line) in reader.lines().enumerate() {
+            let line = line.with_context(|| format!("read --paf {:?}", path))?;
+            if line.is_empty() {
+                continue;
+            }
+            let fields: Vec<&str> = line.split('\t').collect();
+            if fields.len() < 12 {
+                bail!(
+                    "--paf {:?} line {}: expected at least 12 tab-separated columns, found {}",
+                    path,
+                    n + 1,
+                    fields.len()
+                );
+            }
+            let mapped = match fields[4] {
+                "+" => b'+',
+                "-" => b'-',
+                _ => continue,
+            };
+            let tags = &fields[12..];
+            let tag = |name: &str| tags.iter().find_map(|t| t.strip_prefix(name));
+            tagged |= tag("ts:A:").is_some() || tag("cs:Z:").is_some();
+            if tag("tp:A:").is_some_and(|tp| tp != "P") || calls.contains_key(fields[0]) {
+                continue;
+            }
+            let call = match tag("ts:A:") {
+                Some("+") => Some(SpliceCall {
+                    orientation: b'+',
+                    confidence: 1.0,
+                }),
+                Some("-") => Some(SpliceCall {
+                    orientation: b'-',
+                    confidence: 1.0,
+                }),
+                _ => tag("cs:Z:").and_then(|cs| motif_call(cs, mapped)),
+            };
+            if let Some(call) = call {
+                calls.insert(fields[0].to_owned(), call);
+            }
+        }
+        if !tagged {
+            eprintln!(
+                "warning: --paf {:?} has no ts:A or cs tags; align with minimap2 -x splice --cs",
+                path
+            );
+        }
+        Ok(Self { calls })
+    }
+
+    pub fn call(&self, read_id: &str) -> Option<SpliceCall> {
+        self.calls.get(read_id).copied()
+    }
+}
+
+/// Vote the introns of a `cs` string by splice motif; a tie makes no call.
+fn motif_call(cs: &str, mapped: u8) -> Option<SpliceCall> {
+    let (mut plus, mut minus) = (0u32, 0u32);
+    for intron in cs.split('~').skip(1) {
+        let donor = intron.get(..2).unwrap_or("").to_ascii_lowercase();
+        let digits = intron[donor.len()..]
+            .bytes()
+            .take_while(u8::is_ascii_digit)
+            .count();
+        let rest = &intron[donor.len() + digits..];
+        let acceptor = rest.get(..2).unwrap_or("").to_ascii_lowercase();
+        match (donor.as_str(), acceptor.as_str()) {
+            ("gt" | "gc", "ag") => plus += 1,
+            ("ct", "ac" | "gc") => minus += 1,
+            _ => {}
+        }
+    }
+    if plus == minus {
+        return None;
+    }
+    let transcript_plus = plus > minus;
+    let orientation = match (mapped == b'+', transcript_plus) {
+        (true, true) | (false, false) => b'+',
+        _ => b'-',
+    };
+    Some(SpliceCall {
+        orientation,
+        confidence: f64::from(plus.max(minus)) / f64::from(plus + minus),
+    })
+}
@@ -0,0 +1,111 @@
+//! `--split-on-internal-adapter`: reads with primers away from their ends
+//! (ligation chimeras) are cut there into fragments, which then go through
+//! the run as separate reads named `ID:START-END` and are oriented on their
+//! own. Each fragment keeps the primers that bound it, so primer detection
+//! calls it as it would a whole molecule.
+//!
+//! A fragment's ID is not in the table, and the parent's orientation tag is
+//! removed from its description since it says nothing about the fragment.
+
+use crate::primers::PrimerDetector;
+use crate::tags;
+use bio::io::{fasta, fastq};
+use std::cell::Cell;
+use std::collections::VecDeque;
+use std::io;
+use std::rc::Rc;
+
+/// Counts of split reads and the fragments they gave.
+#[derive(Debug, Clone, Default)]
+pub struct SplitCounter {
+    reads: Rc<Cell<u64>>,
+    fragments: Rc<Cell<u64>>,
+}
+
+impl SplitCounter {
+    pub fn reads(&self) -> u64 {
+        self.reads.get()
+    }
+
+    pub fn fragments(&self) -> u64 {
+        self.fragments.get()
+    }
+}
+
+/// A fragment waiting to be returned: ID, description, sequence, quality.
+type Fragment = (String, Option<String>, Vec<u8>, Vec<u8>);
+
+/// Reader adapter that returns the fragments of chimeric reads in place of
+/// the reads.
+pub struct Splitter<R> {
+    inner: R,
+    detector: PrimerDetector,
+    counter: SplitCounter,
+    pending: VecDeque<Fragment>,
+}
+
+impl<R> Splitter<R> {
+    pub fn new(inner: R, detector: PrimerDetector, counter: &SplitCounter) -> Self {
+        Splitter {
+            inner,
+            detector,
+            counter: counter.clone(),
+            pending: VecDeque::new(),
+        }
+    }
+
+    /// Queue the fragments of a read cut at internal primers; false when
+    /// there are none and the read stays whole.
+    fn cut(&mut self, id: &str, desc: Option<&str>, seq: &[u8], qual: &[u8]) -> bool {
+        let cuts = self.detector.internal_cuts(seq);
+        if cuts.is_empty() {
+            return false;
+        }
+        let desc = desc.map(tags::strip).filter(|d| !d.is_empty());
+        let mut bounds = vec![0];
+        bounds.extend(cuts);
+        bounds.push(seq.len());
+        for w in bounds.windows(2) {
+            let (start, stop) = (w[0], w[1]);
+            self.pending.push_back((
+                format!("{}:{}-{}", id, start, stop),
+                desc.clone(),
+                seq[start..stop].to_vec(),
+                qual.get(start..stop).unwrap_or_default().to_vec(),
+            ));
+        }
+        self.counter.reads.set(self.counter.reads.get() + 1);
+        let n = self.counter.fragments.get() + bounds.len() as u64 - 1;
+        self.counter.fragments.set(n);
+        true
+    }
+}
+
+impl fasta::FastaRead for Splitter<Box<dyn fasta::FastaRead>> {
+    fn read(&mut self, record: &mut fasta::Record) -> io::Result<()> {
+        if self.pending.is_empty() {
+            self.inner.read(record)?;
+            if record.is_empty() || !self.cut(record.id(), record.desc(), record.seq(), &[]) {
+                return Ok(());
+            }
+        }
+        let (id, desc, seq, _) = self.pending.pop_front().unwrap();
+        *record = fasta::Record::with_attrs(&id, desc.as_deref(), &seq);
+        Ok(())
+    }
+}
+
+impl fastq::FastqRead for Splitter<Box<dyn fastq::FastqRead>> {
+    fn read(&mut self, record: &mut fastq::Record) -> fastq::Result<()> {
+        if self.pending.is_empty() {
+            self.inner.read(record)?;
+            let (id, desc, seq, qual) = (record.id(), record.desc(), record.seq(), record.qual());
+            if record.is_empty() || !self.cut(id, desc, seq, qual) {
+                return Ok(());
+            }
+        }
+        let (id, desc, seq, qual) = self.pending.pop_front().unwrap();
+        *record = fastq::Record::with_attrs(&id, desc.as_deref(), &seq, &qual);
+        Ok(())
+    }
+}
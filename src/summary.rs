@@ -0,0 +1,80 @@
+//! End-of-run summary: one stderr line by default, or `--summary-file` in human/TSV form.
+
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use std::fmt::Display;
+use std::fs;
+use std::path::Path;
+
+/// Rendering of the run summary.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum SummaryFormat {
+    /// Single `MODE mode: key=value ...` line
+    Human,
+    /// Two-column `metric<TAB>value` table with a header row
+    Tsv,
+}
+
+/// Ordered summary counters for one run.
+pub struct Summary {
+    mode: &'static str,
+    fields: Vec<(&'static str, String)>,
+    human_suffix: String,
+}
+
+impl Summary {
+    pub fn new(mode: &'static str) -> Self {
+        Self {
+            mode,
+            fields: Vec::new(),
+            human_suffix: String::new(),
+        }
+    }
+
+    pub fn field(&mut self, key: &'static str, value: impl Display) -> &mut Self {
+        self.fields.push((key, value.to_string()));
+        self
+    }
+
+    /// Free text appended to the human form only (e.g. `(kept mode)`).
+    pub fn human_suffix(&mut self, suffix: impl Into<String>) -> &mut Self {
+        self.human_suffix = suffix.into();
+        self
+    }
+
+    pub fn render(&self, format: SummaryFormat) -> String {
+        match format {
+            SummaryFormat::Human => {
+                let mut line = format!("{} mode:", self.mode.to_ascii_uppercase());
+                for (k, v) in &self.fields {
+                    line.push_str(&format!(" {}={}", k, v));
+                }
+                line.push_str(&self.human_suffix);
+                line.push('\n');
+                line
+            }
+            SummaryFormat::Tsv => {
+                let mut out = format!("metric\tvalue\nmode\t{}\n", self.mode);
+                for (k, v) in &self.fields {
+                    out.push_str(&format!("{}\t{}\n", k, v));
+                }
+                out
+            }
+        }
+    }
+
+    /// Write to `path` if given, otherwise to stderr unless `quiet`.
+    pub fn emit(&self, path: Option<&Path>, format: SummaryFormat, quiet: bool) -> Result<()> {
+        match path {
+            Some(p) => {
+                fs::write(p, self.render(format)).with_context(|| format!("write summary {:?}", p))
+            }
+            None => {
+                if !quiet {
+                    eprint!("{}", self.render(format));
+                }
+                Ok(())
+            }
+        }
+    }
+}
@@ -0,0 +1,660 @@
+//! Per-read orientation table (`--table`), held in memory or, once it would
+//! exceed `--max-memory`, as a sorted file on disk searched through a sparse
+//! in-memory index, or mapped from a `--table-cache` file.
+
+use crate::compress;
+use crate::table_cache::CachedTable;
+use anyhow::{bail, Context, Result};
+use bio::alphabets::dna;
+use clap::ValueEnum;
+use csv::ReaderBuilder;
+use md5::{Digest, Md5};
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+/// Rough heap cost of one in-memory entry beyond its ID bytes.
+const ENTRY_OVERHEAD: u64 = 56;
+
+/// One index key per this many rows of the on-disk table.
+const INDEX_STRIDE: usize = 256;
+
+/// Parse a size such as `512M`, `2G` or a plain byte count (binary units).
+pub fn parse_size(s: &str) -> Result<u64, String> {
+    let s = s.trim();
+    let (digits, unit) = s.split_at(s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len()));
+    let n: u64 = digits
+        .parse()
+        .map_err(|_| format!("invalid size '{}' (e.g. 512M, 2G)", s))?;
+    let shift = match unit
+        .trim_end_matches(['B', 'b'])
+        .to_ascii_uppercase()
+        .as_str()
+    {
+        "" => 0,
+        "K" => 10,
+        "M" => 20,
+        "G" => 30,
+        "T" => 40,
+        _ => return Err(format!("invalid size unit in '{}' (use K, M, G or T)", s)),
+    };
+    n.checked_mul(1 << shift)
+        .ok_or_else(|| format!("size '{}' is too large", s))
+}
+
+/// Which part of a record's header is looked up in the table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum MatchOn {
+    /// The read ID (header up to the first whitespace)
+    Id,
+    /// The whole header line after '>', ID and description joined by one space
+    FullHeader,
+    /// MD5 (hex) of the uppercased sequence; a read whose reverse complement is listed takes the opposite orientation
+    Sequence,
+    /// The read ID, else for PacBio names (movie/zmw/ccs, movie/zmw/start_end) its movie/zmw, movie/zmw/ccs or bare ZMW
+    Pacbio,
+}
+
+impl MatchOn {
+    /// The header part naming a record with this `id` and full `header`;
+    /// sequence matching names records by ID.
+    pub fn key<'a>(self, id: &'a str, header: &'a str) -> &'a str {
+        match self {
+            MatchOn::Id | MatchOn::Sequence | MatchOn::Pacbio => id,
+            MatchOn::FullHeader => header,
+        }
+    }
+}
+
+/// The `--match-on sequence` key: lowercase hex MD5 of the uppercased
+/// sequence, as `printf %s SEQ | md5sum` prints it.
+pub fn sequence_key(seq: &[u8]) -> String {
+    let digest = Md5::new().chain_update(seq.to_ascii_uppercase()).finalize();
+    digest.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Table keys a PacBio read name `movie/zmw/...` may be listed under after
+/// the name itself, most specific first; empty for other names. By-strand
+/// CCS reads (`/ccs/fwd`, `/ccs/rev`) only match exactly, as the two
+/// strands of a ZMW have opposite orientations.
+pub fn pacbio_keys(id: &str) -> Vec<String> {
+    let mut parts = id.splitn(3, '/');
+    let (Some(movie), Some(zmw), suffix) = (parts.next(), parts.next(), parts.next()) else {
+        return Vec::new();
+    };
+    let is_movie = movie.starts_with('m') && movie.contains('_');
+    let is_zmw = !zmw.is_empty() && zmw.bytes().all(|b| b.is_ascii_digit());
+    if !is_movie || !is_zmw || suffix.is_some_and(|s| s.starts_with("ccs/")) {
+        return Vec::new();
+    }
+    let keys = [
+        format!("{}/{}", movie, zmw),
+        format!("{}/{}/ccs", movie, zmw),
+        zmw.to_owned(),
+    ];
+    keys.into_iter().filter(|k| k != id).collect()
+}
+
+/// Orientation lookups by read ID.
+pub enum OrientationTable {
+    Memory(Arc<HashMap<String, u8>>),
+    Disk(DiskTable),
+    Cached(Arc<CachedTable>),
+}
+
+impl OrientationTable {
+    pub fn get(&mut self, id: &str) -> Result<Option<u8>> {
+        match self {
+            OrientationTable::Memory(map) => Ok(map.get(id).copied()),
+            OrientationTable::Disk(disk) => disk.get(id),
+            OrientationTable::Cached(cached) => cached.get(id),
+        }
+    }
+
+    /// Look a record up as `match_on` says. Sequence keys fall back to the
+    /// reverse complement's entry, with its orientation inverted.
+    pub fn lookup(
+        &mut self,
+        match_on: MatchOn,
+        id: &str,
+        header: &str,
+        seq: &[u8],
+    ) -> Result<Option<u8>> {
+        if match_on == MatchOn::Pacbio {
+            if let Some(ori) = self.get(id)? {
+                return Ok(Some(ori));
+            }
+            for key in pacbio_keys(id) {
+                if let Some(ori) = self.get(&key)? {
+                    return Ok(Some(ori));
+                }
+            }
+            return Ok(None);
+        }
+        if match_on != MatchOn::Sequence {
+            return self.get(match_on.key(id, header));
+        }
+        if let Some(ori) = self.get(&sequence_key(seq))? {
+            return Ok(Some(ori));
+        }
+        let opposite = |o: u8| if o == b'+' { b'-' } else { b'+' };
+        Ok(self.get(&sequence_key(&dna::revcomp(seq)))?.map(opposite))
+    }
+
+    /// Visit every row in ascending ID order.
+    pub fn for_each_sorted(&mut self, mut f: impl FnMut(&str, u8) -> Result<()>) -> Result<()> {
+        match self {
+            OrientationTable::Memory(map) => {
+                let mut rows: Vec<_> = map.iter().collect();
+                rows.sort_unstable();
+                rows.into_iter().try_for_each(|(id, &ori)| f(id, ori))
+            }
+            OrientationTable::Disk(disk) => disk.for_each(f),
+            OrientationTable::Cached(cached) => cached.for_each(f),
+        }
+    }
+
+    /// An empty in-memory table, for runs without `--table`.
+    pub fn empty() -> Self {
+        OrientationTable::Memory(Arc::default())
+    }
+
+    pub fn is_on_disk(&self) -> bool {
+        matches!(self, OrientationTable::Disk(_))
+    }
+}
+
+/// How orientation values in the table are written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Encoding {
+    /// Detect from the first rows: SAM flags if any value is an integer other than -1/0/1, else strand
+    Auto,
+    /// '+'/'-', F/R, plus/fwd/forward/minus/rev/reverse/rc words, 1 (+) and 0 (-)
+    Strand,
+    /// 1 or +1 (+) and -1 (-)
+    Numeric,
+    /// SAM FLAG integers: bit 0x10 set (e.g. 16) is '-', clear (e.g. 0) is '+'
+    SamFlag,
+    /// Primer pairs in read order, as lima names them: '5p--3p' is '+', '3p--5p' is '-'
+    PrimerPair,
+}
+
+impl Encoding {
+    /// Stable code stored in `--table-cache` files.
+    pub fn code(self) -> u32 {
+        match self {
+            Encoding::Auto => 0,
+            Encoding::Strand => 1,
+            Encoding::Numeric => 2,
+            Encoding::SamFlag => 3,
+            Encoding::PrimerPair => 4,
+        }
+    }
+
+    /// Pick a concrete encoding from sample values (`Auto` only).
+    pub fn detect<'a>(self, values: impl IntoIterator<Item = &'a str>) -> Self {
+        if self != Encoding::Auto {
+            return self;
+        }
+        let sam = values
+            .into_iter()
+            .any(|v| v.parse::<i64>().is_ok_and(|n| !(-1..=1).contains(&n)));
+        if sam {
+            Encoding::SamFlag
+        } else {
+            Encoding::Strand
+        }
+    }
+}
+
+/// Where the table comes from, for vendor reports with fixed columns.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum TableFormat {
+    /// Columns named by --id-col, --orientation-col and --orientation-encoding
+    Tsv,
+    /// `isoseq refine` flnc.report.csv: read in 'id', '+'/'-' in 'strand'
+    IsoseqRefine,
+    /// lima .lima.report: ZMW in 'ZMW', primer pair such as '5p--3p' in 'IdxCombinedNamed'
+    Lima,
+}
+
+impl TableFormat {
+    /// ID column, orientation column and encoding of a vendor format.
+    pub fn columns(self) -> Option<(&'static str, &'static str, Encoding)> {
+        match self {
+            TableFormat::Tsv => None,
+            TableFormat::IsoseqRefine => Some(("id", "strand", Encoding::Strand)),
+            TableFormat::Lima => Some(("ZMW", "IdxCombinedNamed", Encoding::PrimerPair)),
+        }
+    }
+}
+
+/// Rows inspected by `--orientation-encoding auto`.
+pub const DETECT_ROWS: usize = 1000;
+
+/// Parse one orientation value in the given (concrete) encoding.
+pub fn parse_orientation(field: &[u8], id: &str, encoding: Encoding) -> Result<u8> {
+    if field.is_empty() {
+        bail!("Empty orientation for read '{}'", id);
+    }
+    let s = String::from_utf8_lossy(field).to_ascii_lowercase();
+    match encoding {
+        Encoding::Numeric => match s.as_str() {
+            "1" | "+1" => return Ok(b'+'),
+            "-1" => return Ok(b'-'),
+            _ => {}
+        },
+        Encoding::SamFlag => {
+            if let Ok(flag) = s.parse::<u32>() {
+                return Ok(if flag & 0x10 != 0 { b'-' } else { b'+' });
+            }
+        }
+        Encoding::PrimerPair => {
+            if let Some((first, second)) = s.split_once("--") {
+                if first.ends_with("5p") && second.ends_with("3p") {
+                    return Ok(b'+');
+                }
+                if first.ends_with("3p") && second.ends_with("5p") {
+                    return Ok(b'-');
+                }
+            }
+        }
+        Encoding::Strand | Encoding::Auto => match field[0] as char {
+            '+' => return Ok(b'+'),
+            '-' => return Ok(b'-'),
+            _ => {
+                if s.starts_with("plus")
+                    || s.starts_with("fwd")
+                    || s.starts_with("forward")
+                    || s == "f"
+                    || s == "1"
+                {
+                    return Ok(b'+');
+                }
+                if s.starts_with("minus")
+                    || s.starts_with("rev")
+                    || s == "r"
+                    || s == "0"
+                    || s == "rc"
+                {
+                    return Ok(b'-');
+                }
+                if s.parse::<u32>().is_ok() {
+                    bail!(
+                        "Unrecognized orientation value '{}' for read '{}' (SAM flags? use --orientation-encoding sam-flag)",
+                        s,
+                        id
+                    );
+                }
+            }
+        },
+    }
+    bail!("Unrecognized orientation value '{}' for read '{}'", s, id);
+}
+
+/// Open the (optionally compressed) TSV with its header row read; a `.csv`
+/// name is read as comma-separated.
+pub fn open_tsv(table_path: &Path) -> Result<(csv::Reader<Box<dyn Read>>, csv::StringRecord)> {
+    let name = table_path.to_string_lossy();
+    let delimiter = if compress::strip_extension(&name).ends_with(".csv") {
+        b','
+    } else {
+        b'\t'
+    };
+    let rdr = compress::open(table_path)?;
+
+    let mut reader = ReaderBuilder::new()
+        .delimiter(delimiter)
+        .has_headers(true)
+        .from_reader(rdr);
+
+    let headers = reader.headers().context("reading TSV headers")?.clone();
+    Ok((reader, headers))
+}
+
+fn column(headers: &csv::StringRecord, name: &str) -> Result<usize> {
+    headers
+        .iter()
+        .position(|h| h == name)
+        .with_context(|| format!("column '{}' not found", name))
+}
+
+/// Map read ID to the value of `group_col` (`--group-col`), kept in memory.
+pub fn load_groups(
+    table_path: &Path,
+    id_col: &str,
+    group_col: &str,
+) -> Result<HashMap<String, String>> {
+    let (mut reader, headers) = open_tsv(table_path)?;
+    let (id_idx, group_idx) = (column(&headers, id_col)?, column(&headers, group_col)?);
+    let mut groups = HashMap::new();
+    for rec in reader.records() {
+        let rec = rec?;
+        groups.insert(
+            rec.get(id_idx).unwrap().to_owned(),
+            rec.get(group_idx).unwrap().to_owned(),
+        );
+    }
+    Ok(groups)
+}
+
+/// How much of the table may be held in memory, and where to spill the rest.
+#[derive(Debug, Clone)]
+pub struct Budget {
+    pub max_memory: Option<u64>,
+    pub tmpdir: PathBuf,
+}
+
+/// Load the table; with a `max_memory` budget, spill to a sorted temporary
+/// file in its `tmpdir` once the estimated in-memory size passes it. Returns
+/// the table and the number of duplicate IDs (the last occurrence wins).
+pub fn load(
+    table_path: &Path,
+    id_col: &str,
+    orientation_col: &str,
+    encoding: Encoding,
+    budget: &Budget,
+) -> Result<(OrientationTable, u64)> {
+    let (reader, headers) = open_tsv(table_path)?;
+    let id_idx = column(&headers, id_col)?;
+    let ori_idx = column(&headers, orientation_col)?;
+
+    // Buffer the first rows so `auto` can look at them before parsing any.
+    let mut records = reader.into_records();
+    let head = records
+        .by_ref()
+        .take(DETECT_ROWS)
+        .collect::<Result<Vec<_>, _>>()?;
+    let encoding = encoding.detect(head.iter().map(|r| r.get(ori_idx).unwrap().trim()));
+
+    let mut map = HashMap::with_capacity(1 << 16);
+    let mut n_duplicates: u64 = 0;
+    let mut spill: Option<Spill> = None;
+    let mut used: u64 = 0;
+    for (row, rec) in head.into_iter().map(Ok).chain(records).enumerate() {
+        let rec = rec?;
+        let id = rec.get(id_idx).unwrap().to_owned();
+        let ori = parse_orientation(rec.get(ori_idx).unwrap().trim().as_bytes(), &id, encoding)?;
+        if let Some(s) = spill.as_mut() {
+            s.push(id, row as u64 + 1, ori)?;
+            continue;
+        }
+        used += id.len() as u64 + ENTRY_OVERHEAD;
+        if map.insert(id, ori).is_some() {
+            n_duplicates += 1;
+        }
+        if budget.max_memory.is_some_and(|max| used > max) {
+            // The map is already deduplicated; its entries rank below every
+            // later row so repeats further down the table still win.
+            let mut s = Spill::new(budget.max_memory.unwrap_or(0), &budget.tmpdir);
+            for (id, ori) in map.drain() {
+                s.push(id, 0, ori)?;
+            }
+            spill = Some(s);
+        }
+    }
+    match spill {
+        None => Ok((OrientationTable::Memory(Arc::new(map)), n_duplicates)),
+        Some(s) => {
+            let (disk, dups) = s.finish()?;
+            Ok((OrientationTable::Disk(disk), n_duplicates + dups))
+        }
+    }
+}
+
+/// Spills started so far in this process; `--jobs` samples spill at once.
+static SPILLS: AtomicUsize = AtomicUsize::new(0);
+
+/// External sort of `(id, row, orientation)` entries: budget-sized sorted
+/// runs on disk, merged into one deduplicated sorted file.
+struct Spill {
+    dir: PathBuf,
+    /// Distinguishes this spill's files from others in the process
+    id: usize,
+    budget: u64,
+    used: u64,
+    chunk: Vec<(String, u64, u8)>,
+    runs: Vec<PathBuf>,
+}
+
+impl Spill {
+    fn new(budget: u64, dir: &Path) -> Self {
+        Self {
+            dir: dir.to_path_buf(),
+            id: SPILLS.fetch_add(1, Ordering::Relaxed),
+            budget,
+            used: 0,
+            chunk: Vec::new(),
+            runs: Vec::new(),
+        }
+    }
+
+    /// Temporary file path in `dir` unique to this spill.
+    fn temp_path(&self, tag: &str, n: usize) -> PathBuf {
+        self.dir.join(format!(
+            "restrand-{}-{}-{}-{}",
+            std::process::id(),
+            self.id,
+            tag,
+            n
+        ))
+    }
+
+    fn push(&mut self, id: String, row: u64, ori: u8) -> Result<()> {
+        if id.contains(['\t', '\n']) {
+            bail!("read ID {:?} contains a tab or newline", id);
+        }
+        self.used += id.len() as u64 + ENTRY_OVERHEAD;
+        self.chunk.push((id, row, ori));
+        if self.used > self.budget {
+            self.flush_run()?;
+        }
+        Ok(())
+    }
+
+    fn flush_run(&mut self) -> Result<()> {
+        if self.chunk.is_empty() {
+            return Ok(());
+        }
+        self.chunk.sort();
+        let path = self.temp_path("run", self.runs.len());
+        let mut w = BufWriter::new(
+            File::create(&path).with_context(|| format!("create spill file {:?}", path))?,
+        );
+        for (id, row, ori) in self.chunk.drain(..) {
+            writeln!(w, "{}\t{}\t{}", id, row, ori as char)?;
+        }
+        w.flush()?;
+        self.runs.push(path);
+        self.used = 0;
+        Ok(())
+    }
+
+    /// Merge the runs; for repeated IDs the highest row wins.
+    fn finish(mut self) -> Result<(DiskTable, u64)> {
+        self.flush_run()?;
+        let mut readers = Vec::with_capacity(self.runs.len());
+        for path in &self.runs {
+            readers.push(BufReader::new(File::open(path)?).lines());
+        }
+        let mut heap = BinaryHeap::new();
+        for (i, r) in readers.iter_mut().enumerate() {
+            if let Some(line) = r.next() {
+                heap.push(Reverse((parse_run_line(&line?)?, i)));
+            }
+        }
+
+        let path = self.temp_path("table", 0);
+        let mut w = BufWriter::new(
+            File::create(&path).with_context(|| format!("create spill file {:?}", path))?,
+        );
+        let mut index = Vec::new();
+        let (mut offset, mut rows) = (0u64, 0usize);
+        let mut n_duplicates = 0;
+        let mut pending: Option<(String, u8)> = None;
+        while let Some(Reverse(((id, _row, ori), i))) = heap.pop() {
+            if let Some(line) = readers[i].next() {
+                heap.push(Reverse((parse_run_line(&line?)?, i)));
+            }
+            match pending.as_mut() {
+                Some((prev, prev_ori)) if *prev == id => {
+                    // Sorted by (id, row): later rows override earlier ones
+                    *prev_ori = ori;
+                    n_duplicates += 1;
+                }
+                _ => {
+                    if let Some((prev, prev_ori)) = pending.replace((id, ori)) {
+                        if rows % INDEX_STRIDE == 0 {
+                            index.push((prev.clone(), offset));
+                        }
+                        writeln!(w, "{}\t{}", prev, prev_ori as char)?;
+                        offset += prev.len() as u64 + 3;
+                        rows += 1;
+                    }
+                }
+            }
+        }
+        if let Some((prev, prev_ori)) = pending {
+            if rows % INDEX_STRIDE == 0 {
+                index.push((prev.clone(), offset));
+            }
+            writeln!(w, "{}\t{}", prev, prev_ori as char)?;
+        }
+        w.flush()?;
+        for run in &self.runs {
+            let _ = fs::remove_file(run);
+        }
+        let file = BufReader::new(File::open(&path)?);
+        let table = DiskTable {
+            spill: Arc::new(SpillFile(path)),
+            file,
+            index: Arc::new(index),
+        };
+        Ok((table, n_duplicates))
+    }
+}
+
+impl Drop for Spill {
+    fn drop(&mut self) {
+        for run in &self.runs {
+            let _ = fs::remove_file(run);
+        }
+    }
+}
+
+fn parse_run_line(line: &str) -> Result<(String, u64, u8)> {
+    let mut fields = line.splitn(3, '\t');
+    let (Some(id), Some(row), Some(ori)) = (fields.next(), fields.next(), fields.next()) else {
+        bail!("corrupt spill file line {:?}", line);
+    };
+    Ok((id.to_owned(), row.parse()?, ori.as_bytes()[0]))
+}
+
+/// The sorted file behind a [`DiskTable`], removed once no handle needs it.
+struct SpillFile(PathBuf);
+
+impl Drop for SpillFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
+
+/// Sorted `id<TAB>orientation` file with every `INDEX_STRIDE`-th key in memory.
+pub struct DiskTable {
+    spill: Arc<SpillFile>,
+    file: BufReader<File>,
+    index: Arc<Vec<(String, u64)>>,
+}
+
+impl DiskTable {
+    /// Another handle on the same file, with its own read position.
+    fn reopen(&self) -> Result<Self> {
+        Ok(Self {
+            spill: Arc::clone(&self.spill),
+            file: BufReader::new(File::open(&self.spill.0)?),
+            index: Arc::clone(&self.index),
+        })
+    }
+
+    fn for_each(&mut self, mut f: impl FnMut(&str, u8) -> Result<()>) -> Result<()> {
+        self.file.seek(SeekFrom::Start(0))?;
+        let mut line = String::new();
+        loop {
+            line.clear();
+            if self.file.read_line(&mut line)? == 0 {
+                return Ok(());
+            }
+            let (key, ori) = line
+                .trim_end_matches('\n')
+                .rsplit_once('\t')
+                .context("corrupt on-disk table")?;
+            f(key, ori.as_bytes()[0])?;
+        }
+    }
+
+    fn get(&mut self, id: &str) -> Result<Option<u8>> {
+        let block = self.index.partition_point(|(k, _)| k.as_str() <= id);
+        if block == 0 {
+            return Ok(None);
+        }
+        self.file.seek(SeekFrom::Start(self.index[block - 1].1))?;
+        let mut line = String::new();
+        for _ in 0..INDEX_STRIDE {
+            line.clear();
+            if self.file.read_line(&mut line)? == 0 {
+                break;
+            }
+            let (key, ori) = line
+                .trim_end_matches('\n')
+                .rsplit_once('\t')
+                .context("corrupt on-disk table")?;
+            match key.cmp(id) {
+                std::cmp::Ordering::Less => continue,
+                std::cmp::Ordering::Equal => return Ok(ori.bytes().next()),
+                std::cmp::Ordering::Greater => break,
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// A table loaded once and looked up by several runs at a time (`--manifest`
+/// and `--out-dir` with `--jobs`); each run takes its own handle.
+#[derive(Clone)]
+pub struct SharedTable {
+    table: Arc<OrientationTable>,
+    duplicates: u64,
+}
+
+impl SharedTable {
+    pub fn new(table: OrientationTable, duplicates: u64) -> Self {
+        Self {
+            table: Arc::new(table),
+            duplicates,
+        }
+    }
+
+    /// A handle for one run, with the table's duplicate-ID count. Only an
+    /// on-disk table needs a new file handle; the rows are never copied.
+    pub fn handle(&self) -> Result<(OrientationTable, u64)> {
+        let table = match &*self.table {
+            OrientationTable::Memory(map) => OrientationTable::Memory(Arc::clone(map)),
+            OrientationTable::Disk(disk) => OrientationTable::Disk(disk.reopen()?),
+            OrientationTable::Cached(cached) => OrientationTable::Cached(Arc::clone(cached)),
+        };
+        Ok((table, self.duplicates))
+    }
+}
+
+impl fmt::Debug for SharedTable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SharedTable")
+            .field("duplicates", &self.duplicates)
+            .finish_non_exhaustive()
+    }
+}
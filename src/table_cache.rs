@@ -0,0 +1,347 @@
+//! `--table-cache`: the parsed orientation table as a sorted binary file,
+//! memory-mapped on later runs instead of re-parsing the TSV.
+//!
+//! Layout (little-endian): magic, the source table's size and mtime, the
+//! id/orientation column names and value encoding, row and duplicate counts, then `n + 1` key
+//! offsets, `n` orientation bytes and the concatenated keys, sorted by key.
+//! A cache whose recorded source or columns no longer match, or whose size
+//! does not match its header and last key offset, is rebuilt. The other
+//! offsets are only checked as their keys are read, so opening a large cache
+//! stays cheap; a corrupt one is an error at that point.
+
+use crate::table::{self, Budget, Encoding, OrientationTable};
+use anyhow::{bail, Context, Result};
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::UNIX_EPOCH;
+
+const MAGIC: &[u8; 8] = b"RSTRTBL1";
+
+/// What the cache was built from; any change invalidates it.
+#[derive(Debug, PartialEq, Eq)]
+struct Source {
+    len: u64,
+    mtime_secs: u64,
+    mtime_nanos: u32,
+    id_col: String,
+    orientation_col: String,
+    encoding: u32,
+}
+
+impl Source {
+    fn of(
+        table_path: &Path,
+        id_col: &str,
+        orientation_col: &str,
+        encoding: Encoding,
+    ) -> Result<Self> {
+        let meta = fs::metadata(table_path).with_context(|| format!("stat {:?}", table_path))?;
+        let mtime = meta
+            .modified()
+            .ok()
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .unwrap_or_default();
+        Ok(Self {
+            len: meta.len(),
+            mtime_secs: mtime.as_secs(),
+            mtime_nanos: mtime.subsec_nanos(),
+            id_col: id_col.to_owned(),
+            orientation_col: orientation_col.to_owned(),
+            encoding: encoding.code(),
+        })
+    }
+}
+
+/// Load the table through the cache at `cache`, building or rebuilding it
+/// when missing or stale. Returns the table and its duplicate-ID count.
+pub fn load(
+    table_path: &Path,
+    id_col: &str,
+    orientation_col: &str,
+    encoding: Encoding,
+    budget: &Budget,
+    cache: &Path,
+    quiet: bool,
+) -> Result<(OrientationTable, u64)> {
+    let source = Source::of(table_path, id_col, orientation_col, encoding)?;
+    if cache.exists() {
+        match CachedTable::open(cache) {
+            Ok((cached, cached_source, dups)) if cached_source == source => {
+                return Ok((OrientationTable::Cached(Arc::new(cached)), dups));
+            }
+            Ok(_) if !quiet => {
+                eprintln!("note: table cache {:?} is stale; rebuilding it", cache)
+            }
+            Err(e) if !quiet => {
+                eprintln!(
+                    "warning: ignoring unreadable table cache {:?}: {:#}",
+                    cache, e
+                )
+            }
+            _ => {}
+        }
+    }
+    let (mut table, dups) = table::load(table_path, id_col, orientation_col, encoding, budget)?;
+    write(&mut table, &source, dups, cache)
+        .with_context(|| format!("writing table cache {:?}", cache))?;
+    Ok((table, dups))
+}
+
+/// Write the cache next to its final path, then rename it into place so
+/// concurrent runs never see a partial file.
+fn write(table: &mut OrientationTable, source: &Source, dups: u64, cache: &Path) -> Result<()> {
+    let tmp = PathBuf::from(format!("{}.{}.tmp", cache.display(), std::process::id()));
+    let sections = ["offsets", "orientations", "keys"]
+        .map(|s| PathBuf::from(format!("{}.{}", tmp.display(), s)));
+    let result = (|| -> Result<()> {
+        // Stream the sorted rows into the three sections, then concatenate.
+        let mut offsets = BufWriter::new(File::create(&sections[0])?);
+        let mut oris = BufWriter::new(File::create(&sections[1])?);
+        let mut keys = BufWriter::new(File::create(&sections[2])?);
+        let (mut n, mut key_bytes) = (0u64, 0u64);
+        offsets.write_all(&0u64.to_le_bytes())?;
+        table.for_each_sorted(|id, ori| {
+            keys.write_all(id.as_bytes())?;
+            key_bytes += id.len() as u64;
+            offsets.write_all(&key_bytes.to_le_bytes())?;
+            oris.write_all(&[ori])?;
+            n += 1;
+            Ok(())
+        })?;
+        oris.write_all(&vec![0; padding(n as usize)])?;
+        for w in [&mut offsets, &mut oris, &mut keys] {
+            w.flush()?;
+        }
+
+        let mut w = BufWriter::new(File::create(&tmp)?);
+        w.write_all(MAGIC)?;
+        w.write_all(&source.len.to_le_bytes())?;
+        w.write_all(&source.mtime_secs.to_le_bytes())?;
+        w.write_all(&source.mtime_nanos.to_le_bytes())?;
+        w.write_all(&(source.id_col.len() as u32).to_le_bytes())?;
+        w.write_all(&(source.orientation_col.len() as u32).to_le_bytes())?;
+        w.write_all(&source.encoding.to_le_bytes())?;
+        w.write_all(&n.to_le_bytes())?;
+        w.write_all(&dups.to_le_bytes())?;
+        let names_len = source.id_col.len() + source.orientation_col.len();
+        w.write_all(source.id_col.as_bytes())?;
+        w.write_all(source.orientation_col.as_bytes())?;
+        w.write_all(&vec![0; padding(names_len)])?;
+        for section in &sections {
+            std::io::copy(&mut File::open(section)?, &mut w)?;
+        }
+        w.flush()?;
+        fs::rename(&tmp, cache)?;
+        Ok(())
+    })();
+    for p in sections.iter().chain([&tmp]) {
+        let _ = fs::remove_file(p);
+    }
+    result
+}
+
+/// Zero bytes that bring `len` up to a multiple of 8.
+fn padding(len: usize) -> usize {
+    (8 - len % 8) % 8
+}
+
+/// A mapped cache file, searched by binary search over its sorted keys.
+pub struct CachedTable {
+    data: Mapped,
+    n: usize,
+    offsets: usize,
+    oris: usize,
+    keys: usize,
+}
+
+impl CachedTable {
+    fn open(path: &Path) -> Result<(Self, Source, u64)> {
+        let data = Mapped::open(path)?;
+        let d: &[u8] = &data;
+        let u32_at = |at: usize| {
+            d.get(at..at + 4)
+                .map(|b| u32::from_le_bytes(b.try_into().unwrap()))
+        };
+        let u64_at = |at: usize| {
+            d.get(at..at + 8)
+                .map(|b| u64::from_le_bytes(b.try_into().unwrap()))
+        };
+        if d.get(..8) != Some(MAGIC) {
+            bail!("not a restrand-fasta table cache");
+        }
+        let (Some(len), Some(mtime_secs), Some(mtime_nanos), Some(id_len), Some(ori_len)) =
+            (u64_at(8), u64_at(16), u32_at(24), u32_at(28), u32_at(32))
+        else {
+            bail!("truncated table cache");
+        };
+        let (Some(encoding), Some(n), Some(dups)) = (u32_at(36), u64_at(40), u64_at(48)) else {
+            bail!("truncated table cache");
+        };
+        let (id_len, ori_len, n) = (id_len as usize, ori_len as usize, n as usize);
+        let names = 56;
+        let names_len = id_len + ori_len;
+        // Sizes come from the file, so a corrupt header must not overflow
+        let offsets = names + names_len + padding(names_len);
+        let layout = || {
+            let oris = offsets.checked_add(n.checked_add(1)?.checked_mul(8)?)?;
+            let keys = oris.checked_add(n)?.checked_add(padding(n))?;
+            (keys <= d.len()).then_some((oris, keys))
+        };
+        let Some((oris, keys)) = layout() else {
+            bail!("table cache size does not match its header");
+        };
+        // The key section must end the file; `key` checks the offsets between
+        let last = u64_at(offsets + n * 8).unwrap_or(u64::MAX);
+        if u64_at(offsets).is_none_or(|first| first != 0)
+            || (keys as u64).checked_add(last) != Some(d.len() as u64)
+        {
+            bail!("table cache size does not match its header");
+        }
+        let name = |r: std::ops::Range<usize>| String::from_utf8_lossy(&d[r]).into_owned();
+        let source = Source {
+            len,
+            mtime_secs,
+            mtime_nanos,
+            id_col: name(names..names + id_len),
+            orientation_col: name(names + id_len..names + id_len + ori_len),
+            encoding,
+        };
+        let table = Self {
+            n,
+            offsets,
+            oris,
+            keys,
+            data,
+        };
+        Ok((table, source, dups))
+    }
+
+    fn offset(&self, i: usize) -> usize {
+        let at = self.offsets + i * 8;
+        u64::from_le_bytes(self.data[at..at + 8].try_into().unwrap()) as usize
+    }
+
+    fn key(&self, i: usize) -> Result<&[u8]> {
+        let (start, end) = (self.offset(i), self.offset(i + 1));
+        match self.data[self.keys..].get(start..end) {
+            Some(key) => Ok(key),
+            None => bail!(
+                "table cache key offsets are corrupt at row {}; delete the cache to rebuild it",
+                i
+            ),
+        }
+    }
+
+    pub fn get(&self, id: &str) -> Result<Option<u8>> {
+        let (mut lo, mut hi) = (0, self.n);
+        while lo < hi {
+            let mid = lo + (hi - lo) / 2;
+            match self.key(mid)?.cmp(id.as_bytes()) {
+                std::cmp::Ordering::Less => lo = mid + 1,
+                std::cmp::Ordering::Greater => hi = mid,
+                std::cmp::Ordering::Equal => return Ok(Some(self.data[self.oris + mid])),
+            }
+        }
+        Ok(None)
+    }
+
+    /// Rows in key order.
+    pub fn for_each(&self, mut f: impl FnMut(&str, u8) -> Result<()>) -> Result<()> {
+        for i in 0..self.n {
+            f(
+                &String::from_utf8_lossy(self.key(i)?),
+                self.data[self.oris + i],
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Read-only view of a whole file: memory-mapped on Unix, read in elsewhere.
+#[cfg(unix)]
+struct Mapped {
+    ptr: *mut libc::c_void,
+    len: usize,
+    _file: File,
+}
+
+#[cfg(unix)]
+impl Mapped {
+    fn open(path: &Path) -> Result<Self> {
+        use std::os::unix::io::AsRawFd;
+        let file = File::open(path).with_context(|| format!("open {:?}", path))?;
+        let len = file.metadata()?.len() as usize;
+        if len == 0 {
+            bail!("empty table cache");
+        }
+        // SAFETY: mapping a regular file read-only; the mapping is released in Drop.
+        let ptr = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                len,
+                libc::PROT_READ,
+                libc::MAP_PRIVATE,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(std::io::Error::last_os_error()).context("mmap table cache");
+        }
+        Ok(Self {
+            ptr,
+            len,
+            _file: file,
+        })
+    }
+}
+
+// SAFETY: the mapping is read-only and private, so sharing or moving it
+// between threads cannot race; it is unmapped only when dropped.
+#[cfg(unix)]
+unsafe impl Send for Mapped {}
+#[cfg(unix)]
+unsafe impl Sync for Mapped {}
+
+#[cfg(unix)]
+impl std::ops::Deref for Mapped {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        // SAFETY: `ptr` maps `len` readable bytes for as long as `self` lives.
+        unsafe { std::slice::from_raw_parts(self.ptr as *const u8, self.len) }
+    }
+}
+
+#[cfg(unix)]
+impl Drop for Mapped {
+    fn drop(&mut self) {
+        // SAFETY: `ptr`/`len` are exactly the mapping created in `open`.
+        unsafe {
+            libc::munmap(self.ptr, self.len);
+        }
+    }
+}
+
+#[cfg(not(unix))]
+struct Mapped(Vec<u8>);
+
+#[cfg(not(unix))]
+impl Mapped {
+    fn open(path: &Path) -> Result<Self> {
+        Ok(Self(
+            fs::read(path).with_context(|| format!("read {:?}", path))?,
+        ))
+    }
+}
+
+#[cfg(not(unix))]
+impl std::ops::Deref for Mapped {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.0
+    }
+}
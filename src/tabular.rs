@@ -0,0 +1,111 @@
+//! `--tab-input`: records as `header<TAB>sequence[<TAB>quality]` lines, the
+//! layout `--out-format tab` writes. The header splits into ID and
+//! description at its first whitespace, as in FASTA.
+
+use bio::io::{fasta, fastq};
+use std::io::{self, BufRead};
+
+/// Reads tabular records as FASTA or FASTQ records.
+pub struct Reader<R> {
+    inner: R,
+    line: String,
+    n: u64,
+}
+
+impl<R: BufRead> Reader<R> {
+    pub fn new(inner: R) -> Self {
+        Reader {
+            inner,
+            line: String::new(),
+            n: 0,
+        }
+    }
+
+    /// The next non-empty line's columns, or None at the end of input.
+    fn next(&mut self) -> io::Result<Option<Fields<'_>>> {
+        loop {
+            self.line.clear();
+            if self.inner.read_line(&mut self.line)? == 0 {
+                return Ok(None);
+            }
+            self.n += 1;
+            if !self.line.trim_end_matches(['\n', '\r']).is_empty() {
+                break;
+            }
+        }
+        let line = self.line.trim_end_matches(['\n', '\r']);
+        let mut cols = line.split('\t');
+        let header = cols.next().unwrap_or("");
+        let (Some(seq), qual) = (cols.next(), cols.next()) else {
+            return Err(invalid(
+                self.n,
+                "expected header<TAB>sequence[<TAB>quality]",
+            ));
+        };
+        if cols.next().is_some() {
+            return Err(invalid(self.n, "more than 3 tab-separated columns"));
+        }
+        let (id, desc) = match header.split_once(char::is_whitespace) {
+            Some((id, desc)) => (id, Some(desc.trim_start())),
+            None => (header, None),
+        };
+        if id.is_empty() {
+            return Err(invalid(self.n, "empty read ID"));
+        }
+        Ok(Some(Fields {
+            line: self.n,
+            id,
+            desc: desc.filter(|d| !d.is_empty()),
+            seq,
+            qual,
+        }))
+    }
+}
+
+struct Fields<'a> {
+    line: u64,
+    id: &'a str,
+    desc: Option<&'a str>,
+    seq: &'a str,
+    qual: Option<&'a str>,
+}
+
+fn invalid(line: u64, what: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("tabular input line {}: {}", line, what),
+    )
+}
+
+/// Quality columns are ignored in FASTA mode.
+impl<R: BufRead> fasta::FastaRead for Reader<R> {
+    fn read(&mut self, record: &mut fasta::Record) -> io::Result<()> {
+        *record = match self.next()? {
+            Some(f) => fasta::Record::with_attrs(f.id, f.desc, f.seq.as_bytes()),
+            None => fasta::Record::new(),
+        };
+        Ok(())
+    }
+}
+
+impl<R: BufRead> fastq::FastqRead for Reader<R> {
+    fn read(&mut self, record: &mut fastq::Record) -> fastq::Result<()> {
+        *record = match self.next()? {
+            Some(f) => {
+                let Some(qual) = f.qual else {
+                    return Err(invalid(f.line, "no quality column for --fastq").into());
+                };
+                if qual.len() != f.seq.len() {
+                    return Err(invalid(
+                        f.line,
+                        &format!("{} qualities for {} bases", qual.len(), f.seq.len()),
+                    )
+                    .into());
+                }
+                fastq::Record::with_attrs(f.id, f.desc, f.seq.as_bytes(), qual.as_bytes())
+            }
+            None => fastq::Record::new(),
+        };
+        Ok(())
+    }
+}
@@ -0,0 +1,161 @@
+//! Orientation tags embedded in read headers.
+//!
+//! Headers are split into fields on `| ; , space tab`, and a field carries the
+//! orientation when it ends in `<key><delim><value>`: key `orientation` or
+//! `strand` (any case), delimiter `:`, `=` or none, value `+` or `-`. Matching
+//! the end of a field also finds tags glued onto a previous field, e.g.
+//! `UMI:AGGCorientation:+`. The SAM transcript-strand tag `ts:A:+` (as
+//! `--emit-strand-tag sam` writes it) is recognized too, but only as a whole
+//! field, since SAM tags are two letters and case-sensitive.
+
+use clap::ValueEnum;
+
+/// Tag keys recognized, in no particular order.
+const TAG_KEYS: [&str; 2] = ["orientation", "strand"];
+
+/// The SAM transcript-strand tag with its type, as a whole field.
+const SAM_TAG: &[u8] = b"ts:A:";
+
+fn is_sep(b: u8) -> bool {
+    matches!(b, b'|' | b';' | b',' | b' ' | b'\t')
+}
+
+/// A located orientation tag: `start..value` is the key and delimiter,
+/// `value` the byte offset of the `+`/`-`.
+#[derive(Debug, Clone, Copy)]
+struct Tag {
+    start: usize,
+    value: usize,
+    orientation: u8,
+}
+
+impl Tag {
+    fn end(&self) -> usize {
+        self.value + 1
+    }
+}
+
+/// Match one field (`field` starts at byte `offset` of the header).
+fn match_field(field: &[u8], offset: usize) -> Option<Tag> {
+    let (&value, rest) = field.split_last()?;
+    if value != b'+' && value != b'-' {
+        return None;
+    }
+    if rest == SAM_TAG {
+        return Some(Tag {
+            start: offset,
+            value: offset + rest.len(),
+            orientation: value,
+        });
+    }
+    let rest = match rest.last() {
+        Some(b':' | b'=') => &rest[..rest.len() - 1],
+        _ => rest,
+    };
+    TAG_KEYS.iter().find_map(|key| {
+        let start = rest.len().checked_sub(key.len())?;
+        rest[start..]
+            .eq_ignore_ascii_case(key.as_bytes())
+            .then_some(Tag {
+                start: offset + start,
+                value: offset + field.len() - 1,
+                orientation: value,
+            })
+    })
+}
+
+/// First orientation tag in the header, scanning fields left to right.
+fn find(header: &str) -> Option<Tag> {
+    let bytes = header.as_bytes();
+    let mut start = 0;
+    for (i, b) in bytes.iter().copied().chain([b' ']).enumerate() {
+        if is_sep(b) {
+            if let Some(tag) = match_field(&bytes[start..i], start) {
+                return Some(tag);
+            }
+            start = i + 1;
+        }
+    }
+    None
+}
+
+/// Orientation carried by the header's tag, if any.
+pub fn orientation(header: &str) -> Option<u8> {
+    find(header).map(|t| t.orientation)
+}
+
+/// Set the tag's value to `ori`, keeping its key and delimiter; with `key`,
+/// rename it to the normalized `key:ori` form (e.g. `strand` yields `strand:+`).
+pub fn update(header: &str, ori: u8, key: Option<&str>) -> String {
+    let Some(tag) = find(header) else {
+        return header.to_owned();
+    };
+    let prefix = match key {
+        Some(k) => format!("{}:", k),
+        None => header[tag.start..tag.value].to_owned(),
+    };
+    format!(
+        "{}{}{}{}",
+        &header[..tag.start],
+        prefix,
+        ori as char,
+        &header[tag.end()..]
+    )
+}
+
+/// Value of the first `key:value` / `key=value` field (`key` matched exactly);
+/// a SAM-style type code is skipped, so `CB:Z:ACGT` gives `ACGT`.
+pub fn value<'a>(header: &'a str, key: &str) -> Option<&'a str> {
+    header
+        .split(|c: char| c.is_ascii() && is_sep(c as u8))
+        .find_map(|field| {
+            let rest = field.strip_prefix(key)?;
+            let v = rest.strip_prefix([':', '='])?;
+            let v = match v.as_bytes() {
+                [t, b':', ..] if t.is_ascii_alphabetic() => &v[2..],
+                _ => v,
+            };
+            (!v.is_empty()).then_some(v)
+        })
+}
+
+/// Remove the tag along with one adjacent field separator, so
+/// `a|orientation:+|b` becomes `a|b`.
+pub fn strip(header: &str) -> String {
+    let Some(tag) = find(header) else {
+        return header.to_owned();
+    };
+    let (mut start, mut end) = (tag.start, tag.end());
+    let bytes = header.as_bytes();
+    if start > 0 && is_sep(bytes[start - 1]) {
+        start -= 1;
+    } else if end < bytes.len() && is_sep(bytes[end]) {
+        end += 1;
+    }
+    let mut out = format!("{}{}", &header[..start], &header[end..]);
+    out.truncate(out.trim_end().len());
+    out
+}
+
+/// Layout of the tag `--emit-strand-tag` writes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum StrandTag {
+    /// `orientation:+`
+    Orientation,
+    /// `strand=+`
+    Strand,
+    /// SAM transcript strand, `ts:A:+`
+    Sam,
+}
+
+impl StrandTag {
+    /// The tag as a header field with its leading space.
+    pub fn field(self, ori: u8) -> String {
+        let key = match self {
+            StrandTag::Orientation => "orientation:",
+            StrandTag::Strand => "strand=",
+            StrandTag::Sam => "ts:A:",
+        };
+        format!(" {}{}", key, ori as char)
+    }
+}
@@ -0,0 +1,114 @@
+//! `--header-template`: compose each output header from the original ID and
+//! description plus computed fields, e.g. `'{id} strand={ori} len={len}'`.
+
+use std::fmt::Write;
+
+/// A value substituted into the template.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Field {
+    Id,
+    Desc,
+    Ori,
+    InputOri,
+    Action,
+    Source,
+    Len,
+}
+
+impl Field {
+    const ALL: [(&'static str, Field); 7] = [
+        ("id", Field::Id),
+        ("desc", Field::Desc),
+        ("ori", Field::Ori),
+        ("input_ori", Field::InputOri),
+        ("action", Field::Action),
+        ("source", Field::Source),
+        ("len", Field::Len),
+    ];
+}
+
+#[derive(Debug, Clone)]
+enum Piece {
+    Literal(String),
+    Field(Field),
+}
+
+/// A parsed `--header-template`.
+#[derive(Debug, Clone)]
+pub struct HeaderTemplate(Vec<Piece>);
+
+/// What a template can refer to for one record.
+pub struct HeaderFields<'a> {
+    pub id: &'a str,
+    pub desc: &'a str,
+    /// Orientation of the read as written, if it was oriented
+    pub ori: Option<u8>,
+    /// Orientation the read was called as on input
+    pub input_ori: Option<u8>,
+    pub flipped: bool,
+    pub source: &'a str,
+    pub len: usize,
+}
+
+impl HeaderTemplate {
+    /// Parse a template (`--header-template`); `{{`/`}}` are literal braces.
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let mut pieces = Vec::new();
+        let mut literal = String::new();
+        let mut rest = s;
+        while let Some(i) = rest.find(['{', '}']) {
+            literal.push_str(&rest[..i]);
+            let tail = &rest[i..];
+            if tail.starts_with("{{") || tail.starts_with("}}") {
+                literal.push_str(&tail[..1]);
+                rest = &tail[2..];
+                continue;
+            }
+            let Some(end) = tail.strip_prefix('{').and_then(|t| t.find('}')) else {
+                return Err(format!("unmatched brace in header template '{}'", s));
+            };
+            let name = &tail[1..end + 1];
+            let Some(&(_, field)) = Field::ALL.iter().find(|(n, _)| *n == name) else {
+                let names: Vec<_> = Field::ALL.iter().map(|(n, _)| *n).collect();
+                return Err(format!(
+                    "unknown field '{{{}}}' in header template (expected one of {{{}}})",
+                    name,
+                    names.join("}, {")
+                ));
+            };
+            if !literal.is_empty() {
+                pieces.push(Piece::Literal(std::mem::take(&mut literal)));
+            }
+            pieces.push(Piece::Field(field));
+            rest = &tail[end + 2..];
+        }
+        literal.push_str(rest);
+        if !literal.is_empty() {
+            pieces.push(Piece::Literal(literal));
+        }
+        Ok(Self(pieces))
+    }
+
+    /// Replace `header` with the template rendered for `f`. Unoriented reads
+    /// render `.` for the orientation fields.
+    pub fn render(&self, f: &HeaderFields, header: &mut String) {
+        let ori = |o: Option<u8>| o.map_or('.', char::from);
+        header.clear();
+        for piece in &self.0 {
+            match piece {
+                Piece::Literal(s) => header.push_str(s),
+                Piece::Field(Field::Id) => header.push_str(f.id),
+                Piece::Field(Field::Desc) => header.push_str(f.desc),
+                Piece::Field(Field::Ori) => header.push(ori(f.ori)),
+                Piece::Field(Field::InputOri) => header.push(ori(f.input_ori)),
+                Piece::Field(Field::Action) => {
+                    header.push_str(if f.flipped { "flipped" } else { "kept" })
+                }
+                Piece::Field(Field::Source) => header.push_str(f.source),
+                Piece::Field(Field::Len) => {
+                    let _ = write!(header, "{}", f.len);
+                }
+            }
+        }
+    }
+}
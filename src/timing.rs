@@ -0,0 +1,213 @@
+//! Per-stage wall and CPU time accounting for `--report` and
+//! `--trace-output`, enabled by `--stage-timings` because every timed call
+//! reads the clocks.
+//!
+//! Stages nest (decompression happens inside record parsing, input reads
+//! inside decompression); each stage is charged only its own time, so the
+//! totals add up to the instrumented part of the run.
+
+use serde::Serialize;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+/// Pipeline stages that are timed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Stage {
+    /// Loading the `--table`
+    TableLoad,
+    /// Raw input reads from the file or stdin
+    Read,
+    /// gzip decompression of the input
+    Decompress,
+    /// Splitting the decompressed text into records
+    Parse,
+    /// Table/tag lookup, primer detection and evidence resolution
+    Lookup,
+    /// Reverse-complementing flipped reads
+    Revcomp,
+    /// gzip compression of `.gz` outputs
+    Compress,
+    /// Formatting and writing output records (including checksums and the .fai)
+    Write,
+}
+
+impl Stage {
+    fn as_str(self) -> &'static str {
+        match self {
+            Stage::TableLoad => "table_load",
+            Stage::Read => "read",
+            Stage::Decompress => "decompress",
+            Stage::Parse => "parse",
+            Stage::Lookup => "lookup",
+            Stage::Revcomp => "revcomp",
+            Stage::Compress => "compress",
+            Stage::Write => "write",
+        }
+    }
+}
+
+/// Seconds spent in one stage.
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct StageTime {
+    wall_secs: f64,
+    /// `None` where per-thread CPU time is unavailable
+    cpu_secs: Option<f64>,
+}
+
+#[derive(Debug, Default)]
+struct Totals {
+    stages: BTreeMap<Stage, (Duration, Duration)>,
+    /// Wall and CPU time already charged to some stage
+    charged: (Duration, Duration),
+}
+
+/// A started measurement; pass it back to [`Timings::stop`].
+pub struct Mark {
+    wall: Instant,
+    cpu: Duration,
+    charged: (Duration, Duration),
+}
+
+/// Shared stage accumulator; a disabled one costs nothing per call.
+#[derive(Debug, Clone, Default)]
+pub struct Timings(Option<Rc<RefCell<Totals>>>);
+
+impl Timings {
+    pub fn new(enabled: bool) -> Self {
+        Self(enabled.then(Rc::default))
+    }
+
+    pub fn start(&self) -> Option<Mark> {
+        let totals = self.0.as_ref()?;
+        Some(Mark {
+            wall: Instant::now(),
+            cpu: thread_cpu_time().unwrap_or_default(),
+            charged: totals.borrow().charged,
+        })
+    }
+
+    /// Charge the time since `mark`, minus nested stages, to `stage`.
+    pub fn stop(&self, stage: Stage, mark: Option<Mark>) {
+        let (Some(totals), Some(mark)) = (self.0.as_ref(), mark) else {
+            return;
+        };
+        let wall = mark.wall.elapsed();
+        let cpu = thread_cpu_time().map_or(Duration::ZERO, |now| now.saturating_sub(mark.cpu));
+        let mut t = totals.borrow_mut();
+        let own_wall = wall.saturating_sub(t.charged.0 - mark.charged.0);
+        let own_cpu = cpu.saturating_sub(t.charged.1 - mark.charged.1);
+        let entry = t.stages.entry(stage).or_default();
+        entry.0 += own_wall;
+        entry.1 += own_cpu;
+        t.charged.0 += own_wall;
+        t.charged.1 += own_cpu;
+    }
+
+    pub fn time<T>(&self, stage: Stage, f: impl FnOnce() -> T) -> T {
+        let mark = self.start();
+        let value = f();
+        self.stop(stage, mark);
+        value
+    }
+
+    /// Stage times keyed by stage name, for the report.
+    pub fn snapshot(&self) -> BTreeMap<&'static str, StageTime> {
+        let Some(totals) = self.0.as_ref() else {
+            return BTreeMap::new();
+        };
+        let cpu_known = thread_cpu_time().is_some();
+        totals
+            .borrow()
+            .stages
+            .iter()
+            .map(|(stage, (wall, cpu))| {
+                (
+                    stage.as_str(),
+                    StageTime {
+                        wall_secs: wall.as_secs_f64(),
+                        cpu_secs: cpu_known.then_some(cpu.as_secs_f64()),
+                    },
+                )
+            })
+            .collect()
+    }
+}
+
+/// Reader adapter that charges the time spent in `read` to a stage.
+pub struct TimedReader<R> {
+    inner: R,
+    timings: Timings,
+    stage: Stage,
+}
+
+impl<R> TimedReader<R> {
+    pub fn new(inner: R, timings: &Timings, stage: Stage) -> Self {
+        Self {
+            inner,
+            timings: timings.clone(),
+            stage,
+        }
+    }
+}
+
+impl<R: Read> Read for TimedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mark = self.timings.start();
+        let n = self.inner.read(buf);
+        self.timings.stop(self.stage, mark);
+        n
+    }
+}
+
+impl<R: Seek> Seek for TimedReader<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.inner.seek(pos)
+    }
+}
+
+/// Writer adapter that charges the time spent in `write` and `flush` to a stage.
+pub struct TimedWriter<W> {
+    inner: W,
+    timings: Timings,
+    stage: Stage,
+}
+
+impl<W> TimedWriter<W> {
+    pub fn new(inner: W, timings: &Timings, stage: Stage) -> Self {
+        Self {
+            inner,
+            timings: timings.clone(),
+            stage,
+        }
+    }
+}
+
+impl<W: Write> Write for TimedWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.timings.time(self.stage, || self.inner.write(buf))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.timings.time(self.stage, || self.inner.flush())
+    }
+}
+
+/// CPU time consumed by the calling thread.
+#[cfg(unix)]
+fn thread_cpu_time() -> Option<Duration> {
+    let mut ts = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    // SAFETY: `ts` is a valid, writable timespec for the duration of the call.
+    let rc = unsafe { libc::clock_gettime(libc::CLOCK_THREAD_CPUTIME_ID, &mut ts) };
+    (rc == 0).then(|| Duration::new(ts.tv_sec as u64, ts.tv_nsec as u32))
+}
+
+#[cfg(not(unix))]
+fn thread_cpu_time() -> Option<Duration> {
+    None
+}
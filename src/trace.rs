@@ -0,0 +1,78 @@
+//! `--trace-output`: spans around the major run phases, written in the Chrome
+//! trace event format (load in `chrome://tracing` or Perfetto).
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+use serde_json::Value;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+/// One complete (`"ph": "X"`) event; times are in microseconds.
+#[derive(Debug, Serialize)]
+struct Event {
+    name: &'static str,
+    cat: &'static str,
+    ph: &'static str,
+    ts: f64,
+    dur: f64,
+    pid: u32,
+    tid: u32,
+    args: Value,
+}
+
+/// Collected spans, written once the run finishes; a disabled trace records nothing.
+pub struct Trace {
+    path: Option<PathBuf>,
+    origin: Instant,
+    events: Vec<Event>,
+}
+
+impl Trace {
+    pub fn new(path: Option<&Path>) -> Self {
+        Self {
+            path: path.map(Path::to_path_buf),
+            origin: Instant::now(),
+            events: Vec::new(),
+        }
+    }
+
+    /// Record the phase `name` that began at `start` and ends now.
+    pub fn span(&mut self, name: &'static str, start: Instant, args: Value) {
+        if self.path.is_none() {
+            return;
+        }
+        let micros = |t: Instant| t.duration_since(self.origin).as_secs_f64() * 1e6;
+        self.events.push(Event {
+            name,
+            cat: "restrand",
+            ph: "X",
+            ts: micros(start),
+            dur: start.elapsed().as_secs_f64() * 1e6,
+            pid: std::process::id(),
+            tid: 1,
+            args,
+        });
+    }
+
+    pub fn write(&self) -> Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let mut w =
+            BufWriter::new(File::create(path).with_context(|| format!("create trace {:?}", path))?);
+        serde_json::to_writer(
+            &mut w,
+            &serde_json::json!({
+                "traceEvents": self.events,
+                "displayTimeUnit": "ms",
+                "otherData": { "version": env!("CARGO_PKG_VERSION") },
+            }),
+        )
+        .context("writing trace")?;
+        w.write_all(b"\n")?;
+        w.flush()?;
+        Ok(())
+    }
+}
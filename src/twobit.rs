@@ -0,0 +1,182 @@
+//! UCSC `.2bit` input, decoded one sequence at a time.
+//!
+//! Layout (all integers in the file's byte order): signature, version,
+//! sequence count, reserved; then an index of `(name, offset)` pairs; each
+//! record holds its length, N blocks, soft-mask blocks and the packed bases
+//! (`T C A G` = `0 1 2 3`, four per byte, first base in the high bits).
+//! Version 1 files use 64-bit record offsets.
+
+use crate::progress::{ByteCounter, CountingReader};
+use anyhow::{bail, Context, Result};
+use bio::io::fasta;
+use std::fs::File;
+use std::io::{self, BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+
+const SIGNATURE: u32 = 0x1A41_2743;
+const BASES: [u8; 4] = [b'T', b'C', b'A', b'G'];
+
+/// Index entries reserved up front; the header's count is not trusted further.
+const INDEX_RESERVE: usize = 1 << 16;
+
+/// True when `path` names a 2bit file.
+pub fn is_twobit(path: &str) -> bool {
+    path.ends_with(".2bit")
+}
+
+/// Iterates the sequences of a 2bit file as FASTA records, in index order.
+pub struct Reader {
+    file: BufReader<CountingReader<File>>,
+    /// File size, which bounds every length read from the file
+    len: u64,
+    big_endian: bool,
+    index: std::vec::IntoIter<(String, u64)>,
+}
+
+impl Reader {
+    pub fn from_path(path: &Path, counter: &ByteCounter) -> Result<Self> {
+        let fh = File::open(path).with_context(|| format!("open {:?}", path))?;
+        let len = fh.metadata()?.len();
+        let mut reader = Self {
+            file: BufReader::new(CountingReader::new(fh, counter)),
+            len,
+            big_endian: false,
+            index: Vec::new().into_iter(),
+        };
+        let sig = reader.u32()?;
+        if sig == SIGNATURE.swap_bytes() {
+            reader.big_endian = true;
+        } else if sig != SIGNATURE {
+            bail!("{:?} is not a 2bit file (bad signature)", path);
+        }
+        let version = reader.u32()?;
+        if version > 1 {
+            bail!("{:?}: unsupported 2bit version {}", path, version);
+        }
+        let count = reader.u32()?;
+        reader.u32()?; // reserved
+        let mut index = Vec::with_capacity((count as usize).min(INDEX_RESERVE));
+        for _ in 0..count {
+            let mut name = vec![0; reader.u8()? as usize];
+            reader
+                .file
+                .read_exact(&mut name)
+                .context("truncated 2bit file")?;
+            let offset = if version == 1 {
+                reader.u64()?
+            } else {
+                u64::from(reader.u32()?)
+            };
+            index.push((String::from_utf8_lossy(&name).into_owned(), offset));
+        }
+        reader.index = index.into_iter();
+        Ok(reader)
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        let mut b = [0; 1];
+        self.file
+            .read_exact(&mut b)
+            .context("truncated 2bit file")?;
+        Ok(b[0])
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        let mut b = [0; 4];
+        self.file
+            .read_exact(&mut b)
+            .context("truncated 2bit file")?;
+        Ok(if self.big_endian {
+            u32::from_be_bytes(b)
+        } else {
+            u32::from_le_bytes(b)
+        })
+    }
+
+    fn u64(&mut self) -> Result<u64> {
+        let mut b = [0; 8];
+        self.file
+            .read_exact(&mut b)
+            .context("truncated 2bit file")?;
+        Ok(if self.big_endian {
+            u64::from_be_bytes(b)
+        } else {
+            u64::from_le_bytes(b)
+        })
+    }
+
+    /// Fail unless `bytes` more bytes are left in the file.
+    fn check_remaining(&mut self, bytes: u64) -> Result<()> {
+        let at = self.file.stream_position()?;
+        if bytes > self.len.saturating_sub(at) {
+            bail!("truncated 2bit file");
+        }
+        Ok(())
+    }
+
+    /// `count` block starts followed by `count` block sizes.
+    fn blocks(&mut self) -> Result<Vec<(usize, usize)>> {
+        let count = self.u32()? as usize;
+        self.check_remaining(count as u64 * 8)?;
+        let starts = (0..count).map(|_| self.u32()).collect::<Result<Vec<_>>>()?;
+        let sizes = (0..count).map(|_| self.u32()).collect::<Result<Vec<_>>>()?;
+        Ok(starts
+            .into_iter()
+            .zip(sizes)
+            .map(|(s, n)| (s as usize, n as usize))
+            .collect())
+    }
+
+    fn read_record(&mut self, name: &str, offset: u64) -> Result<fasta::Record> {
+        self.file.seek(SeekFrom::Start(offset))?;
+        let len = self.u32()? as usize;
+        let n_blocks = self.blocks()?;
+        let mask_blocks = self.blocks()?;
+        self.u32()?; // reserved
+        self.check_remaining(len.div_ceil(4) as u64)
+            .with_context(|| format!("truncated 2bit sequence '{}'", name))?;
+        let mut packed = vec![0; len.div_ceil(4)];
+        self.file
+            .read_exact(&mut packed)
+            .with_context(|| format!("truncated 2bit sequence '{}'", name))?;
+
+        let mut seq: Vec<u8> = packed
+            .iter()
+            .flat_map(|&b| [b >> 6, (b >> 4) & 3, (b >> 2) & 3, b & 3])
+            .take(len)
+            .map(|code| BASES[code as usize])
+            .collect();
+        for (start, size) in n_blocks {
+            let end = (start + size).min(len);
+            seq[start.min(end)..end].fill(b'N');
+        }
+        for (start, size) in mask_blocks {
+            let end = (start + size).min(len);
+            seq[start.min(end)..end].make_ascii_lowercase();
+        }
+        Ok(fasta::Record::with_attrs(name, None, &seq))
+    }
+}
+
+impl Iterator for Reader {
+    type Item = Result<fasta::Record>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (name, offset) = self.index.next()?;
+        Some(
+            self.read_record(&name, offset)
+                .with_context(|| format!("reading 2bit sequence '{}'", name)),
+        )
+    }
+}
+
+/// Lets 2bit input be read into the same reused record as FASTA text.
+impl fasta::FastaRead for Reader {
+    fn read(&mut self, record: &mut fasta::Record) -> io::Result<()> {
+        *record = match self.next() {
+            Some(r) => r.map_err(|e| io::Error::other(format!("{:#}", e)))?,
+            None => fasta::Record::new(),
+        };
+        Ok(())
+    }
+}
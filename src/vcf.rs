@@ -0,0 +1,243 @@
+//! `--remap-vcf`: carry variants over to the restranded sequences. On a
+//! flipped sequence positions are mirrored and alleles reverse-complemented;
+//! sample columns are untouched, as allele order does not change.
+//!
+//! An indel's padding base sits before the variant, so after flipping it has
+//! to be the base that now precedes it: the one just after the variant on the
+//! input. Those bases are picked up while the FASTA is processed
+//! ([`AnchorBases`]), which is why the VCF is scanned once up front.
+
+use crate::liftover::{Liftover, Placement};
+use crate::output::Output;
+use anyhow::{bail, Context, Result};
+use bio::alphabets::dna;
+use std::collections::HashMap;
+use std::io::{BufRead, Write};
+
+/// Input bases that become indel padding bases once their sequence is flipped.
+#[derive(Debug, Default)]
+pub struct AnchorBases {
+    /// 1-based positions wanted, by input ID
+    needed: HashMap<String, Vec<u64>>,
+    /// Complemented bases found, by input ID and position
+    found: HashMap<String, HashMap<u64, u8>>,
+}
+
+impl AnchorBases {
+    /// Note the base after each padded record of the VCF read from `input`.
+    pub fn scan(input: impl BufRead) -> Result<Self> {
+        let mut anchors = Self::default();
+        for (i, line) in input.lines().enumerate() {
+            let line = line.context("reading VCF")?;
+            if line.starts_with('#') || line.is_empty() {
+                continue;
+            }
+            let cols: Vec<&str> = line.splitn(6, '\t').collect();
+            if cols.len() < 5 {
+                continue; // reported by remap()
+            }
+            let alleles = alleles(cols[3], cols[4]);
+            if is_padded(&alleles) {
+                let pos = parse_pos(cols[1], i + 1)?;
+                anchors
+                    .needed
+                    .entry(cols[0].to_owned())
+                    .or_default()
+                    .push(pos + cols[3].len() as u64);
+            }
+        }
+        Ok(anchors)
+    }
+
+    /// Keep the wanted bases of a flipped sequence, `seq` as read.
+    pub fn collect(&mut self, id: &str, seq: &[u8]) {
+        let Some(positions) = self.needed.get(id) else {
+            return;
+        };
+        let bases = positions
+            .iter()
+            .filter_map(|&p| Some((p, dna::complement(*seq.get(p as usize - 1)?))))
+            .collect();
+        self.found.insert(id.to_owned(), bases);
+    }
+
+    fn get(&self, id: &str, pos: u64) -> Option<u8> {
+        self.found.get(id)?.get(&pos).copied()
+    }
+}
+
+/// Rewrite the VCF read from `input` into `out`. Records of a flipped
+/// sequence come out in reverse, so they are sorted again before writing.
+pub fn remap(
+    input: impl BufRead,
+    out: &mut Output,
+    liftover: &Liftover,
+    anchors: &AnchorBases,
+) -> Result<()> {
+    let placements = liftover.by_source();
+    // Records of the current flipped sequence, by new position
+    let mut pending: Vec<(u64, String)> = Vec::new();
+    let mut chrom = String::new();
+    for (i, line) in input.lines().enumerate() {
+        let line = line.context("reading VCF")?;
+        let n = i + 1;
+        if let Some(contig) = line.strip_prefix("##contig=<") {
+            writeln!(out, "##contig=<{}", rename_contig(contig, &placements))?;
+            continue;
+        }
+        if line.starts_with('#') || line.is_empty() {
+            writeln!(out, "{}", line)?;
+            continue;
+        }
+        let mut cols: Vec<&str> = line.split('\t').collect();
+        if cols.len() < 8 {
+            bail!(
+                "VCF line {}: expected at least 8 tab-separated columns, found {}",
+                n,
+                cols.len()
+            );
+        }
+        if chrom != cols[0] {
+            flush(out, &mut pending)?;
+            cols[0].clone_into(&mut chrom);
+        }
+        let Some(p) = placements.get(cols[0]) else {
+            writeln!(out, "{}", line)?;
+            continue;
+        };
+        if !p.flipped {
+            cols[0] = &p.target;
+            writeln!(out, "{}", cols.join("\t"))?;
+            continue;
+        }
+        let (pos, alleles) = flip(p, anchors, &cols, n)?;
+        let (pos_s, reference) = (pos.to_string(), alleles[0].clone());
+        let alt = alleles[1..].join(",");
+        let info = set_end(cols[7], pos + reference.len() as u64 - 1);
+        cols[0] = &p.target;
+        cols[1] = &pos_s;
+        cols[3] = &reference;
+        cols[4] = &alt;
+        cols[7] = &info;
+        pending.push((pos, cols.join("\t")));
+    }
+    flush(out, &mut pending)
+}
+
+fn flush(out: &mut Output, pending: &mut Vec<(u64, String)>) -> Result<()> {
+    pending.sort_by_key(|(pos, _)| *pos);
+    for (_, line) in pending.drain(..) {
+        writeln!(out, "{}", line)?;
+    }
+    Ok(())
+}
+
+/// New POS and alleles (REF first) of a record on a flipped sequence.
+fn flip(
+    p: &Placement,
+    anchors: &AnchorBases,
+    cols: &[&str],
+    n: usize,
+) -> Result<(u64, Vec<String>)> {
+    let pos = parse_pos(cols[1], n)?;
+    let ref_len = cols[3].len() as u64;
+    let end = pos + ref_len.max(1) - 1;
+    if end > p.len {
+        bail!(
+            "VCF line {}: variant at {}-{} does not fit on {} ({} bp)",
+            n,
+            pos,
+            end,
+            p.source,
+            p.len
+        );
+    }
+    let alleles = alleles(cols[3], cols[4]);
+    if let Some(a) = alleles.iter().find(|a| a.contains(['<', '[', ']'])) {
+        bail!(
+            "VCF line {}: symbolic allele '{}' on flipped sequence {} cannot be remapped",
+            n,
+            a,
+            p.source
+        );
+    }
+    let rc = |a: &[u8]| match a {
+        b"*" | b"." => String::from_utf8_lossy(a).into_owned(),
+        _ => String::from_utf8(dna::revcomp(a)).expect("complement of UTF-8 is UTF-8"),
+    };
+    // With no base after the variant to pad with, the old padding base
+    // trails the alleles instead, as VCF allows at a sequence's start
+    if !is_padded(&alleles) || end == p.len {
+        let alleles = alleles.iter().map(|a| rc(a.as_bytes())).collect();
+        return Ok((p.len + 1 - end, alleles));
+    }
+    let Some(anchor) = anchors.get(&p.source, end + 1) else {
+        bail!(
+            "VCF line {}: padding base {}:{} was not read from the FASTA",
+            n,
+            p.source,
+            end + 1
+        );
+    };
+    let alleles = alleles
+        .iter()
+        .map(|a| match a.as_bytes() {
+            b"*" | b"." => a.to_string(),
+            a => format!("{}{}", anchor as char, rc(&a[1..])),
+        })
+        .collect();
+    Ok((p.len - end, alleles))
+}
+
+/// REF followed by each ALT.
+fn alleles<'a>(reference: &'a str, alt: &'a str) -> Vec<&'a str> {
+    std::iter::once(reference).chain(alt.split(',')).collect()
+}
+
+/// Whether the alleles carry a shared leading padding base, i.e. are of
+/// different lengths and all start with the same base.
+fn is_padded(alleles: &[&str]) -> bool {
+    let bases: Vec<&[u8]> = alleles
+        .iter()
+        .map(|a| a.as_bytes())
+        .filter(|a| !matches!(*a, b"*" | b"."))
+        .collect();
+    let Some(reference) = bases.first() else {
+        return false;
+    };
+    let first = reference.first().map(u8::to_ascii_uppercase);
+    bases.iter().any(|a| a.len() != reference.len())
+        && bases
+            .iter()
+            .all(|a| !a.is_empty() && a.first().map(u8::to_ascii_uppercase) == first)
+}
+
+/// `ID=x,...>` with `x` replaced by its output ID.
+fn rename_contig(contig: &str, placements: &HashMap<&str, &Placement>) -> String {
+    let fields = contig.strip_prefix("ID=").map(|rest| {
+        let end = rest.find([',', '>']).unwrap_or(rest.len());
+        (&rest[..end], &rest[end..])
+    });
+    match fields.and_then(|(id, rest)| Some((placements.get(id)?, rest))) {
+        Some((p, rest)) => format!("ID={}{}", p.target, rest),
+        None => contig.to_owned(),
+    }
+}
+
+/// INFO with any `END=` moved to `end`.
+fn set_end(info: &str, end: u64) -> String {
+    info.split(';')
+        .map(|field| match field.strip_prefix("END=") {
+            Some(_) => format!("END={}", end),
+            None => field.to_owned(),
+        })
+        .collect::<Vec<_>>()
+        .join(";")
+}
+
+fn parse_pos(s: &str, line: usize) -> Result<u64> {
+    match s.parse() {
+        Ok(pos) if pos > 0 => Ok(pos),
+        _ => bail!("VCF line {}: '{}' is not a position", line, s),
+    }
+}
@@ -0,0 +1,104 @@
+//! `--verify-ids`: after a run, read the outputs back and check that they
+//! hold exactly the reads that were written, catching truncated or corrupted
+//! files before they move down a pipeline.
+//!
+//! Only a count and an order-independent digest of the IDs are kept, so
+//! memory does not grow with the input; a mismatch is reported as such,
+//! without naming the reads involved.
+
+use crate::output::{self, RecordFormat};
+use crate::progress::ByteCounter;
+use crate::timing::Timings;
+use crate::{open_text, remote};
+use anyhow::{bail, Context, Result};
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+
+/// A multiset of read IDs, as a count and a sum of hashes.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct IdSet {
+    count: u64,
+    digest: u64,
+}
+
+impl IdSet {
+    /// Add the ID of a record written with `header`.
+    pub fn add(&mut self, header: &str) {
+        let id = header.split_whitespace().next().unwrap_or("");
+        let mut h = DefaultHasher::new();
+        id.hash(&mut h);
+        self.count += 1;
+        self.digest = self.digest.wrapping_add(h.finish());
+    }
+
+    /// IDs of the records in `path`, written as `format`.
+    fn read_back(&mut self, path: &Path, format: RecordFormat) -> Result<()> {
+        let name = path.to_str().context("output path is not UTF-8")?;
+        let buffer = output::buffer_size(None, false);
+        let text = open_text(name, buffer, &ByteCounter::default(), &Timings::new(false))?;
+        for (i, line) in BufReader::with_capacity(buffer, text).lines().enumerate() {
+            let line = line.with_context(|| format!("reading back {}", name))?;
+            match format {
+                RecordFormat::Fasta => {
+                    if let Some(header) = line.strip_prefix('>') {
+                        self.add(header);
+                    }
+                }
+                RecordFormat::Fastq if i % 4 == 0 => match line.strip_prefix('@') {
+                    Some(header) => self.add(header),
+                    None => bail!("{} line {}: expected a FASTQ header", name, i + 1),
+                },
+                RecordFormat::Fastq => {}
+                RecordFormat::Tab => {
+                    if !line.is_empty() {
+                        self.add(line.split('\t').next().unwrap_or(""));
+                    }
+                }
+                RecordFormat::Arrow | RecordFormat::Parquet | RecordFormat::Bam => {
+                    unreachable!("--verify-ids is refused for columnar and BAM output")
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Fail unless every output can be read back once the run is done.
+pub fn check_paths(paths: &[&Option<PathBuf>]) -> Result<()> {
+    for path in paths.iter().copied().flatten() {
+        if crate::is_stdout_path(path) || remote::is_remote(path) || path.starts_with("/dev/") {
+            bail!(
+                "--verify-ids reads the outputs back, so {} must be a local file",
+                path.display()
+            );
+        }
+    }
+    if paths[0].is_none() {
+        bail!("--verify-ids reads the outputs back, so it needs --out");
+    }
+    Ok(())
+}
+
+/// Compare the IDs `written` with those found in `paths`.
+pub fn verify(written: IdSet, paths: &[&Option<PathBuf>], format: RecordFormat) -> Result<()> {
+    let mut found = IdSet::default();
+    for path in paths.iter().copied().flatten() {
+        found.read_back(path, format)?;
+    }
+    if found.count != written.count {
+        bail!(
+            "--verify-ids: {} record(s) written but {} read back from the output",
+            written.count,
+            found.count
+        );
+    }
+    if found != written {
+        bail!(
+            "--verify-ids: the output holds {} record(s) as expected, but their IDs differ from those written",
+            found.count
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,132 @@
+//! Directory watch mode (`--watch`): restrand sequence chunks as a basecaller
+//! writes them, one output per chunk. `--metrics-listen` exposes running
+//! totals to Prometheus.
+
+use crate::compress;
+use crate::metrics::{self, Metrics};
+use crate::{run, Cli, Outcome};
+use anyhow::{bail, Context, Result};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const CHUNK_EXTENSIONS: [&str; 4] = [".fa", ".fasta", ".fq", ".fastq"];
+
+/// Sequence files only; partial downloads and hidden files are skipped.
+fn is_chunk(name: &str) -> bool {
+    let name = compress::strip_extension(name);
+    !name.starts_with('.') && CHUNK_EXTENSIONS.iter().any(|ext| name.ends_with(ext))
+}
+
+/// Chunks in `dir`, by name, with their current size.
+fn scan(dir: &Path) -> Result<Vec<(String, u64)>> {
+    let mut chunks = Vec::new();
+    for entry in fs::read_dir(dir).with_context(|| format!("read directory {:?}", dir))? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if is_chunk(&name) && entry.file_type()?.is_file() {
+            chunks.push((name, entry.metadata()?.len()));
+        }
+    }
+    chunks.sort();
+    Ok(chunks)
+}
+
+/// Poll `dir` every `--watch-interval` seconds. A chunk is processed once its
+/// size is unchanged across two polls, into `--watch-out-dir` under the same
+/// name (minus any compression extension). Runs until `--watch-idle-exit`
+/// seconds pass with no new chunk, or forever without it. A chunk that fails
+/// is reported, counted as an error and not retried; the others carry on, and
+/// the run as a whole fails when it ends.
+pub fn watch(cli: &Cli, dir: &Path) -> Result<Outcome> {
+    let out_dir = cli
+        .watch_out_dir
+        .as_deref()
+        .expect("clap requires --watch-out-dir with --watch");
+    fs::create_dir_all(out_dir).with_context(|| format!("create {:?}", out_dir))?;
+    let interval = Duration::try_from_secs_f64(cli.watch_interval)
+        .ok()
+        .filter(|d| !d.is_zero())
+        .with_context(|| {
+            format!(
+                "--watch-interval must be positive, got {}",
+                cli.watch_interval
+            )
+        })?;
+    let idle_exit = cli
+        .watch_idle_exit
+        .map(|s| {
+            Duration::try_from_secs_f64(s)
+                .with_context(|| format!("--watch-idle-exit must be non-negative, got {}", s))
+        })
+        .transpose()?;
+
+    let metrics = Arc::new(Metrics::default());
+    if let Some(addr) = &cli.metrics_listen {
+        metrics::spawn_exporter(addr, metrics.clone())?;
+    }
+
+    let mut done: HashSet<String> = HashSet::new();
+    let mut pending: HashMap<String, u64> = HashMap::new();
+    let mut total = Outcome::default();
+    let mut failed = 0;
+    let mut last_activity = Instant::now();
+    loop {
+        for (name, size) in scan(dir)? {
+            if done.contains(&name) {
+                continue;
+            }
+            last_activity = Instant::now();
+            if pending.insert(name.clone(), size) != Some(size) {
+                continue; // new or still growing
+            }
+            pending.remove(&name);
+            let input = dir.join(&name);
+            let output: PathBuf = out_dir.join(compress::strip_extension(&name));
+            let chunk = cli.for_input(&input.to_string_lossy(), cli.table.clone(), output);
+            let started = Instant::now();
+            let o = match run(&chunk).with_context(|| format!("chunk {:?}", input)) {
+                Ok(o) => o,
+                Err(e) => {
+                    eprintln!("Error: {:?}", e);
+                    metrics.error();
+                    failed += 1;
+                    done.insert(name);
+                    continue;
+                }
+            };
+            metrics.record(&o, started.elapsed());
+            if !cli.quiet {
+                eprintln!(
+                    "chunk {} processed={} flipped={} missing={} warnings={}",
+                    name, o.processed, o.flipped, o.missing, o.warnings
+                );
+            }
+            total.processed += o.processed;
+            total.flipped += o.flipped;
+            total.missing += o.missing;
+            total.warnings += o.warnings;
+            done.insert(name);
+        }
+        if idle_exit.is_some_and(|idle| last_activity.elapsed() >= idle) {
+            break;
+        }
+        thread::sleep(interval);
+    }
+    if !cli.quiet {
+        eprintln!(
+            "WATCH mode: chunks={} failed={} processed={} flipped={}",
+            done.len(),
+            failed,
+            total.processed,
+            total.flipped
+        );
+    }
+    if failed > 0 {
+        bail!("{} of {} chunk(s) failed", failed, done.len());
+    }
+    Ok(total)
+}
@@ -201,3 +201,4857 @@ fn bad_orientation_errors() {
         .failure()
         .stderr(predicate::str::contains("Unrecognized orientation value"));
 }
+
+#[test]
+fn annotate_appends_key_value_fields() {
+    let td = tempfile::tempdir().unwrap();
+    let fasta_p = td.path().join("in.fa");
+    let tsv_p = td.path().join("map.tsv");
+    write(&fasta_p, FASTA);
+    // readB missing from the table -> source=none
+    write(&tsv_p, "ReadName\torientation\nreadA\t-\n");
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    let out = run_ok(cmd.args([
+        "-f",
+        fasta_p.to_str().unwrap(),
+        "-t",
+        tsv_p.to_str().unwrap(),
+        "--annotate",
+    ]));
+
+    assert!(out.contains(">readA some desc restrand=flipped source=table\n"));
+    assert!(out.contains(">readB restrand=kept source=none\n"));
+
+    // FASTQ headers get the same fields
+    let fq_p = td.path().join("in.fq");
+    write(&fq_p, "@readA x\nAAC\n+\nIII\n@readB\nAAC\n+\nIII\n");
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    let out = run_ok(cmd.args(["--fastq", "-f", fq_p.to_str().unwrap()]).args([
+        "-t",
+        tsv_p.to_str().unwrap(),
+        "--annotate",
+    ]));
+    assert!(
+        out.contains("@readA x restrand=flipped source=table\n"),
+        "{}",
+        out
+    );
+    assert!(
+        out.contains("@readB restrand=kept source=none\n"),
+        "{}",
+        out
+    );
+}
+
+#[test]
+fn header_template_composes_headers() {
+    let td = tempfile::tempdir().unwrap();
+    let d = td.path();
+    write(&d.join("in.fa"), FASTA);
+    write(&d.join("map.tsv"), "ReadName\torientation\nreadA\t-\n");
+    write(
+        &d.join("in.fq"),
+        "@r1 cell:1|orientation:-\nAACG\n+\nABCD\n@r2\nAACG\n+\nABCD\n",
+    );
+    let p = |name: &str| d.join(name).to_str().unwrap().to_owned();
+    let template = "{id} strand={ori} was={input_ori} len={len} {action}/{source} [{desc}] {{x}}";
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    let out = run_ok(
+        cmd.args(["-f", &p("in.fa"), "-t", &p("map.tsv")])
+            .args(["--header-template", template]),
+    );
+    assert!(out.contains(">readA strand=+ was=- len=10 flipped/table [some desc] {x}\n"));
+    assert!(out.contains(">readB strand=. was=. len=12 kept/none [] {x}\n"));
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    let out = run_ok(
+        cmd.args(["--fastq", "-f", &p("in.fq")])
+            .args(["--header-template", "{id} {desc} len={len}"]),
+    );
+    let lines: Vec<&str> = out.lines().collect();
+    assert_eq!(lines[0], "@r1 cell:1|orientation:+ len=4");
+    assert_eq!(lines[4], "@r2  len=4");
+
+    for bad in ["{id} {nope}", "{id", "id}"] {
+        let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+        cmd.args([
+            "-f",
+            &p("in.fa"),
+            "-t",
+            &p("map.tsv"),
+            "--header-template",
+            bad,
+        ])
+        .assert()
+        .code(2);
+    }
+}
+
+#[test]
+fn match_on_sequence_finds_renamed_reads() {
+    let td = tempfile::tempdir().unwrap();
+    let d = td.path();
+    // Keyed by `printf %s SEQ | md5sum` of AACCGGTTAC (-) and GGGTTTAAAC (+)
+    write(
+        &d.join("map.tsv"),
+        "ReadName\torientation\n\
+         e3bc05ffd2ae5ed7866695e64adcf253\t-\n\
+         acfb97e6b7fcbe8b5757054986418a1c\t+\n",
+    );
+    write(
+        &d.join("in.fa"),
+        ">x1\nAACCGGTTAC\n>x2\nGTTTAAACCC\n>x3\naaccggttac\n>x4\nACGTTT\n",
+    );
+    let p = |name: &str| d.join(name).to_str().unwrap().to_owned();
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    let out = run_ok(cmd.args(["-f", &p("in.fa"), "-t", &p("map.tsv")]).args([
+        "--match-on",
+        "sequence",
+        "--annotate",
+    ]));
+    assert_eq!(
+        out,
+        ">x1 restrand=flipped source=table\nGTAACCGGTT\n\
+         >x2 restrand=flipped source=table\nGGGTTTAAAC\n\
+         >x3 restrand=flipped source=table\ngtaaccggtt\n\
+         >x4 restrand=kept source=none\nACGTTT\n"
+    );
+}
+
+#[test]
+fn reference_minimizers_orient_reads() {
+    let td = tempfile::tempdir().unwrap();
+    let d = td.path();
+    let mut state = 42u64;
+    let mut random_seq = |n: usize| -> String {
+        (0..n)
+            .map(|_| {
+                state = state
+                    .wrapping_mul(6364136223846793005)
+                    .wrapping_add(1442695040888963407);
+                b"ACGT"[(state >> 62) as usize] as char
+            })
+            .collect()
+    };
+    let (tx1, tx2, other) = (random_seq(300), random_seq(300), random_seq(120));
+    write(
+        &d.join("ref.fa"),
+        &format!(">tx1\n{}\n>tx2\n{}\n", tx1, tx2),
+    );
+    let minus = String::from_utf8(dna::revcomp(&tx2.as_bytes()[40..200])).unwrap();
+    write(
+        &d.join("in.fa"),
+        &format!(">p\n{}\n>m\n{}\n>none\n{}\n", &tx1[20..180], minus, other),
+    );
+    let p = |name: &str| d.join(name).to_str().unwrap().to_owned();
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    let out = run_ok(
+        cmd.args(["-f", &p("in.fa"), "--reference", &p("ref.fa")])
+            .args(["--annotate", "--decision-log", &p("log.tsv")]),
+    );
+    assert!(
+        out.contains(">p restrand=kept source=reference\n"),
+        "{}",
+        out
+    );
+    assert!(
+        out.contains(">m restrand=flipped source=reference\n"),
+        "{}",
+        out
+    );
+    assert!(out.contains(">none restrand=kept source=none\n"), "{}", out);
+    assert!(out.replace('\n', "").contains(&tx2[40..200]));
+    let log = fs::read_to_string(d.join("log.tsv")).unwrap();
+    assert!(
+        log.contains("p\treference\t+\tkept\t1.000\tfalse\n"),
+        "{}",
+        log
+    );
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    cmd.args([
+        "-f",
+        &p("in.fa"),
+        "--reference",
+        &p("ref.fa"),
+        "--minimizer-k",
+        "40",
+    ])
+    .assert()
+    .failure()
+    .stderr(predicate::str::contains("--minimizer-k must be 1-31"));
+}
+
+#[test]
+fn paf_splice_strand_orients_reads() {
+    let td = tempfile::tempdir().unwrap();
+    let d = td.path();
+    write(
+        &d.join("in.fa"),
+        ">ts\nAACC\n>motif\nAAGG\n>rev\nACGT\n>secondary\nAAAT\n>unspliced\nCCCA\n",
+    );
+    let row = |name: &str, strand: &str, tags: &str| {
+        format!(
+            "{}\t4\t0\t4\t{}\tchr1\t1000\t10\t500\t4\t4\t60\t{}\n",
+            name, strand, tags
+        )
+    };
+    write(
+        &d.join("aln.paf"),
+        &[
+            row("ts", "+", "tp:A:P\tts:A:-\tcs:Z::2~gt100ag:2"),
+            row("motif", "+", "tp:A:P\tcs:Z::1~ct50ac:1~CT60AC:1~gt70ag:1"),
+            row("rev", "-", "tp:A:P\tcs:Z::2~ct100ac:2"),
+            row("secondary", "+", "tp:A:S\tts:A:-"),
+            row("unspliced", "-", "tp:A:P\tcs:Z::4"),
+        ]
+        .concat(),
+    );
+    let p = |name: &str| d.join(name).to_str().unwrap().to_owned();
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    let out = run_ok(cmd.args(["-f", &p("in.fa"), "--paf", &p("aln.paf")]).args([
+        "--annotate",
+        "--decision-log",
+        &p("log.tsv"),
+    ]));
+    assert_eq!(
+        out,
+        ">ts restrand=flipped source=splice\nGGTT\n\
+         >motif restrand=flipped source=splice\nCCTT\n\
+         >rev restrand=kept source=splice\nACGT\n\
+         >secondary restrand=kept source=none\nAAAT\n\
+         >unspliced restrand=kept source=none\nCCCA\n"
+    );
+    let log = fs::read_to_string(d.join("log.tsv")).unwrap();
+    assert!(
+        log.contains("motif\tsplice\t-\tflipped\t0.667\tfalse\n"),
+        "{}",
+        log
+    );
+}
+
+#[test]
+fn min_found_fraction_fails_on_wrong_table() {
+    let td = tempfile::tempdir().unwrap();
+    let d = td.path();
+    write(&d.join("in.fa"), FASTA);
+    write(&d.join("map.tsv"), "ReadName\torientation\nreadA\t-\n");
+    let p = |name: &str| d.join(name).to_str().unwrap().to_owned();
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    cmd.args(["-f", &p("in.fa"), "-t", &p("map.tsv"), "-o", &p("out.fa")])
+        .args(["--min-found-fraction", "0.9"])
+        .assert()
+        .code(1)
+        .stderr(predicate::str::contains(
+            "only 1 of 2 reads (50.0%) were found in the table, below --min-found-fraction 0.9",
+        ));
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    cmd.args(["-f", &p("in.fa"), "-t", &p("map.tsv"), "-o", &p("out.fa")])
+        .args(["--min-found-fraction", "0.5"])
+        .assert()
+        .success();
+}
+
+#[test]
+fn flip_fraction_guards_warn_and_fail() {
+    let td = tempfile::tempdir().unwrap();
+    let d = td.path();
+    write(&d.join("in.fa"), FASTA);
+    write(
+        &d.join("map.tsv"),
+        "ReadName\torientation\nreadA\t-\nreadB\t-\n",
+    );
+    let p = |name: &str| d.join(name).to_str().unwrap().to_owned();
+    let base = ["-f", &p("in.fa"), "-t", &p("map.tsv"), "-o", &p("out.fa")];
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    cmd.args(base)
+        .args(["--warn-flip-fraction", "0.95", "--fail-on-warnings"])
+        .assert()
+        .code(4)
+        .stderr(predicate::str::contains(
+            "warning: 2 of 2 reads (100.0%) were flipped, above --warn-flip-fraction 0.95; is --target-orientation + right",
+        ));
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    cmd.args(base)
+        .args(["--max-flip-fraction", "0.5"])
+        .assert()
+        .code(1)
+        .stderr(predicate::str::contains("above --max-flip-fraction 0.5"));
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    cmd.args(base)
+        .args(["--max-flip-fraction", "0.5", "--target-orientation", "-"])
+        .assert()
+        .success();
+}
+
+#[test]
+fn chromosome_scale_records_flip_like_small_ones() {
+    let td = tempfile::tempdir().unwrap();
+    let d = td.path();
+    // Past the streaming threshold, with a ragged last line and soft-masking
+    let unit = b"ACGTTGCAacgtNNNNGGCCA";
+    let big: Vec<u8> = unit.iter().cycle().take((8 << 20) + 37).copied().collect();
+    let wrap = |seq: &[u8]| -> String {
+        seq.chunks(60)
+            .map(|c| format!("{}\n", std::str::from_utf8(c).unwrap()))
+            .collect()
+    };
+    write(
+        &d.join("in.fa"),
+        &format!(">chrBig\n{}>small\nAACG\n", wrap(&big)),
+    );
+    write(
+        &d.join("map.tsv"),
+        "ReadName\torientation\nchrBig\t-\nsmall\t-\n",
+    );
+    let p = |name: &str| d.join(name).to_str().unwrap().to_owned();
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    cmd.args(["-f", &p("in.fa"), "-t", &p("map.tsv"), "-o", &p("out.fa")])
+        .args(["--output-case", "upper", "--flipped-suffix", "/rc"])
+        .assert()
+        .success();
+    let mut expected = dna::revcomp(&big);
+    expected.make_ascii_uppercase();
+    assert_eq!(
+        fs::read_to_string(d.join("out.fa")).unwrap(),
+        format!(">chrBig/rc\n{}>small/rc\nCGTT\n", wrap(&expected))
+    );
+}
+
+#[test]
+fn agp_and_chain_describe_flipped_contigs() {
+    let td = tempfile::tempdir().unwrap();
+    let d = td.path();
+    write(&d.join("in.fa"), FASTA);
+    write(&d.join("map.tsv"), TSV);
+    let p = |name: &str| d.join(name).to_str().unwrap().to_owned();
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    cmd.args(["-f", &p("in.fa"), "-t", &p("map.tsv"), "-o", &p("out.fa")])
+        .args(["--flipped-suffix", "_rc", "--agp-out", &p("out.agp")])
+        .args(["--chain-out", &p("in_to_out.chain")])
+        .assert()
+        .success();
+    let agp = fs::read_to_string(d.join("out.agp")).unwrap();
+    assert!(agp.starts_with("##agp-version\t2.1\n#"));
+    assert!(agp.ends_with(
+        "readA\t1\t10\t1\tW\treadA\t1\t10\t+\n\
+         readB_rc\t1\t12\t1\tW\treadB\t1\t12\t-\n"
+    ));
+    assert_eq!(
+        fs::read_to_string(d.join("in_to_out.chain")).unwrap(),
+        "chain 10 readA 10 + 0 10 readA 10 + 0 10 1\n10\n\n\
+         chain 12 readB 12 + 0 12 readB_rc 12 - 0 12 2\n12\n\n"
+    );
+}
+
+#[test]
+fn remap_gff_mirrors_features_on_flipped_contigs() {
+    let td = tempfile::tempdir().unwrap();
+    let d = td.path();
+    write(&d.join("in.fa"), FASTA);
+    write(&d.join("map.tsv"), TSV);
+    write(
+        &d.join("in.gff3"),
+        "##gff-version 3\n\
+         ##sequence-region readB 1 12\n\
+         readB\tsrc\tgene\t3\t5\t.\t+\t.\tID=g1\n\
+         readB\tsrc\tCDS\t3\t5\t.\t+\t2\tParent=g1\n\
+         readA\tsrc\tgene\t2\t4\t.\t-\t.\tID=g2\n\
+         other\tsrc\tgene\t1\t9\t.\t.\t.\tID=g3\n",
+    );
+    let p = |name: &str| d.join(name).to_str().unwrap().to_owned();
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    cmd.args(["-f", &p("in.fa"), "-t", &p("map.tsv"), "-o", &p("out.fa")])
+        .args(["--flipped-suffix", "_rc", "--remap-gff", &p("in.gff3")])
+        .args(["--remap-gff-out", &p("out.gff3")])
+        .assert()
+        .success();
+    assert_eq!(
+        fs::read_to_string(d.join("out.gff3")).unwrap(),
+        "##gff-version 3\n\
+         ##sequence-region readB_rc 1 12\n\
+         readB_rc\tsrc\tgene\t8\t10\t.\t-\t.\tID=g1\n\
+         readB_rc\tsrc\tCDS\t8\t10\t.\t-\t2\tParent=g1\n\
+         readA\tsrc\tgene\t2\t4\t.\t-\t.\tID=g2\n\
+         other\tsrc\tgene\t1\t9\t.\t.\t.\tID=g3\n"
+    );
+
+    write(
+        &d.join("bad.gff3"),
+        "readB\tsrc\tgene\t3\t50\t.\t+\t.\tID=g1\n",
+    );
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    cmd.args(["-f", &p("in.fa"), "-t", &p("map.tsv"), "-o", &p("out.fa")])
+        .args([
+            "--remap-gff",
+            &p("bad.gff3"),
+            "--remap-gff-out",
+            &p("x.gff3"),
+        ])
+        .assert()
+        .code(1)
+        .stderr(predicate::str::contains(
+            "GFF line 1: feature 3-50 does not fit on readB (12 bp)",
+        ));
+}
+
+#[test]
+fn remap_vcf_mirrors_variants_on_flipped_contigs() {
+    let td = tempfile::tempdir().unwrap();
+    let d = td.path();
+    write(&d.join("in.fa"), FASTA);
+    write(&d.join("map.tsv"), TSV);
+    let header = "##fileformat=VCFv4.2\n\
+         ##contig=<ID=readA,length=10>\n\
+         ##contig=<ID=readB,length=12>\n\
+         #CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\tFORMAT\ts1\n";
+    write(
+        &d.join("in.vcf"),
+        &format!(
+            "{}\
+             readA\t3\tv1\tG\tT\t.\tPASS\t.\tGT\t0/1\n\
+             readB\t2\tv2\tG\tA\t.\tPASS\t.\tGT\t1/1\n\
+             readB\t4\tv3\tCC\tC\t.\tPASS\tEND=5\tGT\t0/1\n\
+             readB\t12\tv4\tt\ttA\t.\tPASS\t.\tGT\t0|1\n\
+             other\t7\tv5\tA\tC\t.\tPASS\t.\tGT\t0/1\n",
+            header
+        ),
+    );
+    let p = |name: &str| d.join(name).to_str().unwrap().to_owned();
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    cmd.args(["-f", &p("in.fa"), "-t", &p("map.tsv"), "-o", &p("out.fa")])
+        .args(["--flipped-suffix", "_rc", "--remap-vcf", &p("in.vcf")])
+        .args(["--remap-vcf-out", &p("out.vcf")])
+        .assert()
+        .success();
+    // readB is written as aaatttGGGCCC; the deletion keeps a leading
+    // padding base and the insertion at the old end trails it instead
+    assert_eq!(
+        fs::read_to_string(d.join("out.vcf")).unwrap(),
+        format!(
+            "{}\
+             readA\t3\tv1\tG\tT\t.\tPASS\t.\tGT\t0/1\n\
+             readB_rc\t1\tv4\ta\tTa\t.\tPASS\t.\tGT\t0|1\n\
+             readB_rc\t7\tv3\tGG\tG\t.\tPASS\tEND=8\tGT\t0/1\n\
+             readB_rc\t11\tv2\tC\tT\t.\tPASS\t.\tGT\t1/1\n\
+             other\t7\tv5\tA\tC\t.\tPASS\t.\tGT\t0/1\n",
+            header.replace("ID=readB,", "ID=readB_rc,")
+        )
+    );
+
+    write(
+        &d.join("sv.vcf"),
+        &format!(
+            "{}readB\t3\tsv\tG\t<DEL>\t.\tPASS\tEND=5\tGT\t0/1\n",
+            header
+        ),
+    );
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    cmd.args(["-f", &p("in.fa"), "-t", &p("map.tsv"), "-o", &p("out.fa")])
+        .args(["--remap-vcf", &p("sv.vcf"), "--remap-vcf-out", &p("x.vcf")])
+        .assert()
+        .code(1)
+        .stderr(predicate::str::contains(
+            "symbolic allele '<DEL>' on flipped sequence readB",
+        ));
+}
+
+#[test]
+fn all_flips_every_record_without_evidence() {
+    let td = tempfile::tempdir().unwrap();
+    let fasta_p = td.path().join("in.fa");
+    let fq_p = td.path().join("in.fq");
+    write(&fasta_p, FASTA);
+    write(
+        &fq_p,
+        "@r1 orientation:-\nAACG\n+\nABCD\n@r2 orientation:+\nGGTA\n+\nEFGH\n",
+    );
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    let out = run_ok(cmd.args(["-f", fasta_p.to_str().unwrap(), "--all", "--annotate"]));
+    assert_eq!(
+        out,
+        ">readA some desc restrand=flipped source=all\nGTACGTACGT\n\
+         >readB restrand=flipped source=all\naaatttGGGCCC\n"
+    );
+
+    // Orientation tags are neither consulted nor rewritten
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    let out = run_ok(cmd.args(["--fastq", "-f", fq_p.to_str().unwrap(), "--all"]));
+    assert_eq!(
+        out,
+        "@r1 orientation:-\nCGTT\n+\nDCBA\n@r2 orientation:+\nTACC\n+\nHGFE\n"
+    );
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    cmd.args(["-f", fasta_p.to_str().unwrap(), "--all", "-t", "map.tsv"])
+        .assert()
+        .code(2);
+}
+
+#[test]
+fn match_on_pacbio_finds_zmw_keys() {
+    let td = tempfile::tempdir().unwrap();
+    let fasta_p = td.path().join("in.fa");
+    let tsv_p = td.path().join("map.tsv");
+    write(
+        &fasta_p,
+        ">m64011_190830_220126/123/ccs\nAACG\n\
+         >m64011_190830_220126/456/0_500\nAACG\n\
+         >m64011_190830_220126/789/ccs/fwd\nAACG\n",
+    );
+    write(
+        &tsv_p,
+        "ReadName\torientation\n\
+         123\t-\n\
+         m64011_190830_220126/456/ccs\t-\n\
+         m64011_190830_220126/789\t-\n",
+    );
+    let args = [
+        "-f",
+        fasta_p.to_str().unwrap(),
+        "-t",
+        tsv_p.to_str().unwrap(),
+    ];
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    let out = run_ok(cmd.args(args).args(["--match-on", "pacbio"]));
+    // By-strand CCS reads only match their exact name
+    assert_eq!(
+        out,
+        ">m64011_190830_220126/123/ccs\nCGTT\n\
+         >m64011_190830_220126/456/0_500\nCGTT\n\
+         >m64011_190830_220126/789/ccs/fwd\nAACG\n"
+    );
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    let out = run_ok(cmd.args(args));
+    assert!(!out.contains("CGTT"));
+}
+
+#[test]
+fn table_format_reads_isoseq_and_lima_reports() {
+    let td = tempfile::tempdir().unwrap();
+    let fasta_p = td.path().join("in.fa");
+    let flnc_p = td.path().join("flnc.report.csv");
+    let lima_p = td.path().join("demux.lima.report");
+    write(&fasta_p, ">m1_2/1/ccs\nAACG\n>m1_2/2/ccs\nAACG\n");
+    write(
+        &flnc_p,
+        "id,strand,fivelen,threelen,polyAlen,insertlen,primer\n\
+         m1_2/1/ccs,+,39,32,30,1500,primer_5p--primer_3p\n\
+         m1_2/2/ccs,-,39,32,30,1500,primer_5p--primer_3p\n",
+    );
+    write(
+        &lima_p,
+        "ZMW\tIdxFirst\tIdxCombined\tIdxFirstNamed\tIdxCombinedNamed\n\
+         m1_2/1\t0\t1\tIsoSeq_5p\tIsoSeq_3p--IsoSeq_5p\n\
+         m1_2/2\t0\t1\tIsoSeq_5p\tIsoSeq_5p--IsoSeq_3p\n",
+    );
+    let fasta = fasta_p.to_str().unwrap();
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    let out = run_ok(
+        cmd.args(["-f", fasta, "-t", flnc_p.to_str().unwrap()])
+            .args(["--table-format", "isoseq-refine"]),
+    );
+    assert_eq!(out, ">m1_2/1/ccs\nAACG\n>m1_2/2/ccs\nCGTT\n");
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    let out = run_ok(
+        cmd.args(["-f", fasta, "-t", lima_p.to_str().unwrap()])
+            .args(["--table-format", "lima", "--match-on", "pacbio"]),
+    );
+    assert_eq!(out, ">m1_2/1/ccs\nCGTT\n>m1_2/2/ccs\nAACG\n");
+}
+
+#[test]
+fn duplex_reads_match_either_simplex_id() {
+    let td = tempfile::tempdir().unwrap();
+    let fasta_p = td.path().join("in.fa");
+    let tsv_p = td.path().join("map.tsv");
+    write(
+        &fasta_p,
+        ">a1;a2\nAACG\n>b1;b2\nAACG\n>c1;c2\nAACG\n>d1\nAACG\n",
+    );
+    // The duplex read follows its template, so a listed complement inverts
+    write(&tsv_p, "ReadName\torientation\na1\t-\nb2\t+\nd1\t-\n");
+    let args = [
+        "-f",
+        fasta_p.to_str().unwrap(),
+        "-t",
+        tsv_p.to_str().unwrap(),
+    ];
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    let assert = cmd
+        .args(args)
+        .args(["--duplex", "match-either"])
+        .assert()
+        .success();
+    assert_eq!(
+        String::from_utf8_lossy(&assert.get_output().stdout),
+        ">a1;a2\nCGTT\n>b1;b2\nCGTT\n>c1;c2\nAACG\n>d1\nCGTT\n"
+    );
+    let stderr = String::from_utf8_lossy(&assert.get_output().stderr).into_owned();
+    assert!(stderr.contains("missing_in_table=1"), "{}", stderr);
+    assert!(stderr.contains("duplex=3"), "{}", stderr);
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    let out = run_ok(cmd.args(args).args(["--duplex", "drop"]));
+    assert_eq!(out, ">d1\nCGTT\n");
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    cmd.args(args)
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("missing_in_table=3"));
+}
+
+#[test]
+fn include_ids_subsets_reads() {
+    let td = tempfile::tempdir().unwrap();
+    let fasta_p = td.path().join("in.fa");
+    let tsv_p = td.path().join("map.tsv");
+    let ids_p = td.path().join("ids.txt.gz");
+    write(&fasta_p, FASTA);
+    write(&tsv_p, TSV);
+    write_gz(&ids_p, ">readB\n\nnot_in_input\n");
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    cmd.args([
+        "-f",
+        fasta_p.to_str().unwrap(),
+        "-t",
+        tsv_p.to_str().unwrap(),
+    ])
+    .args(["--include-ids", ids_p.to_str().unwrap()])
+    .assert()
+    .success()
+    .stdout(">readB\naaatttGGGCCC\n")
+    .stderr(predicate::str::contains("processed=1 flipped=1"));
+}
+
+#[test]
+fn exclude_ids_drops_and_counts_reads() {
+    let td = tempfile::tempdir().unwrap();
+    let fq_p = td.path().join("in.fq");
+    let ids_p = td.path().join("contaminants.txt");
+    write(&fq_p, TAGGED_FASTQ);
+    write(&ids_p, "r1\n");
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    let assert = cmd
+        .args(["--fastq", "-f", fq_p.to_str().unwrap()])
+        .args(["--exclude-ids", ids_p.to_str().unwrap()])
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("excluded=1"));
+    let out = String::from_utf8_lossy(&assert.get_output().stdout).into_owned();
+    assert!(out.starts_with("@r2 "), "{}", out);
+    assert!(!out.contains("@r1"), "{}", out);
+}
+
+#[test]
+fn self_test_checks_round_trips() {
+    let td = tempfile::tempdir().unwrap();
+    let d = td.path();
+    write(&d.join("in.fa"), FASTA);
+    write(&d.join("rna.fa"), ">ok\nACGT\n>rna\nACGUUN\n");
+    write(&d.join("in.fq"), TAGGED_FASTQ);
+    let p = |name: &str| d.join(name).to_str().unwrap().to_owned();
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    cmd.args(["-f", &p("in.fa"), "--self-test"])
+        .assert()
+        .success()
+        .stdout("")
+        .stderr(predicate::str::contains("self-test: 2 record(s) of "));
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    cmd.args(["-f", &p("rna.fa"), "--self-test"])
+        .assert()
+        .code(1)
+        .stderr(predicate::str::contains("rna: base 'U' has no complement"))
+        .stderr(predicate::str::contains(
+            "self-test failed: 1 problem(s) in 2 record(s)",
+        ));
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    cmd.args(["--fastq", "-f", &p("in.fq"), "--self-test"])
+        .args(["--flipped-suffix", "2", "--self-test-records", "1"])
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("self-test: 1 record(s)"));
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    cmd.args(["--fastq", "-f", &p("in.fq"), "--self-test"])
+        .args(["--flipped-suffix", "2"])
+        .assert()
+        .code(1)
+        .stderr(predicate::str::contains(
+            "r2: ID already ends in --flipped-suffix '2'",
+        ));
+}
+
+#[test]
+fn tab_format_round_trips_records() {
+    let td = tempfile::tempdir().unwrap();
+    let d = td.path();
+    write(&d.join("in.fa"), FASTA);
+    write(&d.join("t.tsv"), TSV);
+    write(&d.join("in.fq"), TAGGED_FASTQ);
+    let p = |name: &str| d.join(name).to_str().unwrap().to_owned();
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    cmd.args([
+        "-f",
+        &p("in.fa"),
+        "-t",
+        &p("t.tsv"),
+        "--out-format",
+        "tab",
+        "-q",
+    ]);
+    let tab = run_ok(&mut cmd);
+    assert_eq!(tab, "readA some desc\tACGTACGTAC\nreadB\taaatttGGGCCC\n");
+
+    write(&d.join("in.tab"), &tab);
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    cmd.args(["-f", &p("in.tab"), "--tab-input", "-t", &p("t.tsv"), "-q"]);
+    assert_eq!(
+        run_ok(&mut cmd),
+        ">readA some desc\nACGTACGTAC\n>readB\nGGGCCCaaattt\n"
+    );
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    cmd.args(["-f", &p("in.fq"), "--fastq", "--out-format", "tab", "-q"]);
+    let tab = run_ok(&mut cmd);
+    assert!(tab.lines().all(|l| l.split('\t').count() == 3), "{}", tab);
+    write(&d.join("fq.tab"), &tab);
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    cmd.args(["-f", &p("fq.tab"), "--tab-input", "--fastq", "-q"]);
+    let from_tab = run_ok(&mut cmd);
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    cmd.args(["-f", &p("in.fq"), "--fastq", "-q"]);
+    assert_eq!(from_tab, run_ok(&mut cmd));
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    cmd.args(["-f", &p("in.tab"), "--tab-input", "--fastq"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("no quality column"));
+}
+
+#[test]
+#[cfg(feature = "arrow")]
+fn columnar_formats_write_one_row_per_read() {
+    use arrow_array::cast::AsArray;
+    use arrow_array::RecordBatch;
+
+    let td = tempfile::tempdir().unwrap();
+    let d = td.path();
+    write(&d.join("in.fa"), FASTA);
+    write(&d.join("t.tsv"), TSV);
+    write(&d.join("in.fq"), TAGGED_FASTQ);
+    let p = |name: &str| d.join(name).to_str().unwrap().to_owned();
+    let strings = |batch: &RecordBatch, name: &str| -> Vec<Option<String>> {
+        let column = batch.column_by_name(name).unwrap().as_string::<i32>();
+        column.iter().map(|v| v.map(str::to_owned)).collect()
+    };
+    let some = |values: &[&str]| -> Vec<Option<String>> {
+        values.iter().map(|v| Some(v.to_string())).collect()
+    };
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    cmd.args([
+        "-f",
+        &p("in.fa"),
+        "-t",
+        &p("t.tsv"),
+        "-o",
+        &p("out.parquet"),
+    ])
+    .args(["--out-format", "parquet", "--checksum", "md5"]);
+    run_ok(&mut cmd);
+    let file = fs::File::open(d.join("out.parquet")).unwrap();
+    let batches: Vec<RecordBatch> =
+        parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder::try_new(file)
+            .unwrap()
+            .build()
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+    assert_eq!(batches.len(), 1);
+    let batch = &batches[0];
+    assert_eq!(strings(batch, "read_id"), some(&["readA", "readB"]));
+    assert_eq!(
+        strings(batch, "sequence"),
+        some(&["ACGTACGTAC", "aaatttGGGCCC"])
+    );
+    assert_eq!(strings(batch, "quality"), vec![None, None]);
+    assert_eq!(strings(batch, "final_orientation"), some(&["+", "+"]));
+    let flipped = batch.column_by_name("flipped").unwrap().as_boolean();
+    assert_eq!(
+        flipped.iter().collect::<Vec<_>>(),
+        [Some(false), Some(true)]
+    );
+    // The checksum covers the file as written
+    let manifest = fs::read_to_string(d.join("out.parquet.md5")).unwrap();
+    assert!(manifest.contains(&p("out.parquet")), "{}", manifest);
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    cmd.args(["-f", &p("in.fq"), "--fastq", "-o", &p("out.arrow")])
+        .args(["--out-format", "arrow", "-q"]);
+    run_ok(&mut cmd);
+    let file = fs::File::open(d.join("out.arrow")).unwrap();
+    let batches: Vec<RecordBatch> = arrow_ipc::reader::FileReader::try_new(file, None)
+        .unwrap()
+        .map(Result::unwrap)
+        .collect();
+    let batch = &batches[0];
+    assert_eq!(strings(batch, "read_id"), some(&["r1", "r2"]));
+    assert_eq!(strings(batch, "sequence"), some(&["CGTT", "AACG"]));
+    assert_eq!(strings(batch, "quality"), some(&["DCBA", "ABCD"]));
+    assert_eq!(strings(batch, "final_orientation"), some(&["+", "+"]));
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    cmd.args(["-f", &p("in.fa"), "-t", &p("t.tsv"), "-o", &p("x.arrow")])
+        .args(["--out-format", "arrow", "--unchanged-out", &p("kept.fa")])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "cannot be combined with --unchanged-out",
+        ));
+}
+
+#[test]
+fn casava_comment_survives_header_rewrites() {
+    let td = tempfile::tempdir().unwrap();
+    let d = td.path();
+    write(
+        &d.join("in.fa"),
+        ">M001:1:FC:1:1101:10:20 1:N:0:ACGT+TTGA\nAACG\n>M001:1:FC:1:1101:10:21 2:Y:0:7\nAACG\n",
+    );
+    write(
+        &d.join("t.tsv"),
+        "ReadName\torientation\nM001:1:FC:1:1101:10:20\t-\nM001:1:FC:1:1101:10:21\t-\n",
+    );
+    let p = |name: &str| d.join(name).to_str().unwrap().to_owned();
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    cmd.args([
+        "-f",
+        &p("in.fa"),
+        "-t",
+        &p("t.tsv"),
+        "--flipped-suffix",
+        "_rc",
+    ])
+    .args(["--annotate", "-q"]);
+    assert_eq!(
+        run_ok(&mut cmd),
+        ">M001:1:FC:1:1101:10:20_rc 1:N:0:ACGT+TTGA restrand=flipped source=table\nCGTT\n\
+         >M001:1:FC:1:1101:10:21_rc 2:Y:0:7 restrand=flipped source=table\nCGTT\n"
+    );
+}
+
+#[test]
+fn emit_strand_tag_writes_chosen_layout() {
+    let td = tempfile::tempdir().unwrap();
+    let d = td.path();
+    write(&d.join("in.fq"), TAGGED_FASTQ);
+    let p = |name: &str| d.join(name).to_str().unwrap().to_owned();
+    let headers = |format: &str| {
+        let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+        cmd.args([
+            "-f",
+            &p("in.fq"),
+            "--fastq",
+            "--emit-strand-tag",
+            format,
+            "-q",
+        ]);
+        run_ok(&mut cmd)
+            .lines()
+            .filter(|l| l.starts_with('@'))
+            .map(str::to_owned)
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(headers("sam"), ["@r1 cell:1|UMI:AC ts:A:+", "@r2 ts:A:+"]);
+    // The SAM tag is read back: a rerun replaces it and honors its value
+    write(&d.join("in.fq"), "@r1 x ts:A:-\nAACG\n+\nABCD\n");
+    assert_eq!(headers("sam"), ["@r1 x ts:A:+"]);
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    cmd.args(["-f", &p("in.fq"), "--fastq", "-q"]);
+    assert_eq!(run_ok(&mut cmd), "@r1 x ts:A:+\nCGTT\n+\nDCBA\n");
+    write(&d.join("in.fq"), TAGGED_FASTQ);
+    assert_eq!(
+        headers("strand"),
+        ["@r1 cell:1|UMI:AC strand=+", "@r2 strand=+"]
+    );
+
+    write(&d.join("in.fa"), ">readA strand=-\nACGT\n>readB\nAACC\n");
+    write(&d.join("t.tsv"), TSV);
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    cmd.args([
+        "-f",
+        &p("in.fa"),
+        "-t",
+        &p("t.tsv"),
+        "--target-orientation",
+        "-",
+    ])
+    .args(["--emit-strand-tag", "orientation", "-q"]);
+    assert_eq!(
+        run_ok(&mut cmd),
+        ">readA orientation:-\nACGT\n>readB orientation:-\nAACC\n"
+    );
+}
+
+#[test]
+fn delta_out_applies_back_to_the_output() {
+    let td = tempfile::tempdir().unwrap();
+    let d = td.path();
+    write(
+        &d.join("in.fq"),
+        &format!(
+            "{}@r3\nGGTT\n+\nIIII\n@r4 x=1\nACCA\n+\nABCD\n",
+            TAGGED_FASTQ
+        ),
+    );
+    write(&d.join("drop.txt"), "r3\n");
+    let p = |name: &str| d.join(name).to_str().unwrap().to_owned();
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    cmd.args(["-f", &p("in.fq"), "--fastq", "-o", &p("out.fq")])
+        .args(["--exclude-ids", &p("drop.txt"), "--flipped-suffix", "_rc"])
+        .args(["--delta-out", &p("run.delta"), "-q"]);
+    run_ok(&mut cmd);
+    assert_eq!(
+        fs::read_to_string(d.join("run.delta")).unwrap(),
+        "#restrand-delta 1\tfastq\n\
+         flip\t0\tr1\tr1_rc cell:1|orientation:+|UMI:AC\n\
+         drop\t2\t2\n\
+         #records\t4\n"
+    );
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    cmd.args([
+        "apply",
+        "--original",
+        &p("in.fq"),
+        "--delta",
+        &p("run.delta"),
+    ])
+    .args(["-o", &p("rebuilt.fq")])
+    .assert()
+    .success()
+    .stderr(predicate::str::contains("1 of 4 records flipped"));
+    assert_eq!(
+        fs::read(d.join("rebuilt.fq")).unwrap(),
+        fs::read(d.join("out.fq")).unwrap()
+    );
+
+    write(&d.join("other.fq"), TAGGED_FASTQ);
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    cmd.args([
+        "apply",
+        "--original",
+        &p("other.fq"),
+        "--delta",
+        &p("run.delta"),
+    ])
+    .assert()
+    .failure()
+    .stderr(predicate::str::contains(
+        "has 2 records, but the patch was made from 4",
+    ));
+}
+
+#[test]
+fn verify_ids_reads_outputs_back() {
+    let td = tempfile::tempdir().unwrap();
+    let d = td.path();
+    write(&d.join("in.fa"), FASTA);
+    write(&d.join("t.tsv"), TSV);
+    let p = |name: &str| d.join(name).to_str().unwrap().to_owned();
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    cmd.args(["-f", &p("in.fa"), "-t", &p("t.tsv"), "-o", &p("out.fa.gz")])
+        .args([
+            "--flipped-suffix",
+            "_rc",
+            "--provenance-comment",
+            "--verify-ids",
+            "-q",
+        ]);
+    run_ok(&mut cmd);
+
+    // Two writers clobbering one file lose records
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    cmd.args(["-f", &p("in.fa"), "-t", &p("t.tsv"), "-o", &p("same.fa")])
+        .args(["--unchanged-out", &p("same.fa"), "--verify-ids"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("--verify-ids: "));
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    cmd.args(["-f", &p("in.fa"), "-t", &p("t.tsv"), "--verify-ids"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("it needs --out"));
+}
+
+#[test]
+#[cfg(feature = "zstd")]
+fn compressed_tables_are_detected_by_magic_bytes() {
+    let td = tempfile::tempdir().unwrap();
+    let d = td.path();
+    write(&d.join("in.fa"), FASTA);
+    let p = |name: &str| d.join(name).to_str().unwrap().to_owned();
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    cmd.args(["-f", &p("in.fa"), "-t", &p("plain.tsv"), "-q"]);
+    write(&d.join("plain.tsv"), TSV);
+    let expected = run_ok(&mut cmd);
+
+    fs::write(
+        d.join("t.tsv.zst"),
+        zstd::encode_all(TSV.as_bytes(), 3).unwrap(),
+    )
+    .unwrap();
+    fs::write(
+        d.join("t.csv.zst"),
+        zstd::encode_all(TSV.replace('\t', ",").as_bytes(), 3).unwrap(),
+    )
+    .unwrap();
+    // gzip content without a .gz name
+    write_gz(&d.join("t.tsv"), TSV);
+    for table in ["t.tsv.zst", "t.csv.zst", "t.tsv"] {
+        let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+        cmd.args(["-f", &p("in.fa"), "-t", &p(table), "-q"]);
+        assert_eq!(run_ok(&mut cmd), expected, "{}", table);
+    }
+}
+
+#[test]
+#[cfg(all(feature = "zstd", feature = "xz", feature = "bzip2"))]
+fn compressed_reads_are_detected_by_magic_bytes() {
+    use std::io::Write as _;
+
+    let td = tempfile::tempdir().unwrap();
+    let d = td.path();
+    write(&d.join("map.tsv"), TSV);
+    let p = |name: &str| d.join(name).to_str().unwrap().to_owned();
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    let expected = run_ok(
+        cmd.args(["-f", "-", "-t", &p("map.tsv")])
+            .write_stdin(FASTA),
+    );
+
+    let mut xz = xz2::write::XzEncoder::new(Vec::new(), 6);
+    xz.write_all(FASTA.as_bytes()).unwrap();
+    let mut bz = bzip2::write::BzEncoder::new(Vec::new(), bzip2::Compression::default());
+    bz.write_all(FASTA.as_bytes()).unwrap();
+    let inputs = [
+        ("zst", zstd::encode_all(FASTA.as_bytes(), 3).unwrap()),
+        ("xz", xz.finish().unwrap()),
+        ("bz2", bz.finish().unwrap()),
+    ];
+    // Names that say nothing (or the wrong thing) about the compression
+    for (codec, data) in &inputs {
+        fs::write(d.join(format!("{}.fa.gz", codec)), data).unwrap();
+        let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+        cmd.args(["-f", &p(&format!("{}.fa.gz", codec)), "-t", &p("map.tsv")]);
+        assert_eq!(run_ok(&mut cmd), expected, "{}", codec);
+        let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+        cmd.args(["-f", "-", "-t", &p("map.tsv"), "--read-buffer-size", "1"])
+            .write_stdin(data.clone());
+        assert_eq!(run_ok(&mut cmd), expected, "{} on stdin", codec);
+    }
+}
+
+#[test]
+fn compress_level_trades_size_for_speed() {
+    use flate2::read::MultiGzDecoder;
+    use std::io::Read;
+
+    let td = tempfile::tempdir().unwrap();
+    let d = td.path();
+    let reads: String = (0..200)
+        .map(|i| format!(">r{}\n{}\n", i, "ACGTTGCA".repeat(20)))
+        .collect();
+    write(&d.join("in.fa"), &reads);
+    let p = |name: &str| d.join(name).to_str().unwrap().to_owned();
+    let run = |level: &str| {
+        let out = format!("l{}.fa.gz", level);
+        let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+        cmd.args(["-f", &p("in.fa"), "--all", "-o", &p(&out), "-q"])
+            .args(["--compress-level", level]);
+        run_ok(&mut cmd);
+        let mut text = String::new();
+        MultiGzDecoder::new(File::open(d.join(&out)).unwrap())
+            .read_to_string(&mut text)
+            .unwrap();
+        (fs::metadata(d.join(&out)).unwrap().len(), text)
+    };
+    let (stored, stored_text) = run("0");
+    let (smallest, smallest_text) = run("9");
+    assert_eq!(stored_text, smallest_text);
+    assert!(stored > reads.len() as u64, "level 0 stores: {}", stored);
+    assert!(smallest * 10 < stored, "{} vs {}", smallest, stored);
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    cmd.args(["-f", &p("in.fa"), "--all", "--compress-level", "10"])
+        .assert()
+        .code(2);
+}
+
+#[test]
+fn append_grows_outputs_on_record_boundaries() {
+    use flate2::read::MultiGzDecoder;
+    use std::io::Read;
+
+    let td = tempfile::tempdir().unwrap();
+    let d = td.path();
+    write(&d.join("chunk1.fq"), TAGGED_FASTQ);
+    write(&d.join("chunk2.fq"), "@r3 orientation:-\nGGTT\n+\nIIII\n");
+    let p = |name: &str| d.join(name).to_str().unwrap().to_owned();
+    for chunk in ["chunk1.fq", "chunk2.fq"] {
+        let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+        cmd.args([
+            "-f",
+            &p(chunk),
+            "--fastq",
+            "-o",
+            &p("all.fq.gz"),
+            "--append",
+            "-q",
+        ]);
+        run_ok(&mut cmd);
+    }
+    let mut text = String::new();
+    MultiGzDecoder::new(File::open(d.join("all.fq.gz")).unwrap())
+        .read_to_string(&mut text)
+        .unwrap();
+    assert_eq!(text.matches('@').count(), 3);
+    assert!(
+        text.ends_with("@r3 orientation:+\nAACC\n+\nIIII\n"),
+        "{}",
+        text
+    );
+
+    write(&d.join("cut.fq"), "@r0\nACGT\n+\nII");
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    cmd.args([
+        "-f",
+        &p("chunk2.fq"),
+        "--fastq",
+        "-o",
+        &p("cut.fq"),
+        "--append",
+    ])
+    .assert()
+    .failure()
+    .stderr(predicate::str::contains("does not end with a newline"));
+    assert_eq!(
+        fs::read_to_string(d.join("cut.fq")).unwrap(),
+        "@r0\nACGT\n+\nII"
+    );
+}
+
+#[test]
+fn mask_primers_keeps_read_length() {
+    let td = tempfile::tempdir().unwrap();
+    let fasta_p = td.path().join("in.fa");
+    let primers_p = td.path().join("primers.fa");
+    let insert = "ACGGATTACAGGCATTAGCCAT";
+    let plus = plus_molecule(insert);
+    let minus = String::from_utf8(dna::revcomp(plus.as_bytes())).unwrap();
+    write(&fasta_p, &format!(">p\n{}\n>m\n{}\n", plus, minus));
+    write(&primers_p, PRIMERS);
+
+    let masked = |how: &str| {
+        let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+        cmd.args(["-f", fasta_p.to_str().unwrap(), "--primers"])
+            .arg(&primers_p)
+            .args(["--mask-primers", how]);
+        let out = run_ok(&mut cmd);
+        let seqs: Vec<String> = out
+            .split('>')
+            .skip(1)
+            .map(|r| r.lines().skip(1).collect())
+            .collect();
+        seqs
+    };
+
+    // Both primers are masked; the flipped read is masked on the '+' strand
+    let n_masked = format!("{}{}{}", "N".repeat(26), insert, "N".repeat(22));
+    assert_eq!(masked("n"), [n_masked.clone(), n_masked]);
+    let lower = format!(
+        "{}{}{}",
+        &plus[..26].to_ascii_lowercase(),
+        insert,
+        &plus[26 + insert.len()..].to_ascii_lowercase()
+    );
+    assert_eq!(masked("lowercase"), [lower.clone(), lower]);
+
+    let tsv_p = td.path().join("t.tsv");
+    write(&tsv_p, "ReadName\torientation\np\t+\nm\t-\n");
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    cmd.args([
+        "-f",
+        fasta_p.to_str().unwrap(),
+        "-t",
+        tsv_p.to_str().unwrap(),
+    ])
+    .args(["--mask-primers", "n"]);
+    cmd.assert().failure().stderr(predicate::str::contains(
+        "--mask-primers requires --primers or --preset",
+    ));
+}
+
+#[test]
+#[cfg(not(feature = "remote"))]
+fn remote_inputs_need_the_remote_feature() {
+    let td = tempfile::tempdir().unwrap();
+    let tsv_p = td.path().join("map.tsv");
+    write(&tsv_p, TSV);
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    cmd.args(["-f", "s3://bucket/in.fa.gz", "-t", tsv_p.to_str().unwrap()])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "s3://bucket/in.fa.gz needs the `remote` feature",
+        ));
+}
+
+#[test]
+fn lint_reports_table_and_read_problems() {
+    let td = tempfile::tempdir().unwrap();
+    let fasta_p = td.path().join("in.fa");
+    let good_p = td.path().join("good.tsv");
+    let bad_p = td.path().join("bad.tsv");
+    write(&fasta_p, FASTA);
+    write(&good_p, TSV);
+    write(
+        &bad_p,
+        "ReadName\torientation\r\nreadA\t+\r\nreadA\t-\r\nreadC\tsideways\r\n",
+    );
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    cmd.args(["lint", "-t", good_p.to_str().unwrap()])
+        .args(["-f", fasta_p.to_str().unwrap()]);
+    let out = run_ok(&mut cmd);
+    assert!(out.contains("2 of 2 sampled read(s) (100.0%) are in the table"));
+    assert!(out.ends_with("lint: 0 error(s), 0 warning(s)\n"));
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    cmd.args(["lint", "-t", bad_p.to_str().unwrap()])
+        .args(["-f", fasta_p.to_str().unwrap()]);
+    let assert = cmd.assert().failure();
+    let out = String::from_utf8(assert.get_output().stdout.clone()).unwrap();
+    assert!(out.contains("note     CRLF (Windows) line endings"));
+    assert!(out.contains(
+        "error    1 orientation value(s) not recognized as strand, e.g. line 4: 'sideways'"
+    ));
+    assert!(
+        out.contains("warning  1 duplicate ID(s), 1 with conflicting orientations (e.g. 'readA')")
+    );
+    assert!(out
+        .contains("warning  1 of 2 sampled read(s) (50.0%) are in the table; not found: 'readB'"));
+    assert!(out.ends_with("lint: 1 error(s), 2 warning(s)\n"));
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    cmd.args([
+        "lint",
+        "-t",
+        good_p.to_str().unwrap(),
+        "--id-col",
+        "readname",
+    ]);
+    let assert = cmd.assert().failure();
+    let out = String::from_utf8(assert.get_output().stdout.clone()).unwrap();
+    assert!(out.contains("no column 'readname', but there is 'ReadName'"));
+}
+
+#[test]
+fn explain_shows_sources_and_action_without_writing_reads() {
+    let td = tempfile::tempdir().unwrap();
+    let fasta_p = td.path().join("in.fa");
+    let fastq_p = td.path().join("in.fq");
+    let tsv_p = td.path().join("map.tsv");
+    let ids_p = td.path().join("ids.txt");
+    write(&fasta_p, FASTA);
+    write(&fastq_p, TAGGED_FASTQ);
+    write(&tsv_p, "ReadName\torientation\nr1\t+\n");
+    write(&ids_p, "r1\n");
+
+    // Table and tag disagree; only the listed read is explained
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    cmd.args(["--fastq", "-f", fastq_p.to_str().unwrap()])
+        .args(["-t", tsv_p.to_str().unwrap()])
+        .args(["--include-ids", ids_p.to_str().unwrap(), "--explain", "5"]);
+    let assert = cmd.assert().success();
+    assert_eq!(
+        String::from_utf8(assert.get_output().stdout.clone()).unwrap(),
+        "r1\n  table      '+'\n  tag        '-' from the orientation tag\n  \
+         decision   '+' from table: sources disagree, settled by --on-conflict priority\n  \
+         action     keep: already in the target orientation '+'\n"
+    );
+    assert!(assert.get_output().stderr.is_empty());
+
+    // Stops after N reads
+    let full_tsv = td.path().join("full.tsv");
+    write(&full_tsv, TSV);
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    cmd.args([
+        "-f",
+        fasta_p.to_str().unwrap(),
+        "-t",
+        full_tsv.to_str().unwrap(),
+    ])
+    .args(["--explain", "1"]);
+    let out = run_ok(&mut cmd);
+    assert_eq!(
+        out,
+        "readA\n  table      '+'\n  decision   '+' from table\n  \
+         action     keep: already in the target orientation '+'\n"
+    );
+}
+
+#[test]
+fn paired_outputs_split_and_reinterleave_with_orphans() {
+    let td = tempfile::tempdir().unwrap();
+    let inter_p = td.path().join("in.fq");
+    write(
+        &inter_p,
+        "@p1/1\nAAC\n+\nIII\n@p1/2\nGGT\n+\nIII\n@p2/1\nCCA\n+\nIII\n\
+         @p3/1\nACG\n+\nIII\n@p3/2\nTTG\n+\nIII\n",
+    );
+    let r1_p = td.path().join("r1.fq");
+    let r2_p = td.path().join("r2.fq");
+    let orphans_p = td.path().join("orphans.fq");
+
+    // Interleaved input split into R1/R2; p2 has no mate
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    cmd.args(["--fastq", "--all", "-f", inter_p.to_str().unwrap()])
+        .args(["--out-r1", r1_p.to_str().unwrap()])
+        .args(["--out-r2", r2_p.to_str().unwrap()])
+        .args(["--orphans-out", orphans_p.to_str().unwrap()]);
+    cmd.assert()
+        .success()
+        .stdout("")
+        .stderr(predicate::str::contains(
+            "warning: 1 read(s) without their mate, e.g. p2",
+        ))
+        .stderr(predicate::str::contains("pairs=2 orphans=1"));
+    let r1 = fs::read_to_string(&r1_p).unwrap();
+    let r2 = fs::read_to_string(&r2_p).unwrap();
+    assert_eq!(r1, "@p1/1\nGTT\n+\nIII\n@p3/1\nCGT\n+\nIII\n");
+    assert_eq!(r2, "@p1/2\nACC\n+\nIII\n@p3/2\nCAA\n+\nIII\n");
+    assert_eq!(
+        fs::read_to_string(&orphans_p).unwrap(),
+        "@p2/1\nTGG\n+\nIII\n"
+    );
+
+    // R1 and R2 read in lockstep back into one interleaved file
+    let inter_out = td.path().join("out.fq");
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    cmd.args(["--fastq", "--all", "-f", r1_p.to_str().unwrap()])
+        .args([r2_p.to_str().unwrap(), "--quiet"])
+        .args(["--out-interleaved", inter_out.to_str().unwrap()]);
+    cmd.assert().success().stderr("");
+    assert_eq!(
+        fs::read_to_string(&inter_out).unwrap(),
+        "@p1/1\nAAC\n+\nIII\n@p1/2\nGGT\n+\nIII\n\
+         @p3/1\nACG\n+\nIII\n@p3/2\nTTG\n+\nIII\n"
+    );
+
+    // Orphans without a paired layout make no sense
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    cmd.args(["--fastq", "--all", "-f", inter_p.to_str().unwrap()])
+        .args(["--orphans-out", orphans_p.to_str().unwrap()]);
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("--orphans-out requires"));
+}
+
+#[test]
+fn casava_mates_pair_despite_extra_spaces() {
+    let td = tempfile::tempdir().unwrap();
+    let d = td.path();
+    write(
+        &d.join("in.fq"),
+        "@r1  1:N:0:ACGT\nAAC\n+\nIII\n@r1  2:N:0:ACGT\nGGT\n+\nIII\n",
+    );
+    let p = |name: &str| d.join(name).to_str().unwrap().to_owned();
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    cmd.args([
+        "--fastq",
+        "-f",
+        &p("in.fq"),
+        "--out-interleaved",
+        &p("out.fq"),
+    ])
+    .assert()
+    .success()
+    .stderr(predicate::str::contains("pairs=1 orphans=0"));
+}
+
+#[test]
+fn pair_window_bounds_waiting_reads_and_reports_orphans_early() {
+    let td = tempfile::tempdir().unwrap();
+    let d = td.path();
+    // a's mates are five reads apart
+    write(
+        &d.join("in.fq"),
+        "@a/1\nAAC\n+\nIII\n@b/1\nCCA\n+\nIII\n@b/2\nGGT\n+\nIII\n\
+         @c/1\nACG\n+\nIII\n@c/2\nTTG\n+\nIII\n@a/2\nGTA\n+\nIII\n",
+    );
+    let p = |name: &str| d.join(name).to_str().unwrap().to_owned();
+    let base = [
+        "--fastq",
+        "--all",
+        "-f",
+        &p("in.fq"),
+        "--out-r1",
+        &p("r1.fq"),
+        "--out-r2",
+        &p("r2.fq"),
+    ];
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    cmd.args(base)
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("pairs=3 orphans=0"));
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    cmd.args(base)
+        .args(["--pair-window", "2", "--report-every", "1"])
+        .assert()
+        .success()
+        .stderr(predicate::str::contains(
+            "progress records=5 flipped=4 orphans=1 ",
+        ))
+        .stderr(predicate::str::contains(
+            "warning: read a has lost its mate",
+        ))
+        .stderr(predicate::str::contains("pairs=2 orphans=2"));
+    assert_eq!(
+        fs::read_to_string(d.join("r1.fq")).unwrap(),
+        "@b/1\nTGG\n+\nIII\n@c/1\nCGT\n+\nIII\n"
+    );
+
+    // Checksums of paired outputs go next to --out-r1 by default
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    run_ok(cmd.args(base).args(["--checksum", "md5"]));
+    let manifest = fs::read_to_string(d.join("r1.fq.md5")).unwrap();
+    assert_eq!(manifest.lines().count(), 2);
+    assert!(manifest.contains(&p("r2.fq")), "{}", manifest);
+}
+
+#[test]
+fn parallel_manifest_samples_spill_to_separate_files() {
+    let td = tempfile::tempdir().unwrap();
+    let d = td.path();
+    let p = |name: &str| d.join(name).to_str().unwrap().to_owned();
+    let mut manifest = String::from("sample\tinput\ttable\toutput\n");
+    for s in 0..4 {
+        let (mut fasta, mut tsv) = (String::new(), String::from("ReadName\torientation\n"));
+        for i in 0..2000 {
+            fasta.push_str(&format!(">s{}r{}\nAAC\n", s, i));
+            tsv.push_str(&format!(
+                "s{}r{}\t{}\n",
+                s,
+                i,
+                if i % 2 == 0 { '+' } else { '-' }
+            ));
+        }
+        write(&d.join(format!("{}.fa", s)), &fasta);
+        write(&d.join(format!("{}.tsv", s)), &tsv);
+        manifest.push_str(&format!(
+            "S{}\t{}\t{}\t{}\n",
+            s,
+            p(&format!("{}.fa", s)),
+            p(&format!("{}.tsv", s)),
+            p(&format!("{}.out.fa", s))
+        ));
+    }
+    write(&d.join("samples.tsv"), &manifest);
+    fs::create_dir(d.join("scratch")).unwrap();
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    cmd.args(["--manifest", &p("samples.tsv"), "--jobs", "4"])
+        .args(["--max-memory", "4K", "--tmpdir", &p("scratch")])
+        .args(["--summary-file", &p("batch.tsv")])
+        .assert()
+        .success();
+    let summary = fs::read_to_string(d.join("batch.tsv")).unwrap();
+    for line in summary.lines().skip(1) {
+        assert!(line.ends_with("\tok\t2000\t1000\t0\t0"), "{}", line);
+    }
+    for s in 0..4 {
+        let out = fs::read_to_string(d.join(format!("{}.out.fa", s))).unwrap();
+        assert!(out.starts_with(&format!(">s{}r0\nAAC\n>s{}r1\nGTT\n", s, s)));
+    }
+    assert_eq!(fs::read_dir(d.join("scratch")).unwrap().count(), 0);
+}
+
+#[test]
+fn internal_adapters_split_chimeras_into_oriented_fragments() {
+    let td = tempfile::tempdir().unwrap();
+    let fastq_p = td.path().join("in.fq");
+    let primers_p = td.path().join("primers.fa");
+    write(&primers_p, PRIMERS);
+    // Inserts without primer-like stretches
+    let insert = |seed: u64| -> String {
+        let mut x = seed;
+        (0..300)
+            .map(|_| {
+                x = x
+                    .wrapping_mul(6364136223846793005)
+                    .wrapping_add(1442695040888963407);
+                b"ACGT"[(x >> 62) as usize] as char
+            })
+            .collect()
+    };
+    let (a, b) = (plus_molecule(&insert(1)), plus_molecule(&insert(2)));
+    let b_rc = String::from_utf8(dna::revcomp(b.as_bytes())).unwrap();
+    let q = |seq: &str| "I".repeat(seq.len());
+    let chimera = format!("{}{}", a, b_rc);
+    write(
+        &fastq_p,
+        &format!(
+            "@chimera orientation:- run=1\n{}\n+\n{}\n@single\n{}\n+\n{}\n",
+            chimera,
+            q(&chimera),
+            b,
+            q(&b)
+        ),
+    );
+
+    // The minus-strand half is flipped on its own, and the parent's tag no
+    // longer applies to either half
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    cmd.args(["--fastq", "-f", fastq_p.to_str().unwrap()])
+        .args(["--primers", primers_p.to_str().unwrap()])
+        .arg("--split-on-internal-adapter");
+    cmd.assert()
+        .success()
+        .stdout(format!(
+            "@chimera:0-{0} run=1\n{1}\n+\n{2}\n@chimera:{0}-{3} run=1\n{4}\n+\n{5}\n\
+             @single\n{4}\n+\n{5}\n",
+            a.len(),
+            a,
+            q(&a),
+            chimera.len(),
+            b,
+            q(&b)
+        ))
+        .stderr(predicate::str::contains("split_reads=1 fragments=2"));
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    cmd.args(["--fastq", "-f", fastq_p.to_str().unwrap()])
+        .arg("--split-on-internal-adapter")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("requires --primers or --preset"));
+}
+
+#[test]
+fn merge_pairs_joins_overlapping_mates_before_orienting() {
+    let td = tempfile::tempdir().unwrap();
+    let d = td.path();
+    let p = |name: &str| d.join(name).to_str().unwrap().to_owned();
+    let amplicon = "ACGGTCAGTTCAGGATCCATGCTAGCTTAGGCATCGATCGGCTAATCGCATTCAGC";
+    let rc = |s: &str| String::from_utf8(dna::revcomp(s.as_bytes())).unwrap();
+    // Mates overlap by 24 bases; mate 2 has a low-quality error in it
+    let r1 = &amplicon[..40];
+    let mut r2 = rc(&amplicon[16..]);
+    r2.replace_range(30..31, "A");
+    let r2_qual = format!("{}#{}", "I".repeat(30), "I".repeat(9));
+    write(
+        &d.join("r1.fq"),
+        &format!(
+            "@p1/1\n{}\n+\n{}\n@p2/1\nAAAA\n+\nIIII\n",
+            r1,
+            "I".repeat(40)
+        ),
+    );
+    write(
+        &d.join("r2.fq"),
+        &format!("@p1/2\n{}\n+\n{}\n@p2/2\nCCCC\n+\nIIII\n", r2, r2_qual),
+    );
+    write(&d.join("map.tsv"), "ReadName\torientation\np1\t-\n");
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    cmd.args(["--fastq", "-f", &p("r1.fq"), &p("r2.fq")]).args([
+        "-t",
+        &p("map.tsv"),
+        "--merge-pairs",
+    ]);
+    cmd.assert()
+        .success()
+        .stdout(format!(
+            "@p1\n{}\n+\n{}G{}\n@p2/1\nAAAA\n+\nIIII\n@p2/2\nCCCC\n+\nIIII\n",
+            rc(amplicon),
+            // The disagreement keeps mate 1's base, at the quality margin
+            "I".repeat(30),
+            "I".repeat(25)
+        ))
+        .stderr(predicate::str::contains("merged_pairs=1 unmerged_pairs=1"));
+}
+
+#[test]
+fn fastq_flipped_suffix_goes_on_read_id() {
+    let td = tempfile::tempdir().unwrap();
+    let fq_p = td.path().join("in.fq");
+    write(
+        &fq_p,
+        "@r1 cell:1|orientation:-\nAACG\n+\nABCD\n@r2 orientation:+\nAACG\n+\nABCD\n",
+    );
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    let out = run_ok(cmd.args([
+        "--fastq",
+        "-f",
+        fq_p.to_str().unwrap(),
+        "--flipped-suffix",
+        "/rc",
+    ]));
+
+    let lines: Vec<&str> = out.lines().collect();
+    assert_eq!(lines[0], "@r1/rc cell:1|orientation:+");
+    assert_eq!(lines[1], "CGTT");
+    assert_eq!(lines[3], "DCBA");
+    assert_eq!(lines[4], "@r2 orientation:+");
+}
+
+/// FASTQ with one '-' read (tag mid-comment) and one '+' read (tag alone).
+const TAGGED_FASTQ: &str = "\
+@r1 cell:1|orientation:-|UMI:AC
+AACG
++
+ABCD
+@r2 orientation:+
+AACG
++
+ABCD
+";
+
+#[test]
+fn strip_orientation_tag_removes_token_and_separator() {
+    let td = tempfile::tempdir().unwrap();
+    let fq_p = td.path().join("in.fq");
+    write(&fq_p, TAGGED_FASTQ);
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    let out = run_ok(cmd.args([
+        "--fastq",
+        "-f",
+        fq_p.to_str().unwrap(),
+        "--strip-orientation-tag",
+    ]));
+
+    let lines: Vec<&str> = out.lines().collect();
+    assert_eq!(lines[0], "@r1 cell:1|UMI:AC");
+    assert_eq!(lines[4], "@r2");
+}
+
+#[test]
+fn rewrite_orientation_tag_uses_key_and_final_orientation() {
+    let td = tempfile::tempdir().unwrap();
+    let fq_p = td.path().join("in.fq");
+    write(&fq_p, TAGGED_FASTQ);
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    let out = run_ok(cmd.args([
+        "--fastq",
+        "-f",
+        fq_p.to_str().unwrap(),
+        "--target-orientation",
+        "-",
+        "--rewrite-orientation-tag",
+        "strand",
+    ]));
+
+    let lines: Vec<&str> = out.lines().collect();
+    assert_eq!(lines[0], "@r1 cell:1|strand:-|UMI:AC");
+    assert_eq!(lines[4], "@r2 strand:-");
+    // r2 was '+', so it is the one that flipped
+    assert_eq!(lines[5], "CGTT");
+}
+
+#[test]
+fn mean_quality_tag_appends_mq() {
+    let td = tempfile::tempdir().unwrap();
+    let fq_p = td.path().join("in.fq");
+    write(&fq_p, TAGGED_FASTQ);
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    let out = run_ok(cmd.args([
+        "--fastq",
+        "-f",
+        fq_p.to_str().unwrap(),
+        "--mean-quality-tag",
+    ]));
+
+    let lines: Vec<&str> = out.lines().collect();
+    // Q32..Q35 averaged as error probabilities, not as scores (33.5)
+    assert_eq!(lines[0], "@r1 cell:1|orientation:+|UMI:AC mq=33.4");
+    assert_eq!(lines[4], "@r2 orientation:+ mq=33.4");
+}
+
+#[test]
+fn output_case_normalizes_sequences() {
+    let td = tempfile::tempdir().unwrap();
+    let fasta_p = td.path().join("in.fa");
+    let tsv_p = td.path().join("map.tsv");
+    write(&fasta_p, FASTA);
+    write(&tsv_p, TSV);
+    let base = [
+        "-f",
+        fasta_p.to_str().unwrap(),
+        "-t",
+        tsv_p.to_str().unwrap(),
+    ];
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    let out = run_ok(cmd.args(base).args(["--output-case", "upper"]));
+    assert_eq!(out, ">readA some desc\nACGTACGTAC\n>readB\nAAATTTGGGCCC\n");
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    let out = run_ok(cmd.args(base).args(["--output-case", "lower"]));
+    assert_eq!(out, ">readA some desc\nacgtacgtac\n>readB\naaatttgggccc\n");
+}
+
+#[test]
+fn only_flipped_emits_just_flipped_reads() {
+    let td = tempfile::tempdir().unwrap();
+    let fasta_p = td.path().join("in.fa");
+    let tsv_p = td.path().join("map.tsv");
+    write(&fasta_p, FASTA);
+    write(&tsv_p, TSV);
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    let out = run_ok(cmd.args([
+        "-f",
+        fasta_p.to_str().unwrap(),
+        "-t",
+        tsv_p.to_str().unwrap(),
+        "--only-flipped",
+    ]));
+    assert!(!out.contains(">readA"));
+    assert!(out.contains(">readB"));
+
+    let fq_p = td.path().join("in.fq");
+    write(&fq_p, TAGGED_FASTQ);
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    let out = run_ok(cmd.args(["--fastq", "-f", fq_p.to_str().unwrap(), "--only-flipped"]));
+    assert!(out.starts_with("@r1 "));
+    assert!(!out.contains("@r2"));
+}
+
+#[test]
+fn unchanged_out_splits_populations() {
+    let td = tempfile::tempdir().unwrap();
+    let fasta_p = td.path().join("in.fa");
+    let tsv_p = td.path().join("map.tsv");
+    let kept_p = td.path().join("kept.fa");
+    write(
+        &fasta_p,
+        ">readA some desc\nACGT\n>readB\nGGCC\n>readC\nTTTT\n",
+    );
+    // readC missing from the table -> passes through as unchanged
+    write(&tsv_p, TSV);
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    let out = run_ok(cmd.args([
+        "-f",
+        fasta_p.to_str().unwrap(),
+        "-t",
+        tsv_p.to_str().unwrap(),
+        "--unchanged-out",
+        kept_p.to_str().unwrap(),
+    ]));
+
+    assert_eq!(out, ">readB\nGGCC\n");
+    let kept = fs::read_to_string(&kept_p).unwrap();
+    assert_eq!(kept, ">readA some desc\nACGT\n>readC\nTTTT\n");
+}
+
+#[test]
+fn report_includes_length_histograms() {
+    let td = tempfile::tempdir().unwrap();
+    let fasta_p = td.path().join("in.fa");
+    let tsv_p = td.path().join("map.tsv");
+    let report_p = td.path().join("report.json");
+    write(&fasta_p, FASTA);
+    write(&tsv_p, TSV);
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    run_ok(cmd.args([
+        "-f",
+        fasta_p.to_str().unwrap(),
+        "-t",
+        tsv_p.to_str().unwrap(),
+        "--report",
+        report_p.to_str().unwrap(),
+        "--length-bin-width",
+        "10",
+    ]));
+
+    let report: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(&report_p).unwrap()).unwrap();
+    assert_eq!(report["mode"], "fasta");
+    assert_eq!(report["counts"]["processed"], 2);
+    assert_eq!(report["counts"]["flipped"], 1);
+
+    let lengths = &report["read_lengths"];
+    assert_eq!(lengths["all"]["reads"], 2);
+    assert_eq!(lengths["all"]["bases"], 22);
+    // readA (10 nt) lands in [10, 20), readB (12 nt) too
+    assert_eq!(lengths["all"]["bins"][0]["start"], 10);
+    assert_eq!(lengths["all"]["bins"][0]["count"], 2);
+    assert_eq!(lengths["by_action"]["flipped"]["max"], 12);
+    assert_eq!(lengths["by_action"]["kept"]["max"], 10);
+    assert_eq!(lengths["by_final_orientation"]["+"]["reads"], 2);
+}
+
+#[test]
+fn report_counts_resources_of_every_output() {
+    let td = tempfile::tempdir().unwrap();
+    let d = td.path();
+    let p = |name: &str| d.join(name).to_str().unwrap().to_owned();
+    write(&d.join("in.fa"), FASTA);
+    write(&d.join("map.tsv"), TSV);
+    write(&d.join("in.fq"), "@p1/1\nAAC\n+\nIII\n@p1/2\nGGT\n+\nIII\n");
+    let resources = |report: &str| -> serde_json::Value {
+        let report: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(d.join(report)).unwrap()).unwrap();
+        report["resources"].clone()
+    };
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    cmd.args(["-f", &p("in.fa"), "-t", &p("map.tsv"), "-o", &p("out.fa")])
+        .args(["--unchanged-out", &p("same.fa"), "--report", &p("a.json")])
+        .assert()
+        .success();
+    let r = resources("a.json");
+    for key in [
+        "wall_secs",
+        "user_cpu_secs",
+        "system_cpu_secs",
+        "peak_rss_bytes",
+        "bytes_read",
+        "bytes_written",
+    ] {
+        assert!(r.get(key).is_some(), "missing {}", key);
+    }
+    let on_disk = fs::metadata(d.join("out.fa")).unwrap().len()
+        + fs::metadata(d.join("same.fa")).unwrap().len();
+    assert_eq!(r["bytes_written"], on_disk);
+    assert_eq!(
+        r["bytes_read"],
+        fs::metadata(d.join("in.fa")).unwrap().len()
+    );
+
+    // Paired outputs count too
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    cmd.args(["--fastq", "--all", "-f", &p("in.fq")])
+        .args(["--out-r1", &p("r1.fq"), "--out-r2", &p("r2.fq")])
+        .args(["--report", &p("b.json")])
+        .assert()
+        .success();
+    let on_disk =
+        fs::metadata(d.join("r1.fq")).unwrap().len() + fs::metadata(d.join("r2.fq")).unwrap().len();
+    assert!(on_disk > 0);
+    assert_eq!(resources("b.json")["bytes_written"], on_disk);
+}
+
+#[test]
+fn report_times_each_stage() {
+    let td = tempfile::tempdir().unwrap();
+    let fasta_p = td.path().join("in.fa.gz");
+    let tsv_p = td.path().join("map.tsv");
+    let report_p = td.path().join("report.json");
+    write_gz(&fasta_p, FASTA);
+    write(&tsv_p, TSV);
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    run_ok(cmd.args([
+        "-f",
+        fasta_p.to_str().unwrap(),
+        "-t",
+        tsv_p.to_str().unwrap(),
+        "--report",
+        report_p.to_str().unwrap(),
+        "--stage-timings",
+        "-o",
+        td.path().join("out.fa.gz").to_str().unwrap(),
+    ]));
+
+    let report: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(&report_p).unwrap()).unwrap();
+    let resources = &report["resources"];
+    let size = |name: &str| fs::metadata(td.path().join(name)).unwrap().len();
+    assert_eq!(resources["bytes_read"], size("in.fa.gz"));
+    assert_eq!(resources["bytes_written"], size("out.fa.gz"));
+    assert!(resources["wall_secs"].as_f64().unwrap() >= 0.0);
+    if cfg!(unix) {
+        assert!(resources["peak_rss_bytes"].as_u64().unwrap() > 0);
+        assert!(resources["user_cpu_secs"].as_f64().unwrap() >= 0.0);
+    }
+    let timings = report["timings"].as_object().unwrap();
+    for stage in [
+        "table_load",
+        "read",
+        "decompress",
+        "parse",
+        "lookup",
+        "revcomp",
+        "compress",
+        "write",
+    ] {
+        assert!(
+            timings[stage]["wall_secs"].as_f64().unwrap() >= 0.0,
+            "{}",
+            stage
+        );
+    }
+
+    // Without --stage-timings the report leaves the clocks alone
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    run_ok(cmd.args([
+        "-f",
+        fasta_p.to_str().unwrap(),
+        "-t",
+        tsv_p.to_str().unwrap(),
+        "--report",
+        report_p.to_str().unwrap(),
+    ]));
+    let report: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(&report_p).unwrap()).unwrap();
+    assert_eq!(report["timings"], serde_json::json!({}));
+}
+
+#[test]
+fn report_groups_by_column_or_header_tag() {
+    let td = tempfile::tempdir().unwrap();
+    let fasta_p = td.path().join("in.fa");
+    let tsv_p = td.path().join("map.tsv");
+    let fq_p = td.path().join("in.fq");
+    let report_p = td.path().join("report.json");
+    write(&fasta_p, FASTA);
+    write(
+        &tsv_p,
+        "ReadName\torientation\tbarcode\nreadA\t+\tBC1\nreadB\t-\tBC2\n",
+    );
+    write(
+        &fq_p,
+        "@r1 CB:Z:AAA orientation:-\nAC\n+\nII\n@r2 CB:Z:AAA orientation:-\nAC\n+\nII\n\
+         @r3 CB:Z:CCC orientation:+\nAC\n+\nII\n@r4\nAC\n+\nII\n",
+    );
+    let read_report = || -> serde_json::Value {
+        serde_json::from_str(&fs::read_to_string(&report_p).unwrap()).unwrap()
+    };
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    run_ok(cmd.args([
+        "-f",
+        fasta_p.to_str().unwrap(),
+        "-t",
+        tsv_p.to_str().unwrap(),
+        "--report",
+        report_p.to_str().unwrap(),
+        "--group-col",
+        "barcode",
+    ]));
+    let groups = &read_report()["groups"];
+    assert_eq!(groups["BC1"]["kept"], 1);
+    assert_eq!(groups["BC2"]["flipped"], 1);
+    assert_eq!(groups["BC2"]["plus_fraction"], 0.0);
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    run_ok(cmd.args([
+        "--fastq",
+        "-f",
+        fq_p.to_str().unwrap(),
+        "--report",
+        report_p.to_str().unwrap(),
+        "--group-tag",
+        "CB",
+    ]));
+    let groups = &read_report()["groups"];
+    assert_eq!(groups["AAA"]["reads"], 2);
+    assert_eq!(groups["AAA"]["flip_rate"], 1.0);
+    assert_eq!(groups["AAA"]["minus"], 2);
+    assert_eq!(groups["CCC"]["plus"], 1);
+    assert_eq!(groups["unassigned"]["unknown"], 1);
+    assert!(groups["unassigned"]["plus_fraction"].is_null());
+}
+
+#[test]
+fn trace_output_has_phase_spans() {
+    let td = tempfile::tempdir().unwrap();
+    let fasta_p = td.path().join("in.fa");
+    let tsv_p = td.path().join("map.tsv");
+    let trace_p = td.path().join("trace.json");
+    write(&fasta_p, FASTA);
+    write(&tsv_p, TSV);
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    run_ok(cmd.args([
+        "-f",
+        fasta_p.to_str().unwrap(),
+        "-t",
+        tsv_p.to_str().unwrap(),
+        "--trace-output",
+        trace_p.to_str().unwrap(),
+        "--stage-timings",
+    ]));
+
+    let trace: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(&trace_p).unwrap()).unwrap();
+    let events = trace["traceEvents"].as_array().unwrap();
+    let names: Vec<&str> = events.iter().map(|e| e["name"].as_str().unwrap()).collect();
+    assert_eq!(names, ["table_load", "setup", "records", "finish"]);
+    assert!(events.iter().all(|e| e["ph"] == "X"));
+    let records = &events[2]["args"];
+    assert_eq!(records["processed"], 2);
+    assert!(records["stages"]["revcomp"]["wall_secs"].is_number());
+
+    // FASTQ mode traces the same phases, with or without a table
+    let fq_p = td.path().join("in.fq");
+    write(&fq_p, TAGGED_FASTQ);
+    for table in [Some(tsv_p.to_str().unwrap()), None] {
+        let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+        cmd.args(["--fastq", "-f", fq_p.to_str().unwrap()])
+            .args(["--trace-output", trace_p.to_str().unwrap()]);
+        if let Some(table) = table {
+            cmd.args(["-t", table]);
+        }
+        run_ok(&mut cmd);
+        let trace: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(&trace_p).unwrap()).unwrap();
+        let events = trace["traceEvents"].as_array().unwrap();
+        let names: Vec<&str> = events.iter().map(|e| e["name"].as_str().unwrap()).collect();
+        assert_eq!(names, ["table_load", "setup", "records", "finish"]);
+    }
+}
+
+#[test]
+fn checksum_manifest_covers_all_outputs() {
+    use sha2::{Digest, Sha256};
+
+    let td = tempfile::tempdir().unwrap();
+    let fasta_p = td.path().join("in.fa");
+    let tsv_p = td.path().join("map.tsv");
+    let out_p = td.path().join("out.fa");
+    let kept_p = td.path().join("kept.fa");
+    write(&fasta_p, FASTA);
+    write(&tsv_p, TSV);
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    run_ok(cmd.args([
+        "-f",
+        fasta_p.to_str().unwrap(),
+        "-t",
+        tsv_p.to_str().unwrap(),
+        "-o",
+        out_p.to_str().unwrap(),
+        "--unchanged-out",
+        kept_p.to_str().unwrap(),
+        "--checksum",
+        "sha256",
+    ]));
+
+    let manifest = fs::read_to_string(td.path().join("out.fa.sha256")).unwrap();
+    let lines: Vec<&str> = manifest.lines().collect();
+    assert_eq!(lines.len(), 2);
+    for (line, path) in lines.iter().zip([&out_p, &kept_p]) {
+        let digest = Sha256::digest(fs::read(path).unwrap());
+        let hex: String = digest.iter().map(|b| format!("{:02x}", b)).collect();
+        assert_eq!(*line, format!("{}  {}", hex, path.display()));
+    }
+}
+
+#[test]
+fn checksum_to_stdout_requires_manifest_path() {
+    let td = tempfile::tempdir().unwrap();
+    let fasta_p = td.path().join("in.fa");
+    let tsv_p = td.path().join("map.tsv");
+    write(&fasta_p, FASTA);
+    write(&tsv_p, TSV);
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    cmd.args([
+        "-f",
+        fasta_p.to_str().unwrap(),
+        "-t",
+        tsv_p.to_str().unwrap(),
+        "--checksum",
+        "md5",
+    ]);
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("requires --checksum-manifest"));
+}
+
+#[test]
+fn fail_on_missing_exits_3_after_writing_output() {
+    let td = tempfile::tempdir().unwrap();
+    let fasta_p = td.path().join("in.fa");
+    let tsv_p = td.path().join("map.tsv");
+    write(&fasta_p, FASTA);
+    write(&tsv_p, "ReadName\torientation\nreadA\t+\n");
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    cmd.args([
+        "-f",
+        fasta_p.to_str().unwrap(),
+        "-t",
+        tsv_p.to_str().unwrap(),
+        "--fail-on-missing",
+    ]);
+    cmd.assert()
+        .code(3)
+        .stdout(predicate::str::contains(">readB"))
+        .stderr(predicate::str::contains("1 read(s) missing from the table"));
+}
+
+#[test]
+fn fail_on_warnings_exits_4() {
+    let td = tempfile::tempdir().unwrap();
+    let fq_p = td.path().join("in.fq");
+    write(&fq_p, "@untagged\nACGT\n+\nIIII\n");
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    cmd.args(["--fastq", "-f", fq_p.to_str().unwrap()]);
+    cmd.assert().success();
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    cmd.args([
+        "--fastq",
+        "-f",
+        fq_p.to_str().unwrap(),
+        "--fail-on-warnings",
+    ]);
+    cmd.assert().code(4);
+
+    // Duplicate table IDs are warnings in FASTA mode
+    let fasta_p = td.path().join("in.fa");
+    let tsv_p = td.path().join("map.tsv");
+    write(&fasta_p, FASTA);
+    write(
+        &tsv_p,
+        "ReadName\torientation\nreadA\t+\nreadB\t-\nreadA\t-\n",
+    );
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    cmd.args([
+        "-f",
+        fasta_p.to_str().unwrap(),
+        "-t",
+        tsv_p.to_str().unwrap(),
+        "--fail-on-warnings",
+    ]);
+    cmd.assert()
+        .code(4)
+        .stderr(predicate::str::contains("1 duplicate read ID(s) in table"));
+
+    // So are reads no source could orient, as in FASTQ mode
+    let tagged_p = td.path().join("tagged.fa");
+    write(&tagged_p, ">a orientation:-\nAACC\n>untagged\nACGT\n");
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    cmd.args(["-f", tagged_p.to_str().unwrap(), "--use-orientation-tag"]);
+    cmd.assert().success();
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    cmd.args(["-f", tagged_p.to_str().unwrap(), "--use-orientation-tag"])
+        .arg("--fail-on-warnings");
+    cmd.assert()
+        .code(4)
+        .stderr(predicate::str::contains("completed with 1 warning(s)"));
+}
+
+#[test]
+fn dash_and_dev_stdout_write_to_stdout() {
+    let td = tempfile::tempdir().unwrap();
+    let fasta_p = td.path().join("in.fa");
+    let tsv_p = td.path().join("map.tsv");
+    write(&fasta_p, FASTA);
+    write(&tsv_p, TSV);
+
+    for out in ["-", "/dev/stdout"] {
+        let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+        let stdout = run_ok(cmd.current_dir(td.path()).args([
+            "-f",
+            fasta_p.to_str().unwrap(),
+            "-t",
+            tsv_p.to_str().unwrap(),
+            "-o",
+            out,
+        ]));
+        assert!(stdout.contains(">readA some desc"));
+        assert!(stdout.contains(">readB"));
+    }
+    // '-' must not have created a file literally named '-'
+    assert!(!td.path().join("-").exists());
+}
+
+#[test]
+fn env_defaults_apply_and_flags_override() {
+    let td = tempfile::tempdir().unwrap();
+    let fasta_p = td.path().join("in.fa");
+    let tsv_p = td.path().join("map.tsv");
+    write(&fasta_p, FASTA);
+    write(&tsv_p, "id\tstrand\nreadA\t+\nreadB\t-\n");
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    let out = run_ok(
+        cmd.env("RESTRAND_ID_COL", "id")
+            .env("RESTRAND_ORIENTATION_COL", "strand")
+            .env("RESTRAND_TARGET_ORIENTATION", "+")
+            .args([
+                "-f",
+                fasta_p.to_str().unwrap(),
+                "-t",
+                tsv_p.to_str().unwrap(),
+                "--flipped-suffix",
+                "/rc",
+            ]),
+    );
+    assert!(out.contains(">readB/rc"));
+
+    // The CLI flag wins over the environment
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    let out = run_ok(
+        cmd.env("RESTRAND_ID_COL", "id")
+            .env("RESTRAND_ORIENTATION_COL", "strand")
+            .env("RESTRAND_TARGET_ORIENTATION", "+")
+            .args([
+                "-f",
+                fasta_p.to_str().unwrap(),
+                "-t",
+                tsv_p.to_str().unwrap(),
+                "--flipped-suffix",
+                "/rc",
+                "--target-orientation",
+                "-",
+            ]),
+    );
+    assert!(out.contains(">readA some desc/rc"));
+
+    // RESTRAND_JOBS is validated like --jobs, and the flag wins
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    cmd.env("RESTRAND_JOBS", "0")
+        .args([
+            "-f",
+            fasta_p.to_str().unwrap(),
+            "-t",
+            tsv_p.to_str().unwrap(),
+        ])
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains("--jobs"));
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    cmd.env("RESTRAND_JOBS", "0")
+        .args([
+            "-f",
+            fasta_p.to_str().unwrap(),
+            "-t",
+            tsv_p.to_str().unwrap(),
+        ])
+        .args([
+            "--id-col",
+            "id",
+            "--orientation-col",
+            "strand",
+            "--jobs",
+            "2",
+        ])
+        .assert()
+        .success();
+}
+
+#[test]
+fn completions_subcommand_prints_script() {
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    let out = run_ok(cmd.args(["completions", "bash"]));
+    assert!(out.contains("_restrand__fasta()"));
+    assert!(out.contains("--target-orientation"));
+
+    // Hidden from the main help
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    let help = run_ok(cmd.arg("--help"));
+    assert!(!help.contains("completions"));
+}
+
+#[test]
+fn quiet_and_summary_file() {
+    let td = tempfile::tempdir().unwrap();
+    let fasta_p = td.path().join("in.fa");
+    let tsv_p = td.path().join("map.tsv");
+    let summary_p = td.path().join("summary.tsv");
+    write(&fasta_p, FASTA);
+    write(&tsv_p, TSV);
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    cmd.args([
+        "-f",
+        fasta_p.to_str().unwrap(),
+        "-t",
+        tsv_p.to_str().unwrap(),
+        "--quiet",
+    ]);
+    cmd.assert().success().stderr(predicate::str::is_empty());
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    cmd.args([
+        "-f",
+        fasta_p.to_str().unwrap(),
+        "-t",
+        tsv_p.to_str().unwrap(),
+        "--summary-file",
+        summary_p.to_str().unwrap(),
+        "--summary-format",
+        "tsv",
+    ]);
+    cmd.assert().success().stderr(predicate::str::is_empty());
+    assert_eq!(
+        fs::read_to_string(&summary_p).unwrap(),
+        "metric\tvalue\nmode\tfasta\nprocessed\t2\nflipped\t1\nmissing_in_table\t0\n"
+    );
+}
+
+#[test]
+fn report_every_prints_heartbeat_lines() {
+    let td = tempfile::tempdir().unwrap();
+    let fasta_p = td.path().join("in.fa");
+    let tsv_p = td.path().join("map.tsv");
+    write(&fasta_p, FASTA);
+    write(&tsv_p, TSV);
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    cmd.args([
+        "-f",
+        fasta_p.to_str().unwrap(),
+        "-t",
+        tsv_p.to_str().unwrap(),
+        "--quiet",
+        "--report-every",
+        "1",
+    ]);
+    let stderr = String::from_utf8(cmd.assert().success().get_output().stderr.clone()).unwrap();
+    let lines: Vec<&str> = stderr.lines().collect();
+    assert_eq!(lines.len(), 2);
+    assert!(lines[0].starts_with("progress records=1 flipped=0 bytes_read="));
+    assert!(lines[1].starts_with("progress records=2 flipped=0 bytes_read="));
+    assert!(lines[1].contains(" elapsed_secs="));
+}
+
+/// cDNA-PCR style primer pair (forward/SSP, reverse/VNP).
+const PRIMERS: &str = "\
+>fwd
+TTTCTGTTGGTGCTGATATTGCTGGG
+>rev
+ACTTGCCTGTCGCTCTATCTTC
+";
+
+/// A '+' molecule: FWD + insert + rc(REV).
+fn plus_molecule(insert: &str) -> String {
+    let rev_rc = dna::revcomp(b"ACTTGCCTGTCGCTCTATCTTC");
+    format!(
+        "TTTCTGTTGGTGCTGATATTGCTGGG{}{}",
+        insert,
+        String::from_utf8(rev_rc).unwrap()
+    )
+}
+
+#[test]
+fn primers_orient_reads_without_table() {
+    let td = tempfile::tempdir().unwrap();
+    let fasta_p = td.path().join("in.fa");
+    let primers_p = td.path().join("primers.fa");
+    let plus = plus_molecule("ACGGATTACAGGCATTAGCCAT");
+    let minus = String::from_utf8(dna::revcomp(plus.as_bytes())).unwrap();
+    write(
+        &fasta_p,
+        &format!(">p\n{}\n>m\n{}\n>none\nACGTACGTAC\n", plus, minus),
+    );
+    write(&primers_p, PRIMERS);
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    cmd.args([
+        "-f",
+        fasta_p.to_str().unwrap(),
+        "--primers",
+        primers_p.to_str().unwrap(),
+        "--annotate",
+    ]);
+    let assert = cmd.assert().success();
+    let out = String::from_utf8(assert.get_output().stdout.clone()).unwrap();
+    let stderr = String::from_utf8(assert.get_output().stderr.clone()).unwrap();
+
+    // 70 nt records wrap onto two lines
+    let lines: Vec<&str> = out.lines().collect();
+    assert_eq!(lines[0], ">p restrand=kept source=primers");
+    assert_eq!(lines[3], ">m restrand=flipped source=primers");
+    // Flipped read now matches the '+' molecule
+    let m_seq: String = lines[4..6].concat();
+    assert_eq!(m_seq, plus);
+    assert!(out.contains(">none restrand=kept source=none"));
+    assert!(stderr.contains("oriented_by_primers=2"));
+}
+
+#[test]
+fn primer_hits_out_exports_detected_regions() {
+    let td = tempfile::tempdir().unwrap();
+    let d = td.path();
+    let plus = plus_molecule("ACGGATTACAGGCATTAGCCAT");
+    write(
+        &d.join("in.fa"),
+        &format!(">p\n{}\n>none\nACGTACGTAC\n", plus),
+    );
+    write(&d.join("primers.fa"), PRIMERS);
+    let p = |name: &str| d.join(name).to_str().unwrap().to_owned();
+
+    for hits in ["hits.tsv", "hits.fa"] {
+        let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+        cmd.args(["-f", &p("in.fa"), "--primers", &p("primers.fa"), "-q"])
+            .args(["--primer-hits-out", &p(hits)])
+            .assert()
+            .success();
+    }
+    let rev_rc = String::from_utf8(dna::revcomp(b"ACTTGCCTGTCGCTCTATCTTC")).unwrap();
+    let tsv = fs::read_to_string(d.join("hits.tsv")).unwrap();
+    let lines: Vec<&str> = tsv.lines().collect();
+    assert_eq!(
+        lines[0],
+        "read_id\tprimer\tread_end\tstart\tend\tscore\tidentity\tsequence"
+    );
+    assert_eq!(
+        lines[1],
+        "p\tfwd\tstart\t0\t26\t52\t1.000\tTTTCTGTTGGTGCTGATATTGCTGGG"
+    );
+    assert_eq!(
+        lines[2],
+        format!("p\trev_rc\tend\t48\t70\t44\t1.000\t{}", rev_rc)
+    );
+    assert_eq!(lines.len(), 3, "{}", tsv);
+    let fa = fs::read_to_string(d.join("hits.fa")).unwrap();
+    assert!(fa.starts_with(
+        ">p:0-26 primer=fwd read_end=start score=52 identity=1.000\nTTTCTGTTGGTGCTGATATTGCTGGG\n"
+    ));
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    write(&d.join("map.tsv"), "ReadName\torientation\np\t+\n");
+    cmd.args(["-f", &p("in.fa"), "-t", &p("map.tsv")])
+        .args(["--primer-hits-out", &p("x.tsv")])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("requires --primers or --preset"));
+}
+
+#[test]
+fn adapter_report_places_primer_hits() {
+    let td = tempfile::tempdir().unwrap();
+    let d = td.path();
+    let plus = plus_molecule("ACGGATTACAGGCATTAGCCAT");
+    let minus = String::from_utf8(dna::revcomp(plus.as_bytes())).unwrap();
+    // The '-' read carries three extra bases ahead of its adapter
+    write(
+        &d.join("in.fa"),
+        &format!(">p\n{}\n>m\nGGG{}\n>none\nACGTACGTAC\n", plus, minus),
+    );
+    write(&d.join("primers.fa"), PRIMERS);
+    let p = |name: &str| d.join(name).to_str().unwrap().to_owned();
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    cmd.args(["-f", &p("in.fa"), "--primers", &p("primers.fa")])
+        .args(["--report", &p("report.json"), "--adapter-report", "-q"])
+        .assert()
+        .success();
+    let report: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(d.join("report.json")).unwrap()).unwrap();
+    let adapters = &report["adapters"];
+    assert_eq!(adapters["reads"], 3);
+    assert_eq!(adapters["start_positions"]["fwd"]["0"], 1);
+    assert_eq!(adapters["start_positions"]["rev"]["3"], 1);
+    assert_eq!(adapters["start_positions"]["fwd_rc"]["0"], 1);
+    let start = adapters["content"]["start"].as_array().unwrap();
+    assert_eq!(start.len(), 26);
+    assert_eq!(start[0].as_f64().unwrap(), 1.0 / 3.0);
+    assert_eq!(start[5].as_f64().unwrap(), 2.0 / 3.0);
+
+    // Without a primer search there is nothing to report
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    write(&d.join("map.tsv"), TSV);
+    cmd.args(["-f", &p("in.fa"), "-t", &p("map.tsv")])
+        .args(["--report", &p("r2.json"), "--adapter-report"])
+        .assert()
+        .code(1)
+        .stderr(predicate::str::contains("--adapter-report requires"));
+}
+
+#[test]
+fn diff_recovers_table_of_restranded_file() {
+    let td = tempfile::tempdir().unwrap();
+    let d = td.path();
+    write(&d.join("in.fa"), FASTA);
+    write(&d.join("t.tsv"), TSV);
+    let p = |name: &str| d.join(name).to_str().unwrap().to_owned();
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    cmd.args(["-f", &p("in.fa"), "-t", &p("t.tsv"), "-o", &p("out.fa")])
+        .args(["--flipped-suffix", "_rc", "-q"]);
+    run_ok(&mut cmd);
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    cmd.args([
+        "diff",
+        "--original",
+        &p("in.fa"),
+        "--restranded",
+        &p("out.fa"),
+    ])
+    .args(["--flipped-suffix", "_rc", "-o", &p("recovered.tsv")])
+    .assert()
+    .success()
+    .stderr(predicate::str::contains("1 of 2 reads differ"));
+    assert_eq!(
+        fs::read_to_string(d.join("recovered.tsv")).unwrap(),
+        "ReadName\torientation\nreadA\t+\nreadB\t-\n"
+    );
+
+    write(
+        &d.join("other.fa"),
+        ">readA\nACGTACGTAC\n>readB\nTTTTTTTTTTTT\n",
+    );
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    cmd.args([
+        "diff",
+        "--original",
+        &p("in.fa"),
+        "--restranded",
+        &p("other.fa"),
+    ])
+    .assert()
+    .failure()
+    .stderr(predicate::str::contains(
+        "readB: the restranded sequence is neither",
+    ));
+}
+
+#[test]
+fn scramble_writes_truth_that_restores_reads() {
+    let td = tempfile::tempdir().unwrap();
+    let d = td.path();
+    let reads = ">r1 sample=a\nAACCGGTTAC\n>r2\nGGGTTTAAAC\n>r3\nACGTTGCA\n";
+    write(&d.join("in.fa"), reads);
+    let p = |name: &str| d.join(name).to_str().unwrap().to_owned();
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    cmd.args(["scramble", "-f", &p("in.fa"), "-o", &p("scrambled.fa")])
+        .args(["--truth-out", &p("truth.tsv"), "--fraction", "1"])
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("scrambled 3 of 3 reads"));
+    assert_eq!(
+        fs::read_to_string(d.join("truth.tsv")).unwrap(),
+        "ReadName\torientation\nr1\t-\nr2\t-\nr3\t-\n"
+    );
+    assert!(fs::read_to_string(d.join("scrambled.fa"))
+        .unwrap()
+        .starts_with(">r1 sample=a\nGTAACCGGTT\n"));
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    cmd.args([
+        "-f",
+        &p("scrambled.fa"),
+        "-t",
+        &p("truth.tsv"),
+        "-o",
+        &p("restored.fa"),
+        "-q",
+    ])
+    .assert()
+    .success();
+    assert_eq!(fs::read_to_string(d.join("restored.fa")).unwrap(), reads);
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    cmd.args(["scramble", "-f", &p("in.fa"), "--truth-out", &p("t.tsv")])
+        .args(["--fraction", "1.5"])
+        .assert()
+        .code(2);
+}
+
+#[test]
+fn simulate_reads_round_trip_through_evaluate() {
+    let td = tempfile::tempdir().unwrap();
+    let d = td.path();
+    write(&d.join("primers.fa"), PRIMERS);
+    let p = |name: &str| d.join(name).to_str().unwrap().to_owned();
+    let simulate = |out: &str| {
+        let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+        cmd.args([
+            "simulate",
+            "-n",
+            "20",
+            "--min-length",
+            "50",
+            "--max-length",
+            "80",
+        ])
+        .args(["--primers", &p("primers.fa"), "--seed", "7", "-o", &p(out)])
+        .args(["--truth-out", &p("truth.tsv")])
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("simulated 20 reads"));
+        fs::read_to_string(d.join(out)).unwrap()
+    };
+    let reads = simulate("sim.fa");
+    assert_eq!(reads, simulate("again.fa"));
+    assert_eq!(reads.matches('>').count(), 20);
+    let truth = fs::read_to_string(d.join("truth.tsv")).unwrap();
+    assert!(truth.starts_with("ReadName\torientation\nsim1\t"));
+    assert!(truth.contains("\t+\n") && truth.contains("\t-\n"));
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    let out = run_ok(
+        cmd.args(["evaluate", "--truth", &p("truth.tsv"), "--"])
+            .args(["-f", &p("sim.fa"), "--primers", &p("primers.fa")]),
+    );
+    assert!(out.contains("call_rate\t1.0000\n"), "{}", out);
+    assert!(out.contains("accuracy\t1.0000\n"), "{}", out);
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    cmd.args(["simulate", "--min-length", "90", "--max-length", "80"])
+        .args(["--truth-out", &p("t.tsv")])
+        .assert()
+        .code(1)
+        .stderr(predicate::str::contains("greater than --max-length"));
+}
+
+#[test]
+fn evaluate_scores_calls_against_truth() {
+    let td = tempfile::tempdir().unwrap();
+    let d = td.path();
+    let plus = plus_molecule("ACGGATTACAGGCATTAGCCAT");
+    let minus = String::from_utf8(dna::revcomp(plus.as_bytes())).unwrap();
+    write(
+        &d.join("in.fa"),
+        &format!(
+            ">p\n{}\n>m\n{}\n>none\nACGTACGTAC\n>extra\nAC\n",
+            plus, minus
+        ),
+    );
+    write(&d.join("primers.fa"), PRIMERS);
+    write(
+        &d.join("truth.tsv"),
+        "ReadName\torientation\np\t+\nm\t-\nnone\t+\n",
+    );
+    let p = |name: &str| d.join(name).to_str().unwrap().to_owned();
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    let out = run_ok(
+        cmd.args(["evaluate", "--truth", &p("truth.tsv"), "--"])
+            .args(["-f", &p("in.fa"), "--primers", &p("primers.fa")]),
+    );
+    for line in [
+        "evaluated\t3\n",
+        "not_in_truth\t1\n",
+        "called\t2\n",
+        "call_rate\t0.6667\n",
+        "accuracy\t1.0000\n",
+        "precision_plus\t1.0000\n",
+        "recall_plus\t0.5000\n",
+        "+\t1\t0\t1\n",
+        "-\t0\t1\t0\n",
+    ] {
+        assert!(out.contains(line), "missing {:?} in\n{}", line, out);
+    }
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    cmd.args(["evaluate", "--truth", &p("truth.tsv")])
+        .assert()
+        .code(2);
+}
+
+#[test]
+fn primers_fill_in_untagged_fastq_reads() {
+    let td = tempfile::tempdir().unwrap();
+    let fq_p = td.path().join("in.fq");
+    let primers_p = td.path().join("primers.fa");
+    let plus = plus_molecule("ACGGATTACAGGCATTAGCCAT");
+    let minus = String::from_utf8(dna::revcomp(plus.as_bytes())).unwrap();
+    let qual = "I".repeat(minus.len());
+    write(&fq_p, &format!("@m\n{}\n+\n{}\n", minus, qual));
+    write(&primers_p, PRIMERS);
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    let out = run_ok(cmd.args([
+        "--fastq",
+        "-f",
+        fq_p.to_str().unwrap(),
+        "--primers",
+        primers_p.to_str().unwrap(),
+    ]));
+    assert_eq!(out.lines().nth(1).unwrap(), plus);
+}
+
+#[test]
+fn primer_thresholds_and_debug_dump() {
+    let td = tempfile::tempdir().unwrap();
+    let fasta_p = td.path().join("in.fa");
+    let primers_p = td.path().join("primers.fa");
+    let debug_p = td.path().join("hits.tsv");
+    // One substitution in the forward primer, no reverse primer at all
+    let read = "TTTCTGTTGGTGCTGAAATTGCTGGGACGGATTACAGGCATTAGCCAT";
+    write(&fasta_p, &format!(">r\n{}\n", read));
+    write(&primers_p, PRIMERS);
+
+    let run = |extra: &[&str]| {
+        let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+        cmd.args([
+            "-f",
+            fasta_p.to_str().unwrap(),
+            "--primers",
+            primers_p.to_str().unwrap(),
+            "--annotate",
+            "--primer-debug",
+            debug_p.to_str().unwrap(),
+        ]);
+        cmd.args(extra);
+        run_ok(&mut cmd)
+    };
+
+    assert!(run(&[]).starts_with(">r restrand=kept source=primers"));
+    let debug = fs::read_to_string(&debug_p).unwrap();
+    let rows: Vec<&str> = debug.lines().collect();
+    assert!(rows[0].starts_with("read_id\tprimer\tread_end\tstart\tend\tscore"));
+    assert_eq!(rows.len(), 5);
+    assert!(rows[1].starts_with("r\tfwd\tstart\t0\t26\t"));
+    assert!(rows[1].ends_with("\ttrue\t+"));
+
+    // Demanding a perfect match leaves the read unresolved
+    assert!(run(&["--primer-min-identity", "1"]).starts_with(">r restrand=kept source=none"));
+    // So does a minimum score above what 26 nt with one mismatch can reach
+    assert!(run(&["--primer-min-score", "50"]).starts_with(">r restrand=kept source=none"));
+}
+
+#[test]
+fn tenx_preset_orients_and_reports_barcode_window() {
+    let td = tempfile::tempdir().unwrap();
+    let fq_p = td.path().join("in.fq");
+    let rc = |s: &str| String::from_utf8(dna::revcomp(s.as_bytes())).unwrap();
+    let (cb, umi) = ("AAACCCAAGAAACACT", "GCATGCATGCAT");
+    // Sense molecule: TSO-cDNA-polyA-rc(UMI)-rc(CB)-rc(Read1)
+    let sense = format!(
+        "AAGCAGTGGTATCAACGCAGAGTACATGGG{}{}{}{}{}",
+        "GATTACAGGCATTAGCCATGGCTAGCTAGGATCCGA",
+        "A".repeat(30),
+        rc(umi),
+        rc(cb),
+        rc("CTACACGACGCTCTTCCGATCT")
+    );
+    let antisense = rc(&sense);
+    let qual = "I".repeat(sense.len());
+    write(
+        &fq_p,
+        &format!(
+            "@as\n{}\n+\n{}\n@s\n{}\n+\n{}\n",
+            antisense, qual, sense, qual
+        ),
+    );
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    let out = run_ok(cmd.args([
+        "--fastq",
+        "-f",
+        fq_p.to_str().unwrap(),
+        "--preset",
+        "10x-3p",
+        "--barcode-window",
+    ]));
+
+    let lines: Vec<&str> = out.lines().collect();
+    let len = sense.len();
+    let expected = format!(
+        "cb_window={}-{} umi_window={}-{}",
+        len - 38,
+        len - 22,
+        len - 50,
+        len - 38
+    );
+    // Antisense read is flipped; both now report the same window
+    assert_eq!(lines[0], format!("@as {}", expected));
+    assert_eq!(lines[1], sense);
+    assert_eq!(lines[4], format!("@s {}", expected));
+    assert_eq!(&sense[len - 38..len - 22], rc(cb));
+}
+
+#[test]
+fn isoseq_preset_orients_pacbio_ccs_reads() {
+    let td = tempfile::tempdir().unwrap();
+    let fq_p = td.path().join("in.fq");
+    let rc = |s: &str| String::from_utf8(dna::revcomp(s.as_bytes())).unwrap();
+    // Sense Iso-Seq read: 5p-cDNA-polyA-3p
+    let sense = format!(
+        "AAGCAGTGGTATCAACGCAGAGTACATGGG{}{}GTACTCTGCGTTGATACCACTGCTT",
+        "GATTACAGGCATTAGCCATGGCTAGCTAGGATCCGA",
+        "A".repeat(25)
+    );
+    let antisense = rc(&sense);
+    let qual = "I".repeat(sense.len());
+    write(
+        &fq_p,
+        &format!(
+            "@m64011_190830_220126/101/ccs\n{}\n+\n{}\n@m64011_190830_220126/102/ccs\n{}\n+\n{}\n",
+            antisense, qual, sense, qual
+        ),
+    );
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    let assert = cmd
+        .args([
+            "--fastq",
+            "-f",
+            fq_p.to_str().unwrap(),
+            "--preset",
+            "isoseq",
+            "--flipped-suffix",
+            "/rc",
+        ])
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("PacBio movie/zmw"));
+    let out = String::from_utf8(assert.get_output().stdout.clone()).unwrap();
+    let lines: Vec<&str> = out.lines().collect();
+    assert_eq!(lines[0], "@m64011_190830_220126/101/ccs/rc");
+    assert_eq!(lines[1], sense);
+    assert_eq!(lines[4], "@m64011_190830_220126/102/ccs");
+    assert_eq!(lines[5], sense);
+}
+
+#[test]
+fn kit_preset_orients_without_primer_fasta_and_lists_presets() {
+    let td = tempfile::tempdir().unwrap();
+    let fasta_p = td.path().join("in.fa");
+    let plus = plus_molecule("ACGGATTACAGGCATTAGCCAT");
+    let minus = String::from_utf8(dna::revcomp(plus.as_bytes())).unwrap();
+    write(&fasta_p, &format!(">m\n{}\n", minus));
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    let out = run_ok(cmd.args([
+        "-f",
+        fasta_p.to_str().unwrap(),
+        "--preset",
+        "dcs109",
+        "--annotate",
+    ]));
+    let lines: Vec<&str> = out.lines().collect();
+    assert_eq!(lines[0], ">m restrand=flipped source=primers");
+    assert_eq!(lines[1..3].concat(), plus);
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    let list = run_ok(cmd.arg("--list-presets"));
+    let names: Vec<&str> = list
+        .lines()
+        .skip(1)
+        .map(|l| l.split('\t').next().unwrap())
+        .collect();
+    assert_eq!(
+        names,
+        [
+            "pcs111",
+            "dcs109",
+            "pcb114",
+            "10x-3p",
+            "isoseq",
+            "isoseq-neb"
+        ]
+    );
+    assert!(list.contains("dcs109\t200\t0.7\t15\t-\tTTTCTGTTGGTGCTGATATTGCTGGG\t"));
+}
+
+#[test]
+fn conflicting_table_and_primer_evidence_is_resolved_explicitly() {
+    let td = tempfile::tempdir().unwrap();
+    let fasta_p = td.path().join("in.fa");
+    let tsv_p = td.path().join("map.tsv");
+    let primers_p = td.path().join("primers.fa");
+    // Primers say '+', the table says '-'
+    let plus = plus_molecule("ACGGATTACAGGCATTAGCCAT");
+    write(&fasta_p, &format!(">c\n{}\n", plus));
+    write(&tsv_p, "ReadName\torientation\nc\t-\n");
+    write(&primers_p, PRIMERS);
+    let base = [
+        "-f",
+        fasta_p.to_str().unwrap(),
+        "-t",
+        tsv_p.to_str().unwrap(),
+        "--primers",
+        primers_p.to_str().unwrap(),
+        "--annotate",
+    ];
+
+    // Default: the table outranks primers, and the conflict is counted
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    let assert = cmd.args(base).assert().success();
+    let out = String::from_utf8(assert.get_output().stdout.clone()).unwrap();
+    let stderr = String::from_utf8(assert.get_output().stderr.clone()).unwrap();
+    assert!(out.starts_with(">c restrand=flipped source=table\n"));
+    assert!(stderr.contains("conflicts=1"));
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    let out = run_ok(
+        cmd.args(base)
+            .args(["--evidence-priority", "primers,table"]),
+    );
+    assert!(out.starts_with(">c restrand=kept source=primers\n"));
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    let out = run_ok(cmd.args(base).args(["--on-conflict", "unknown"]));
+    assert!(out.starts_with(">c restrand=kept source=none\n"));
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    cmd.args(base)
+        .args(["--on-conflict", "error"])
+        .assert()
+        .code(1)
+        .stderr(predicate::str::contains(
+            "conflicting orientation evidence for read 'c': table=- primers=+",
+        ));
+}
+
+#[test]
+fn fasta_orientation_tags_without_table() {
+    let td = tempfile::tempdir().unwrap();
+    let fasta_p = td.path().join("in.fa");
+    write(
+        &fasta_p,
+        ">a cell=1;orientation:-\nAACG\n>b strand=+\nAACG\n>c\nAACG\n",
+    );
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    cmd.args(["-f", fasta_p.to_str().unwrap()])
+        .assert()
+        .code(1)
+        .stderr(predicate::str::contains("--use-orientation-tag"));
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    let assert = cmd
+        .args([
+            "-f",
+            fasta_p.to_str().unwrap(),
+            "--use-orientation-tag",
+            "--annotate",
+        ])
+        .assert()
+        .success();
+    let out = String::from_utf8(assert.get_output().stdout.clone()).unwrap();
+    let stderr = String::from_utf8(assert.get_output().stderr.clone()).unwrap();
+    assert_eq!(
+        out,
+        ">a cell=1;orientation:+ restrand=flipped source=tag\nCGTT\n\
+         >b strand=+ restrand=kept source=tag\nAACG\n\
+         >c restrand=kept source=none\nAACG\n"
+    );
+    assert!(stderr.contains("flipped=1"), "{}", stderr);
+    assert!(stderr.contains("no_orientation_tag=1"), "{}", stderr);
+}
+
+#[test]
+fn fastq_table_orients_untagged_reads_and_reverses_qualities() {
+    let td = tempfile::tempdir().unwrap();
+    let fq_p = td.path().join("in.fq");
+    let tsv_p = td.path().join("map.tsv");
+    write(
+        &fq_p,
+        "@q1 desc\nAACG\n+\nABCD\n@q2\nAACG\n+\nABCD\n@q3\nAACG\n+\nABCD\n",
+    );
+    write(&tsv_p, "ReadName\torientation\nq1\t-\nq2\t+\n");
+    let base = [
+        "--fastq",
+        "-f",
+        fq_p.to_str().unwrap(),
+        "-t",
+        tsv_p.to_str().unwrap(),
+    ];
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    let assert = cmd.args(base).assert().success();
+    let out = String::from_utf8(assert.get_output().stdout.clone()).unwrap();
+    let stderr = String::from_utf8(assert.get_output().stderr.clone()).unwrap();
+    assert!(out.starts_with("@q1 desc\nCGTT\n+\nDCBA\n@q2\nAACG\n+\nABCD\n@q3\n"));
+    assert!(stderr.contains("flipped=1"), "{}", stderr);
+    assert!(stderr.contains("missing_in_table=1"), "{}", stderr);
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    let out = run_ok(cmd.args(base).arg("--drop-missing"));
+    assert!(!out.contains("@q3"));
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    cmd.args(base).arg("--fail-on-missing").assert().code(3);
+}
+
+#[test]
+fn fastq_tags_and_table_conflicts_follow_on_conflict() {
+    let td = tempfile::tempdir().unwrap();
+    let fq_p = td.path().join("in.fq");
+    let tsv_p = td.path().join("map.tsv");
+    write(&fq_p, TAGGED_FASTQ);
+    // r1 agrees with its tag ('-'); r2 is tagged '+' but the table says '-'
+    write(&tsv_p, "ReadName\torientation\nr1\t-\nr2\t-\n");
+    let base = [
+        "--fastq",
+        "-f",
+        fq_p.to_str().unwrap(),
+        "-t",
+        tsv_p.to_str().unwrap(),
+    ];
+    let run = |extra: &[&str]| {
+        let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+        let assert = cmd.args(base).args(extra).assert().success();
+        let out = String::from_utf8(assert.get_output().stdout.clone()).unwrap();
+        let stderr = String::from_utf8(assert.get_output().stderr.clone()).unwrap();
+        (out, stderr)
+    };
+
+    let (out, stderr) = run(&["--on-conflict", "prefer-table"]);
+    assert!(stderr.contains("flipped=2"), "{}", stderr);
+    assert!(stderr.contains("conflicts=1"), "{}", stderr);
+    assert_eq!(out.lines().nth(5), Some("CGTT"));
+
+    let (out, stderr) = run(&["--on-conflict", "prefer-tag"]);
+    assert!(stderr.contains("flipped=1"), "{}", stderr);
+    assert_eq!(out.lines().nth(5), Some("AACG"));
+
+    // Unresolved reads keep their sequence and original tag
+    let (out, _) = run(&["--on-conflict", "unknown"]);
+    assert_eq!(out.lines().nth(4), Some("@r2 orientation:+"));
+    assert_eq!(out.lines().nth(5), Some("AACG"));
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    cmd.args(base)
+        .args(["--on-conflict", "error"])
+        .assert()
+        .code(1)
+        .stderr(predicate::str::contains(
+            "conflicting orientation evidence for read 'r2': table=- tag=+",
+        ));
+}
+
+#[test]
+fn ambiguous_reads_are_flagged_and_routed() {
+    let td = tempfile::tempdir().unwrap();
+    let d = td.path();
+    let plus = plus_molecule("ACGGATTACAGGCATTAGCCAT");
+    // A reverse primer in front of a '+' molecule: primers point both ways
+    write(
+        &d.join("in.fa"),
+        &format!(">amb\nACTTGCCTGTCGCTCTATCTTC{}\n>ok\n{}\n", plus, plus),
+    );
+    write(&d.join("primers.fa"), PRIMERS);
+    let p = |name: &str| d.join(name).to_str().unwrap().to_owned();
+    let args = ["-f", &p("in.fa"), "--primers", &p("primers.fa")].map(String::from);
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    let assert = cmd
+        .args(&args)
+        .args(["--decision-log", &p("log.tsv")])
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("ambiguous=1"));
+    assert_eq!(
+        String::from_utf8_lossy(&assert.get_output().stdout)
+            .matches('>')
+            .count(),
+        2
+    );
+    let log = fs::read_to_string(d.join("log.tsv")).unwrap();
+    assert!(log.contains("\namb\tprimers\t+\tkept\t"), "{}", log);
+    assert!(log.lines().nth(1).unwrap().ends_with("\ttrue"), "{}", log);
+    assert!(log.lines().nth(2).unwrap().ends_with("\tfalse"), "{}", log);
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    let out = run_ok(cmd.args(&args).args(["--on-ambiguous", "drop"]));
+    assert!(out.starts_with(">ok\n") && !out.contains(">amb"));
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    let out = run_ok(cmd.args(&args).args([
+        "--on-ambiguous",
+        "separate-file",
+        "--ambiguous-out",
+        &p("amb.fa"),
+    ]));
+    assert!(out.starts_with(">ok\n") && !out.contains(">amb"));
+    assert!(fs::read_to_string(d.join("amb.fa"))
+        .unwrap()
+        .starts_with(">amb\n"));
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    cmd.args(&args)
+        .args(["--on-ambiguous", "separate-file"])
+        .assert()
+        .code(1)
+        .stderr(predicate::str::contains(
+            "--on-ambiguous separate-file requires --ambiguous-out",
+        ));
+}
+
+#[test]
+fn decision_log_and_header_carry_confidence() {
+    let td = tempfile::tempdir().unwrap();
+    let fasta_p = td.path().join("in.fa");
+    let tsv_p = td.path().join("map.tsv");
+    let primers_p = td.path().join("primers.fa");
+    let log_p = td.path().join("decisions.tsv");
+    let plus = plus_molecule("ACGGATTACAGGCATTAGCCAT");
+    let minus = String::from_utf8(dna::revcomp(plus.as_bytes())).unwrap();
+    write(
+        &fasta_p,
+        &format!(">t\nACGTACGTAC\n>m\n{}\n>none\nACGTACGTAC\n", minus),
+    );
+    write(&tsv_p, "ReadName\torientation\nt\t+\n");
+    write(&primers_p, PRIMERS);
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    let out = run_ok(cmd.args([
+        "-f",
+        fasta_p.to_str().unwrap(),
+        "-t",
+        tsv_p.to_str().unwrap(),
+        "--primers",
+        primers_p.to_str().unwrap(),
+        "--decision-log",
+        log_p.to_str().unwrap(),
+        "--confidence-in-header",
+    ]));
+    assert!(out.starts_with(">t restrand_confidence=1.000\n"));
+    assert!(out.contains(">m restrand_confidence=1.000\n"));
+    assert!(out.contains(">none\n"));
+
+    let log = fs::read_to_string(&log_p).unwrap();
+    assert_eq!(
+        log,
+        "read_id\tsource\torientation\taction\tconfidence\tambiguous\n\
+         t\ttable\t+\tkept\t1.000\tfalse\n\
+         m\tprimers\t-\tflipped\t1.000\tfalse\n\
+         none\tnone\t.\tunknown\t.\tfalse\n"
+    );
+}
+
+#[test]
+fn header_tag_variants_are_recognized() {
+    let td = tempfile::tempdir().unwrap();
+    let fq_p = td.path().join("in.fq");
+    let headers = [
+        "a strand=-",
+        "b cell:10|Orientation:+",
+        "c UMI:AGGCorientation:-",
+        "d x|strand-|y",
+        "e orientation:?",
+    ];
+    let mut fq = String::new();
+    for h in headers {
+        fq.push_str(&format!("@{}\nAACC\n+\nIIJJ\n", h));
+    }
+    write(&fq_p, &fq);
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    let assert = cmd
+        .args(["--fastq", "-f", fq_p.to_str().unwrap()])
+        .assert()
+        .success();
+    let out = String::from_utf8(assert.get_output().stdout.clone()).unwrap();
+    let stderr = String::from_utf8(assert.get_output().stderr.clone()).unwrap();
+    let lines: Vec<&str> = out.lines().collect();
+
+    // Keys and delimiters are kept; only the value moves to the target
+    assert_eq!(lines[0], "@a strand=+");
+    assert_eq!(lines[1], "GGTT");
+    assert_eq!(lines[4], "@b cell:10|Orientation:+");
+    assert_eq!(lines[5], "AACC");
+    assert_eq!(lines[8], "@c UMI:AGGCorientation:+");
+    assert_eq!(lines[9], "GGTT");
+    assert_eq!(lines[12], "@d x|strand+|y");
+    assert_eq!(lines[16], "@e orientation:?");
+    assert!(stderr.contains("no_orientation_tag=1"));
+}
+
+/// Runs of bytes matching `pred` as (start, len) pairs.
+fn runs(seq: &[u8], pred: impl Fn(u8) -> bool) -> Vec<(u32, u32)> {
+    let mut out: Vec<(u32, u32)> = Vec::new();
+    for (i, &b) in seq.iter().enumerate() {
+        if !pred(b) {
+            continue;
+        }
+        match out.last_mut() {
+            Some((s, n)) if (*s + *n) as usize == i => *n += 1,
+            _ => out.push((i as u32, 1)),
+        }
+    }
+    out
+}
+
+/// Little-endian, version 0 2bit file holding `records`.
+fn twobit_bytes(records: &[(&str, &str)]) -> Vec<u8> {
+    let u32s = |v: &mut Vec<u8>, xs: &[u32]| xs.iter().for_each(|x| v.extend(x.to_le_bytes()));
+    let mut out = Vec::new();
+    u32s(&mut out, &[0x1A41_2743, 0, records.len() as u32, 0]);
+    let mut offset = 16 + records.iter().map(|(n, _)| 5 + n.len()).sum::<usize>();
+    let mut bodies = Vec::new();
+    for (name, seq) in records {
+        let seq = seq.as_bytes();
+        let mut body = Vec::new();
+        for blocks in [
+            runs(seq, |b| b.eq_ignore_ascii_case(&b'N')),
+            runs(seq, |b| b.is_ascii_lowercase()),
+        ] {
+            u32s(&mut body, &[blocks.len() as u32]);
+            u32s(&mut body, &blocks.iter().map(|b| b.0).collect::<Vec<_>>());
+            u32s(&mut body, &blocks.iter().map(|b| b.1).collect::<Vec<_>>());
+        }
+        u32s(&mut body, &[0]);
+        for chunk in seq.chunks(4) {
+            let mut byte = 0u8;
+            for (i, b) in chunk.iter().enumerate() {
+                let code = match b.to_ascii_uppercase() {
+                    b'T' | b'N' => 0,
+                    b'C' => 1,
+                    b'A' => 2,
+                    _ => 3,
+                };
+                byte |= code << (6 - 2 * i);
+            }
+            body.push(byte);
+        }
+        let mut record = (seq.len() as u32).to_le_bytes().to_vec();
+        record.extend(body);
+        out.push(name.len() as u8);
+        out.extend(name.as_bytes());
+        u32s(&mut out, &[offset as u32]);
+        offset += record.len();
+        bodies.push(record);
+    }
+    bodies.into_iter().for_each(|b| out.extend(b));
+    out
+}
+
+#[test]
+fn twobit_input_is_decoded_in_fasta_mode() {
+    let td = tempfile::tempdir().unwrap();
+    let tb_p = td.path().join("genome.2bit");
+    let tsv_p = td.path().join("map.tsv");
+    fs::write(
+        &tb_p,
+        twobit_bytes(&[("chr1", "ACGTNNacg"), ("chr2", "GGTTA")]),
+    )
+    .unwrap();
+    write(&tsv_p, "ReadName\torientation\nchr1\t+\nchr2\t-\n");
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    let out = run_ok(cmd.args(["-f", tb_p.to_str().unwrap(), "-t", tsv_p.to_str().unwrap()]));
+    assert_eq!(out, ">chr1\nACGTNNacg\n>chr2\nTAACC\n");
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    cmd.args(["--fastq", "-f", tb_p.to_str().unwrap()])
+        .assert()
+        .code(1)
+        .stderr(predicate::str::contains("2bit input holds no qualities"));
+
+    // Corrupt counts and lengths are reported, not allocated
+    let good = twobit_bytes(&[("chr1", "ACGTNNacg")]);
+    // The sequence count in the header, chr1's length and its N block count
+    let record = 16 + 1 + 4 + 4;
+    for at in [8, record, record + 4] {
+        let mut bad = good.clone();
+        bad[at..at + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+        fs::write(&tb_p, bad).unwrap();
+        let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+        cmd.args(["-f", tb_p.to_str().unwrap(), "-t", tsv_p.to_str().unwrap()])
+            .assert()
+            .code(1)
+            .stderr(predicate::str::contains("truncated 2bit"))
+            .stderr(predicate::str::contains("memory allocation").not());
+    }
+}
+
+#[test]
+fn faidx_out_indexes_wrapped_fasta_output() {
+    let td = tempfile::tempdir().unwrap();
+    let fasta_p = td.path().join("in.fa");
+    let tsv_p = td.path().join("map.tsv");
+    let out_p = td.path().join("out.fa");
+    let fai_p = td.path().join("out.fa.fai");
+    let long = "ACGT".repeat(33);
+    write(
+        &fasta_p,
+        &format!(">readA some desc\n{}\n>readB\nGGGCCCaaattt\n", long),
+    );
+    write(&tsv_p, TSV);
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    cmd.args([
+        "-f",
+        fasta_p.to_str().unwrap(),
+        "-t",
+        tsv_p.to_str().unwrap(),
+        "-o",
+        out_p.to_str().unwrap(),
+        "--flipped-suffix",
+        "/rc",
+        "--faidx-out",
+        fai_p.to_str().unwrap(),
+    ])
+    .assert()
+    .success();
+
+    let out = fs::read_to_string(&out_p).unwrap();
+    let fai = fs::read_to_string(&fai_p).unwrap();
+    assert_eq!(fai, "readA\t132\t17\t60\t61\nreadB/rc\t12\t162\t60\t61\n");
+    assert_eq!(&out[17..77], &long[..60]);
+    assert_eq!(&out[162..174], "aaatttGGGCCC");
+}
+
+#[test]
+fn gz_output_is_bgzf_with_gzi_and_fai_indexes() {
+    use flate2::read::MultiGzDecoder;
+    use std::io::Read;
+
+    let td = tempfile::tempdir().unwrap();
+    let d = td.path();
+    let long = "ACGTTGCA".repeat(10_000);
+    write(
+        &d.join("in.fa"),
+        &format!(">readA\n{}\n>readB\n{}\n", long, long),
+    );
+    write(&d.join("map.tsv"), TSV);
+    let p = |name: &str| d.join(name).to_str().unwrap().to_owned();
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    cmd.args(["-f", &p("in.fa"), "-t", &p("map.tsv"), "-o", &p("out.fa")])
+        .args(["--faidx-out", &p("out.fa.fai")])
+        .assert()
+        .success();
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    cmd.args([
+        "-f",
+        &p("in.fa"),
+        "-t",
+        &p("map.tsv"),
+        "-o",
+        &p("out.fa.gz"),
+    ])
+    .args(["--gzi-out", &p("out.fa.gz.gzi")])
+    .args(["--faidx-out", &p("out.fa.gz.fai")])
+    .assert()
+    .success();
+
+    let plain = fs::read(d.join("out.fa")).unwrap();
+    let gz = fs::read(d.join("out.fa.gz")).unwrap();
+    let mut text = Vec::new();
+    MultiGzDecoder::new(&gz[..]).read_to_end(&mut text).unwrap();
+    assert_eq!(text, plain);
+    assert_eq!(
+        fs::read(d.join("out.fa.gz.fai")).unwrap(),
+        fs::read(d.join("out.fa.fai")).unwrap()
+    );
+
+    // Walk the blocks by their BSIZE and ISIZE fields.
+    let u16_at = |i: usize| u16::from_le_bytes([gz[i], gz[i + 1]]) as usize;
+    let mut starts = Vec::new();
+    let (mut at, mut uncompressed) = (0, 0u64);
+    while at < gz.len() {
+        assert_eq!(&gz[at..at + 4], &[0x1f, 0x8b, 8, 4]);
+        assert_eq!(&gz[at + 12..at + 14], b"BC");
+        let end = at + u16_at(at + 16) + 1;
+        let isize = u32::from_le_bytes(gz[end - 4..end].try_into().unwrap());
+        if isize > 0 && at > 0 {
+            starts.push((at as u64, uncompressed));
+        }
+        uncompressed += u64::from(isize);
+        at = end;
+    }
+    assert_eq!(uncompressed, plain.len() as u64);
+    assert_eq!(
+        gz[gz.len() - 28..][16],
+        0x1b,
+        "ends with the BGZF EOF block"
+    );
+    assert!(starts.len() >= 2);
+
+    let gzi = fs::read(d.join("out.fa.gz.gzi")).unwrap();
+    let u64_at = |i: usize| u64::from_le_bytes(gzi[i..i + 8].try_into().unwrap());
+    assert_eq!(u64_at(0), starts.len() as u64);
+    assert_eq!(gzi.len(), 8 + 16 * starts.len());
+    for (i, start) in starts.iter().enumerate() {
+        assert_eq!((u64_at(8 + 16 * i), u64_at(16 + 16 * i)), *start);
+    }
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    cmd.args(["-f", &p("in.fa"), "-t", &p("map.tsv"), "-o", &p("x.fa")])
+        .args(["--gzi-out", &p("x.gzi")])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("--gzi-out needs a .gz --out"));
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    cmd.args(["-f", &p("in.fa"), "-t", &p("map.tsv"), "-o", &p("x.fa.gz")])
+        .args(["--faidx-out", &p("x.fai")])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("also needs --gzi-out"));
+}
+
+#[test]
+fn provenance_comment_and_sidecar_record_the_run() {
+    let td = tempfile::tempdir().unwrap();
+    let fasta_p = td.path().join("in.fa");
+    let tsv_p = td.path().join("map.tsv");
+    let prov_p = td.path().join("prov.json");
+    write(&fasta_p, FASTA);
+    write(&tsv_p, TSV);
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    let out = run_ok(cmd.args([
+        "-f",
+        fasta_p.to_str().unwrap(),
+        "-t",
+        tsv_p.to_str().unwrap(),
+        "--flipped-suffix",
+        "/rc x",
+        "--provenance-comment",
+        "--provenance-out",
+        prov_p.to_str().unwrap(),
+    ]));
+    let first = out.lines().next().unwrap();
+    assert!(first.starts_with(&format!("; restrand-fasta {}: ", env!("CARGO_PKG_VERSION"))));
+    assert!(first.contains("--flipped-suffix '/rc x' --provenance-comment"));
+    assert_eq!(out.lines().nth(1), Some(">readA some desc"));
+
+    let prov: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(&prov_p).unwrap()).unwrap();
+    assert_eq!(prov["tool"], "restrand-fasta");
+    assert_eq!(prov["mode"], "fasta");
+    assert_eq!(prov["input"], fasta_p.to_str().unwrap());
+    assert_eq!(prov["target_orientation"], "+");
+    assert!(prov["command_line"]
+        .as_str()
+        .unwrap()
+        .contains("--flipped-suffix '/rc x'"));
+    assert_eq!(prov["parameters"]["flipped-suffix"], "/rc x");
+    assert_eq!(prov["parameters"]["provenance-comment"], "true");
+    assert_eq!(prov["parameters"]["table"], tsv_p.to_str().unwrap());
+    assert!(prov["parameters"].get("id-col").is_none());
+}
+
+#[test]
+fn max_memory_spills_table_to_disk_with_same_output() {
+    let td = tempfile::tempdir().unwrap();
+    let fasta_p = td.path().join("in.fa");
+    let tsv_p = td.path().join("map.tsv");
+    let mut fasta = String::new();
+    let mut tsv = String::from("ReadName\torientation\n");
+    for i in 0..600 {
+        fasta.push_str(&format!(">r{}\nAACG\n", i));
+        tsv.push_str(&format!("r{}\t{}\n", i, if i % 3 == 0 { '-' } else { '+' }));
+    }
+    // Duplicates before and after the spill point; the last one wins
+    tsv.push_str("r0\t+\nr1\t-\nr599\t+\n");
+    fasta.push_str(">absent\nAACG\n");
+    write(&fasta_p, &fasta);
+    write(&tsv_p, &tsv);
+    let base = [
+        "-f",
+        fasta_p.to_str().unwrap(),
+        "-t",
+        tsv_p.to_str().unwrap(),
+    ];
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    let in_memory = run_ok(cmd.args(base));
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    let assert = cmd
+        .args(base)
+        .args(["--max-memory", "2K"])
+        .assert()
+        .success();
+    let spilled = String::from_utf8(assert.get_output().stdout.clone()).unwrap();
+    let stderr = String::from_utf8(assert.get_output().stderr.clone()).unwrap();
+    assert_eq!(spilled, in_memory);
+    assert!(stderr.contains("sorted on-disk copy"));
+    assert!(stderr.contains("3 duplicate read ID(s)"));
+    assert!(stderr.contains("flipped=200 missing_in_table=1"));
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    cmd.args(base)
+        .args(["--max-memory", "lots"])
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains("invalid size"));
+}
+
+#[test]
+fn spill_files_honor_tmpdir() {
+    let td = tempfile::tempdir().unwrap();
+    let d = td.path();
+    let mut tsv = String::from("ReadName\torientation\n");
+    for i in 0..200 {
+        tsv.push_str(&format!("r{}\t-\n", i));
+    }
+    write(&d.join("map.tsv"), &tsv);
+    write(&d.join("in.fa"), ">r7\nAACG\n");
+    fs::create_dir(d.join("scratch")).unwrap();
+    let p = |name: &str| d.join(name).to_str().unwrap().to_owned();
+    let base = ["-f", &p("in.fa"), "-t", &p("map.tsv"), "--max-memory", "1K"];
+
+    // TMPDIR is used by default; --tmpdir overrides it
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    cmd.env("TMPDIR", p("missing"))
+        .args(base)
+        .assert()
+        .code(1)
+        .stderr(predicate::str::contains("create spill file"))
+        .stderr(predicate::str::contains(p("missing")));
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    cmd.env("TMPDIR", p("missing"))
+        .args(base)
+        .args(["--tmpdir", &p("scratch")])
+        .assert()
+        .success()
+        .stdout(">r7\nCGTT\n");
+
+    // RESTRAND_TMPDIR wins over TMPDIR, and --tmpdir over both
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    cmd.env("TMPDIR", p("missing"))
+        .env("RESTRAND_TMPDIR", p("scratch"))
+        .args(base)
+        .assert()
+        .success();
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    cmd.env("RESTRAND_TMPDIR", p("scratch"))
+        .args(base)
+        .args(["--tmpdir", &p("missing")])
+        .assert()
+        .code(1)
+        .stderr(predicate::str::contains(p("missing")));
+}
+
+#[test]
+fn orientation_encodings_are_detected_or_selected() {
+    let td = tempfile::tempdir().unwrap();
+    let fasta_p = td.path().join("in.fa");
+    let tsv_p = td.path().join("map.tsv");
+    write(&fasta_p, FASTA);
+    let expected = ">readA some desc\nACGTACGTAC\n>readB\naaatttGGGCCC\n";
+    let run = |tsv: &str, extra: &[&str]| {
+        write(&tsv_p, tsv);
+        let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+        run_ok(
+            cmd.args([
+                "-f",
+                fasta_p.to_str().unwrap(),
+                "-t",
+                tsv_p.to_str().unwrap(),
+            ])
+            .args(extra),
+        )
+    };
+
+    // SAM flags are detected from the values; 0 then means forward
+    assert_eq!(
+        run("ReadName\torientation\nreadA\t0\nreadB\t16\n", &[]),
+        expected
+    );
+    assert_eq!(
+        run("ReadName\torientation\nreadA\t1\nreadB\t-1\n", &[]),
+        expected
+    );
+    assert_eq!(
+        run("ReadName\torientation\nreadA\tF\nreadB\tR\n", &[]),
+        expected
+    );
+    // Without a telltale flag value, 0 is only forward when asked for
+    assert_eq!(
+        run("ReadName\torientation\nreadA\t0\nreadB\t-\n", &[]),
+        ">readA some desc\nGTACGTACGT\n>readB\naaatttGGGCCC\n"
+    );
+    assert_eq!(
+        run(
+            "ReadName\torientation\nreadA\t0\nreadB\t2064\n",
+            &["--orientation-encoding", "sam-flag"]
+        ),
+        expected
+    );
+
+    write(&tsv_p, "ReadName\torientation\nreadA\t0\nreadB\t16\n");
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    cmd.args([
+        "-f",
+        fasta_p.to_str().unwrap(),
+        "-t",
+        tsv_p.to_str().unwrap(),
+    ])
+    .args(["--orientation-encoding", "numeric"])
+    .assert()
+    .failure()
+    .stderr(predicate::str::contains(
+        "Unrecognized orientation value '0'",
+    ));
+}
+
+#[test]
+fn match_on_full_header_uses_description() {
+    let td = tempfile::tempdir().unwrap();
+    let fasta_p = td.path().join("in.fa");
+    let tsv_p = td.path().join("map.tsv");
+    write(&fasta_p, FASTA);
+    write(
+        &tsv_p,
+        "ReadName\torientation\nreadA some desc\t-\nreadB\t-\nreadA\t+\n",
+    );
+    let args = [
+        "-f",
+        fasta_p.to_str().unwrap(),
+        "-t",
+        tsv_p.to_str().unwrap(),
+    ];
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    let out = run_ok(cmd.args(args).args(["--match-on", "full-header"]));
+    assert_eq!(out, ">readA some desc\nGTACGTACGT\n>readB\naaatttGGGCCC\n");
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    let out = run_ok(cmd.args(args));
+    assert_eq!(out, ">readA some desc\nACGTACGTAC\n>readB\naaatttGGGCCC\n");
+}
+
+#[test]
+fn table_cache_is_reused_and_rebuilt_when_stale() {
+    let td = tempfile::tempdir().unwrap();
+    let fasta_p = td.path().join("in.fa");
+    let tsv_p = td.path().join("map.tsv");
+    let cache_p = td.path().join("map.cache");
+    let mut fasta = String::new();
+    let mut tsv = String::from("ReadName\torientation\n");
+    for i in 0..300 {
+        fasta.push_str(&format!(">r{}\nAACG\n", i));
+        tsv.push_str(&format!("r{}\t{}\n", i, if i % 3 == 0 { '-' } else { '+' }));
+    }
+    tsv.push_str("r1\t-\n");
+    fasta.push_str(">absent\nAACG\n");
+    write(&fasta_p, &fasta);
+    write(&tsv_p, &tsv);
+    let args = [
+        "-f",
+        fasta_p.to_str().unwrap(),
+        "-t",
+        tsv_p.to_str().unwrap(),
+        "--table-cache",
+        cache_p.to_str().unwrap(),
+    ];
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    let built = run_ok(cmd.args(args));
+    assert!(cache_p.exists());
+
+    // Served from the cache: same output and duplicate count
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    let assert = cmd.args(args).assert().success();
+    let cached = String::from_utf8(assert.get_output().stdout.clone()).unwrap();
+    let stderr = String::from_utf8(assert.get_output().stderr.clone()).unwrap();
+    assert_eq!(cached, built);
+    assert!(stderr.contains("1 duplicate read ID(s)"));
+    assert!(stderr.contains("flipped=101 missing_in_table=1"));
+
+    // A key offset corrupted without changing the size is an error when its
+    // key is read, not a wrong lookup
+    let mut bytes = fs::read(&cache_p).unwrap();
+    let intact = bytes.clone();
+    let first_offset = 56 + "ReadNameorientation".len() + 5;
+    let second_offset = first_offset + 8;
+    bytes[second_offset..second_offset + 8].copy_from_slice(&u64::MAX.to_le_bytes());
+    fs::write(&cache_p, &bytes).unwrap();
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    cmd.args(args)
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "table cache key offsets are corrupt",
+        ));
+
+    // One whose last offset no longer ends the file is rebuilt, not trusted
+    let mut bytes = intact;
+    let last_offset = first_offset + 300 * 8;
+    bytes[last_offset..last_offset + 8].copy_from_slice(&u64::MAX.to_le_bytes());
+    fs::write(&cache_p, &bytes).unwrap();
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    cmd.args(args)
+        .assert()
+        .success()
+        .stdout(built.clone())
+        .stderr(predicate::str::contains("ignoring unreadable table cache"));
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    cmd.args(args)
+        .assert()
+        .success()
+        .stdout(built)
+        .stderr(predicate::str::contains("table cache").not());
+
+    // A changed table invalidates the cache
+    write(&tsv_p, "ReadName\torientation\nr0\t+\n");
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    cmd.args(args)
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("is stale"))
+        .stderr(predicate::str::contains("flipped=0 missing_in_table=300"));
+}
+
+#[cfg(unix)]
+#[test]
+fn out_dir_loads_the_shared_table_once() {
+    let td = tempfile::tempdir().unwrap();
+    let d = td.path();
+    write(&d.join("a.fa"), FASTA);
+    write(&d.join("b.fa"), ">readB\nAAC\n");
+    let p = |name: &str| d.join(name).to_str().unwrap().to_owned();
+    // A FIFO can only be read once, so a second load would wait forever
+    let status = std::process::Command::new("mkfifo")
+        .arg(p("map.tsv"))
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let fifo = p("map.tsv");
+    let writer = std::thread::spawn(move || {
+        fs::write(fifo, format!("{}readB\t-\n", TSV)).unwrap();
+    });
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    let assert = cmd
+        .args(["-f", &p("a.fa"), &p("b.fa"), "-t", &p("map.tsv")])
+        .args(["--out-dir", &p("out"), "--jobs", "2"])
+        .timeout(std::time::Duration::from_secs(30))
+        .assert()
+        .success();
+    writer.join().unwrap();
+    let stderr = String::from_utf8(assert.get_output().stderr.clone()).unwrap();
+    assert_eq!(
+        stderr.matches("1 duplicate read ID(s)").count(),
+        1,
+        "{}",
+        stderr
+    );
+    assert_eq!(
+        fs::read_to_string(d.join("out/b.fa")).unwrap(),
+        ">readB\nGTT\n"
+    );
+    assert!(fs::read_to_string(d.join("out/a.fa"))
+        .unwrap()
+        .contains(">readB\naaatttGGGCCC\n"));
+}
+
+#[test]
+fn out_dir_maps_each_input_through_template() {
+    use flate2::read::MultiGzDecoder;
+    use std::io::Read;
+
+    let td = tempfile::tempdir().unwrap();
+    let d = td.path();
+    fs::create_dir_all(d.join("pass/barcode01")).unwrap();
+    fs::create_dir_all(d.join("pass/barcode02")).unwrap();
+    write(&d.join("pass/barcode01/x.fa"), FASTA);
+    write_gz(&d.join("pass/barcode02/y.fasta.gz"), ">readB\nAAC\n");
+    write(&d.join("map.tsv"), TSV);
+    let p = |name: &str| d.join(name).to_str().unwrap().to_owned();
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    cmd.args([
+        "-f",
+        &p("pass/barcode01/x.fa"),
+        &p("pass/barcode02/y.fasta.gz"),
+        "-t",
+        &p("map.tsv"),
+        "--out-dir",
+        &p("out"),
+        "--out-template",
+        "{stem}.restranded.fa.gz",
+        "-q",
+    ])
+    .assert()
+    .success();
+
+    let gunzip = |name: &str| {
+        let mut s = String::new();
+        MultiGzDecoder::new(File::open(d.join(name)).unwrap())
+            .read_to_string(&mut s)
+            .unwrap();
+        s
+    };
+    assert!(gunzip("out/barcode01/x.restranded.fa.gz").contains(">readB\naaatttGGGCCC\n"));
+    assert_eq!(gunzip("out/barcode02/y.restranded.fa.gz"), ">readB\nGTT\n");
+
+    // Several inputs without --out-dir, or colliding names, are refused
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    cmd.args([
+        "-f",
+        &p("pass/barcode01/x.fa"),
+        &p("pass/barcode02/y.fasta.gz"),
+    ])
+    .args(["-t", &p("map.tsv")])
+    .assert()
+    .code(1)
+    .stderr(predicate::str::contains("use --out-dir"));
+    write(&d.join("pass/barcode01/z.fa"), FASTA);
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    cmd.args(["-f", &p("pass/barcode01/x.fa"), &p("pass/barcode01/z.fa")])
+        .args(["-t", &p("map.tsv"), "--out-dir", &p("out2")])
+        .args(["--out-template", "all.fa"])
+        .assert()
+        .code(1)
+        .stderr(predicate::str::contains("both map to"));
+}
+
+#[test]
+fn input_dir_discovers_matching_files() {
+    let td = tempfile::tempdir().unwrap();
+    let d = td.path();
+    fs::create_dir_all(d.join("fastq_pass/barcode01")).unwrap();
+    fs::create_dir_all(d.join("fastq_pass/barcode02")).unwrap();
+    write(&d.join("fastq_pass/top.fa"), FASTA);
+    write(&d.join("fastq_pass/barcode01/a.fa"), FASTA);
+    write(&d.join("fastq_pass/barcode02/b.fa"), ">readB\nAAC\n");
+    write(&d.join("fastq_pass/barcode02/notes.txt"), "not a read\n");
+    write(&d.join("map.tsv"), TSV);
+    let p = |name: &str| d.join(name).to_str().unwrap().to_owned();
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    cmd.args(["--input-dir", &p("fastq_pass"), "--recursive"])
+        .args(["-t", &p("map.tsv"), "--out-dir", &p("out")])
+        .args(["--pattern", "?.fa", "--summary-file", &p("batch.tsv")])
+        .assert()
+        .success();
+    assert_eq!(
+        fs::read_to_string(d.join("out/barcode02/b.fa")).unwrap(),
+        ">readB\nGTT\n"
+    );
+    assert!(d.join("out/barcode01/a.fa").exists());
+    assert!(!d.join("out/top.fa").exists());
+    let summary = fs::read_to_string(d.join("batch.tsv")).unwrap();
+    assert_eq!(summary.lines().count(), 3, "{}", summary);
+
+    // Without --recursive only the top level is searched; the default
+    // pattern picks sequence files only
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    cmd.args(["--input-dir", &p("fastq_pass/barcode02")])
+        .args(["-t", &p("map.tsv"), "--out-dir", &p("out2"), "-q"])
+        .assert()
+        .success();
+    assert!(d.join("out2/b.fa").exists());
+    assert!(!d.join("out2/notes.txt").exists());
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    cmd.args(["--input-dir", &p("fastq_pass"), "--pattern", "*.fq"])
+        .args(["-t", &p("map.tsv"), "--out-dir", &p("out3")])
+        .assert()
+        .code(1)
+        .stderr(predicate::str::contains("no matching input files"));
+}
+
+#[cfg(all(feature = "xz", feature = "zstd"))]
+#[test]
+fn input_dir_and_watch_take_every_compression() {
+    let td = tempfile::tempdir().unwrap();
+    let d = td.path();
+    fs::create_dir(d.join("pass")).unwrap();
+    fs::create_dir(d.join("live")).unwrap();
+    let mut xz = xz2::write::XzEncoder::new(Vec::new(), 6);
+    xz.write_all(FASTA.as_bytes()).unwrap();
+    fs::write(d.join("pass/a.fa.xz"), xz.finish().unwrap()).unwrap();
+    fs::write(
+        d.join("live/b.fastq.zst"),
+        zstd::encode_all("@r1 orientation:-\nAACC\n+\nIIJJ\n".as_bytes(), 3).unwrap(),
+    )
+    .unwrap();
+    write(&d.join("map.tsv"), TSV);
+    let p = |name: &str| d.join(name).to_str().unwrap().to_owned();
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    cmd.args(["--input-dir", &p("pass")])
+        .args(["-t", &p("map.tsv"), "--out-dir", &p("out"), "-q"])
+        .args(["--out-template", "{stem}.fa"])
+        .assert()
+        .success();
+    assert!(d.join("out/a.fa").exists());
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    cmd.args([
+        "--fastq",
+        "--watch",
+        &p("live"),
+        "--watch-out-dir",
+        &p("chunks"),
+    ])
+    .args(["--watch-interval", "0.05", "--watch-idle-exit", "0.3"])
+    .assert()
+    .success();
+    assert_eq!(
+        fs::read_to_string(d.join("chunks/b.fastq")).unwrap(),
+        "@r1 orientation:+\nGGTT\n+\nJJII\n"
+    );
+}
+
+#[test]
+fn manifest_processes_samples_with_combined_summary() {
+    let td = tempfile::tempdir().unwrap();
+    let d = td.path();
+    write(&d.join("a.fa"), FASTA);
+    write(&d.join("b.fa"), ">readB\nAAC\n>readC\nGGT\n");
+    write(&d.join("map.tsv"), TSV);
+    let p = |name: &str| d.join(name).to_str().unwrap().to_owned();
+    write(
+        &d.join("samples.tsv"),
+        &format!(
+            "sample\tinput\ttable\toutput\nA\t{}\t\t{}\nB\t{}\t{}\t{}\n",
+            p("a.fa"),
+            p("a.out.fa"),
+            p("b.fa"),
+            p("map.tsv"),
+            p("b.out.fa")
+        ),
+    );
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    cmd.args([
+        "--manifest",
+        &p("samples.tsv"),
+        "-t",
+        &p("map.tsv"),
+        "--jobs",
+        "2",
+        "--summary-file",
+        &p("batch.tsv"),
+    ])
+    .assert()
+    .success()
+    .stderr("");
+
+    assert!(fs::read_to_string(d.join("a.out.fa"))
+        .unwrap()
+        .contains(">readB\naaatttGGGCCC\n"));
+    assert_eq!(
+        fs::read_to_string(d.join("b.out.fa")).unwrap(),
+        ">readB\nGTT\n>readC\nGGT\n"
+    );
+    let summary = fs::read_to_string(d.join("batch.tsv")).unwrap();
+    let lines: Vec<&str> = summary.lines().collect();
+    assert_eq!(
+        lines[0],
+        "sample\tinput\toutput\tstatus\tprocessed\tflipped\tmissing\twarnings"
+    );
+    assert!(lines[1].starts_with("A\t"));
+    assert!(lines[1].ends_with("\tok\t2\t1\t0\t0"));
+    assert!(lines[2].ends_with("\tok\t2\t1\t1\t1"));
+
+    // A failing sample is reported, the others still run, and the exit is 1
+    write(
+        &d.join("bad.tsv"),
+        &format!(
+            "input\toutput\n{}\t{}\n{}\t{}\n",
+            p("nope.fa"),
+            p("nope.out.fa"),
+            p("a.fa"),
+            p("a2.out.fa")
+        ),
+    );
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    cmd.args(["--manifest", &p("bad.tsv"), "-t", &p("map.tsv")])
+        .assert()
+        .code(1)
+        .stderr(predicate::str::contains("sample 'nope.fa'"))
+        .stderr(
+            predicate::str::contains("a.fa\t").and(predicate::str::contains("\tok\t2\t1\t0\t0")),
+        )
+        .stderr(predicate::str::contains("1 of 2 sample(s) failed"));
+    assert!(d.join("a2.out.fa").exists());
+}
+
+#[test]
+fn watch_mode_restrands_each_chunk_then_exits_when_idle() {
+    let td = tempfile::tempdir().unwrap();
+    let in_dir = td.path().join("chunks");
+    let out_dir = td.path().join("out");
+    fs::create_dir(&in_dir).unwrap();
+    write(
+        &in_dir.join("batch_0.fastq"),
+        "@r1 orientation:-\nAACC\n+\nIIJJ\n",
+    );
+    write_gz(
+        &in_dir.join("batch_1.fastq.gz"),
+        "@r2 orientation:+\nACGT\n+\nIIII\n",
+    );
+    write(&in_dir.join("notes.txt"), "not a chunk\n");
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    cmd.args([
+        "--fastq",
+        "--watch",
+        in_dir.to_str().unwrap(),
+        "--watch-out-dir",
+        out_dir.to_str().unwrap(),
+        "--watch-interval",
+        "0.05",
+        "--watch-idle-exit",
+        "0.3",
+    ])
+    .assert()
+    .success()
+    .stderr(predicate::str::contains(
+        "chunk batch_0.fastq processed=1 flipped=1",
+    ))
+    .stderr(predicate::str::contains(
+        "WATCH mode: chunks=2 failed=0 processed=2 flipped=1",
+    ));
+
+    assert_eq!(
+        fs::read_to_string(out_dir.join("batch_0.fastq")).unwrap(),
+        "@r1 orientation:+\nGGTT\n+\nJJII\n"
+    );
+    assert_eq!(
+        fs::read_to_string(out_dir.join("batch_1.fastq")).unwrap(),
+        "@r2 orientation:+\nACGT\n+\nIIII\n"
+    );
+    assert!(!out_dir.join("notes.txt").exists());
+}
+
+#[test]
+fn watch_mode_keeps_going_after_a_failed_chunk() {
+    let td = tempfile::tempdir().unwrap();
+    let in_dir = td.path().join("chunks");
+    let out_dir = td.path().join("out");
+    fs::create_dir(&in_dir).unwrap();
+    write(&in_dir.join("batch_0.fastq"), "not a FASTQ record\n");
+    write(
+        &in_dir.join("batch_1.fastq"),
+        "@r1 orientation:-\nAACC\n+\nIIJJ\n",
+    );
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    cmd.args(["--fastq", "--watch", in_dir.to_str().unwrap()])
+        .args(["--watch-out-dir", out_dir.to_str().unwrap()])
+        .args(["--watch-interval", "0.05", "--watch-idle-exit", "0.3"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("chunk \""))
+        .stderr(predicate::str::contains(
+            "WATCH mode: chunks=2 failed=1 processed=1 flipped=1",
+        ))
+        .stderr(predicate::str::contains("1 of 2 chunk(s) failed"));
+    assert_eq!(
+        fs::read_to_string(out_dir.join("batch_1.fastq")).unwrap(),
+        "@r1 orientation:+\nGGTT\n+\nJJII\n"
+    );
+}
+
+/// Send one raw HTTP request and return the full response text.
+#[cfg(feature = "server")]
+fn http(addr: &str, request: &str) -> String {
+    use std::io::Read;
+    let mut stream = std::net::TcpStream::connect(addr).unwrap();
+    stream.write_all(request.as_bytes()).unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    response
+}
+
+#[test]
+#[cfg(feature = "server")]
+fn serve_restrands_posted_records() {
+    use std::io::{BufRead, BufReader};
+    use std::process::Stdio;
+
+    let td = tempfile::tempdir().unwrap();
+    let tsv_p = td.path().join("map.tsv");
+    write(&tsv_p, TSV);
+    let mut child = std::process::Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"))
+        .args([
+            "serve",
+            "--listen",
+            "127.0.0.1:0",
+            "-t",
+            tsv_p.to_str().unwrap(),
+            "--max-requests",
+            "3",
+        ])
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut line = String::new();
+    BufReader::new(child.stderr.take().unwrap())
+        .read_line(&mut line)
+        .unwrap();
+    let addr = line
+        .trim()
+        .strip_prefix("listening on ")
+        .unwrap()
+        .to_owned();
+
+    let resp = http(
+        &addr,
+        &format!(
+            "POST /restrand?format=fasta HTTP/1.1\r\nContent-Length: {}\r\n\r\n{}",
+            FASTA.len(),
+            FASTA
+        ),
+    );
+    assert!(resp.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(resp.contains("X-Restrand-Summary: processed=2 flipped=1 missing=0 warnings=0\r\n"));
+    assert!(resp.ends_with("\r\n\r\n>readA some desc\nACGTACGTAC\n>readB\naaatttGGGCCC\n"));
+
+    let fq = "@r orientation:+\nAACC\n+\nIIJJ\n";
+    let resp = http(
+        &addr,
+        &format!(
+            "POST /restrand?format=fastq&target=%2D HTTP/1.1\r\nContent-Length: {}\r\n\r\n{}",
+            fq.len(),
+            fq
+        ),
+    );
+    assert!(resp.ends_with("\r\n\r\n@r orientation:-\nGGTT\n+\nJJII\n"));
+
+    let resp = http(&addr, "POST /restrand?format=bam HTTP/1.1\r\n\r\n");
+    assert!(resp.starts_with("HTTP/1.1 422 "));
+    assert!(resp.contains("format must be 'fasta' or 'fastq'"));
+
+    assert!(child.wait().unwrap().success());
+}
+
+#[test]
+#[cfg(feature = "server")]
+fn serve_is_not_blocked_by_an_idle_or_short_request() {
+    use std::io::{BufRead, BufReader, Read};
+    use std::net::{Shutdown, TcpStream};
+    use std::process::Stdio;
+
+    let td = tempfile::tempdir().unwrap();
+    let tsv_p = td.path().join("map.tsv");
+    write(&tsv_p, TSV);
+    let mut child = std::process::Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"))
+        .args([
+            "serve",
+            "--listen",
+            "127.0.0.1:0",
+            "-t",
+            tsv_p.to_str().unwrap(),
+            "--max-requests",
+            "3",
+        ])
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut stderr = BufReader::new(child.stderr.take().unwrap());
+    let mut line = String::new();
+    stderr.read_line(&mut line).unwrap();
+    let addr = line
+        .trim()
+        .strip_prefix("listening on ")
+        .unwrap()
+        .to_owned();
+
+    // Connects but never sends anything.
+    let idle = TcpStream::connect(&addr).unwrap();
+
+    // Claims a large body, then stops short.
+    let mut short = TcpStream::connect(&addr).unwrap();
+    short
+        .write_all(b"POST /restrand?format=fasta HTTP/1.1\r\nContent-Length: 1000000\r\n\r\n>r\nAC")
+        .unwrap();
+    short.shutdown(Shutdown::Write).unwrap();
+    let mut resp = String::new();
+    short.read_to_string(&mut resp).unwrap();
+    assert!(resp.starts_with("HTTP/1.1 400 "), "{}", resp);
+    assert!(resp.contains("request body ended after 5 of 1000000 bytes"));
+
+    let resp = http(
+        &addr,
+        &format!(
+            "POST /restrand?format=fasta HTTP/1.1\r\nContent-Length: {}\r\n\r\n{}",
+            FASTA.len(),
+            FASTA
+        ),
+    );
+    assert!(resp.ends_with("\r\n\r\n>readA some desc\nACGTACGTAC\n>readB\naaatttGGGCCC\n"));
+
+    drop(idle);
+    assert!(child.wait().unwrap().success());
+    let mut log = String::new();
+    stderr.read_to_string(&mut log).unwrap();
+    assert!(!log.contains("panicked"), "{}", log);
+}
+
+#[test]
+#[cfg(feature = "server")]
+fn serve_exposes_prometheus_metrics() {
+    use std::io::{BufRead, BufReader};
+    use std::process::Stdio;
+
+    let td = tempfile::tempdir().unwrap();
+    let tsv_p = td.path().join("map.tsv");
+    write(&tsv_p, TSV);
+    let mut child = std::process::Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"))
+        .args([
+            "serve",
+            "--listen",
+            "127.0.0.1:0",
+            "-t",
+            tsv_p.to_str().unwrap(),
+        ])
+        .args(["--metrics-listen", "127.0.0.1:0", "--max-requests", "3"])
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut stderr = BufReader::new(child.stderr.take().unwrap());
+    let mut addr = |prefix: &str| {
+        let mut line = String::new();
+        stderr.read_line(&mut line).unwrap();
+        line.trim().strip_prefix(prefix).unwrap().to_owned()
+    };
+    let (addr, metrics_addr) = (addr("listening on "), addr("metrics on "));
+
+    http(
+        &addr,
+        &format!(
+            "POST /restrand?format=fasta HTTP/1.1\r\nContent-Length: {}\r\n\r\n{}",
+            FASTA.len(),
+            FASTA
+        ),
+    );
+    http(&addr, "POST /restrand?format=bam HTTP/1.1\r\n\r\n");
+    let resp = http(&metrics_addr, "GET /metrics HTTP/1.1\r\n\r\n");
+    assert!(resp.starts_with("HTTP/1.1 200 OK\r\n"));
+    for line in [
+        "# TYPE restrand_records_processed_total counter\n",
+        "restrand_batches_total 1\n",
+        "restrand_records_processed_total 2\n",
+        "restrand_records_flipped_total 1\n",
+        "restrand_errors_total 1\n",
+        "# TYPE restrand_throughput_records_per_second gauge\n",
+    ] {
+        assert!(resp.contains(line), "{} missing from\n{}", line, resp);
+    }
+    let resp = http(&metrics_addr, "GET / HTTP/1.1\r\n\r\n");
+    assert!(resp.starts_with("HTTP/1.1 404 "));
+
+    http(&addr, "GET /health HTTP/1.1\r\n\r\n");
+    assert!(child.wait().unwrap().success());
+}
+
+#[cfg(unix)]
+#[test]
+#[cfg(feature = "remote")]
+fn object_store_outputs_stream_through_the_cli() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let td = tempfile::tempdir().unwrap();
+    let d = td.path();
+    write(&d.join("in.fa"), FASTA);
+    write(&d.join("map.tsv"), TSV);
+    // Stand-in clients that store the uploaded bytes next to their argument list
+    fs::create_dir_all(d.join("bin")).unwrap();
+    for client in ["aws", "gcloud"] {
+        let script = d.join("bin").join(client);
+        write(
+            &script,
+            &format!(
+                "#!/bin/sh\necho \"$@\" > {0}/{1}.args\ncat > {0}/{1}.body\n",
+                d.display(),
+                client
+            ),
+        );
+        fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+    }
+    let path = format!(
+        "{}:{}",
+        d.join("bin").display(),
+        std::env::var("PATH").unwrap_or_default()
+    );
+    let p = |name: &str| d.join(name).to_str().unwrap().to_owned();
+
+    for (uri, client) in [
+        ("s3://bucket/run/out.fa", "aws"),
+        ("gs://bucket/run/out.fa", "gcloud"),
+    ] {
+        let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+        cmd.env("PATH", &path)
+            .args(["-f", &p("in.fa"), "-t", &p("map.tsv"), "-o", uri, "-q"])
+            .assert()
+            .success();
+        let args = fs::read_to_string(d.join(format!("{}.args", client))).unwrap();
+        assert!(
+            args.trim_end().ends_with(&format!("cp - {}", uri)),
+            "{}",
+            args
+        );
+        let body = fs::read_to_string(d.join(format!("{}.body", client))).unwrap();
+        assert!(body.contains(">readB\naaatttGGGCCC\n"), "{}", body);
+    }
+
+    // A failing upload fails the run
+    write(&d.join("bin/aws"), "#!/bin/sh\ncat > /dev/null\nexit 1\n");
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    cmd.env("PATH", &path)
+        .args(["-f", &p("in.fa"), "-t", &p("map.tsv"), "-o", "s3://b/x.fa"])
+        .assert()
+        .code(1)
+        .stderr(predicate::str::contains("upload of s3://b/x.fa failed"));
+}
+
+#[cfg(unix)]
+#[test]
+#[cfg(feature = "remote")]
+fn object_store_inputs_stream_from_the_cli() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let td = tempfile::tempdir().unwrap();
+    let d = td.path();
+    write_gz(&d.join("object.fa.gz"), FASTA);
+    write(&d.join("map.tsv"), TSV);
+    // Stand-in clients that print the stored object and record their arguments
+    fs::create_dir_all(d.join("bin")).unwrap();
+    for client in ["aws", "gcloud"] {
+        let script = d.join("bin").join(client);
+        write(
+            &script,
+            &format!(
+                "#!/bin/sh\necho \"$@\" > {0}/{1}.args\n\
+                 echo \"$CLOUDSDK_AUTH_CREDENTIAL_FILE_OVERRIDE\" > {0}/{1}.key\n\
+                 cat {0}/object.fa.gz\n",
+                d.display(),
+                client
+            ),
+        );
+        fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+    }
+    let path = format!(
+        "{}:{}",
+        d.join("bin").display(),
+        std::env::var("PATH").unwrap_or_default()
+    );
+    let p = |name: &str| d.join(name).to_str().unwrap().to_owned();
+
+    for (uri, client, expected_args) in [
+        (
+            "gs://bucket/in.fa.gz",
+            "gcloud",
+            "storage cat gs://bucket/in.fa.gz",
+        ),
+        (
+            "s3://bucket/in.fa.gz",
+            "aws",
+            "s3 cp s3://bucket/in.fa.gz -",
+        ),
+    ] {
+        let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+        cmd.env("PATH", &path)
+            .env_remove("GOOGLE_APPLICATION_CREDENTIALS")
+            .env_remove("CLOUDSDK_AUTH_CREDENTIAL_FILE_OVERRIDE")
+            .args(["-f", uri, "-t", &p("map.tsv"), "-q"])
+            .assert()
+            .success()
+            .stdout(predicate::str::contains(">readB\naaatttGGGCCC\n"));
+        let args = fs::read_to_string(d.join(format!("{}.args", client))).unwrap();
+        assert_eq!(args.trim_end(), expected_args);
+    }
+
+    // gcloud ignores application-default credentials, so their key file is
+    // passed on as its credential file override, unless one is already set
+    for (own, expected) in [
+        (None, "/keys/sa.json"),
+        (Some("/keys/own.json"), "/keys/own.json"),
+    ] {
+        let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+        cmd.env("PATH", &path)
+            .env("GOOGLE_APPLICATION_CREDENTIALS", "/keys/sa.json")
+            .env_remove("CLOUDSDK_AUTH_CREDENTIAL_FILE_OVERRIDE");
+        if let Some(key) = own {
+            cmd.env("CLOUDSDK_AUTH_CREDENTIAL_FILE_OVERRIDE", key);
+        }
+        run_ok(cmd.args(["-f", "gs://bucket/in.fa.gz", "-t", &p("map.tsv"), "-q"]));
+        let key = fs::read_to_string(d.join("gcloud.key")).unwrap();
+        assert_eq!(key.trim_end(), expected);
+    }
+
+    // A failing download fails the run
+    write(
+        &d.join("bin/gcloud"),
+        "#!/bin/sh\necho denied >&2\nexit 1\n",
+    );
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    cmd.env("PATH", &path)
+        .args(["-f", "gs://b/x.fa", "-t", &p("map.tsv")])
+        .assert()
+        .code(1)
+        .stderr(predicate::str::contains("download of gs://b/x.fa failed"));
+}
+
+#[test]
+fn buffer_sizes_do_not_change_output() {
+    let td = tempfile::tempdir().unwrap();
+    let d = td.path();
+    write_gz(&d.join("in.fa.gz"), FASTA);
+    write(&d.join("map.tsv"), TSV);
+    let p = |name: &str| d.join(name).to_str().unwrap().to_owned();
+    let base = ["-f", &p("in.fa.gz"), "-t", &p("map.tsv")];
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    let default = run_ok(cmd.args(base));
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    let tiny = run_ok(
+        cmd.args(base)
+            .args(["--read-buffer-size", "1", "--write-buffer-size", "3"]),
+    );
+    assert_eq!(tiny, default);
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    cmd.args(base)
+        .args(["-o", &p("out.fa"), "--write-buffer-size", "4M"])
+        .assert()
+        .success();
+    assert_eq!(fs::read_to_string(d.join("out.fa")).unwrap(), default);
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    cmd.args(base)
+        .args(["--read-buffer-size", "big"])
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains("invalid size"));
+}
+
+#[test]
+fn only_listed_emits_just_table_reads() {
+    let td = tempfile::tempdir().unwrap();
+    let d = td.path();
+    write(
+        &d.join("in.fa"),
+        ">a\nAACG\n>b orientation:-\nAACG\n>c\nAACG\n",
+    );
+    write(&d.join("map.tsv"), "ReadName\torientation\na\t+\nc\t-\n");
+    let p = |name: &str| d.join(name).to_str().unwrap().to_owned();
+    let base = [
+        "-f",
+        &p("in.fa"),
+        "-t",
+        &p("map.tsv"),
+        "--use-orientation-tag",
+    ];
+
+    // The tag resolves 'b', so --drop-missing keeps it; --only-listed does not
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    let out = run_ok(cmd.args(base).arg("--drop-missing"));
+    assert!(out.contains(">b"));
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    cmd.args(base)
+        .arg("--only-listed")
+        .assert()
+        .success()
+        .stdout(">a\nAACG\n>c\nCGTT\n")
+        .stderr(predicate::str::contains("missing_in_table=1"));
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    cmd.args(["--fastq", "-f", &p("in.fa"), "--only-listed"])
+        .assert()
+        .code(2);
+}
+
+#[cfg(unix)]
+#[test]
+#[cfg(feature = "remote")]
+fn sra_runs_stream_through_fastq_dump() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let td = tempfile::tempdir().unwrap();
+    let d = td.path();
+    write(&d.join("run.fq"), TAGGED_FASTQ);
+    // Stand-in fastq-dump that prints a stored run and records its arguments
+    fs::create_dir_all(d.join("bin")).unwrap();
+    let script = d.join("bin/fastq-dump");
+    write(
+        &script,
+        &format!(
+            "#!/bin/sh\necho \"$@\" > {0}/args\ncat {0}/run.fq\n",
+            d.display()
+        ),
+    );
+    fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+    let path = format!(
+        "{}:{}",
+        d.join("bin").display(),
+        std::env::var("PATH").unwrap_or_default()
+    );
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    cmd.env("PATH", &path)
+        .args(["--fastq", "--sra", "SRR000001", "-q"])
+        .assert()
+        .success()
+        .stdout(predicate::str::starts_with(
+            "@r1 cell:1|orientation:+|UMI:AC\nCGTT\n+\nDCBA\n",
+        ));
+    let args = fs::read_to_string(d.join("args")).unwrap();
+    assert!(args.contains("--stdout") && args.trim_end().ends_with("SRR000001"));
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    cmd.args(["--fastq", "--sra", "GSM12345"])
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains("not an SRA run accession"));
+}
+
+#[test]
+fn gzip_on_stdin_is_detected() {
+    let td = tempfile::tempdir().unwrap();
+    let d = td.path();
+    write(&d.join("map.tsv"), TSV);
+    write_gz(&d.join("in.fa.gz"), FASTA);
+    let tsv = d.join("map.tsv").to_str().unwrap().to_owned();
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    let expected = run_ok(cmd.args(["-f", "-", "-t", &tsv]).write_stdin(FASTA));
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    cmd.args(["-f", "-", "-t", &tsv])
+        .write_stdin(fs::read(d.join("in.fa.gz")).unwrap())
+        .assert()
+        .success()
+        .stdout(expected);
+}
+
+/// Uncompressed BAM with no references holding `(name, flag, seq, qual)`
+/// records, qualities as phred+33 text.
+fn bam(records: &[(&str, u16, &str, &str)]) -> Vec<u8> {
+    let records: Vec<_> = records
+        .iter()
+        .map(|&(name, flag, seq, qual)| (name, flag, seq, qual, &b""[..]))
+        .collect();
+    bam_with_aux(&records)
+}
+
+/// As [`bam`], with each record's encoded aux fields last.
+fn bam_with_aux(records: &[(&str, u16, &str, &str, &[u8])]) -> Vec<u8> {
+    let mut out = b"BAM\x01".to_vec();
+    out.extend_from_slice(&0u32.to_le_bytes()); // l_text
+    out.extend_from_slice(&0u32.to_le_bytes()); // n_ref
+    for (name, flag, seq, qual, aux) in records {
+        let mut r = Vec::new();
+        r.extend_from_slice(&(-1i32).to_le_bytes()); // refID
+        r.extend_from_slice(&(-1i32).to_le_bytes()); // pos
+        r.push(name.len() as u8 + 1);
+        r.push(255); // MAPQ
+        r.extend_from_slice(&4680u16.to_le_bytes()); // bin
+        r.extend_from_slice(&0u16.to_le_bytes()); // n_cigar_op
+        r.extend_from_slice(&flag.to_le_bytes());
+        r.extend_from_slice(&(seq.len() as u32).to_le_bytes());
+        r.extend_from_slice(&(-1i32).to_le_bytes()); // next refID
+        r.extend_from_slice(&(-1i32).to_le_bytes()); // next pos
+        r.extend_from_slice(&0i32.to_le_bytes()); // TLEN
+        r.extend_from_slice(name.as_bytes());
+        r.push(0);
+        let code = |b: u8| b"=ACMGRSVTWYHKDBN".iter().position(|&c| c == b).unwrap() as u8;
+        for pair in seq.as_bytes().chunks(2) {
+            r.push(code(pair[0]) << 4 | pair.get(1).map_or(0, |&b| code(b)));
+        }
+        if qual.is_empty() {
+            r.extend(std::iter::repeat_n(0xff, seq.len()));
+        } else {
+            r.extend(qual.bytes().map(|q| q - 33));
+        }
+        r.extend_from_slice(aux);
+        out.extend_from_slice(&(r.len() as u32).to_le_bytes());
+        out.extend_from_slice(&r);
+    }
+    out
+}
+
+#[cfg(feature = "bam")]
+#[test]
+fn bam_input_is_read_as_sequenced() {
+    let td = tempfile::tempdir().unwrap();
+    let d = td.path();
+    write(&d.join("map.tsv"), TSV);
+    write(
+        &d.join("in.fq"),
+        "@readA\nACGTACGTAC\n+\nABCDEFGHIJ\n@readB\nGGGCCCAAATTT\n+\n#$%&'()*+,-.\n",
+    );
+    // readB is stored reverse-complemented (FLAG 0x10), with a secondary copy
+    let records = bam(&[
+        ("readA", 0, "ACGTACGTAC", "ABCDEFGHIJ"),
+        ("readB", 0x10, "AAATTTGGGCCC", ".-,+*)('&%$#"),
+        ("readB", 0x100, "GGGCCC", ""),
+    ]);
+    let f = File::create(d.join("in.bam")).unwrap();
+    let mut gz = GzEncoder::new(f, Compression::default());
+    gz.write_all(&records).unwrap();
+    gz.finish().unwrap();
+    let p = |name: &str| d.join(name).to_str().unwrap().to_owned();
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    let expected = run_ok(cmd.args(["--fastq", "-f", &p("in.fq"), "-t", &p("map.tsv")]));
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    cmd.args(["--fastq", "-f", &p("in.bam"), "-t", &p("map.tsv")])
+        .assert()
+        .success()
+        .stdout(expected.clone());
+    // Uncompressed BAM on stdin, as from samtools view -u
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    cmd.args(["--fastq", "-f", "-", "-t", &p("map.tsv")])
+        .write_stdin(records.clone())
+        .assert()
+        .success()
+        .stdout(expected);
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    cmd.args(["-f", "-", "-t", &p("map.tsv")])
+        .write_stdin(records)
+        .assert()
+        .success()
+        .stdout(">readA\nACGTACGTAC\n>readB\nAAATTTGGGCCC\n");
+
+    // A record without QUAL cannot become FASTQ
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    cmd.args(["--fastq", "-f", "-", "-t", &p("map.tsv")])
+        .write_stdin(bam(&[("readA", 0, "ACGT", "")]))
+        .assert()
+        .code(1)
+        .stderr(predicate::str::contains(
+            "BAM record 1: no QUAL for --fastq",
+        ));
+}
+
+/// `(name, flag, seq, aux)` of each record of a BGZF-compressed BAM with no references.
+#[cfg(feature = "bam")]
+fn bam_records(bgzf: &[u8]) -> Vec<(String, u16, String, Vec<u8>)> {
+    use flate2::read::MultiGzDecoder;
+    use std::io::Read;
+    let mut raw = Vec::new();
+    MultiGzDecoder::new(bgzf).read_to_end(&mut raw).unwrap();
+    assert!(raw.starts_with(b"BAM\x01"));
+    let u32_at = |b: &[u8], i: usize| u32::from_le_bytes(b[i..i + 4].try_into().unwrap()) as usize;
+    let l_text = u32_at(&raw, 4);
+    assert_eq!(u32_at(&raw, 8 + l_text), 0, "no references");
+    let mut rest = &raw[12 + l_text..];
+    let mut records = Vec::new();
+    while !rest.is_empty() {
+        let len = u32_at(rest, 0);
+        let r = &rest[4..4 + len];
+        let l_name = r[8] as usize;
+        let n_cigar = u16::from_le_bytes([r[12], r[13]]) as usize;
+        let flag = u16::from_le_bytes([r[14], r[15]]);
+        let l_seq = u32_at(r, 16);
+        let name = String::from_utf8(r[32..32 + l_name - 1].to_vec()).unwrap();
+        let packed = &r[32 + l_name + 4 * n_cigar..];
+        let seq: String = (0..l_seq)
+            .map(|i| {
+                let code = packed[i / 2] >> (4 * (1 - i % 2)) & 0xf;
+                b"=ACMGRSVTWYHKDBN"[code as usize] as char
+            })
+            .collect();
+        let aux = packed[l_seq.div_ceil(2) + l_seq..].to_vec();
+        records.push((name, flag, seq, aux));
+        rest = &rest[4 + len..];
+    }
+    records
+}
+
+#[cfg(feature = "bam")]
+#[test]
+fn bam_out_patches_the_orientation_without_touching_reads() {
+    let td = tempfile::tempdir().unwrap();
+    let d = td.path();
+    write(
+        &d.join("map.tsv"),
+        "ReadName\torientation\nreadA\t-\nreadB\t-\nreadC\t+\n",
+    );
+    // readA is aligned (no FLAG 0x4), readB and readC are unmapped; the
+    // secondary record of readB passes through unchanged
+    let input = bam(&[
+        ("readA", 0, "ACGTACGTAC", "ABCDEFGHIJ"),
+        ("readB", 0x100, "GGGCCC", ""),
+        ("readB", 0x4, "GGGCCCAAATTT", "IIIIIIIIIIII"),
+        ("readC", 0x4, "TTTT", "IIII"),
+    ]);
+    let p = |name: &str| d.join(name).to_str().unwrap().to_owned();
+    let run = |args: &[&str]| {
+        let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+        let out = cmd
+            .args([
+                "--fastq",
+                "-f",
+                "-",
+                "-t",
+                &p("map.tsv"),
+                "--out-format",
+                "bam",
+            ])
+            .args(args)
+            .write_stdin(input.clone())
+            .assert()
+            .success()
+            .get_output()
+            .stdout
+            .clone();
+        bam_records(&out)
+    };
+    let record = |name: &str, flag: u16, seq: &str, aux: &[u8]| {
+        (name.to_owned(), flag, seq.to_owned(), aux.to_vec())
+    };
+
+    assert_eq!(
+        run(&[]),
+        [
+            record("readA", 0, "ACGTACGTAC", b"tsA-"),
+            record("readB", 0x100, "GGGCCC", b""),
+            record("readB", 0x4, "GGGCCCAAATTT", b"tsA-"),
+            record("readC", 0x4, "TTTT", b"tsA+"),
+        ]
+    );
+    assert_eq!(
+        run(&["--bam-patch-tag", "XO"])[3],
+        record("readC", 0x4, "TTTT", b"XOA+")
+    );
+    // Only unmapped reads that would be flipped get FLAG 0x10
+    assert_eq!(
+        run(&["--bam-patch", "flag"]),
+        [
+            record("readA", 0, "ACGTACGTAC", b""),
+            record("readB", 0x100, "GGGCCC", b""),
+            record("readB", 0x14, "GGGCCCAAATTT", b""),
+            record("readC", 0x4, "TTTT", b""),
+        ]
+    );
+
+    write(&d.join("in.fq"), "@readA\nACGT\n+\nIIII\n");
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    cmd.args(["--fastq", "-f", &p("in.fq"), "-t", &p("map.tsv")])
+        .args(["--out-format", "bam"])
+        .assert()
+        .code(1)
+        .stderr(predicate::str::contains("is not BAM"));
+    write(&d.join("in.2bit"), "");
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    cmd.args(["-f", &p("in.2bit"), "-t", &p("map.tsv")])
+        .args(["--out-format", "bam"])
+        .assert()
+        .code(1)
+        .stderr(predicate::str::contains("is 2bit"));
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    cmd.args([
+        "--fastq",
+        "-f",
+        "-",
+        "-t",
+        &p("map.tsv"),
+        "--out-format",
+        "bam",
+    ])
+    .args(["--unchanged-out", &p("kept.bam")])
+    .write_stdin(input.clone())
+    .assert()
+    .code(1)
+    .stderr(predicate::str::contains(
+        "cannot be combined with --unchanged-out",
+    ));
+}
+
+#[cfg(feature = "bam")]
+#[test]
+fn bam_tag_orients_reads_by_ts_or_xs() {
+    // ts is relative to the read; XS to the reference, so readB (FLAG 0x10)
+    // is '-' by XS:A:+
+    let input = bam_with_aux(&[
+        ("readA", 0, "ACGTACGTAC", "", b"tsA-XSA-"),
+        ("readB", 0x10, "AAATTTGGGCCC", "", b"tsA+XSA+"),
+        ("readC", 0, "TTTTGG", "", b"XSA?"),
+    ]);
+    let run = |tag: &str| {
+        let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+        run_ok(
+            cmd.args(["-f", "-", "--bam-tag", tag])
+                .write_stdin(input.clone()),
+        )
+    };
+    assert_eq!(
+        run("ts"),
+        ">readA\nGTACGTACGT\n>readB\nGGGCCCAAATTT\n>readC\nTTTTGG\n"
+    );
+    assert_eq!(
+        run("XS"),
+        ">readA\nGTACGTACGT\n>readB\nAAATTTGGGCCC\n>readC\nTTTTGG\n"
+    );
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    cmd.args(["-f", "-", "--bam-tag", "ts", "--explain", "1"])
+        .write_stdin(input.clone())
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("bam        '-' from the ts:A tag"));
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    cmd.args(["-f", "-", "--bam-tag", "ts"])
+        .write_stdin(FASTA)
+        .assert()
+        .code(1)
+        .stderr(predicate::str::contains(
+            "--bam-tag reads the aux tags of BAM input, and - is not BAM",
+        ));
+}
+
+#[cfg(not(feature = "bam"))]
+#[test]
+fn bam_input_needs_the_bam_feature() {
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    cmd.args(["--fastq", "-f", "-"])
+        .write_stdin(bam(&[("readA", 0, "ACGT", "IIII")]))
+        .assert()
+        .code(1)
+        .stderr(predicate::str::contains(
+            "stdin is BAM, which needs the `bam` feature",
+        ));
+}
+
+#[test]
+fn trim_qual_trims_ends_in_output_orientation() {
+    let td = tempfile::tempdir().unwrap();
+    let d = td.path();
+    write(
+        &d.join("in.fq"),
+        "@r1 orientation:-\nAACGTAG\n+\n#IIII!!\n@r2 orientation:+\nACGT\n+\n!!!!\n@r3 orientation:+\nACGT\n+\nIIII\n",
+    );
+    let p = |name: &str| d.join(name).to_str().unwrap().to_owned();
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("restrand-fasta"));
+    cmd.args(["--fastq", "-f", &p("in.fq"), "--trim-qual", "10"])
+        .args(["--report", &p("report.json")])
+        .assert()
+        .success()
+        .stdout(
+            "@r1 orientation:+\nACGT\n+\nIIII\n@r2 orientation:+\n\n+\n\n@r3 orientation:+\nACGT\n+\nIIII\n",
+        )
+        .stderr(predicate::str::contains("trimmed_bases=7"));
+    let report: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(d.join("report.json")).unwrap()).unwrap();
+    assert_eq!(report["counts"]["reads_trimmed"], 2);
+    assert_eq!(report["counts"]["trimmed_leading_bases"], 2);
+    assert_eq!(report["counts"]["trimmed_trailing_bases"], 5);
+}